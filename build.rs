@@ -12,6 +12,12 @@
 //! This script relies on the environment variables `WIN10SDK_PATH` and `WIN10SDK_VERSION`.
 //! `WIN10SDK_PATH` defaults to `c:\Program Files (x86)\Windows Kits\10` if not set.
 //! `WIN10SDK_VERSION` defaults to `10.0.18362.0` if not set.
+//! The library is only linked and deployed when building for Windows, the only target the SDK has it for.
+//!
+//! With the `capi` feature enabled, it also generates the C header declaring the exported C ABI
+//! into `OUT_DIR`. The copy checked in as `include/vmss.h` is compared against it
+//! by the integration tests, and is refreshed with
+//! `cbindgen --config cbindgen.toml --output include/vmss.h`.
 
 use std::env::var;
 use std::fs;
@@ -41,7 +47,7 @@ pub fn deploy_dll() {
     let destination = Path::new(&destination);
 
     if !destination.exists() {
-        fs::copy(&dll_path, &destination).unwrap();
+        fs::copy(&dll_path, destination).unwrap();
         println!(
             "cargo:vmsavedstatedump-rs-dll-copied-to={}",
             destination.to_str().unwrap()
@@ -54,10 +60,34 @@ pub fn deploy_dll() {
     }
 }
 
+#[cfg(feature = "capi")]
+pub fn generate_header() {
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let out_dir = PathBuf::from(var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write_to_file(out_dir.join("vmss.h"));
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=WIN10SDK_PATH");
 
+    #[cfg(feature = "capi")]
+    generate_header();
+
+    // The build script runs on the host, so the target is checked through cargo's cfg variables
+    if var("CARGO_CFG_WINDOWS").is_err() {
+        return;
+    }
+
     let root_win10_sdk_path = match var("WIN10SDK_PATH") {
         Ok(path) => path,
         Err(_) => String::from("c:\\Program Files (x86)\\Windows Kits\\10"),
@@ -0,0 +1,456 @@
+/* Generated by cbindgen from src/capi.rs. Do not edit. */
+
+#ifndef VMSS_H
+#define VMSS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum PagingMode {
+  PAGING_MODE_INVALID = 0,
+  PAGING_MODE_NON_PAGED,
+  PAGING_MODE_BIT32,
+  PAGING_MODE_PAE,
+  PAGING_MODE_LONG,
+} PagingMode;
+
+typedef enum RegisterIdx64 {
+  REGISTER_IDX64_RAX = 0,
+  REGISTER_IDX64_RCX,
+  REGISTER_IDX64_RDX,
+  REGISTER_IDX64_RBX,
+  REGISTER_IDX64_RSP,
+  REGISTER_IDX64_RBP,
+  REGISTER_IDX64_RSI,
+  REGISTER_IDX64_RDI,
+  REGISTER_IDX64_R8,
+  REGISTER_IDX64_R9,
+  REGISTER_IDX64_R10,
+  REGISTER_IDX64_R11,
+  REGISTER_IDX64_R12,
+  REGISTER_IDX64_R13,
+  REGISTER_IDX64_R14,
+  REGISTER_IDX64_R15,
+  REGISTER_IDX64_RIP,
+  REGISTER_IDX64_R_FLAGS,
+  REGISTER_IDX64_LOW_XMM0,
+  REGISTER_IDX64_HIGH_XMM0,
+  REGISTER_IDX64_LOW_XMM1,
+  REGISTER_IDX64_HIGH_XMM1,
+  REGISTER_IDX64_LOW_XMM2,
+  REGISTER_IDX64_HIGH_XMM2,
+  REGISTER_IDX64_LOW_XMM3,
+  REGISTER_IDX64_HIGH_XMM3,
+  REGISTER_IDX64_LOW_XMM4,
+  REGISTER_IDX64_HIGH_XMM4,
+  REGISTER_IDX64_LOW_XMM5,
+  REGISTER_IDX64_HIGH_XMM5,
+  REGISTER_IDX64_LOW_XMM6,
+  REGISTER_IDX64_HIGH_XMM6,
+  REGISTER_IDX64_LOW_XMM7,
+  REGISTER_IDX64_HIGH_XMM7,
+  REGISTER_IDX64_LOW_XMM8,
+  REGISTER_IDX64_HIGH_XMM8,
+  REGISTER_IDX64_LOW_XMM9,
+  REGISTER_IDX64_HIGH_XMM9,
+  REGISTER_IDX64_LOW_XMM10,
+  REGISTER_IDX64_HIGH_XMM10,
+  REGISTER_IDX64_LOW_XMM11,
+  REGISTER_IDX64_HIGH_XMM11,
+  REGISTER_IDX64_LOW_XMM12,
+  REGISTER_IDX64_HIGH_XMM12,
+  REGISTER_IDX64_LOW_XMM13,
+  REGISTER_IDX64_HIGH_XMM13,
+  REGISTER_IDX64_LOW_XMM14,
+  REGISTER_IDX64_HIGH_XMM14,
+  REGISTER_IDX64_LOW_XMM15,
+  REGISTER_IDX64_HIGH_XMM15,
+  REGISTER_IDX64_LOW_XMM_CONTROL_STATUS,
+  REGISTER_IDX64_HIGH_XMM_CONTROL_STATUS,
+  REGISTER_IDX64_LOW_FP_CONTROL_STATUS,
+  REGISTER_IDX64_HIGH_FP_CONTROL_STATUS,
+  REGISTER_IDX64_CR0,
+  REGISTER_IDX64_CR2,
+  REGISTER_IDX64_CR3,
+  REGISTER_IDX64_CR4,
+  REGISTER_IDX64_CR8,
+  REGISTER_IDX64_EFER,
+  REGISTER_IDX64_DR0,
+  REGISTER_IDX64_DR1,
+  REGISTER_IDX64_DR2,
+  REGISTER_IDX64_DR3,
+  REGISTER_IDX64_DR6,
+  REGISTER_IDX64_DR7,
+  REGISTER_IDX64_BASE_GS,
+  REGISTER_IDX64_BASE_FS,
+  REGISTER_IDX64_SEG_CS,
+  REGISTER_IDX64_SEG_DS,
+  REGISTER_IDX64_SEG_ES,
+  REGISTER_IDX64_SEG_FS,
+  REGISTER_IDX64_SEG_GS,
+  REGISTER_IDX64_SEG_SS,
+  REGISTER_IDX64_TR,
+  REGISTER_IDX64_LDTR,
+  REGISTER_IDX64_BASE_IDTR,
+  REGISTER_IDX64_LIMIT_IDTR,
+  REGISTER_IDX64_BASE_GDTR,
+  REGISTER_IDX64_LIMIT_GDTR,
+  REGISTER_IDX64_COUNT,
+} RegisterIdx64;
+
+typedef enum RegisterIdx86 {
+  REGISTER_IDX86_EAX = 0,
+  REGISTER_IDX86_ECX,
+  REGISTER_IDX86_EDX,
+  REGISTER_IDX86_EBX,
+  REGISTER_IDX86_ESP,
+  REGISTER_IDX86_EBP,
+  REGISTER_IDX86_ESI,
+  REGISTER_IDX86_EDI,
+  REGISTER_IDX86_EIP,
+  REGISTER_IDX86_E_FLAGS,
+  REGISTER_IDX86_LOW_XMM0,
+  REGISTER_IDX86_HIGH_XMM0,
+  REGISTER_IDX86_LOW_XMM1,
+  REGISTER_IDX86_HIGH_XMM1,
+  REGISTER_IDX86_LOW_XMM2,
+  REGISTER_IDX86_HIGH_XMM2,
+  REGISTER_IDX86_LOW_XMM3,
+  REGISTER_IDX86_HIGH_XMM3,
+  REGISTER_IDX86_LOW_XMM4,
+  REGISTER_IDX86_HIGH_XMM4,
+  REGISTER_IDX86_LOW_XMM5,
+  REGISTER_IDX86_HIGH_XMM5,
+  REGISTER_IDX86_LOW_XMM6,
+  REGISTER_IDX86_HIGH_XMM6,
+  REGISTER_IDX86_LOW_XMM7,
+  REGISTER_IDX86_HIGH_XMM7,
+  REGISTER_IDX86_LOW_XMM8,
+  REGISTER_IDX86_HIGH_XMM8,
+  REGISTER_IDX86_LOW_XMM9,
+  REGISTER_IDX86_HIGH_XMM9,
+  REGISTER_IDX86_LOW_XMM10,
+  REGISTER_IDX86_HIGH_XMM10,
+  REGISTER_IDX86_LOW_XMM11,
+  REGISTER_IDX86_HIGH_XMM11,
+  REGISTER_IDX86_LOW_XMM12,
+  REGISTER_IDX86_HIGH_XMM12,
+  REGISTER_IDX86_LOW_XMM13,
+  REGISTER_IDX86_HIGH_XMM13,
+  REGISTER_IDX86_LOW_XMM14,
+  REGISTER_IDX86_HIGH_XMM14,
+  REGISTER_IDX86_LOW_XMM15,
+  REGISTER_IDX86_HIGH_XMM15,
+  REGISTER_IDX86_LOW_XMM_CONTROL_STATUS,
+  REGISTER_IDX86_HIGH_XMM_CONTROL_STATUS,
+  REGISTER_IDX86_LOW_FP_CONTROL_STATUS,
+  REGISTER_IDX86_HIGH_FP_CONTROL_STATUS,
+  REGISTER_IDX86_CR0,
+  REGISTER_IDX86_CR2,
+  REGISTER_IDX86_CR3,
+  REGISTER_IDX86_CR4,
+  REGISTER_IDX86_CR8,
+  REGISTER_IDX86_EFER,
+  REGISTER_IDX86_DR0,
+  REGISTER_IDX86_DR1,
+  REGISTER_IDX86_DR2,
+  REGISTER_IDX86_DR3,
+  REGISTER_IDX86_DR6,
+  REGISTER_IDX86_DR7,
+  REGISTER_IDX86_BASE_GS,
+  REGISTER_IDX86_BASE_FS,
+  REGISTER_IDX86_SEG_CS,
+  REGISTER_IDX86_SEG_DS,
+  REGISTER_IDX86_SEG_ES,
+  REGISTER_IDX86_SEG_FS,
+  REGISTER_IDX86_SEG_GS,
+  REGISTER_IDX86_SEG_SS,
+  REGISTER_IDX86_TR,
+  REGISTER_IDX86_LDTR,
+  REGISTER_IDX86_BASE_IDTR,
+  REGISTER_IDX86_LIMIT_IDTR,
+  REGISTER_IDX86_BASE_GDTR,
+  REGISTER_IDX86_LIMIT_GDTR,
+  REGISTER_IDX86_COUNT,
+} RegisterIdx86;
+
+typedef enum VirtualProcessorArch {
+  VIRTUAL_PROCESSOR_ARCH_UNKNOWN = 0,
+  VIRTUAL_PROCESSOR_ARCH_X86,
+  VIRTUAL_PROCESSOR_ARCH_X64,
+} VirtualProcessorArch;
+
+/*
+ Dump formats that can be written through the C API.
+ */
+typedef enum VmssDumpFormat {
+  VMSS_DUMP_FORMAT_WINDOWS_FULL = 0,
+  VMSS_DUMP_FORMAT_WINDOWS_BITMAP,
+  VMSS_DUMP_FORMAT_ELF_CORE,
+  VMSS_DUMP_FORMAT_KDUMP,
+  VMSS_DUMP_FORMAT_LIME,
+  VMSS_DUMP_FORMAT_RAW,
+} VmssDumpFormat;
+
+/*
+ Result codes returned by every function of the C API.
+ */
+typedef enum VmssResult {
+  VMSS_RESULT_SUCCESS = 0,
+  VMSS_RESULT_OUT_OF_MEMORY,
+  VMSS_RESULT_FILE_NOT_FOUND,
+  VMSS_RESULT_FAIL,
+  VMSS_RESULT_INVALID_ARGUMENT,
+  VMSS_RESULT_UNEXPECTED,
+  VMSS_RESULT_REGISTER_ARCHITECTURE_MISMATCH,
+  VMSS_RESULT_KD_DEBUGGER_DATA_BLOCK_NOT_FOUND,
+  VMSS_RESULT_CANCELLED,
+  VMSS_RESULT_IO,
+  /*
+   Failure HRESULT returned by vmsavedstatedumpprovider.dll.
+   */
+  VMSS_RESULT_WINDOWS_H_RESULT,
+  /*
+   The supplied buffer can't hold the result, whose required size is returned.
+   */
+  VMSS_RESULT_BUFFER_TOO_SMALL,
+  VMSS_RESULT_NULL_POINTER,
+  /*
+   The call panicked, which is a bug of this crate.
+   */
+  VMSS_RESULT_PANIC,
+} VmssResult;
+
+/*
+ Loaded VM saved state.
+ */
+typedef struct VmssProvider VmssProvider;
+
+typedef struct GpaMemoryChunk {
+  uint64_t guest_physical_start_page_index;
+  uint64_t page_count;
+} GpaMemoryChunk;
+
+/*
+ Keys the KdDebuggerDataBlock of Windows 8 and later 64-bit guests is encoded with:
+ the values of nt!KiWaitNever and nt!KiWaitAlways, and the address of nt!KdpDataBlockEncoded.
+ */
+typedef struct VmssKdDebuggerDataEncoding {
+  uint64_t wait_never;
+  uint64_t wait_always;
+  uint64_t data_block_encoded;
+} VmssKdDebuggerDataEncoding;
+
+/*
+ Called with the bytes of guest memory written so far, the total to write,
+ and the context supplied along with the callback.
+ */
+typedef void (*VmssProgressCallback)(uint64_t bytes_written,
+                                     uint64_t total_bytes,
+                                     void *context);
+
+/*
+ Statistics of a written dump file.
+ */
+typedef struct VmssDumpStatistics {
+  uint64_t pages_written;
+  uint64_t bytes_written;
+  uint64_t zero_pages_elided;
+  /*
+   Set when a Windows dump was written without a KdDebuggerDataBlock, because it wasn't found.
+   */
+  bool kd_debugger_data_block_missing;
+} VmssDumpStatistics;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Copies the message of the last error returned on the calling thread, NUL terminated and
+ truncated to the given size, and returns the size the whole message needs including the NUL.
+
+ # Safety
+
+ `buffer` must be null or point to `size` writable bytes.
+ */
+size_t vmss_last_error_message(char *buffer,
+                               size_t size);
+
+/*
+ Loads the VM saved state file(s) found at the given path, detecting their format and
+ loading the companion file of BIN/VSV pairs. The provider must be closed with `vmss_close`.
+
+ # Safety
+
+ `path` must be a NUL terminated string, and `provider` must point to writable memory.
+ */
+enum VmssResult vmss_open(const char *path,
+                          struct VmssProvider **provider);
+
+/*
+ Loads a BIN/VSV VM saved state file pair. The provider must be closed with `vmss_close`.
+
+ # Safety
+
+ `bin_path` and `vsv_path` must be NUL terminated strings,
+ and `provider` must point to writable memory.
+ */
+enum VmssResult vmss_open_bin_vsv(const char *bin_path,
+                                  const char *vsv_path,
+                                  struct VmssProvider **provider);
+
+/*
+ Releases a provider returned by `vmss_open` or `vmss_open_bin_vsv`.
+
+ # Safety
+
+ `provider` must be null or a provider that hasn't been closed yet.
+ */
+enum VmssResult vmss_close(struct VmssProvider *provider);
+
+/*
+ Returns the count of virtual processors of the saved state.
+
+ # Safety
+
+ `provider` must be an open provider, and `vp_count` must point to writable memory.
+ */
+enum VmssResult vmss_vp_count(const struct VmssProvider *provider,
+                              uint32_t *vp_count);
+
+/*
+ Returns the architecture of a virtual processor.
+
+ # Safety
+
+ `provider` must be an open provider, and `architecture` must point to writable memory.
+ */
+enum VmssResult vmss_vp_architecture(const struct VmssProvider *provider,
+                                     uint32_t vp_id,
+                                     enum VirtualProcessorArch *architecture);
+
+/*
+ Returns the paging mode of a virtual processor.
+
+ # Safety
+
+ `provider` must be an open provider, and `paging_mode` must point to writable memory.
+ */
+enum VmssResult vmss_vp_paging_mode(const struct VmssProvider *provider,
+                                    uint32_t vp_id,
+                                    enum PagingMode *paging_mode);
+
+/*
+ Returns the value of a register of a virtual processor. The register is identified by its
+ `VirtualProcessorArch` architecture and its `RegisterIdx86` or `RegisterIdx64` value.
+
+ # Safety
+
+ `provider` must be an open provider, and `value` must point to writable memory.
+ */
+enum VmssResult vmss_vp_register_value(const struct VmssProvider *provider,
+                                       uint32_t vp_id,
+                                       uint32_t architecture,
+                                       uint32_t register_id,
+                                       uint64_t *value);
+
+/*
+ Returns the identifier of the register with the given name, such as `rip` or `cs`, of the
+ given `VirtualProcessorArch` architecture, which can be passed to `vmss_vp_register_value`.
+
+ # Safety
+
+ `name` must be a NUL terminated string, and `register_id` must point to writable memory.
+ */
+enum VmssResult vmss_register_from_name(uint32_t architecture,
+                                        const char *name,
+                                        uint32_t *register_id);
+
+/*
+ Reads guest physical memory, returning the count of bytes read.
+ Sizes of 4 GiB or more fail with `InvalidArgument`.
+
+ # Safety
+
+ `provider` must be an open provider, `buffer` must point to `size` writable bytes,
+ and `bytes_read` must point to writable memory.
+ */
+enum VmssResult vmss_read_physical_memory(const struct VmssProvider *provider,
+                                          uint64_t physical_address,
+                                          uint8_t *buffer,
+                                          size_t size,
+                                          size_t *bytes_read);
+
+/*
+ Reads guest virtual memory, translated through the given virtual processor,
+ returning the count of bytes read. Sizes of 4 GiB or more fail with `InvalidArgument`.
+
+ # Safety
+
+ `provider` must be an open provider, `buffer` must point to `size` writable bytes,
+ and `bytes_read` must point to writable memory.
+ */
+enum VmssResult vmss_read_virtual_memory(const struct VmssProvider *provider,
+                                         uint32_t vp_id,
+                                         uint64_t virtual_address,
+                                         uint8_t *buffer,
+                                         size_t size,
+                                         size_t *bytes_read);
+
+/*
+ Translates a guest virtual address through the given virtual processor.
+
+ # Safety
+
+ `provider` must be an open provider, and `physical_address` must point to writable memory.
+ */
+enum VmssResult vmss_virtual_to_physical_address(const struct VmssProvider *provider,
+                                                 uint32_t vp_id,
+                                                 uint64_t virtual_address,
+                                                 uint64_t *physical_address);
+
+/*
+ Returns the page size and the memory chunks of guest physical memory. `chunk_count` holds
+ the capacity of `chunks` on input, and the count of chunks on output. When the capacity
+ is too small, `VMSS_RESULT_BUFFER_TOO_SMALL` is returned and no chunk is copied.
+
+ # Safety
+
+ `provider` must be an open provider, `page_size` and `chunk_count` must point to writable
+ memory, and `chunks` must be null or point to `*chunk_count` writable chunks.
+ */
+enum VmssResult vmss_memory_chunks(const struct VmssProvider *provider,
+                                   uint64_t *page_size,
+                                   struct GpaMemoryChunk *chunks,
+                                   size_t *chunk_count);
+
+/*
+ Writes a dump of the saved state to the file at the given path, in the given `VmssDumpFormat`
+ with the default options of the format. The KdDebuggerDataBlock encoding keys are optional,
+ and only used by Windows dumps; when the block isn't found, the dump is written without it
+ and the statistics report it missing. The progress callback is optional, and called with
+ the given context.
+
+ # Safety
+
+ `provider` must be an open provider, `output_path` must be a NUL terminated string,
+ and `kd_debugger_data_encoding` and `statistics` must be null or point to a valid
+ `VmssKdDebuggerDataEncoding` and writable memory respectively.
+ */
+enum VmssResult vmss_write_dump(const struct VmssProvider *provider,
+                                const char *output_path,
+                                uint32_t format,
+                                const struct VmssKdDebuggerDataEncoding *kd_debugger_data_encoding,
+                                VmssProgressCallback progress,
+                                void *context,
+                                struct VmssDumpStatistics *statistics);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* VMSS_H */
@@ -0,0 +1,521 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Command line tool that inspects VM saved state files and converts them to dump files.
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use vmsavedstatedump_rs::dump::elf::*;
+use vmsavedstatedump_rs::dump::kdump::*;
+use vmsavedstatedump_rs::dump::lime::*;
+use vmsavedstatedump_rs::dump::raw::*;
+use vmsavedstatedump_rs::dump::verify::*;
+use vmsavedstatedump_rs::dump::windows::*;
+use vmsavedstatedump_rs::dump::*;
+use vmsavedstatedump_rs::gdb::*;
+use vmsavedstatedump_rs::open::*;
+use vmsavedstatedump_rs::shell::*;
+use vmsavedstatedump_rs::vmsavedstatedump::*;
+use vmsavedstatedump_rs::vmsavedstatedumpdefs::*;
+
+const USAGE: &str = "\
+Usage: vmss2dump <command> <saved-state> [arguments] [options]
+
+Commands:
+    info                        Virtual processors, paging modes and guest memory layout
+    regs                        Register values of every virtual processor
+    read <address> <length>     Hexdump of guest physical memory
+    convert <format> <output>   Writes a dump file, where format is one of
+                                windows-full, windows-bitmap, elf, kdump, lime or raw
+    shell                       Interactive shell with WinDbg-like commands, enter help to list them
+    gdbserver                   Serves the saved state to debuggers over the GDB remote protocol
+
+Options:
+    --companion <path>          BIN or VSV file loaded along with the saved state
+    --apply-replay-log          Applies the pending replay log to the VMRS file before loading it
+    --replay-log-copy <path>    Applies the pending replay log to a copy of the VMRS file instead
+    --vp <id>                   Virtual processor to show the registers of in regs,
+                                or to translate virtual addresses with in read
+    --virtual                   Reads guest virtual memory in read
+    --context-vp <id>           Virtual processor whose context is stored in Windows dumps
+    --kdbg <address>            Virtual address of the KdDebuggerDataBlock stored in Windows dumps
+    --kdbg-encoding <keys>      KiWaitNever,KiWaitAlways,&KdpDataBlockEncoded the KdDebuggerDataBlock
+                                is encoded with, as in Windows 8 and later 64-bit guests
+    --direct-map <address>      Virtual address the direct map of 64-bit Linux guests starts at,
+                                stored in ELF cores; looked up in the page tables by default
+    --compression <name>        Compression of kdump pages: none, lzo, zlib or zstd,
+                                lzo by default
+    --sparse                    Leaves holes and zero pages of raw images sparse
+    --verify                    Verifies the dump against the saved state after writing it
+    --listen <address>          Address gdbserver listens on, 127.0.0.1:1234 by default
+";
+
+/// Options that take a value.
+const VALUE_OPTIONS: [&str; 9] = [
+    "--companion",
+    "--replay-log-copy",
+    "--vp",
+    "--context-vp",
+    "--kdbg",
+    "--kdbg-encoding",
+    "--direct-map",
+    "--compression",
+    "--listen",
+];
+
+/// Command line arguments, split in positional arguments and options.
+struct Arguments {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Arguments {
+    fn parse<I: Iterator<Item = String>>(mut arguments: I) -> Result<Arguments, Box<dyn Error>> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+
+        while let Some(argument) = arguments.next() {
+            if !argument.starts_with("--") {
+                positional.push(argument);
+            } else if VALUE_OPTIONS.contains(&argument.as_str()) {
+                match arguments.next() {
+                    Some(value) => options.push((argument, Some(value))),
+                    None => return Err(format!("{} requires a value", argument).into()),
+                }
+            } else {
+                options.push((argument, None));
+            }
+        }
+
+        Ok(Arguments {
+            positional,
+            options,
+        })
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    fn number(&self, name: &str) -> Result<Option<u64>, Box<dyn Error>> {
+        self.value(name).map(parse_number).transpose()
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str, Box<dyn Error>> {
+        match self.positional.get(index) {
+            Some(argument) => Ok(argument),
+            None => Err(format!("missing {}\n\n{}", name, USAGE).into()),
+        }
+    }
+}
+
+/// Parses a hexadecimal number with a 0x prefix, or a decimal number.
+fn parse_number(value: &str) -> Result<u64, Box<dyn Error>> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(&hex.replace('`', ""), 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number {}", value).into())
+}
+
+fn open_saved_state(
+    path: &str,
+    arguments: &Arguments,
+) -> Result<VmSavedStateDumpProvider, Box<dyn Error>> {
+    let mut options = SavedStateOpenOptions::new();
+    if let Some(companion) = arguments.value("--companion") {
+        options.companion(companion);
+    }
+    if let Some(copy) = arguments.value("--replay-log-copy") {
+        options.replay_log(ReplayLog::ApplyToCopy(PathBuf::from(copy)));
+    } else if arguments.flag("--apply-replay-log") {
+        options.replay_log(ReplayLog::ApplyInPlace);
+    }
+
+    Ok(options.open(path)?)
+}
+
+fn info(provider: &VmSavedStateDumpProvider) -> Result<(), Box<dyn Error>> {
+    println!("Virtual processors: {}", provider.vp_count()?);
+    for vp in provider.vp_iter()? {
+        let describe = |value: VmSavedStateDumpResult<String>| match value {
+            Ok(value) => value,
+            Err(error) => format!("<{}>", error),
+        };
+        println!(
+            "    VP {}: {}, paging mode {}, CPU mode {}",
+            vp.id(),
+            describe(vp.architecture().map(|value| format!("{:?}", value))),
+            describe(vp.paging_mode().map(|value| format!("{:?}", value))),
+            describe(vp.cpu_mode().map(|value| format!("{:?}", value))),
+        );
+    }
+
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    println!("Page size: {:#x}", page_size);
+    println!("Guest physical memory chunks: {}", memory_chunks.len());
+    // Chunks without pages have no last address to print
+    for chunk in memory_chunks.iter().filter(|chunk| chunk.page_count != 0) {
+        let start = chunk.guest_physical_start_page_index * page_size;
+        println!(
+            "    {:#018x}-{:#018x} ({} pages)",
+            start,
+            start + chunk.page_count * page_size - 1,
+            chunk.page_count
+        );
+    }
+    println!(
+        "Raw saved memory size: {:#x}",
+        provider.guest_raw_saved_memory_size()?
+    );
+    Ok(())
+}
+
+fn regs(provider: &VmSavedStateDumpProvider, vp_id: Option<u64>) -> Result<(), Box<dyn Error>> {
+    for vp in provider.vp_iter()? {
+        if vp_id.is_some_and(|vp_id| vp_id != u64::from(vp.id())) {
+            continue;
+        }
+
+        let architecture = vp.architecture()?;
+        println!("VP {} ({:?}):", vp.id(), architecture);
+        for register in Register::all(architecture) {
+            match vp.register_value(register) {
+                Ok(value) => println!("    {:<22} {:#018x}", register.name(), value.value),
+                Err(error) => println!("    {:<22} <{}>", register.name(), error),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Largest amount of guest memory read and printed at once by the read command.
+const READ_CHUNK_SIZE: u64 = 0x10000;
+
+fn read(
+    provider: &VmSavedStateDumpProvider,
+    address: u64,
+    length: u64,
+    virtual_vp_id: Option<u32>,
+) -> Result<(), Box<dyn Error>> {
+    let mut buffer = vec![0u8; std::cmp::min(length, READ_CHUNK_SIZE) as usize];
+    let mut total_read = 0;
+
+    // Memory is read and printed in chunks, so the length never has to fit in memory
+    while total_read < length {
+        let chunk_address = match address.checked_add(total_read) {
+            Some(chunk_address) => chunk_address,
+            None => break,
+        };
+        let chunk = &mut buffer[..std::cmp::min(length - total_read, READ_CHUNK_SIZE) as usize];
+        let bytes_read = match virtual_vp_id {
+            Some(vp_id) => provider.read_guest_virtual_address(vp_id, chunk_address, chunk)?,
+            None => provider.read_guest_physical_address(chunk_address, chunk)?,
+        };
+
+        for (index, line) in chunk[..bytes_read as usize].chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = line
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7E => *byte as char,
+                    _ => '.',
+                })
+                .collect();
+            println!(
+                "{:016x}  {:<47}  {}",
+                chunk_address.wrapping_add(index as u64 * 16),
+                hex.join(" "),
+                ascii
+            );
+        }
+
+        total_read += u64::from(bytes_read);
+        if bytes_read as usize != chunk.len() {
+            break;
+        }
+    }
+
+    if total_read != length {
+        println!("Read {:#x} of {:#x} bytes", total_read, length);
+    }
+    Ok(())
+}
+
+/// Parses the comma separated KiWaitNever, KiWaitAlways and KdpDataBlockEncoded address values.
+fn parse_kd_debugger_data_encoding(value: &str) -> Result<KdDebuggerDataEncoding, Box<dyn Error>> {
+    let keys = value
+        .split(',')
+        .map(parse_number)
+        .collect::<Result<Vec<_>, _>>()?;
+    match keys[..] {
+        [wait_never, wait_always, data_block_encoded] => Ok(KdDebuggerDataEncoding {
+            wait_never,
+            wait_always,
+            data_block_encoded,
+        }),
+        _ => Err(format!("invalid --kdbg-encoding {}\n\n{}", value, USAGE).into()),
+    }
+}
+
+fn parse_compression(name: Option<&str>) -> Result<KdumpCompression, Box<dyn Error>> {
+    match name {
+        None => Ok(KdumpOptions::default().compression),
+        Some("none") => Ok(KdumpCompression::None),
+        Some("lzo") => Ok(KdumpCompression::Lzo),
+        #[cfg(feature = "flate2")]
+        Some("zlib") => Ok(KdumpCompression::Zlib),
+        #[cfg(feature = "zstd")]
+        Some("zstd") => Ok(KdumpCompression::Zstd),
+        #[cfg(not(feature = "flate2"))]
+        Some("zlib") => Err("zlib compression requires the flate2 feature".into()),
+        #[cfg(not(feature = "zstd"))]
+        Some("zstd") => Err("zstd compression requires the zstd feature".into()),
+        Some(name) => Err(format!("unknown compression {}", name).into()),
+    }
+}
+
+/// Dump formats the convert command writes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum DumpFormat {
+    WindowsFull,
+    WindowsBitmap,
+    Elf,
+    Kdump,
+    Lime,
+    Raw,
+}
+
+impl DumpFormat {
+    fn from_name(name: &str) -> Result<DumpFormat, Box<dyn Error>> {
+        match name {
+            "windows-full" => Ok(DumpFormat::WindowsFull),
+            "windows-bitmap" => Ok(DumpFormat::WindowsBitmap),
+            "elf" => Ok(DumpFormat::Elf),
+            "kdump" => Ok(DumpFormat::Kdump),
+            "lime" => Ok(DumpFormat::Lime),
+            "raw" => Ok(DumpFormat::Raw),
+            _ => Err(format!("unknown format {}\n\n{}", name, USAGE).into()),
+        }
+    }
+}
+
+/// Writes a dump in the given format. Every argument is validated before the output
+/// is opened, so mistyped arguments never truncate an existing file.
+fn convert(
+    provider: &VmSavedStateDumpProvider,
+    format: DumpFormat,
+    output_path: &str,
+    arguments: &Arguments,
+) -> Result<bool, Box<dyn Error>> {
+    let context_vp_id = arguments.number("--context-vp")?.unwrap_or(0) as u32;
+    let kd_debugger_data_block = arguments.number("--kdbg")?;
+    let kd_debugger_data_encoding = arguments
+        .value("--kdbg-encoding")
+        .map(parse_kd_debugger_data_encoding)
+        .transpose()?;
+    let windows_options = |dump_type| WindowsCrashDumpOptions {
+        dump_type,
+        kd_debugger_data_block,
+        kd_debugger_data_encoding,
+        context_vp_id,
+        ..Default::default()
+    };
+    let elf_options = ElfCoreOptions {
+        direct_map_base: arguments.number("--direct-map")?,
+        ..Default::default()
+    };
+    let kdump_options = KdumpOptions {
+        compression: parse_compression(arguments.value("--compression"))?,
+        ..Default::default()
+    };
+    let raw_options = RawImageOptions {
+        sparse: arguments.flag("--sparse"),
+    };
+
+    let mut output = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_path)?;
+
+    let mut last_percent = None;
+    let mut control = DumpControl {
+        progress: Some(Box::new(|progress: DumpProgress| {
+            let percent = match progress.total_bytes {
+                0 => 100,
+                total_bytes => progress.bytes_written * 100 / total_bytes,
+            };
+            if last_percent != Some(percent) {
+                last_percent = Some(percent);
+                eprint!("\rWriting {}: {}%", output_path, percent);
+            }
+        })),
+        ..Default::default()
+    };
+
+    let statistics = match format {
+        DumpFormat::WindowsFull => write_windows_crash_dump(
+            provider,
+            &mut output,
+            &windows_options(WindowsDumpType::Full),
+            &mut control,
+        )?,
+        DumpFormat::WindowsBitmap => write_windows_crash_dump(
+            provider,
+            &mut output,
+            &windows_options(WindowsDumpType::Bitmap),
+            &mut control,
+        )?,
+        DumpFormat::Elf => write_elf_core(provider, &mut output, &elf_options, &mut control)?,
+        DumpFormat::Kdump => write_kdump(provider, &mut output, &kdump_options, &mut control)?,
+        DumpFormat::Lime => write_lime_image(provider, &mut output, &mut control)?,
+        DumpFormat::Raw => write_raw_image(provider, &mut output, &raw_options, &mut control)?,
+    };
+    drop(control);
+    eprintln!();
+    println!(
+        "Wrote {} pages, {} bytes, {} zero pages elided",
+        statistics.pages_written, statistics.bytes_written, statistics.zero_pages_elided
+    );
+    if statistics.kd_debugger_data_block_missing {
+        eprintln!(
+            "warning: the KdDebuggerDataBlock wasn't found, so the dump doesn't point to it; \
+             Windows 8 and later 64-bit guests need --kdbg-encoding"
+        );
+    }
+
+    if !arguments.flag("--verify") {
+        return Ok(true);
+    }
+
+    let report = match format {
+        DumpFormat::WindowsFull => verify_windows_crash_dump(
+            provider,
+            &mut output,
+            &windows_options(WindowsDumpType::Full),
+        )?,
+        DumpFormat::WindowsBitmap => verify_windows_crash_dump(
+            provider,
+            &mut output,
+            &windows_options(WindowsDumpType::Bitmap),
+        )?,
+        DumpFormat::Elf => verify_elf_core(provider, &mut output)?,
+        DumpFormat::Kdump => verify_kdump(provider, &mut output)?,
+        DumpFormat::Lime => verify_lime_image(provider, &mut output)?,
+        DumpFormat::Raw => verify_raw_image(provider, &mut output)?,
+    };
+    print_report(&report);
+    Ok(report.is_valid())
+}
+
+fn print_report(report: &VerificationReport) {
+    println!(
+        "Verified {} pages ({} excluded) and {} registers",
+        report.pages_verified, report.pages_excluded, report.registers_verified
+    );
+    for mismatch in &report.page_mismatches {
+        println!(
+            "    Pages {:#x}-{:#x}: {:?}",
+            mismatch.first_page_index,
+            mismatch.first_page_index + mismatch.page_count - 1,
+            mismatch.kind
+        );
+    }
+    for mismatch in &report.register_mismatches {
+        println!(
+            "    VP {} {}: expected {:#x}, found {:#x}",
+            mismatch.vp_id,
+            mismatch.register.name(),
+            mismatch.expected,
+            mismatch.actual
+        );
+    }
+    for vp_id in &report.missing_vp_ids {
+        println!("    VP {}: registers missing", vp_id);
+    }
+    println!(
+        "Verification {}",
+        if report.is_valid() {
+            "passed"
+        } else {
+            "failed"
+        }
+    );
+}
+
+fn run() -> Result<bool, Box<dyn Error>> {
+    let arguments = Arguments::parse(std::env::args().skip(1))?;
+    if arguments.flag("--help") || arguments.positional.is_empty() {
+        print!("{}", USAGE);
+        return Ok(true);
+    }
+
+    let command = arguments.positional(0, "command")?;
+    let provider = open_saved_state(arguments.positional(1, "saved state file")?, &arguments)?;
+    match command {
+        "info" => info(&provider)?,
+        "regs" => regs(&provider, arguments.number("--vp")?)?,
+        "read" => {
+            let address = parse_number(arguments.positional(2, "address")?)?;
+            let length = parse_number(arguments.positional(3, "length")?)?;
+            let virtual_vp_id = match arguments.flag("--virtual") {
+                true => Some(arguments.number("--vp")?.unwrap_or(0) as u32),
+                false => None,
+            };
+            read(&provider, address, length, virtual_vp_id)?
+        }
+        "convert" => {
+            let format = DumpFormat::from_name(arguments.positional(2, "format")?)?;
+            let output = arguments.positional(3, "output file")?;
+            if !convert(&provider, format, output, &arguments)? {
+                return Ok(false);
+            }
+        }
+        "shell" => {
+            let stdin = std::io::stdin();
+            Shell::new(&provider).run(stdin.lock(), std::io::stdout())?
+        }
+        "gdbserver" => {
+            let address = arguments.value("--listen").unwrap_or("127.0.0.1:1234");
+            let mut server = GdbServer::new(&provider)?;
+            eprintln!("Listening for debuggers on {}", address);
+            server.listen(address)?
+        }
+        _ => return Err(format!("unknown command {}\n\n{}", command, USAGE).into()),
+    }
+
+    std::io::stdout().flush()?;
+    provider.close()?;
+    Ok(true)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,649 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module exports a C ABI over the safe API of this crate, built when the `capi` feature
+//! is enabled. The declarations are generated with cbindgen into `OUT_DIR` by the build script,
+//! and checked in as `include/vmss.h`, which the integration tests compare against them.
+//!
+//! Every function returns a `VmssResult`, and the message of the last error returned
+//! on a thread can be retrieved with `vmss_last_error_message`. Paths are UTF-8 strings.
+
+use crate::dump::elf::*;
+use crate::dump::kdump::*;
+use crate::dump::lime::*;
+use crate::dump::raw::*;
+use crate::dump::windows::*;
+use crate::dump::*;
+use crate::open::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::path::PathBuf;
+
+/// Result codes returned by every function of the C API.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum VmssResult {
+    Success = 0,
+    OutOfMemory,
+    FileNotFound,
+    Fail,
+    InvalidArgument,
+    Unexpected,
+    RegisterArchitectureMismatch,
+    KdDebuggerDataBlockNotFound,
+    Cancelled,
+    Io,
+    /// Failure HRESULT returned by vmsavedstatedumpprovider.dll.
+    WindowsHResult,
+    /// The supplied buffer can't hold the result, whose required size is returned.
+    BufferTooSmall,
+    NullPointer,
+    /// The call panicked, which is a bug of this crate.
+    Panic,
+}
+
+impl From<ResultCode> for VmssResult {
+    fn from(code: ResultCode) -> Self {
+        match code {
+            ResultCode::Success => VmssResult::Success,
+            ResultCode::OutOfMemory => VmssResult::OutOfMemory,
+            ResultCode::FileNotFound => VmssResult::FileNotFound,
+            ResultCode::Fail => VmssResult::Fail,
+            ResultCode::InvalidArgument => VmssResult::InvalidArgument,
+            ResultCode::Unexpected => VmssResult::Unexpected,
+            ResultCode::RegisterArchitectureMismatch { .. } => {
+                VmssResult::RegisterArchitectureMismatch
+            }
+            ResultCode::KdDebuggerDataBlockNotFound => VmssResult::KdDebuggerDataBlockNotFound,
+            ResultCode::Cancelled => VmssResult::Cancelled,
+            ResultCode::Io(std::io::ErrorKind::NotFound) => VmssResult::FileNotFound,
+            ResultCode::Io(_) => VmssResult::Io,
+            ResultCode::WindowsHResult(_) => VmssResult::WindowsHResult,
+        }
+    }
+}
+
+/// Dump formats that can be written through the C API.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum VmssDumpFormat {
+    WindowsFull = 0,
+    WindowsBitmap,
+    ElfCore,
+    Kdump,
+    Lime,
+    Raw,
+}
+
+impl VmssDumpFormat {
+    const ALL: [VmssDumpFormat; 6] = [
+        VmssDumpFormat::WindowsFull,
+        VmssDumpFormat::WindowsBitmap,
+        VmssDumpFormat::ElfCore,
+        VmssDumpFormat::Kdump,
+        VmssDumpFormat::Lime,
+        VmssDumpFormat::Raw,
+    ];
+}
+
+/// Statistics of a written dump file.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct VmssDumpStatistics {
+    pub pages_written: u64,
+    pub bytes_written: u64,
+    pub zero_pages_elided: u64,
+    /// Set when a Windows dump was written without a KdDebuggerDataBlock, because it wasn't found.
+    pub kd_debugger_data_block_missing: bool,
+}
+
+/// Keys the KdDebuggerDataBlock of Windows 8 and later 64-bit guests is encoded with:
+/// the values of nt!KiWaitNever and nt!KiWaitAlways, and the address of nt!KdpDataBlockEncoded.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct VmssKdDebuggerDataEncoding {
+    pub wait_never: u64,
+    pub wait_always: u64,
+    pub data_block_encoded: u64,
+}
+
+/// Called with the bytes of guest memory written so far, the total to write,
+/// and the context supplied along with the callback.
+pub type VmssProgressCallback =
+    Option<extern "C" fn(bytes_written: u64, total_bytes: u64, context: *mut c_void)>;
+
+/// Loaded VM saved state.
+pub struct VmssProvider {
+    provider: VmSavedStateDumpProvider,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Records the outcome of a call as the last error of the thread, and returns its result code.
+fn record(result: Result<(), (VmssResult, String)>) -> VmssResult {
+    match result {
+        Ok(()) => VmssResult::Success,
+        Err((code, message)) => {
+            LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+            code
+        }
+    }
+}
+
+/// Runs the body of an exported function, turning its errors and panics into result codes.
+fn ffi_call<F>(body: F) -> VmssResult
+where
+    F: FnOnce() -> Result<(), (VmssResult, String)>,
+{
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
+        Ok(result) => record(result),
+        Err(_) => record(Err((VmssResult::Panic, String::from("the call panicked")))),
+    }
+}
+
+fn error(error: VmSavedStateDumpError) -> (VmssResult, String) {
+    (error.code().into(), error.to_string())
+}
+
+fn null_pointer(name: &str) -> (VmssResult, String) {
+    (VmssResult::NullPointer, format!("{} is null", name))
+}
+
+/// Validates a `VirtualProcessorArch` value received from C, where any other value
+/// would be undefined behavior if taken as the enum itself.
+fn architecture(value: u32) -> Result<VirtualProcessorArch, (VmssResult, String)> {
+    [
+        VirtualProcessorArch::Unknown,
+        VirtualProcessorArch::X86,
+        VirtualProcessorArch::X64,
+    ]
+    .iter()
+    .copied()
+    .find(|architecture| *architecture as u32 == value)
+    .ok_or_else(|| {
+        (
+            VmssResult::InvalidArgument,
+            format!("unknown architecture {}", value),
+        )
+    })
+}
+
+/// Validates a `VmssDumpFormat` value received from C.
+fn dump_format(value: u32) -> Result<VmssDumpFormat, (VmssResult, String)> {
+    VmssDumpFormat::ALL
+        .iter()
+        .copied()
+        .find(|format| *format as u32 == value)
+        .ok_or_else(|| {
+            (
+                VmssResult::InvalidArgument,
+                format!("unknown dump format {}", value),
+            )
+        })
+}
+
+unsafe fn provider<'a>(
+    provider: *const VmssProvider,
+) -> Result<&'a VmSavedStateDumpProvider, (VmssResult, String)> {
+    match provider.as_ref() {
+        Some(provider) => Ok(&provider.provider),
+        None => Err(null_pointer("provider")),
+    }
+}
+
+unsafe fn string<'a>(value: *const c_char, name: &str) -> Result<&'a str, (VmssResult, String)> {
+    if value.is_null() {
+        return Err(null_pointer(name));
+    }
+    CStr::from_ptr(value).to_str().map_err(|_| {
+        (
+            VmssResult::InvalidArgument,
+            format!("{} isn't valid UTF-8", name),
+        )
+    })
+}
+
+unsafe fn path(value: *const c_char, name: &str) -> Result<PathBuf, (VmssResult, String)> {
+    string(value, name).map(PathBuf::from)
+}
+
+unsafe fn output<'a, T>(value: *mut T, name: &str) -> Result<&'a mut T, (VmssResult, String)> {
+    value.as_mut().ok_or_else(|| null_pointer(name))
+}
+
+/// Takes a buffer to read memory into. Sizes the count of bytes read can't be returned for
+/// are rejected before the slice is built.
+unsafe fn buffer<'a>(buffer: *mut u8, size: usize) -> Result<&'a mut [u8], (VmssResult, String)> {
+    match (buffer.is_null(), size) {
+        (_, 0) => Ok(&mut []),
+        (true, _) => Err(null_pointer("buffer")),
+        (false, size) if size > u32::MAX as usize => Err((
+            VmssResult::InvalidArgument,
+            format!("buffer size {:#x} is 4 GiB or more", size),
+        )),
+        (false, _) => Ok(std::slice::from_raw_parts_mut(buffer, size)),
+    }
+}
+
+/// Copies the message of the last error returned on the calling thread, NUL terminated and
+/// truncated to the given size, and returns the size the whole message needs including the NUL.
+///
+/// # Safety
+///
+/// `buffer` must be null or point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_last_error_message(buffer: *mut c_char, size: usize) -> usize {
+    LAST_ERROR.with(|last_error| {
+        let message = last_error.borrow();
+        if !buffer.is_null() && size > 0 {
+            let length = std::cmp::min(message.len(), size - 1);
+            std::ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, buffer, length);
+            *buffer.add(length) = 0;
+        }
+        message.len() + 1
+    })
+}
+
+/// Loads the VM saved state file(s) found at the given path, detecting their format and
+/// loading the companion file of BIN/VSV pairs. The provider must be closed with `vmss_close`.
+///
+/// # Safety
+///
+/// `path` must be a NUL terminated string, and `provider` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_open(
+    path: *const c_char,
+    provider: *mut *mut VmssProvider,
+) -> VmssResult {
+    ffi_call(|| {
+        let path = self::path(path, "path")?;
+        let provider = output(provider, "provider")?;
+        let loaded = SavedStateOpenOptions::new().open(path).map_err(error)?;
+        *provider = Box::into_raw(Box::new(VmssProvider { provider: loaded }));
+        Ok(())
+    })
+}
+
+/// Loads a BIN/VSV VM saved state file pair. The provider must be closed with `vmss_close`.
+///
+/// # Safety
+///
+/// `bin_path` and `vsv_path` must be NUL terminated strings,
+/// and `provider` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_open_bin_vsv(
+    bin_path: *const c_char,
+    vsv_path: *const c_char,
+    provider: *mut *mut VmssProvider,
+) -> VmssResult {
+    ffi_call(|| {
+        let bin_path = path(bin_path, "bin_path")?;
+        let vsv_path = path(vsv_path, "vsv_path")?;
+        let provider = output(provider, "provider")?;
+        let loaded = VmSavedStateDumpProvider::load_bin_vsv(bin_path, vsv_path).map_err(error)?;
+        *provider = Box::into_raw(Box::new(VmssProvider { provider: loaded }));
+        Ok(())
+    })
+}
+
+/// Releases a provider returned by `vmss_open` or `vmss_open_bin_vsv`.
+///
+/// # Safety
+///
+/// `provider` must be null or a provider that hasn't been closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_close(provider: *mut VmssProvider) -> VmssResult {
+    ffi_call(|| {
+        if provider.is_null() {
+            return Ok(());
+        }
+        Box::from_raw(provider).provider.close().map_err(error)
+    })
+}
+
+/// Returns the count of virtual processors of the saved state.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, and `vp_count` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_vp_count(
+    provider: *const VmssProvider,
+    vp_count: *mut u32,
+) -> VmssResult {
+    ffi_call(|| {
+        *output(vp_count, "vp_count")? = self::provider(provider)?.vp_count().map_err(error)?;
+        Ok(())
+    })
+}
+
+/// Returns the architecture of a virtual processor.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, and `architecture` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_vp_architecture(
+    provider: *const VmssProvider,
+    vp_id: u32,
+    architecture: *mut VirtualProcessorArch,
+) -> VmssResult {
+    ffi_call(|| {
+        *output(architecture, "architecture")? = self::provider(provider)?
+            .get_vp_architecture(vp_id)
+            .map_err(error)?;
+        Ok(())
+    })
+}
+
+/// Returns the paging mode of a virtual processor.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, and `paging_mode` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_vp_paging_mode(
+    provider: *const VmssProvider,
+    vp_id: u32,
+    paging_mode: *mut PagingMode,
+) -> VmssResult {
+    ffi_call(|| {
+        *output(paging_mode, "paging_mode")? = self::provider(provider)?
+            .get_vp_paging_mode(vp_id)
+            .map_err(error)?;
+        Ok(())
+    })
+}
+
+/// Returns the value of a register of a virtual processor. The register is identified by its
+/// `VirtualProcessorArch` architecture and its `RegisterIdx86` or `RegisterIdx64` value.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, and `value` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_vp_register_value(
+    provider: *const VmssProvider,
+    vp_id: u32,
+    architecture: u32,
+    register_id: u32,
+    value: *mut u64,
+) -> VmssResult {
+    ffi_call(|| {
+        let architecture = self::architecture(architecture)?;
+        let register = Register::all(architecture)
+            .into_iter()
+            .find(|register| match register {
+                Register::X86(id) => *id as u32 == register_id,
+                Register::X64(id) => *id as u32 == register_id,
+            });
+        let register = match register {
+            Some(register) => register,
+            None => {
+                return Err((
+                    VmssResult::InvalidArgument,
+                    format!("unknown {:?} register {}", architecture, register_id),
+                ))
+            }
+        };
+        *output(value, "value")? = self::provider(provider)?
+            .get_vp_register_value(vp_id, register)
+            .map_err(error)?
+            .value;
+        Ok(())
+    })
+}
+
+/// Returns the identifier of the register with the given name, such as `rip` or `cs`, of the
+/// given `VirtualProcessorArch` architecture, which can be passed to `vmss_vp_register_value`.
+///
+/// # Safety
+///
+/// `name` must be a NUL terminated string, and `register_id` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_register_from_name(
+    architecture: u32,
+    name: *const c_char,
+    register_id: *mut u32,
+) -> VmssResult {
+    ffi_call(|| {
+        let architecture = self::architecture(architecture)?;
+        let name = string(name, "name")?;
+        let register_id = output(register_id, "register_id")?;
+        match Register::from_name(architecture, name) {
+            Some(Register::X86(id)) => *register_id = id as u32,
+            Some(Register::X64(id)) => *register_id = id as u32,
+            None => {
+                return Err((
+                    VmssResult::InvalidArgument,
+                    format!("unknown {:?} register {}", architecture, name),
+                ))
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Reads guest physical memory, returning the count of bytes read.
+/// Sizes of 4 GiB or more fail with `InvalidArgument`.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, `buffer` must point to `size` writable bytes,
+/// and `bytes_read` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_read_physical_memory(
+    provider: *const VmssProvider,
+    physical_address: u64,
+    buffer: *mut u8,
+    size: usize,
+    bytes_read: *mut usize,
+) -> VmssResult {
+    ffi_call(|| {
+        let bytes_read = output(bytes_read, "bytes_read")?;
+        let buffer = self::buffer(buffer, size)?;
+        *bytes_read = self::provider(provider)?
+            .read_guest_physical_address(physical_address, buffer)
+            .map_err(error)? as usize;
+        Ok(())
+    })
+}
+
+/// Reads guest virtual memory, translated through the given virtual processor,
+/// returning the count of bytes read. Sizes of 4 GiB or more fail with `InvalidArgument`.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, `buffer` must point to `size` writable bytes,
+/// and `bytes_read` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_read_virtual_memory(
+    provider: *const VmssProvider,
+    vp_id: u32,
+    virtual_address: u64,
+    buffer: *mut u8,
+    size: usize,
+    bytes_read: *mut usize,
+) -> VmssResult {
+    ffi_call(|| {
+        let bytes_read = output(bytes_read, "bytes_read")?;
+        let buffer = self::buffer(buffer, size)?;
+        *bytes_read = self::provider(provider)?
+            .read_guest_virtual_address(vp_id, virtual_address, buffer)
+            .map_err(error)? as usize;
+        Ok(())
+    })
+}
+
+/// Translates a guest virtual address through the given virtual processor.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, and `physical_address` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_virtual_to_physical_address(
+    provider: *const VmssProvider,
+    vp_id: u32,
+    virtual_address: u64,
+    physical_address: *mut u64,
+) -> VmssResult {
+    ffi_call(|| {
+        *output(physical_address, "physical_address")? = self::provider(provider)?
+            .guest_virtual_to_physical_address(vp_id, virtual_address)
+            .map_err(error)?;
+        Ok(())
+    })
+}
+
+/// Returns the page size and the memory chunks of guest physical memory. `chunk_count` holds
+/// the capacity of `chunks` on input, and the count of chunks on output. When the capacity
+/// is too small, `VMSS_RESULT_BUFFER_TOO_SMALL` is returned and no chunk is copied.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, `page_size` and `chunk_count` must point to writable
+/// memory, and `chunks` must be null or point to `*chunk_count` writable chunks.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_memory_chunks(
+    provider: *const VmssProvider,
+    page_size: *mut u64,
+    chunks: *mut GpaMemoryChunk,
+    chunk_count: *mut usize,
+) -> VmssResult {
+    ffi_call(|| {
+        let page_size = output(page_size, "page_size")?;
+        let chunk_count = output(chunk_count, "chunk_count")?;
+        let (size, memory_chunks) = self::provider(provider)?
+            .guest_physical_memory_chunks()
+            .map_err(error)?;
+
+        let capacity = if chunks.is_null() { 0 } else { *chunk_count };
+        *page_size = size;
+        *chunk_count = memory_chunks.len();
+        if capacity < memory_chunks.len() {
+            return Err((
+                VmssResult::BufferTooSmall,
+                format!(
+                    "{} memory chunks don't fit in {}",
+                    memory_chunks.len(),
+                    capacity
+                ),
+            ));
+        }
+
+        std::ptr::copy_nonoverlapping(memory_chunks.as_ptr(), chunks, memory_chunks.len());
+        Ok(())
+    })
+}
+
+/// Writes a dump of the saved state to the file at the given path, in the given `VmssDumpFormat`
+/// with the default options of the format. The KdDebuggerDataBlock encoding keys are optional,
+/// and only used by Windows dumps; when the block isn't found, the dump is written without it
+/// and the statistics report it missing. The progress callback is optional, and called with
+/// the given context.
+///
+/// # Safety
+///
+/// `provider` must be an open provider, `output_path` must be a NUL terminated string,
+/// and `kd_debugger_data_encoding` and `statistics` must be null or point to a valid
+/// `VmssKdDebuggerDataEncoding` and writable memory respectively.
+#[no_mangle]
+pub unsafe extern "C" fn vmss_write_dump(
+    provider: *const VmssProvider,
+    output_path: *const c_char,
+    format: u32,
+    kd_debugger_data_encoding: *const VmssKdDebuggerDataEncoding,
+    progress: VmssProgressCallback,
+    context: *mut c_void,
+    statistics: *mut VmssDumpStatistics,
+) -> VmssResult {
+    ffi_call(|| {
+        let provider = self::provider(provider)?;
+        let format = dump_format(format)?;
+        let output_path = path(output_path, "output_path")?;
+        let mut output = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_path)
+            .map_err(|io_error| error(io_error.into()))?;
+
+        let mut control = DumpControl {
+            progress: progress.map(|progress| {
+                Box::new(move |update: DumpProgress| {
+                    progress(update.bytes_written, update.total_bytes, context)
+                }) as Box<dyn FnMut(DumpProgress)>
+            }),
+            ..Default::default()
+        };
+
+        let kd_debugger_data_encoding =
+            kd_debugger_data_encoding
+                .as_ref()
+                .map(|encoding| KdDebuggerDataEncoding {
+                    wait_never: encoding.wait_never,
+                    wait_always: encoding.wait_always,
+                    data_block_encoded: encoding.data_block_encoded,
+                });
+        let windows_options = |dump_type| WindowsCrashDumpOptions {
+            dump_type,
+            kd_debugger_data_encoding,
+            ..Default::default()
+        };
+        let written = match format {
+            VmssDumpFormat::WindowsFull => write_windows_crash_dump(
+                provider,
+                &mut output,
+                &windows_options(WindowsDumpType::Full),
+                &mut control,
+            ),
+            VmssDumpFormat::WindowsBitmap => write_windows_crash_dump(
+                provider,
+                &mut output,
+                &windows_options(WindowsDumpType::Bitmap),
+                &mut control,
+            ),
+            VmssDumpFormat::ElfCore => write_elf_core(
+                provider,
+                &mut output,
+                &ElfCoreOptions::default(),
+                &mut control,
+            ),
+            VmssDumpFormat::Kdump => write_kdump(
+                provider,
+                &mut output,
+                &KdumpOptions::default(),
+                &mut control,
+            ),
+            VmssDumpFormat::Lime => write_lime_image(provider, &mut output, &mut control),
+            VmssDumpFormat::Raw => write_raw_image(
+                provider,
+                &mut output,
+                &RawImageOptions::default(),
+                &mut control,
+            ),
+        }
+        .map_err(error)?;
+
+        if let Some(statistics) = statistics.as_mut() {
+            *statistics = VmssDumpStatistics {
+                pages_written: written.pages_written,
+                bytes_written: written.bytes_written,
+                zero_pages_elided: written.zero_pages_elided,
+                kd_debugger_data_block_missing: written.kd_debugger_data_block_missing,
+            };
+        }
+        Ok(())
+    })
+}
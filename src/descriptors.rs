@@ -0,0 +1,544 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module contains decoders of the guest descriptor tables and of the
+//! segment and system descriptors stored in them.
+
+use crate::registers::*;
+use crate::vmsavedstatedumpdefs::*;
+
+use std::ops;
+
+/// Segment registers whose selector can be resolved to a descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SegmentRegister {
+    Cs,
+    Ds,
+    Es,
+    Fs,
+    Gs,
+    Ss,
+    Tr,
+    Ldtr,
+}
+
+impl SegmentRegister {
+    /// Returns the x86 and x64 register identifiers that hold the selector of this segment register.
+    pub(crate) fn register_ids(&self) -> (RegisterIdx86, RegisterIdx64) {
+        match self {
+            SegmentRegister::Cs => (RegisterIdx86::SegCs, RegisterIdx64::SegCs),
+            SegmentRegister::Ds => (RegisterIdx86::SegDs, RegisterIdx64::SegDs),
+            SegmentRegister::Es => (RegisterIdx86::SegEs, RegisterIdx64::SegEs),
+            SegmentRegister::Fs => (RegisterIdx86::SegFs, RegisterIdx64::SegFs),
+            SegmentRegister::Gs => (RegisterIdx86::SegGs, RegisterIdx64::SegGs),
+            SegmentRegister::Ss => (RegisterIdx86::SegSs, RegisterIdx64::SegSs),
+            SegmentRegister::Tr => (RegisterIdx86::Tr, RegisterIdx64::Tr),
+            SegmentRegister::Ldtr => (RegisterIdx86::Ldtr, RegisterIdx64::Ldtr),
+        }
+    }
+}
+
+/// Segment selector, as loaded in a segment register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Selector(pub u16);
+
+impl Selector {
+    /// Returns the index of the descriptor referenced by this selector.
+    pub fn index(&self) -> u16 {
+        self.0 >> 3
+    }
+
+    /// Returns whether this selector references the LDT instead of the GDT.
+    pub fn is_ldt(&self) -> bool {
+        self.0 & 0x4 != 0
+    }
+
+    /// Returns the requested privilege level.
+    pub fn rpl(&self) -> u8 {
+        (self.0 & 0x3) as u8
+    }
+
+    /// Returns whether this is a null selector.
+    pub fn is_null(&self) -> bool {
+        self.index() == 0 && !self.is_ldt()
+    }
+}
+
+/// Type of a system descriptor or gate, as encoded in the type field of descriptors with S = 0.
+/// In IA-32e mode the 32 bit TSS and gate types refer to their 64 bit counterparts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SystemDescriptorType {
+    Tss16Available,
+    Ldt,
+    Tss16Busy,
+    CallGate16,
+    TaskGate,
+    InterruptGate16,
+    TrapGate16,
+    TssAvailable,
+    TssBusy,
+    CallGate,
+    InterruptGate,
+    TrapGate,
+    Reserved(u8),
+}
+
+impl SystemDescriptorType {
+    /// Decodes the 4 bit type field of a system descriptor.
+    pub fn from_type_field(type_field: u8) -> SystemDescriptorType {
+        match type_field & 0xF {
+            0x1 => SystemDescriptorType::Tss16Available,
+            0x2 => SystemDescriptorType::Ldt,
+            0x3 => SystemDescriptorType::Tss16Busy,
+            0x4 => SystemDescriptorType::CallGate16,
+            0x5 => SystemDescriptorType::TaskGate,
+            0x6 => SystemDescriptorType::InterruptGate16,
+            0x7 => SystemDescriptorType::TrapGate16,
+            0x9 => SystemDescriptorType::TssAvailable,
+            0xB => SystemDescriptorType::TssBusy,
+            0xC => SystemDescriptorType::CallGate,
+            0xE => SystemDescriptorType::InterruptGate,
+            0xF => SystemDescriptorType::TrapGate,
+            reserved => SystemDescriptorType::Reserved(reserved),
+        }
+    }
+
+    /// Returns whether descriptors of this type take 16 bytes in IA-32e mode.
+    pub fn is_expanded_in_long_mode(&self) -> bool {
+        matches!(
+            self,
+            SystemDescriptorType::Ldt
+                | SystemDescriptorType::TssAvailable
+                | SystemDescriptorType::TssBusy
+                | SystemDescriptorType::CallGate
+                | SystemDescriptorType::InterruptGate
+                | SystemDescriptorType::TrapGate
+        )
+    }
+}
+
+/// Decoded type of a segment descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DescriptorType {
+    Data {
+        accessed: bool,
+        writable: bool,
+        expand_down: bool,
+    },
+    Code {
+        accessed: bool,
+        readable: bool,
+        conforming: bool,
+    },
+    System(SystemDescriptorType),
+}
+
+/// Decoded segment or system descriptor found in a descriptor table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SegmentDescriptor {
+    /// Selector that references this descriptor in its table, with an RPL of 0.
+    pub selector: Selector,
+    pub base: u64,
+    /// Segment limit in bytes, with the granularity already applied.
+    pub limit: u32,
+    pub descriptor_type: DescriptorType,
+    pub dpl: u8,
+    pub present: bool,
+    pub available: bool,
+    /// L bit, set for 64 bit code segments.
+    pub long_mode: bool,
+    /// D/B bit, set for 32 bit segments.
+    pub default_big: bool,
+    pub granularity: bool,
+}
+
+impl SegmentDescriptor {
+    /// Decodes a descriptor from its raw low 8 bytes and, for IA-32e system descriptors,
+    /// the high 8 bytes that hold the upper half of the base address.
+    pub fn decode(selector: Selector, low: u64, high: Option<u64>) -> SegmentDescriptor {
+        let type_field = ((low >> 40) & 0xF) as u8;
+        let granularity = low & (1 << 55) != 0;
+
+        let descriptor_type = if low & (1 << 44) == 0 {
+            DescriptorType::System(SystemDescriptorType::from_type_field(type_field))
+        } else if type_field & 0x8 != 0 {
+            DescriptorType::Code {
+                accessed: type_field & 0x1 != 0,
+                readable: type_field & 0x2 != 0,
+                conforming: type_field & 0x4 != 0,
+            }
+        } else {
+            DescriptorType::Data {
+                accessed: type_field & 0x1 != 0,
+                writable: type_field & 0x2 != 0,
+                expand_down: type_field & 0x4 != 0,
+            }
+        };
+
+        let mut limit = ((low & 0xFFFF) | ((low >> 32) & 0xF_0000)) as u32;
+        if granularity {
+            limit = (limit << 12) | 0xFFF;
+        }
+
+        let mut base = ((low >> 16) & 0xFF_FFFF) | ((low >> 32) & 0xFF00_0000);
+        if let Some(high) = high {
+            base |= (high & 0xFFFF_FFFF) << 32;
+        }
+
+        SegmentDescriptor {
+            selector,
+            base,
+            limit,
+            descriptor_type,
+            dpl: ((low >> 45) & 0x3) as u8,
+            present: low & (1 << 47) != 0,
+            available: low & (1 << 52) != 0,
+            long_mode: low & (1 << 53) != 0,
+            default_big: low & (1 << 54) != 0,
+            granularity,
+        }
+    }
+
+    /// Returns whether this is a TSS descriptor, either available or busy.
+    pub fn is_tss(&self) -> bool {
+        matches!(
+            self.descriptor_type,
+            DescriptorType::System(SystemDescriptorType::Tss16Available)
+                | DescriptorType::System(SystemDescriptorType::Tss16Busy)
+                | DescriptorType::System(SystemDescriptorType::TssAvailable)
+                | DescriptorType::System(SystemDescriptorType::TssBusy)
+        )
+    }
+}
+
+/// Reads the little endian 8 byte entry found at the given index of a raw descriptor table.
+pub(crate) fn descriptor_table_entry(table: &[u8], index: usize) -> Option<u64> {
+    let offset = index * 8;
+    table.get(offset..offset + 8).map(|entry| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(entry);
+        u64::from_le_bytes(bytes)
+    })
+}
+
+/// Decoded global descriptor table of a virtual processor.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Gdt {
+    pub base: GuestVirtualAddress,
+    pub limit: u16,
+    /// Descriptors found in the table, ordered by selector. Empty entries are skipped.
+    pub descriptors: Vec<SegmentDescriptor>,
+}
+
+impl Gdt {
+    /// Decodes the raw contents of a GDT. When `long_mode` is set, LDT, TSS and call gate
+    /// descriptors are decoded as 16 byte IA-32e system descriptors.
+    pub fn parse(base: GuestVirtualAddress, limit: u16, table: &[u8], long_mode: bool) -> Gdt {
+        let mut descriptors = Vec::new();
+        let mut index = 1;
+
+        while let Some(low) = descriptor_table_entry(table, index) {
+            let selector = Selector((index as u16) << 3);
+            index += 1;
+
+            if low == 0 {
+                continue;
+            }
+
+            let mut descriptor = SegmentDescriptor::decode(selector, low, None);
+
+            if let DescriptorType::System(system_type) = descriptor.descriptor_type {
+                if long_mode && system_type.is_expanded_in_long_mode() {
+                    let high = descriptor_table_entry(table, index);
+                    descriptor = SegmentDescriptor::decode(selector, low, high);
+                    index += 1;
+                }
+            }
+
+            descriptors.push(descriptor);
+        }
+
+        Gdt {
+            base,
+            limit,
+            descriptors,
+        }
+    }
+
+    /// Returns the descriptor referenced by the given GDT selector, if any.
+    pub fn descriptor(&self, selector: Selector) -> Option<&SegmentDescriptor> {
+        if selector.is_null() || selector.is_ldt() {
+            return None;
+        }
+
+        self.descriptors
+            .iter()
+            .find(|descriptor| descriptor.selector.index() == selector.index())
+    }
+}
+
+/// Operating mode of a virtual processor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CpuMode {
+    Real,
+    Virtual8086,
+    Protected16,
+    Protected32,
+    Compatibility16,
+    Compatibility32,
+    Long,
+}
+
+impl CpuMode {
+    /// Determines the operating mode from the control registers and the current code segment.
+    pub fn from_state(
+        cr0: Cr0,
+        efer: Efer,
+        flags: RFlags,
+        code_segment: Option<&SegmentDescriptor>,
+    ) -> CpuMode {
+        if !cr0.pe() {
+            return CpuMode::Real;
+        }
+
+        let long_mode = matches!(code_segment, Some(cs) if cs.long_mode);
+        let default_big = matches!(code_segment, Some(cs) if cs.default_big);
+
+        match (efer.lma(), long_mode, default_big) {
+            (true, true, _) => CpuMode::Long,
+            (true, false, true) => CpuMode::Compatibility32,
+            (true, false, false) => CpuMode::Compatibility16,
+            (false, _, _) if flags.vm() => CpuMode::Virtual8086,
+            (false, _, true) => CpuMode::Protected32,
+            (false, _, false) => CpuMode::Protected16,
+        }
+    }
+}
+
+/// Decoded gate descriptor found in the interrupt descriptor table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GateDescriptor {
+    pub vector: u8,
+    /// Address of the interrupt handler. Task gates have no handler address.
+    pub handler: GuestVirtualAddress,
+    pub selector: Selector,
+    pub gate_type: SystemDescriptorType,
+    pub dpl: u8,
+    pub present: bool,
+    /// Interrupt stack table index, only used in IA-32e mode.
+    pub ist: u8,
+}
+
+impl GateDescriptor {
+    /// Decodes a gate from its raw low 8 bytes and, for IA-32e gates,
+    /// the high 8 bytes that hold the upper half of the handler address.
+    pub fn decode(vector: u8, low: u64, high: Option<u64>) -> GateDescriptor {
+        let gate_type = SystemDescriptorType::from_type_field(((low >> 40) & 0xF) as u8);
+
+        let handler = match gate_type {
+            SystemDescriptorType::TaskGate => 0,
+            _ => {
+                let mut handler = (low & 0xFFFF) | ((low >> 32) & 0xFFFF_0000);
+                if let Some(high) = high {
+                    handler |= (high & 0xFFFF_FFFF) << 32;
+                }
+                handler
+            }
+        };
+
+        GateDescriptor {
+            vector,
+            handler,
+            selector: Selector((low >> 16) as u16),
+            gate_type,
+            dpl: ((low >> 45) & 0x3) as u8,
+            present: low & (1 << 47) != 0,
+            ist: ((low >> 32) & 0x7) as u8,
+        }
+    }
+}
+
+/// Decoded interrupt descriptor table of a virtual processor.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Idt {
+    pub base: GuestVirtualAddress,
+    pub limit: u16,
+    /// Gates found in the table, ordered by vector. Empty entries are skipped.
+    pub gates: Vec<GateDescriptor>,
+}
+
+impl Idt {
+    /// Decodes the raw contents of an IDT. When `long_mode` is set,
+    /// gates are decoded as 16 byte IA-32e gate descriptors.
+    pub fn parse(base: GuestVirtualAddress, limit: u16, table: &[u8], long_mode: bool) -> Idt {
+        let entry_size = if long_mode { 2 } else { 1 };
+        let mut gates = Vec::new();
+
+        for vector in 0..=255u8 {
+            let index = vector as usize * entry_size;
+            let low = match descriptor_table_entry(table, index) {
+                Some(low) => low,
+                None => break,
+            };
+
+            if low == 0 {
+                continue;
+            }
+
+            let high = if long_mode {
+                descriptor_table_entry(table, index + 1)
+            } else {
+                None
+            };
+
+            gates.push(GateDescriptor::decode(vector, low, high));
+        }
+
+        Idt { base, limit, gates }
+    }
+
+    /// Returns the gate installed for the given interrupt vector, if any.
+    pub fn gate(&self, vector: u8) -> Option<&GateDescriptor> {
+        self.gates.iter().find(|gate| gate.vector == vector)
+    }
+
+    /// Returns the present interrupt and trap gates whose handler lies outside of the given
+    /// address range, usually the range the kernel image is loaded at.
+    /// Hooked interrupt handlers commonly point outside of the kernel image; see
+    /// `dump::windows::find_interrupt_handlers_outside_kernel` to check them against it.
+    pub fn handlers_outside<'a>(
+        &'a self,
+        image_range: &'a ops::Range<GuestVirtualAddress>,
+    ) -> impl Iterator<Item = &'a GateDescriptor> + 'a {
+        self.gates.iter().filter(move |gate| {
+            gate.present
+                && gate.gate_type != SystemDescriptorType::TaskGate
+                && !image_range.contains(&gate.handler)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor_table(entries: &[u64]) -> Vec<u8> {
+        entries
+            .iter()
+            .flat_map(|entry| entry.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn gdt_parsing() {
+        // Typical x64 Windows GDT layout with a 16 byte TSS descriptor at selector 0x40
+        let table = descriptor_table(&[
+            0,
+            0,
+            0x0020_9B00_0000_0000,
+            0x0040_9300_0000_0000,
+            0x00CF_FB00_0000_FFFF,
+            0x00CF_F300_0000_FFFF,
+            0x0020_FB00_0000_0000,
+            0,
+            0x0A00_8BBC_D000_0067,
+            0x0000_0000_FFFF_F800,
+        ]);
+        let gdt = Gdt::parse(0xFFFF_F800_0ABC_E000, table.len() as u16 - 1, &table, true);
+        assert_eq!(6, gdt.descriptors.len());
+
+        let kernel_code = gdt.descriptor(Selector(0x10)).unwrap();
+        assert!(kernel_code.long_mode && kernel_code.present);
+        assert_eq!(0, kernel_code.dpl);
+        match kernel_code.descriptor_type {
+            DescriptorType::Code { readable, .. } => assert!(readable),
+            other => panic!("Unexpected descriptor type {:?}", other),
+        }
+
+        let user_compatibility_code = gdt.descriptor(Selector(0x23)).unwrap();
+        assert!(!user_compatibility_code.long_mode && user_compatibility_code.default_big);
+        assert_eq!(3, user_compatibility_code.dpl);
+        assert_eq!(0xFFFF_FFFF, user_compatibility_code.limit);
+
+        let tss = gdt.descriptor(Selector(0x40)).unwrap();
+        assert!(tss.is_tss());
+        assert_eq!(
+            DescriptorType::System(SystemDescriptorType::TssBusy),
+            tss.descriptor_type
+        );
+        assert_eq!(0xFFFF_F800_0ABC_D000, tss.base);
+        assert_eq!(0x67, tss.limit);
+
+        assert_eq!(None, gdt.descriptor(Selector(0x48)));
+        assert_eq!(None, gdt.descriptor(Selector(0)));
+
+        assert_eq!(
+            CpuMode::Long,
+            CpuMode::from_state(
+                Cr0(0x8005_0033),
+                Efer(0xD01),
+                RFlags(0x202),
+                Some(kernel_code)
+            )
+        );
+        assert_eq!(
+            CpuMode::Compatibility32,
+            CpuMode::from_state(
+                Cr0(0x8005_0033),
+                Efer(0xD01),
+                RFlags(0x202),
+                Some(user_compatibility_code)
+            )
+        );
+        assert_eq!(
+            CpuMode::Real,
+            CpuMode::from_state(Cr0(0x10), Efer(0), RFlags(0x2), None)
+        );
+    }
+
+    #[test]
+    fn idt_parsing() {
+        let kernel_range = 0xFFFF_F800_0000_0000..0xFFFF_F800_0100_0000;
+
+        // Vector 0 points inside the kernel image, vector 1 is empty,
+        // vector 2 uses IST 2 and vector 3 points outside the kernel image.
+        let table = descriptor_table(&[
+            0x0040_8E00_0010_1100,
+            0xFFFF_F800,
+            0,
+            0,
+            0x0080_8E02_0010_2200,
+            0xFFFF_F800,
+            0xBEEF_EE00_0010_3300,
+            0xFFFF_FA80,
+        ]);
+        let idt = Idt::parse(0xFFFF_F800_0ABC_F000, table.len() as u16 - 1, &table, true);
+        assert_eq!(3, idt.gates.len());
+        assert_eq!(None, idt.gate(1));
+
+        let divide_error = idt.gate(0).unwrap();
+        assert_eq!(0xFFFF_F800_0040_1100, divide_error.handler);
+        assert_eq!(Selector(0x10), divide_error.selector);
+        assert_eq!(SystemDescriptorType::InterruptGate, divide_error.gate_type);
+        assert_eq!(0, divide_error.ist);
+        assert!(divide_error.present);
+
+        let nmi = idt.gate(2).unwrap();
+        assert_eq!(2, nmi.ist);
+
+        let breakpoint = idt.gate(3).unwrap();
+        assert_eq!(3, breakpoint.dpl);
+        assert_eq!(0xFFFF_FA80_BEEF_3300, breakpoint.handler);
+
+        let hooked: Vec<u8> = idt
+            .handlers_outside(&kernel_range)
+            .map(|gate| gate.vector)
+            .collect();
+        assert_eq!(vec![3], hooked);
+    }
+}
@@ -0,0 +1,741 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Writer of ELF64 core files of the guest physical memory, consumable by `crash` and `gdb`,
+//! along with the reader used to verify them.
+
+use crate::dump::verify::*;
+use crate::dump::*;
+use crate::paging::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const NOTE_HEADER_SIZE: usize = 12;
+
+pub(crate) const ET_CORE: u16 = 4;
+const EM_386: u16 = 3;
+const EM_X86_64: u16 = 62;
+const PT_NULL: u32 = 0;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RWX: u32 = 7;
+const NT_PRSTATUS: u32 = 1;
+
+const CORE_NOTE_NAME: &str = "CORE";
+const VMCOREINFO_NOTE_NAME: &str = "VMCOREINFO";
+/// Text every Linux VMCOREINFO buffer starts with.
+const VMCOREINFO_PREFIX: &[u8] = b"OSRELEASE=";
+
+/// Virtual address x86_64 Linux maps its kernel image at, __START_KERNEL_map.
+const START_KERNEL_MAP: u64 = 0xFFFF_FFFF_8000_0000;
+/// Virtual addresses x86_64 Linux maps all of the physical memory at without KASLR,
+/// with 4-level and 5-level paging.
+const DIRECT_MAP_BASE_4_LEVEL: u64 = 0xFFFF_8880_0000_0000;
+const DIRECT_MAP_BASE_5_LEVEL: u64 = 0xFF11_0000_0000_0000;
+/// End of the region KASLR moves the direct map within with 4-level paging, CPU_ENTRY_AREA_BASE.
+const DIRECT_MAP_KASLR_END: u64 = 0xFFFF_FE00_0000_0000;
+/// KASLR moves the direct map in steps of the memory mapped by a PDPT entry.
+const DIRECT_MAP_KASLR_ALIGNMENT: u64 = 1 << 30;
+/// Memory mapped by a PML4 entry.
+const PML4_ENTRY_SPAN: u64 = 1 << 39;
+
+/// Size of the x86_64 elf_prstatus structure, along with the offsets of the fields that are written.
+const PRSTATUS64_SIZE: usize = 336;
+const PRSTATUS64_PID: usize = 32;
+const PRSTATUS64_REGISTERS: usize = 112;
+
+/// Size of the i386 elf_prstatus structure, along with the offsets of the fields that are written.
+const PRSTATUS32_SIZE: usize = 144;
+const PRSTATUS32_PID: usize = 24;
+const PRSTATUS32_REGISTERS: usize = 72;
+
+/// Registers of the x86_64 user_regs_struct, in the order they are stored.
+/// `None` marks orig_rax, which has no saved state counterpart.
+const USER_REGISTERS_X64: [Option<RegisterIdx64>; 27] = [
+    Some(RegisterIdx64::R15),
+    Some(RegisterIdx64::R14),
+    Some(RegisterIdx64::R13),
+    Some(RegisterIdx64::R12),
+    Some(RegisterIdx64::Rbp),
+    Some(RegisterIdx64::Rbx),
+    Some(RegisterIdx64::R11),
+    Some(RegisterIdx64::R10),
+    Some(RegisterIdx64::R9),
+    Some(RegisterIdx64::R8),
+    Some(RegisterIdx64::Rax),
+    Some(RegisterIdx64::Rcx),
+    Some(RegisterIdx64::Rdx),
+    Some(RegisterIdx64::Rsi),
+    Some(RegisterIdx64::Rdi),
+    None,
+    Some(RegisterIdx64::Rip),
+    Some(RegisterIdx64::SegCs),
+    Some(RegisterIdx64::RFlags),
+    Some(RegisterIdx64::Rsp),
+    Some(RegisterIdx64::SegSs),
+    Some(RegisterIdx64::BaseFs),
+    Some(RegisterIdx64::BaseGs),
+    Some(RegisterIdx64::SegDs),
+    Some(RegisterIdx64::SegEs),
+    Some(RegisterIdx64::SegFs),
+    Some(RegisterIdx64::SegGs),
+];
+
+/// Registers of the i386 user_regs_struct, in the order they are stored.
+/// `None` marks orig_eax, which has no saved state counterpart.
+const USER_REGISTERS_X86: [Option<RegisterIdx86>; 17] = [
+    Some(RegisterIdx86::Ebx),
+    Some(RegisterIdx86::Ecx),
+    Some(RegisterIdx86::Edx),
+    Some(RegisterIdx86::Esi),
+    Some(RegisterIdx86::Edi),
+    Some(RegisterIdx86::Ebp),
+    Some(RegisterIdx86::Eax),
+    Some(RegisterIdx86::SegDs),
+    Some(RegisterIdx86::SegEs),
+    Some(RegisterIdx86::SegFs),
+    Some(RegisterIdx86::SegGs),
+    None,
+    Some(RegisterIdx86::Eip),
+    Some(RegisterIdx86::SegCs),
+    Some(RegisterIdx86::EFlags),
+    Some(RegisterIdx86::Esp),
+    Some(RegisterIdx86::SegSs),
+];
+
+/// Options that control how an ELF core file is written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElfCoreOptions {
+    /// VMCOREINFO contents to store in the core file. When `None`, the guest physical memory
+    /// is searched for the VMCOREINFO buffer of a Linux kernel while it is being written,
+    /// and the note is only stored if one is found.
+    pub vmcoreinfo: Option<String>,
+    /// Virtual address the direct map of a 64-bit Linux guest maps guest physical address 0 at,
+    /// page_offset_base. When `None`, it is looked up in the page tables of the guest.
+    pub direct_map_base: Option<GuestVirtualAddress>,
+}
+
+/// Appends an ELF note with its name and descriptor padded to 4 bytes.
+fn push_note(notes: &mut Vec<u8>, name: &str, note_type: u32, descriptor: &[u8]) {
+    let mut header = [0u8; NOTE_HEADER_SIZE];
+    put_u32(&mut header, 0x0, name.len() as u32 + 1);
+    put_u32(&mut header, 0x4, descriptor.len() as u32);
+    put_u32(&mut header, 0x8, note_type);
+    notes.extend_from_slice(&header);
+
+    notes.extend_from_slice(name.as_bytes());
+    notes.push(0);
+    notes.resize(align_up(notes.len() as u64, 4) as usize, 0);
+
+    notes.extend_from_slice(descriptor);
+    notes.resize(align_up(notes.len() as u64, 4) as usize, 0);
+}
+
+/// Builds the elf_prstatus structure of the given virtual processor.
+fn build_prstatus(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    match provider.get_vp_architecture(vp_id)? {
+        VirtualProcessorArch::X64 => {
+            let mut prstatus = vec![0u8; PRSTATUS64_SIZE];
+            put_u32(&mut prstatus, PRSTATUS64_PID, vp_id + 1);
+            for (index, register_id) in USER_REGISTERS_X64.iter().enumerate() {
+                let value = match register_id {
+                    Some(register_id) => {
+                        provider
+                            .get_vp_register_value(vp_id, Register::X64(*register_id))?
+                            .value
+                    }
+                    None => u64::MAX,
+                };
+                put_u64(&mut prstatus, PRSTATUS64_REGISTERS + index * 8, value);
+            }
+            Ok(prstatus)
+        }
+        VirtualProcessorArch::X86 => {
+            let mut prstatus = vec![0u8; PRSTATUS32_SIZE];
+            put_u32(&mut prstatus, PRSTATUS32_PID, vp_id + 1);
+            for (index, register_id) in USER_REGISTERS_X86.iter().enumerate() {
+                let value = match register_id {
+                    Some(register_id) => {
+                        provider
+                            .get_vp_register_value(vp_id, Register::X86(*register_id))?
+                            .value as u32
+                    }
+                    None => u32::MAX,
+                };
+                put_u32(&mut prstatus, PRSTATUS32_REGISTERS + index * 4, value);
+            }
+            Ok(prstatus)
+        }
+        VirtualProcessorArch::Unknown => Err(ResultCode::Unexpected.into()),
+    }
+}
+
+/// Returns the VMCOREINFO text stored in the given page, if the page holds a Linux VMCOREINFO buffer.
+fn page_vmcoreinfo(page: &[u8]) -> Option<String> {
+    if !page.starts_with(VMCOREINFO_PREFIX) {
+        return None;
+    }
+
+    let length = page
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(page.len());
+    let vmcoreinfo = std::str::from_utf8(&page[..length]).ok()?;
+    if vmcoreinfo.contains("\nPAGESIZE=") {
+        Some(String::from(vmcoreinfo))
+    } else {
+        None
+    }
+}
+
+/// Returns the value of a VMCOREINFO entry, such as `SIZE(page)`.
+pub(crate) fn vmcoreinfo_value<'a>(vmcoreinfo: &'a str, key: &str) -> Option<&'a str> {
+    vmcoreinfo.lines().find_map(|line| {
+        let mut entry = line.splitn(2, '=');
+        if entry.next()? == key {
+            entry.next()
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the value of a decimal VMCOREINFO entry, such as `OFFSET(page.flags)`.
+pub(crate) fn vmcoreinfo_number(vmcoreinfo: &str, key: &str) -> Option<i64> {
+    vmcoreinfo_value(vmcoreinfo, key)?.parse().ok()
+}
+
+/// Returns the value of a hexadecimal VMCOREINFO entry, such as `SYMBOL(mem_section)`.
+pub(crate) fn vmcoreinfo_symbol(vmcoreinfo: &str, key: &str) -> Option<u64> {
+    u64::from_str_radix(vmcoreinfo_value(vmcoreinfo, key)?, 16).ok()
+}
+
+/// Searches the guest physical memory for the VMCOREINFO buffer of a Linux kernel,
+/// returning its contents if the guest is running one.
+pub fn find_vmcoreinfo(
+    provider: &VmSavedStateDumpProvider,
+) -> VmSavedStateDumpResult<Option<String>> {
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    let mut reader = MemoryBlockReader::new(provider, page_size, &memory_chunks);
+    while let Some((_, block)) = reader.next_block()? {
+        if let Some(vmcoreinfo) = block.chunks(page_size as usize).find_map(page_vmcoreinfo) {
+            return Ok(Some(vmcoreinfo));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns whether the given virtual address translates to guest physical address 0.
+fn maps_physical_address_zero(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    virtual_address: GuestVirtualAddress,
+) -> bool {
+    match walk_page_tables(provider, vp_id, virtual_address) {
+        Ok(walk) => walk.physical_address == Some(0),
+        Err(_) => false,
+    }
+}
+
+/// Looks up the virtual address the direct map of a 64-bit Linux guest starts at, through
+/// the page tables of the given virtual processor. With 4-level paging, every address KASLR
+/// can move the direct map to is tried in ascending order, skipping those whose PML4 entry
+/// isn't present; the direct map is placed before the rest of the randomized regions, so the
+/// first one that maps guest physical address 0 is taken. 5-level paging only tries the
+/// address used without KASLR.
+pub fn find_direct_map_base(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+) -> VmSavedStateDumpResult<Option<GuestVirtualAddress>> {
+    if provider.get_vp_architecture(vp_id)? != VirtualProcessorArch::X64
+        || provider.get_vp_paging_mode(vp_id)? != PagingMode::Long
+    {
+        return Ok(None);
+    }
+
+    if provider.get_vp_control_registers(vp_id)?.cr4.la57() {
+        return Ok(Some(DIRECT_MAP_BASE_5_LEVEL)
+            .filter(|base| maps_physical_address_zero(provider, vp_id, *base)));
+    }
+
+    let pml4_regions = (DIRECT_MAP_KASLR_END - DIRECT_MAP_BASE_4_LEVEL) / PML4_ENTRY_SPAN;
+    for pml4_region in
+        (0..pml4_regions).map(|index| DIRECT_MAP_BASE_4_LEVEL + index * PML4_ENTRY_SPAN)
+    {
+        let pml4_entry_present = match walk_page_tables(provider, vp_id, pml4_region) {
+            Ok(walk) => walk.entries.first().is_some_and(PageTableEntry::present),
+            Err(_) => false,
+        };
+        if !pml4_entry_present {
+            continue;
+        }
+
+        let base = (0..PML4_ENTRY_SPAN / DIRECT_MAP_KASLR_ALIGNMENT)
+            .map(|index| pml4_region + index * DIRECT_MAP_KASLR_ALIGNMENT)
+            .find(|base| maps_physical_address_zero(provider, vp_id, *base));
+        if base.is_some() {
+            return Ok(base);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the virtual address, guest physical address and size of the kernel image mapping
+/// of a 64-bit Linux guest, which starts at _stext and spans up to the end of the
+/// KERNEL_IMAGE_SIZE bytes at __START_KERNEL_map. Its physical address is offset by phys_base.
+fn kernel_image_mapping(
+    vmcoreinfo: &str,
+) -> Option<(GuestVirtualAddress, GuestPhysicalAddress, u64)> {
+    let phys_base = vmcoreinfo_number(vmcoreinfo, "NUMBER(phys_base)")?;
+    let image_size =
+        u64::try_from(vmcoreinfo_number(vmcoreinfo, "NUMBER(KERNEL_IMAGE_SIZE)")?).ok()?;
+    let end = START_KERNEL_MAP.checked_add(image_size)?;
+
+    // Virtual addresses of the mapping are their physical address plus this offset,
+    // and those that would map below guest physical address 0 are left out
+    let offset = u64::try_from(i128::from(START_KERNEL_MAP) - i128::from(phys_base)).ok()?;
+    let start = vmcoreinfo_symbol(vmcoreinfo, "SYMBOL(_stext)")
+        .unwrap_or(START_KERNEL_MAP)
+        .max(START_KERNEL_MAP)
+        .max(offset);
+    if start >= end {
+        return None;
+    }
+
+    Some((start, start - offset, end - start))
+}
+
+/// Builds the ELF notes that describe the guest, made of an NT_PRSTATUS note per
+/// virtual processor followed by the VMCOREINFO note, if any.
+pub(crate) fn build_notes(
+    provider: &VmSavedStateDumpProvider,
+    vmcoreinfo: Option<&str>,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    let mut notes = Vec::new();
+    for vp_id in 0..provider.vp_count()? {
+        push_note(
+            &mut notes,
+            CORE_NOTE_NAME,
+            NT_PRSTATUS,
+            &build_prstatus(provider, vp_id)?,
+        );
+    }
+
+    if let Some(vmcoreinfo) = vmcoreinfo {
+        push_note(&mut notes, VMCOREINFO_NOTE_NAME, 0, vmcoreinfo.as_bytes());
+    }
+
+    Ok(notes)
+}
+
+/// Builds a program header.
+fn program_header(
+    program_type: u32,
+    offset: u64,
+    virtual_address: u64,
+    physical_address: u64,
+    size: u64,
+) -> [u8; PROGRAM_HEADER_SIZE] {
+    let mut header = [0u8; PROGRAM_HEADER_SIZE];
+    put_u32(&mut header, 0x0, program_type);
+    if program_type == PT_LOAD {
+        put_u32(&mut header, 0x4, PF_RWX);
+    }
+    put_u64(&mut header, 0x8, offset);
+    put_u64(&mut header, 0x10, virtual_address);
+    put_u64(&mut header, 0x18, physical_address);
+    put_u64(&mut header, 0x20, size);
+    put_u64(&mut header, 0x28, size);
+    header
+}
+
+/// Writes an ELF64 core file of the loaded saved state to the given output.
+/// Every guest physical memory chunk is stored as a PT_LOAD segment whose `p_paddr` holds its
+/// guest physical address. As in /proc/vmcore, the `p_vaddr` of 64-bit Linux guests holds the
+/// address the direct map maps the chunk at, and another PT_LOAD segment maps the kernel image
+/// at its virtual address, sharing the file contents of the chunk that holds it. Otherwise,
+/// `p_vaddr` is left as 0 and the kernel image segment as PT_NULL.
+/// The PT_NOTE segment holds an NT_PRSTATUS note per virtual processor, followed by the
+/// VMCOREINFO note of Linux guests. The kernel image segment needs the VMCOREINFO, and notes
+/// are written after the guest memory, so the output is seeked back to their program headers
+/// once they are known.
+pub fn write_elf_core<W: Read + Write + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    output: &mut W,
+    options: &ElfCoreOptions,
+    control: &mut DumpControl,
+) -> VmSavedStateDumpResult<DumpStatistics> {
+    let machine = match provider.get_vp_architecture(0)? {
+        VirtualProcessorArch::X64 => EM_X86_64,
+        VirtualProcessorArch::X86 => EM_386,
+        VirtualProcessorArch::Unknown => return Err(ResultCode::Unexpected.into()),
+    };
+
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    let direct_map_base = match options.direct_map_base {
+        Some(direct_map_base) => Some(direct_map_base),
+        None => find_direct_map_base(provider, kernel_mode_vp_id(provider)?)?,
+    };
+    let program_header_count = memory_chunks.len() + 2;
+    if program_header_count >= 0xFFFF {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+    let headers_size = ELF_HEADER_SIZE + program_header_count * PROGRAM_HEADER_SIZE;
+
+    let mut elf_header = [0u8; ELF_HEADER_SIZE];
+    elf_header[..8].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    put_u16(&mut elf_header, 0x10, ET_CORE);
+    put_u16(&mut elf_header, 0x12, machine);
+    put_u32(&mut elf_header, 0x14, 1);
+    put_u64(&mut elf_header, 0x20, ELF_HEADER_SIZE as u64);
+    put_u16(&mut elf_header, 0x34, ELF_HEADER_SIZE as u16);
+    put_u16(&mut elf_header, 0x36, PROGRAM_HEADER_SIZE as u16);
+    put_u16(&mut elf_header, 0x38, program_header_count as u16);
+
+    // Segments are stored page aligned, so the note and kernel image program headers are written last
+    let mut program_headers = Vec::with_capacity(program_header_count * PROGRAM_HEADER_SIZE);
+    program_headers.extend_from_slice(&program_header(PT_NOTE, 0, 0, 0, 0));
+    program_headers.extend_from_slice(&program_header(PT_NULL, 0, 0, 0, 0));
+    let mut chunk_offsets = vec![align_up(headers_size as u64, page_size)];
+    for chunk in &memory_chunks {
+        let offset = chunk_offsets[chunk_offsets.len() - 1];
+        let physical_address = chunk.guest_physical_start_page_index * page_size;
+        let size = chunk.page_count * page_size;
+        program_headers.extend_from_slice(&program_header(
+            PT_LOAD,
+            offset,
+            direct_map_base.map_or(0, |base| base.wrapping_add(physical_address)),
+            physical_address,
+            size,
+        ));
+        chunk_offsets.push(offset + size);
+    }
+
+    // The note and kernel image program headers of resumed output are only known once it has been
+    // completely written
+    let resume = control.resume;
+    let mut tracker = control.begin(provider)?;
+    let start = output.stream_position()?;
+    let resume_point = if resume {
+        find_resume_point(
+            output,
+            start,
+            &[
+                (0, &elf_header),
+                (
+                    (ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE) as u64,
+                    &program_headers[2 * PROGRAM_HEADER_SIZE..],
+                ),
+            ],
+            &chunk_offsets,
+        )?
+    } else {
+        None
+    };
+    let first_chunk = match resume_point {
+        Some(chunk_index) => {
+            output.seek(SeekFrom::Start(start + chunk_offsets[chunk_index]))?;
+            chunk_index
+        }
+        None => {
+            output.seek(SeekFrom::Start(start))?;
+            output.write_all(&elf_header)?;
+            output.write_all(&program_headers)?;
+            output.write_all(&vec![0u8; chunk_offsets[0] as usize - headers_size])?;
+            0
+        }
+    };
+
+    let resumed_pages = total_page_count(&memory_chunks[..first_chunk]);
+    tracker.skip(resumed_pages * page_size);
+    let mut statistics = DumpStatistics {
+        pages_written: resumed_pages,
+        bytes_written: chunk_offsets[first_chunk],
+        ..Default::default()
+    };
+
+    let mut vmcoreinfo = options.vmcoreinfo.clone();
+    let mut reader = MemoryBlockReader::new(provider, page_size, &memory_chunks[first_chunk..]);
+    while let Some((_, block)) = reader.next_block()? {
+        if vmcoreinfo.is_none() {
+            vmcoreinfo = block.chunks(page_size as usize).find_map(page_vmcoreinfo);
+        }
+
+        output.write_all(block)?;
+        statistics.pages_written += block.len() as u64 / page_size;
+        statistics.bytes_written += block.len() as u64;
+        tracker.advance(block.len() as u64)?;
+    }
+
+    // The VMCOREINFO may be stored in the memory chunks that were already written
+    if vmcoreinfo.is_none() && first_chunk > 0 {
+        vmcoreinfo = find_vmcoreinfo(provider)?;
+    }
+
+    let notes = build_notes(provider, vmcoreinfo.as_deref())?;
+    output.write_all(&notes)?;
+    let note_header = program_header(PT_NOTE, statistics.bytes_written, 0, 0, notes.len() as u64);
+    statistics.bytes_written += notes.len() as u64;
+
+    // Only the part of the kernel image stored in a single chunk is mapped
+    let kernel_image_header = match (
+        machine,
+        vmcoreinfo.as_deref().and_then(kernel_image_mapping),
+    ) {
+        (EM_X86_64, Some((virtual_address, physical_address, size))) => memory_chunks
+            .iter()
+            .zip(chunk_offsets.iter())
+            .find_map(|(chunk, offset)| {
+                let chunk_start = chunk.guest_physical_start_page_index * page_size;
+                let chunk_end = chunk_start + chunk.page_count * page_size;
+                if !(chunk_start..chunk_end).contains(&physical_address) {
+                    return None;
+                }
+                Some(program_header(
+                    PT_LOAD,
+                    offset + physical_address - chunk_start,
+                    virtual_address,
+                    physical_address,
+                    size.min(chunk_end - physical_address),
+                ))
+            }),
+        _ => None,
+    };
+
+    output.seek(SeekFrom::Start(start + ELF_HEADER_SIZE as u64))?;
+    output.write_all(&note_header)?;
+    output
+        .write_all(&kernel_image_header.unwrap_or_else(|| program_header(PT_NULL, 0, 0, 0, 0)))?;
+    output.seek(SeekFrom::Start(start + statistics.bytes_written))?;
+
+    output.flush()?;
+    Ok(statistics)
+}
+
+/// Decodes the registers stored in the NT_PRSTATUS notes of the given ELF notes,
+/// using the elf_prstatus layout of the given architecture.
+pub(crate) fn read_prstatus_notes(
+    notes: &[u8],
+    architecture: VirtualProcessorArch,
+) -> Vec<StoredRegister> {
+    let mut registers = Vec::new();
+    let mut offset = 0;
+
+    while offset + NOTE_HEADER_SIZE <= notes.len() {
+        let name_size = get_u32(notes, offset) as usize;
+        let descriptor_size = get_u32(notes, offset + 0x4) as usize;
+        let note_type = get_u32(notes, offset + 0x8);
+        let name_offset = offset + NOTE_HEADER_SIZE;
+        let descriptor_offset = align_up((name_offset + name_size) as u64, 4) as usize;
+        let descriptor = match notes.get(descriptor_offset..descriptor_offset + descriptor_size) {
+            Some(descriptor) => descriptor,
+            None => break,
+        };
+        offset = align_up((descriptor_offset + descriptor_size) as u64, 4) as usize;
+
+        let name = &notes[name_offset..name_offset + name_size];
+        if note_type != NT_PRSTATUS || name != b"CORE\0" {
+            continue;
+        }
+
+        match architecture {
+            VirtualProcessorArch::X64 if descriptor.len() >= PRSTATUS64_SIZE => {
+                let vp_id = get_u32(descriptor, PRSTATUS64_PID).wrapping_sub(1);
+                for (index, register_id) in USER_REGISTERS_X64.iter().enumerate() {
+                    if let Some(register_id) = register_id {
+                        registers.push(StoredRegister {
+                            vp_id,
+                            register: Register::X64(*register_id),
+                            value: get_u64(descriptor, PRSTATUS64_REGISTERS + index * 8),
+                            size: 8,
+                        });
+                    }
+                }
+            }
+            VirtualProcessorArch::X86 if descriptor.len() >= PRSTATUS32_SIZE => {
+                let vp_id = get_u32(descriptor, PRSTATUS32_PID).wrapping_sub(1);
+                for (index, register_id) in USER_REGISTERS_X86.iter().enumerate() {
+                    if let Some(register_id) = register_id {
+                        registers.push(StoredRegister {
+                            vp_id,
+                            register: Register::X86(*register_id),
+                            value: u64::from(get_u32(descriptor, PRSTATUS32_REGISTERS + index * 4)),
+                            size: 4,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    registers
+}
+
+/// Reads the layout of an ELF64 core file. Notes that are stored past the end of the file
+/// are left out, as truncated files are expected while verifying.
+pub(crate) fn read_elf_core_layout<R: Read + Seek>(
+    input: &mut R,
+    page_size: u64,
+) -> VmSavedStateDumpResult<DumpLayout> {
+    let mut elf_header = [0u8; ELF_HEADER_SIZE];
+    input.seek(SeekFrom::Start(0))?;
+    input.read_exact(&mut elf_header)?;
+    if elf_header[..6] != [0x7F, b'E', b'L', b'F', 2, 1] || get_u16(&elf_header, 0x10) != ET_CORE {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let architecture = match get_u16(&elf_header, 0x12) {
+        EM_X86_64 => VirtualProcessorArch::X64,
+        EM_386 => VirtualProcessorArch::X86,
+        _ => return Err(ResultCode::InvalidArgument.into()),
+    };
+
+    let program_header_count = get_u16(&elf_header, 0x38) as usize;
+    let mut program_headers = vec![0u8; program_header_count * PROGRAM_HEADER_SIZE];
+    input.seek(SeekFrom::Start(get_u64(&elf_header, 0x20)))?;
+    input.read_exact(&mut program_headers)?;
+
+    let mut runs = Vec::new();
+    let mut registers = Vec::new();
+    for program_header in program_headers.chunks(PROGRAM_HEADER_SIZE) {
+        let offset = get_u64(program_header, 0x8);
+        let size = get_u64(program_header, 0x20);
+        match get_u32(program_header, 0x0) {
+            PT_LOAD => runs.push(StoredRun {
+                first_page_index: get_u64(program_header, 0x18) / page_size,
+                page_count: size / page_size,
+                offset,
+            }),
+            PT_NOTE => {
+                if let Ok(notes) = read_region(input, offset, size) {
+                    registers.extend(read_prstatus_notes(&notes, architecture));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Segments that map memory another one already stores, such as the kernel image, are left out
+    let aliases: Vec<bool> = runs
+        .iter()
+        .map(|run| {
+            runs.iter().any(|other| {
+                other != run
+                    && other.first_page_index <= run.first_page_index
+                    && run.first_page_index + run.page_count
+                        <= other.first_page_index + other.page_count
+                    && other.offset + (run.first_page_index - other.first_page_index) * page_size
+                        == run.offset
+            })
+        })
+        .collect();
+    let mut aliases = aliases.into_iter();
+    runs.retain(|_| !aliases.next().unwrap_or(false));
+
+    Ok(DumpLayout {
+        pages: StoredPages::Runs(runs),
+        registers,
+        page_index_limit: None,
+        zero_pages_elided: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn kernel_image_mapping_from_vmcoreinfo() {
+        let vmcoreinfo =
+            "OSRELEASE=6.8.0-45-generic\nPAGESIZE=4096\nSYMBOL(_stext)=ffffffff9a000000\n\
+                          NUMBER(phys_base)=1476395008\nNUMBER(KERNEL_IMAGE_SIZE)=1073741824\n";
+        assert_eq!(
+            Some((0xFFFF_FFFF_9A00_0000, 0x7200_0000, 0x2600_0000)),
+            kernel_image_mapping(vmcoreinfo)
+        );
+
+        // Without _stext the mapping starts at the first address of physical memory it maps
+        let vmcoreinfo = "NUMBER(phys_base)=-2097152\nNUMBER(KERNEL_IMAGE_SIZE)=536870912\n";
+        assert_eq!(
+            Some((0xFFFF_FFFF_8020_0000, 0, 0x1FE0_0000)),
+            kernel_image_mapping(vmcoreinfo)
+        );
+
+        assert_eq!(None, kernel_image_mapping("NUMBER(phys_base)=0\n"));
+        assert_eq!(
+            None,
+            kernel_image_mapping("NUMBER(phys_base)=0\nNUMBER(KERNEL_IMAGE_SIZE)=-1\n")
+        );
+    }
+
+    #[test]
+    fn elf_core_layout_leaves_aliases_out() {
+        let mut elf_header = [0u8; ELF_HEADER_SIZE];
+        elf_header[..8].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+        put_u16(&mut elf_header, 0x10, ET_CORE);
+        put_u16(&mut elf_header, 0x12, EM_X86_64);
+        put_u64(&mut elf_header, 0x20, ELF_HEADER_SIZE as u64);
+        put_u16(&mut elf_header, 0x38, 4);
+
+        let mut core = elf_header.to_vec();
+        core.extend_from_slice(&program_header(PT_NULL, 0, 0, 0, 0));
+        core.extend_from_slice(&program_header(
+            PT_LOAD,
+            0x3000,
+            0xFFFF_FFFF_8100_0000,
+            0x2000,
+            0x1000,
+        ));
+        core.extend_from_slice(&program_header(
+            PT_LOAD,
+            0x1000,
+            0xFFFF_8880_0000_0000,
+            0,
+            0x4000,
+        ));
+        core.extend_from_slice(&program_header(
+            PT_LOAD,
+            0x5000,
+            0xFFFF_8880_0010_0000,
+            0x10_0000,
+            0x1000,
+        ));
+        core.resize(0x6000, 0);
+
+        let layout = read_elf_core_layout(&mut Cursor::new(core), 0x1000).unwrap();
+        match layout.pages {
+            StoredPages::Runs(runs) => assert_eq!(
+                vec![
+                    StoredRun {
+                        first_page_index: 0,
+                        page_count: 4,
+                        offset: 0x1000,
+                    },
+                    StoredRun {
+                        first_page_index: 0x100,
+                        page_count: 1,
+                        offset: 0x5000,
+                    },
+                ],
+                runs
+            ),
+            _ => panic!("ELF core files are made of runs"),
+        }
+    }
+}
@@ -0,0 +1,865 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Writer of kdump-compressed dump files, the format produced by `makedumpfile` and read by `crash`,
+//! along with the reader used to verify them.
+
+use crate::dump::elf::{
+    build_notes, find_vmcoreinfo, read_prstatus_notes, vmcoreinfo_number, vmcoreinfo_symbol,
+    vmcoreinfo_value,
+};
+use crate::dump::lzo::{lzo1x_1_compress, lzo1x_decompress};
+use crate::dump::verify::*;
+use crate::dump::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub(crate) const KDUMP_SIGNATURE: &[u8] = b"KDUMP   ";
+const KDUMP_HEADER_VERSION: u32 = 6;
+const KDUMP_SUB_HEADER_SIZE: usize = 104;
+const PAGE_DESCRIPTOR_SIZE: usize = 24;
+const UTSNAME_FIELD_SIZE: usize = 65;
+
+const DUMP_LEVEL_EXCLUDE_ZERO: u32 = 1;
+const DUMP_LEVEL_EXCLUDE_FREE: u32 = 16;
+
+/// Highest order of a block of free pages kept by the buddy allocator.
+const MAX_BUDDY_ORDER: u64 = 10;
+/// Count of struct pages read from the guest at once when looking for free pages.
+const STRUCT_PAGE_BATCH: u64 = 512;
+/// Largest struct page size accepted from the VMCOREINFO. Struct pages take 64 bytes,
+/// or a few more with debugging options, so anything larger comes from a corrupt guest.
+const MAX_STRUCT_PAGE_SIZE: u64 = 256;
+
+/// Offsets of the fields of the disk_dump_header structure.
+mod disk_dump_header {
+    pub const SIGNATURE: usize = 0;
+    pub const HEADER_VERSION: usize = 8;
+    pub const UTSNAME: usize = 12;
+    pub const TIMESTAMP: usize = 408;
+    pub const STATUS: usize = 424;
+    pub const BLOCK_SIZE: usize = 428;
+    pub const SUB_HEADER_SIZE: usize = 432;
+    pub const BITMAP_BLOCKS: usize = 436;
+    pub const MAX_MAPNR: usize = 440;
+    pub const NR_CPUS: usize = 460;
+}
+
+/// Offsets of the fields of the kdump_sub_header structure.
+mod kdump_sub_header {
+    pub const PHYS_BASE: usize = 0;
+    pub const DUMP_LEVEL: usize = 8;
+    pub const OFFSET_VMCOREINFO: usize = 32;
+    pub const SIZE_VMCOREINFO: usize = 40;
+    pub const OFFSET_NOTE: usize = 48;
+    pub const SIZE_NOTE: usize = 56;
+    pub const MAX_MAPNR_64: usize = 96;
+}
+
+/// Algorithm used to compress each page stored in a kdump-compressed dump.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KdumpCompression {
+    /// Pages are stored uncompressed.
+    None,
+    /// Requires the `flate2` feature.
+    #[cfg(feature = "flate2")]
+    Zlib,
+    Lzo,
+    /// Requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl KdumpCompression {
+    /// Flag that marks the algorithm in the dump header and in the page descriptors.
+    fn flag(self) -> u32 {
+        match self {
+            KdumpCompression::None => 0,
+            #[cfg(feature = "flate2")]
+            KdumpCompression::Zlib => 0x1,
+            KdumpCompression::Lzo => 0x2,
+            #[cfg(feature = "zstd")]
+            KdumpCompression::Zstd => 0x20,
+        }
+    }
+
+    /// Compresses a page, returning `None` if the compressed page isn't smaller than the page.
+    fn compress(self, page: &[u8]) -> VmSavedStateDumpResult<Option<Vec<u8>>> {
+        let compressed = match self {
+            KdumpCompression::None => return Ok(None),
+            #[cfg(feature = "flate2")]
+            KdumpCompression::Zlib => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(page)?;
+                encoder.finish()?
+            }
+            KdumpCompression::Lzo => lzo1x_1_compress(page),
+            #[cfg(feature = "zstd")]
+            KdumpCompression::Zstd => zstd::bulk::compress(page, 1)?,
+        };
+
+        if compressed.len() < page.len() {
+            Ok(Some(compressed))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Options that control how a kdump-compressed dump is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdumpOptions {
+    pub compression: KdumpCompression,
+    /// Leaves pages filled with zeros out of the dump. When not set,
+    /// all of them share a single copy of a zero page.
+    pub exclude_zero_pages: bool,
+    /// Leaves pages owned by the buddy allocator of the guest kernel out of the dump.
+    /// Free pages are found through the guest's VMCOREINFO, so this is skipped for kernels
+    /// that don't describe their memory map there.
+    pub exclude_free_pages: bool,
+    /// VMCOREINFO contents to store in the dump. When `None`, the guest physical memory
+    /// is searched for the VMCOREINFO buffer of the Linux kernel.
+    pub vmcoreinfo: Option<String>,
+}
+
+impl Default for KdumpOptions {
+    fn default() -> Self {
+        KdumpOptions {
+            compression: KdumpCompression::Lzo,
+            exclude_zero_pages: true,
+            exclude_free_pages: true,
+            vmcoreinfo: None,
+        }
+    }
+}
+
+/// Location and layout of the struct pages of a Linux guest, used to find its free pages.
+struct StructPageLayout {
+    vmemmap: GuestVirtualAddress,
+    size: u64,
+    mapcount_offset: usize,
+    private_offset: usize,
+    buddy_mapcount_value: u32,
+}
+
+impl StructPageLayout {
+    /// Finds the struct pages of an x86_64 guest, which are always mapped at vmemmap.
+    /// The start of vmemmap is the encoded memory map of the first memory section,
+    /// found through the root of the SPARSEMEM_EXTREME section table.
+    fn find(
+        provider: &VmSavedStateDumpProvider,
+        vp_id: u32,
+        vmcoreinfo: &str,
+    ) -> Option<StructPageLayout> {
+        let read_pointer = |virtual_address: GuestVirtualAddress| {
+            let mut pointer = [0u8; 8];
+            match provider.read_guest_virtual_address(vp_id, virtual_address, &mut pointer) {
+                Ok(8) => Some(u64::from_le_bytes(pointer)),
+                _ => None,
+            }
+        };
+
+        let section_mem_map_offset =
+            vmcoreinfo_number(vmcoreinfo, "OFFSET(mem_section.section_mem_map)").unwrap_or(0);
+        let section_roots = read_pointer(vmcoreinfo_symbol(vmcoreinfo, "SYMBOL(mem_section)")?)?;
+        let first_section = read_pointer(section_roots)?;
+        let section_mem_map =
+            read_pointer(first_section.checked_add(u64::try_from(section_mem_map_offset).ok()?)?)?;
+        let vmemmap = section_mem_map & !0xFFF;
+        if vmemmap < 0xFFFF_8000_0000_0000 {
+            return None;
+        }
+
+        StructPageLayout::from_vmcoreinfo(vmemmap, vmcoreinfo)
+    }
+
+    /// Builds the layout of the struct pages mapped at the given vmemmap from the sizes and
+    /// offsets found in the VMCOREINFO. Those come from the guest, so layouts whose fields
+    /// don't fit in a struct page of a sensible size are rejected.
+    fn from_vmcoreinfo(vmemmap: GuestVirtualAddress, vmcoreinfo: &str) -> Option<StructPageLayout> {
+        let size = u64::try_from(vmcoreinfo_number(vmcoreinfo, "SIZE(page)")?).ok()?;
+        if size == 0 || size > MAX_STRUCT_PAGE_SIZE {
+            return None;
+        }
+
+        let field_offset = |key: &str, width: u64| {
+            let offset = u64::try_from(vmcoreinfo_number(vmcoreinfo, key)?).ok()?;
+            if offset.checked_add(width)? > size {
+                return None;
+            }
+            Some(offset as usize)
+        };
+
+        Some(StructPageLayout {
+            vmemmap,
+            size,
+            mapcount_offset: field_offset("OFFSET(page._mapcount)", 4)?,
+            private_offset: field_offset("OFFSET(page.private)", 8)?,
+            buddy_mapcount_value: vmcoreinfo_number(
+                vmcoreinfo,
+                "NUMBER(PAGE_BUDDY_MAPCOUNT_VALUE)",
+            )? as u32,
+        })
+    }
+
+    /// Returns a bitmap with a bit set for every page that belongs to the buddy allocator.
+    /// Struct pages that can't be read are treated as pages in use.
+    fn free_pages(
+        &self,
+        provider: &VmSavedStateDumpProvider,
+        vp_id: u32,
+        memory_chunks: &[GpaMemoryChunk],
+        bitmap_size: usize,
+    ) -> Vec<u8> {
+        let mut free_pages = vec![0u8; bitmap_size];
+        let page_limit = bitmap_size as u64 * 8;
+        let batch_size = match STRUCT_PAGE_BATCH.checked_mul(self.size) {
+            Some(batch_size) => batch_size as usize,
+            None => return free_pages,
+        };
+        let mut struct_pages = vec![0u8; batch_size];
+
+        for chunk in memory_chunks {
+            let end_page_index = chunk.guest_physical_start_page_index + chunk.page_count;
+            let mut page_index = chunk.guest_physical_start_page_index;
+
+            while page_index < end_page_index {
+                let batch = std::cmp::min(STRUCT_PAGE_BATCH, end_page_index - page_index);
+                let struct_pages = &mut struct_pages[..(batch * self.size) as usize];
+                let address = page_index
+                    .checked_mul(self.size)
+                    .and_then(|offset| self.vmemmap.checked_add(offset));
+                let read = address.map(|address| {
+                    provider.read_guest_virtual_address(vp_id, address, struct_pages)
+                });
+                match read {
+                    Some(Ok(bytes_read)) if bytes_read as usize == struct_pages.len() => {}
+                    _ => {
+                        page_index += batch;
+                        continue;
+                    }
+                }
+
+                for (index, struct_page) in struct_pages.chunks(self.size as usize).enumerate() {
+                    if get_u32(struct_page, self.mapcount_offset) != self.buddy_mapcount_value {
+                        continue;
+                    }
+
+                    let order = get_u64(struct_page, self.private_offset);
+                    if order > MAX_BUDDY_ORDER {
+                        continue;
+                    }
+
+                    let first = page_index + index as u64;
+                    for free_page in first..std::cmp::min(first + (1 << order), page_limit) {
+                        free_pages[(free_page / 8) as usize] |= 1 << (free_page % 8);
+                    }
+                }
+
+                page_index += batch;
+            }
+        }
+
+        free_pages
+    }
+}
+
+fn bit_is_set(bitmap: &[u8], index: u64) -> bool {
+    bitmap[(index / 8) as usize] & (1 << (index % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], index: u64) {
+    bitmap[(index / 8) as usize] |= 1 << (index % 8);
+}
+
+/// Appends a page_desc structure.
+fn push_page_descriptor(descriptors: &mut Vec<u8>, offset: u64, size: u32, flags: u32) {
+    let mut descriptor = [0u8; PAGE_DESCRIPTOR_SIZE];
+    put_u64(&mut descriptor, 0x0, offset);
+    put_u32(&mut descriptor, 0x8, size);
+    put_u32(&mut descriptor, 0xC, flags);
+    descriptors.extend_from_slice(&descriptor);
+}
+
+/// Builds the disk_dump_header structure.
+fn build_disk_dump_header(
+    nr_cpus: u32,
+    options: &KdumpOptions,
+    vmcoreinfo: Option<&str>,
+    block_size: u64,
+    sub_header_blocks: u64,
+    bitmap_blocks: u64,
+    max_mapnr: u64,
+) -> Vec<u8> {
+    let mut header = vec![0u8; block_size as usize];
+    let signature =
+        disk_dump_header::SIGNATURE..disk_dump_header::SIGNATURE + KDUMP_SIGNATURE.len();
+    header[signature].copy_from_slice(KDUMP_SIGNATURE);
+    put_u32(
+        &mut header,
+        disk_dump_header::HEADER_VERSION,
+        KDUMP_HEADER_VERSION,
+    );
+
+    // Only the fields of new_utsname that can be derived from the VMCOREINFO are filled
+    let release = vmcoreinfo
+        .and_then(|vmcoreinfo| vmcoreinfo_value(vmcoreinfo, "OSRELEASE"))
+        .unwrap_or("");
+    for (index, field) in ["Linux", "", release, "", "x86_64", ""].iter().enumerate() {
+        let offset = disk_dump_header::UTSNAME + index * UTSNAME_FIELD_SIZE;
+        let length = std::cmp::min(field.len(), UTSNAME_FIELD_SIZE - 1);
+        header[offset..offset + length].copy_from_slice(&field.as_bytes()[..length]);
+    }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    put_u64(
+        &mut header,
+        disk_dump_header::TIMESTAMP,
+        timestamp.as_secs(),
+    );
+    put_u64(
+        &mut header,
+        disk_dump_header::TIMESTAMP + 0x8,
+        u64::from(timestamp.subsec_micros()),
+    );
+
+    put_u32(
+        &mut header,
+        disk_dump_header::STATUS,
+        options.compression.flag(),
+    );
+    put_u32(&mut header, disk_dump_header::BLOCK_SIZE, block_size as u32);
+    put_u32(
+        &mut header,
+        disk_dump_header::SUB_HEADER_SIZE,
+        sub_header_blocks as u32,
+    );
+    put_u32(
+        &mut header,
+        disk_dump_header::BITMAP_BLOCKS,
+        bitmap_blocks as u32,
+    );
+    put_u32(
+        &mut header,
+        disk_dump_header::MAX_MAPNR,
+        std::cmp::min(max_mapnr, u64::from(u32::MAX)) as u32,
+    );
+    put_u32(&mut header, disk_dump_header::NR_CPUS, nr_cpus);
+    header
+}
+
+/// Builds the kdump_sub_header structure, followed by the VMCOREINFO and the ELF notes.
+fn build_kdump_sub_header(
+    vmcoreinfo: Option<&str>,
+    notes: &[u8],
+    block_size: u64,
+    dump_level: u32,
+    max_mapnr: u64,
+) -> Vec<u8> {
+    let vmcoreinfo = vmcoreinfo.unwrap_or("").as_bytes();
+    let vmcoreinfo_offset = block_size + KDUMP_SUB_HEADER_SIZE as u64;
+    let notes_offset = vmcoreinfo_offset + vmcoreinfo.len() as u64;
+
+    let mut sub_header = vec![0u8; KDUMP_SUB_HEADER_SIZE];
+    let phys_base = vmcoreinfo_number(
+        std::str::from_utf8(vmcoreinfo).unwrap_or(""),
+        "NUMBER(phys_base)",
+    )
+    .unwrap_or(0);
+    put_u64(
+        &mut sub_header,
+        kdump_sub_header::PHYS_BASE,
+        phys_base as u64,
+    );
+    put_u32(&mut sub_header, kdump_sub_header::DUMP_LEVEL, dump_level);
+    if !vmcoreinfo.is_empty() {
+        put_u64(
+            &mut sub_header,
+            kdump_sub_header::OFFSET_VMCOREINFO,
+            vmcoreinfo_offset,
+        );
+        put_u64(
+            &mut sub_header,
+            kdump_sub_header::SIZE_VMCOREINFO,
+            vmcoreinfo.len() as u64,
+        );
+    }
+    put_u64(&mut sub_header, kdump_sub_header::OFFSET_NOTE, notes_offset);
+    put_u64(
+        &mut sub_header,
+        kdump_sub_header::SIZE_NOTE,
+        notes.len() as u64,
+    );
+    put_u64(&mut sub_header, kdump_sub_header::MAX_MAPNR_64, max_mapnr);
+
+    sub_header.extend_from_slice(vmcoreinfo);
+    sub_header.extend_from_slice(notes);
+    sub_header.resize(align_up(sub_header.len() as u64, block_size) as usize, 0);
+    sub_header
+}
+
+/// Writes a kdump-compressed dump of the loaded saved state of a 64-bit Linux guest to the
+/// given output. The page descriptors and the compressed pages are written as the guest memory
+/// is streamed, and the headers and bitmaps are written last, once the dumped pages are known.
+/// An interrupted write therefore never leaves behind a file with a valid header, and
+/// the output is always written from scratch rather than resumed.
+pub fn write_kdump<W: Write + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    output: &mut W,
+    options: &KdumpOptions,
+    control: &mut DumpControl,
+) -> VmSavedStateDumpResult<DumpStatistics> {
+    if provider.get_vp_architecture(0)? != VirtualProcessorArch::X64 {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    let vmcoreinfo = match &options.vmcoreinfo {
+        Some(vmcoreinfo) => Some(vmcoreinfo.clone()),
+        None => find_vmcoreinfo(provider)?,
+    };
+    let vmcoreinfo = vmcoreinfo.as_deref();
+
+    let max_mapnr = page_index_limit(&memory_chunks);
+    let bitmap_size = align_up(align_up(max_mapnr, 8) / 8, page_size) as usize;
+    let mut present_pages = vec![0u8; bitmap_size];
+    for chunk in &memory_chunks {
+        let start = chunk.guest_physical_start_page_index;
+        (start..start + chunk.page_count)
+            .for_each(|page_index| set_bit(&mut present_pages, page_index));
+    }
+
+    let vp_id = kernel_mode_vp_id(provider)?;
+    let free_pages = match vmcoreinfo {
+        Some(vmcoreinfo) if options.exclude_free_pages => {
+            StructPageLayout::find(provider, vp_id, vmcoreinfo)
+                .map(|layout| layout.free_pages(provider, vp_id, &memory_chunks, bitmap_size))
+        }
+        _ => None,
+    };
+
+    let mut dump_level = 0;
+    if options.exclude_zero_pages {
+        dump_level |= DUMP_LEVEL_EXCLUDE_ZERO;
+    }
+    if free_pages.is_some() {
+        dump_level |= DUMP_LEVEL_EXCLUDE_FREE;
+    }
+
+    let notes = build_notes(provider, vmcoreinfo)?;
+    let sub_header = build_kdump_sub_header(vmcoreinfo, &notes, page_size, dump_level, max_mapnr);
+
+    // Room is reserved for the descriptors of every page that may be dumped
+    let free_page_count = free_pages.as_ref().map_or(0, |free_pages| {
+        free_pages
+            .iter()
+            .zip(present_pages.iter())
+            .map(|(free, present)| (free & present).count_ones() as u64)
+            .sum()
+    });
+    let reserved_descriptors = total_page_count(&memory_chunks) - free_page_count;
+    let bitmap_offset = page_size + sub_header.len() as u64;
+    let mut descriptor_offset = bitmap_offset + 2 * bitmap_size as u64;
+    let mut data_offset = descriptor_offset + reserved_descriptors * PAGE_DESCRIPTOR_SIZE as u64;
+
+    let mut tracker = control.begin(provider)?;
+    let start = output.stream_position()?;
+    let mut statistics = DumpStatistics::default();
+
+    // All zero pages that are dumped share the same data
+    let zero_page = vec![0u8; page_size as usize];
+    let mut zero_page_descriptor = Vec::new();
+    if !options.exclude_zero_pages {
+        let (flags, data) = match options.compression.compress(&zero_page)? {
+            Some(compressed) => (options.compression.flag(), compressed),
+            None => (0, zero_page.clone()),
+        };
+        push_page_descriptor(
+            &mut zero_page_descriptor,
+            data_offset,
+            data.len() as u32,
+            flags,
+        );
+        output.seek(SeekFrom::Start(start + data_offset))?;
+        output.write_all(&data)?;
+        data_offset += data.len() as u64;
+    }
+
+    let mut dumped_pages = vec![0u8; bitmap_size];
+    let mut descriptors = Vec::new();
+    let mut data = Vec::new();
+    let mut reader = MemoryBlockReader::new(provider, page_size, &memory_chunks);
+    while let Some((address, block)) = reader.next_block()? {
+        descriptors.clear();
+        data.clear();
+
+        for (index, page) in block.chunks(page_size as usize).enumerate() {
+            let page_index = address / page_size + index as u64;
+            if let Some(free_pages) = &free_pages {
+                if bit_is_set(free_pages, page_index) {
+                    continue;
+                }
+            }
+
+            if is_zero_page(page) {
+                if options.exclude_zero_pages {
+                    statistics.zero_pages_elided += 1;
+                    continue;
+                }
+                descriptors.extend_from_slice(&zero_page_descriptor);
+            } else {
+                let offset = data_offset + data.len() as u64;
+                match options.compression.compress(page)? {
+                    Some(compressed) => {
+                        push_page_descriptor(
+                            &mut descriptors,
+                            offset,
+                            compressed.len() as u32,
+                            options.compression.flag(),
+                        );
+                        data.extend_from_slice(&compressed);
+                    }
+                    None => {
+                        push_page_descriptor(&mut descriptors, offset, page_size as u32, 0);
+                        data.extend_from_slice(page);
+                    }
+                }
+            }
+
+            set_bit(&mut dumped_pages, page_index);
+            statistics.pages_written += 1;
+        }
+
+        output.seek(SeekFrom::Start(start + data_offset))?;
+        output.write_all(&data)?;
+        data_offset += data.len() as u64;
+
+        output.seek(SeekFrom::Start(start + descriptor_offset))?;
+        output.write_all(&descriptors)?;
+        descriptor_offset += descriptors.len() as u64;
+        tracker.advance(block.len() as u64)?;
+    }
+
+    let header = build_disk_dump_header(
+        provider.vp_count()?,
+        options,
+        vmcoreinfo,
+        page_size,
+        sub_header.len() as u64 / page_size,
+        2 * bitmap_size as u64 / page_size,
+        max_mapnr,
+    );
+
+    output.seek(SeekFrom::Start(start))?;
+    output.write_all(&header)?;
+    output.write_all(&sub_header)?;
+    output.write_all(&present_pages)?;
+    output.write_all(&dumped_pages)?;
+    output.seek(SeekFrom::Start(start + data_offset))?;
+
+    statistics.bytes_written = data_offset;
+    output.flush()?;
+    Ok(statistics)
+}
+
+/// Decodes a page stored in a kdump-compressed dump with the given page descriptor flags,
+/// returning `None` if it can't be decompressed into a full page.
+pub(crate) fn decompress_page(flags: u32, data: &[u8], page_size: usize) -> Option<Vec<u8>> {
+    let page = if flags & KdumpCompression::Lzo.flag() != 0 {
+        lzo1x_decompress(data, page_size)?
+    } else if flags & 0x1 != 0 {
+        decompress_zlib_page(data)?
+    } else if flags & 0x20 != 0 {
+        decompress_zstd_page(data, page_size)?
+    } else {
+        data.to_vec()
+    };
+
+    if page.len() == page_size {
+        Some(page)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "flate2")]
+fn decompress_zlib_page(data: &[u8]) -> Option<Vec<u8>> {
+    let mut page = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .read_to_end(&mut page)
+        .ok()?;
+    Some(page)
+}
+
+#[cfg(not(feature = "flate2"))]
+fn decompress_zlib_page(_data: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd_page(data: &[u8], page_size: usize) -> Option<Vec<u8>> {
+    zstd::bulk::decompress(data, page_size).ok()
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_zstd_page(_data: &[u8], _page_size: usize) -> Option<Vec<u8>> {
+    None
+}
+
+/// Reads the layout of a kdump-compressed dump of an x86_64 guest.
+pub(crate) fn read_kdump_layout<R: Read + Seek>(
+    input: &mut R,
+) -> VmSavedStateDumpResult<DumpLayout> {
+    let mut header = vec![0u8; disk_dump_header::NR_CPUS + 4];
+    input.seek(SeekFrom::Start(0))?;
+    input.read_exact(&mut header)?;
+    if &header[..KDUMP_SIGNATURE.len()] != KDUMP_SIGNATURE {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let block_size = u64::from(get_u32(&header, disk_dump_header::BLOCK_SIZE));
+    let sub_header_blocks = u64::from(get_u32(&header, disk_dump_header::SUB_HEADER_SIZE));
+    let bitmap_blocks = u64::from(get_u32(&header, disk_dump_header::BITMAP_BLOCKS));
+    if block_size == 0 || bitmap_blocks % 2 != 0 {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let mut sub_header = [0u8; KDUMP_SUB_HEADER_SIZE];
+    input.seek(SeekFrom::Start(block_size))?;
+    input.read_exact(&mut sub_header)?;
+
+    let bitmap_size = bitmap_blocks / 2 * block_size;
+    let mut bitmaps = read_region(input, (1 + sub_header_blocks) * block_size, 2 * bitmap_size)?;
+    let dumped_pages = bitmaps.split_off(bitmap_size as usize);
+
+    let notes = read_region(
+        input,
+        get_u64(&sub_header, kdump_sub_header::OFFSET_NOTE),
+        get_u64(&sub_header, kdump_sub_header::SIZE_NOTE),
+    )?;
+
+    Ok(DumpLayout {
+        pages: StoredPages::Kdump(KdumpPages {
+            present_pages: bitmaps,
+            dumped_pages,
+            descriptors_offset: (1 + sub_header_blocks + bitmap_blocks).saturating_mul(block_size),
+        }),
+        registers: read_prstatus_notes(&notes, VirtualProcessorArch::X64),
+        page_index_limit: None,
+        zero_pages_elided: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: u64 = 0x1000;
+    const VMCOREINFO: &str =
+        "OSRELEASE=5.15.0-91-generic\nPAGESIZE=4096\nNUMBER(phys_base)=-2097152\n";
+
+    /// Builds a CORE NT_PRSTATUS note of the first virtual processor, with the given RIP.
+    fn prstatus_note(rip: u64) -> Vec<u8> {
+        let mut note = vec![0u8; 0x14 + 336];
+        put_u32(&mut note, 0x0, 5);
+        put_u32(&mut note, 0x4, 336);
+        put_u32(&mut note, 0x8, 1);
+        note[0xC..0x11].copy_from_slice(b"CORE\0");
+        put_u32(&mut note, 0x14 + 32, 1);
+        put_u64(&mut note, 0x14 + 112 + 16 * 8, rip);
+        note
+    }
+
+    #[test]
+    fn vmcoreinfo_entries() {
+        assert_eq!(
+            Some("5.15.0-91-generic"),
+            vmcoreinfo_value(VMCOREINFO, "OSRELEASE")
+        );
+        assert_eq!(Some(4096), vmcoreinfo_number(VMCOREINFO, "PAGESIZE"));
+        assert_eq!(
+            Some(-0x20_0000),
+            vmcoreinfo_number(VMCOREINFO, "NUMBER(phys_base)")
+        );
+        assert_eq!(None, vmcoreinfo_value(VMCOREINFO, "PAGE"));
+    }
+
+    #[test]
+    fn struct_page_layout_from_vmcoreinfo() {
+        const VMEMMAP: u64 = 0xFFFF_EA00_0000_0000;
+        let vmcoreinfo = |size: &str, mapcount: &str, private: &str| {
+            format!(
+                "SIZE(page)={}\nOFFSET(page._mapcount)={}\nOFFSET(page.private)={}\n\
+                 NUMBER(PAGE_BUDDY_MAPCOUNT_VALUE)=-129\n",
+                size, mapcount, private
+            )
+        };
+
+        let layout =
+            StructPageLayout::from_vmcoreinfo(VMEMMAP, &vmcoreinfo("64", "48", "40")).unwrap();
+        assert_eq!(VMEMMAP, layout.vmemmap);
+        assert_eq!(64, layout.size);
+        assert_eq!(48, layout.mapcount_offset);
+        assert_eq!(40, layout.private_offset);
+        assert_eq!(-129i32 as u32, layout.buddy_mapcount_value);
+
+        // Fields that end right at the end of the struct page still fit
+        assert!(
+            StructPageLayout::from_vmcoreinfo(VMEMMAP, &vmcoreinfo("64", "60", "56")).is_some()
+        );
+
+        // Malformed VMCOREINFO leaves free pages in the dump rather than reading out of bounds
+        for (size, mapcount, private) in &[
+            ("0", "48", "40"),
+            ("-64", "48", "40"),
+            ("4096", "48", "40"),
+            ("18446744073709551615", "48", "40"),
+            ("64", "61", "40"),
+            ("64", "48", "57"),
+            ("64", "-8", "40"),
+            ("64", "48", "9223372036854775807"),
+            ("64", "48", "forty"),
+        ] {
+            assert!(
+                StructPageLayout::from_vmcoreinfo(VMEMMAP, &vmcoreinfo(size, mapcount, private))
+                    .is_none(),
+                "SIZE(page)={} OFFSET(page._mapcount)={} OFFSET(page.private)={}",
+                size,
+                mapcount,
+                private
+            );
+        }
+        assert!(StructPageLayout::from_vmcoreinfo(VMEMMAP, VMCOREINFO).is_none());
+    }
+
+    #[test]
+    fn disk_dump_header_fields() {
+        let options = KdumpOptions::default();
+        let header = build_disk_dump_header(2, &options, Some(VMCOREINFO), BLOCK_SIZE, 1, 2, 0x300);
+        assert_eq!(BLOCK_SIZE as usize, header.len());
+        assert_eq!(KDUMP_SIGNATURE, &header[..8]);
+        assert_eq!(
+            KDUMP_HEADER_VERSION,
+            get_u32(&header, disk_dump_header::HEADER_VERSION)
+        );
+        assert_eq!(b"Linux\0", &header[disk_dump_header::UTSNAME..][..6]);
+        let release = disk_dump_header::UTSNAME + 2 * UTSNAME_FIELD_SIZE;
+        assert_eq!(b"5.15.0-91-generic\0", &header[release..release + 18]);
+        assert_eq!(
+            KdumpCompression::Lzo.flag(),
+            get_u32(&header, disk_dump_header::STATUS)
+        );
+        assert_eq!(0x1000, get_u32(&header, disk_dump_header::BLOCK_SIZE));
+        assert_eq!(1, get_u32(&header, disk_dump_header::SUB_HEADER_SIZE));
+        assert_eq!(2, get_u32(&header, disk_dump_header::BITMAP_BLOCKS));
+        assert_eq!(0x300, get_u32(&header, disk_dump_header::MAX_MAPNR));
+        assert_eq!(2, get_u32(&header, disk_dump_header::NR_CPUS));
+    }
+
+    #[test]
+    fn kdump_sub_header_fields() {
+        let notes = prstatus_note(0);
+        let sub_header = build_kdump_sub_header(Some(VMCOREINFO), &notes, BLOCK_SIZE, 17, 0x300);
+        assert_eq!(BLOCK_SIZE as usize, sub_header.len());
+        assert_eq!(
+            -0x20_0000i64 as u64,
+            get_u64(&sub_header, kdump_sub_header::PHYS_BASE)
+        );
+        assert_eq!(17, get_u32(&sub_header, kdump_sub_header::DUMP_LEVEL));
+        assert_eq!(0x300, get_u64(&sub_header, kdump_sub_header::MAX_MAPNR_64));
+
+        // Offsets are relative to the start of the file, where the sub header follows the header
+        let vmcoreinfo_offset = get_u64(&sub_header, kdump_sub_header::OFFSET_VMCOREINFO);
+        let vmcoreinfo_size = get_u64(&sub_header, kdump_sub_header::SIZE_VMCOREINFO);
+        let vmcoreinfo = (vmcoreinfo_offset - BLOCK_SIZE) as usize;
+        assert_eq!(
+            VMCOREINFO.as_bytes(),
+            &sub_header[vmcoreinfo..vmcoreinfo + vmcoreinfo_size as usize]
+        );
+        let notes_offset =
+            (get_u64(&sub_header, kdump_sub_header::OFFSET_NOTE) - BLOCK_SIZE) as usize;
+        assert_eq!(
+            notes.len() as u64,
+            get_u64(&sub_header, kdump_sub_header::SIZE_NOTE)
+        );
+        assert_eq!(
+            &notes[..],
+            &sub_header[notes_offset..notes_offset + notes.len()]
+        );
+
+        // Without VMCOREINFO its offset and size are left as zero
+        let sub_header = build_kdump_sub_header(None, &notes, BLOCK_SIZE, 1, 0x300);
+        assert_eq!(0, get_u64(&sub_header, kdump_sub_header::OFFSET_VMCOREINFO));
+        assert_eq!(0, get_u64(&sub_header, kdump_sub_header::SIZE_VMCOREINFO));
+        assert_eq!(0, get_u64(&sub_header, kdump_sub_header::PHYS_BASE));
+    }
+
+    #[test]
+    fn kdump_layout_of_synthetic_dump() {
+        let options = KdumpOptions::default();
+        let notes = prstatus_note(0xFFFF_FFFF_8100_1234);
+        let sub_header = build_kdump_sub_header(Some(VMCOREINFO), &notes, BLOCK_SIZE, 1, 0x300);
+        let header = build_disk_dump_header(1, &options, Some(VMCOREINFO), BLOCK_SIZE, 1, 2, 0x300);
+
+        let mut present_pages = vec![0u8; BLOCK_SIZE as usize];
+        let mut dumped_pages = vec![0u8; BLOCK_SIZE as usize];
+        for page_index in 0..0x10 {
+            set_bit(&mut present_pages, page_index);
+        }
+        set_bit(&mut dumped_pages, 0x3);
+
+        let mut dump = header;
+        dump.extend_from_slice(&sub_header);
+        dump.extend_from_slice(&present_pages);
+        dump.extend_from_slice(&dumped_pages);
+        push_page_descriptor(&mut dump, 5 * BLOCK_SIZE, 0x1000, 0);
+
+        let layout = read_kdump_layout(&mut Cursor::new(dump)).unwrap();
+        match &layout.pages {
+            StoredPages::Kdump(pages) => {
+                assert_eq!(present_pages, pages.present_pages);
+                assert_eq!(dumped_pages, pages.dumped_pages);
+                assert!(bit_is_set(&pages.dumped_pages, 0x3));
+                assert!(!bit_is_set(&pages.dumped_pages, 0x4));
+                assert_eq!(4 * BLOCK_SIZE, pages.descriptors_offset);
+            }
+            pages => panic!("unexpected pages {:?}", pages),
+        }
+        assert!(layout.registers.contains(&StoredRegister {
+            vp_id: 0,
+            register: Register::X64(RegisterIdx64::Rip),
+            value: 0xFFFF_FFFF_8100_1234,
+            size: 8,
+        }));
+        assert!(layout.zero_pages_elided);
+    }
+
+    #[test]
+    fn kdump_layout_rejects_other_files() {
+        let mut dump = vec![0u8; BLOCK_SIZE as usize];
+        dump[..8].copy_from_slice(b"KDUMP  X");
+        assert_eq!(
+            ResultCode::InvalidArgument,
+            read_kdump_layout(&mut Cursor::new(dump)).unwrap_err()
+        );
+
+        // Bitmaps always come in pairs of the same size
+        let options = KdumpOptions::default();
+        let dump = build_disk_dump_header(1, &options, None, BLOCK_SIZE, 1, 3, 0x300);
+        assert_eq!(
+            ResultCode::InvalidArgument,
+            read_kdump_layout(&mut Cursor::new(dump)).unwrap_err()
+        );
+    }
+}
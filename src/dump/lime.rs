@@ -0,0 +1,203 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Writer of LiME memory images, as produced by the Linux Memory Extractor
+//! and ingested by Volatility and other memory forensics tools, along with the reader used to verify them.
+
+use crate::dump::verify::*;
+use crate::dump::*;
+use crate::vmsavedstatedump::*;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub const LIME_MAGIC: u32 = 0x4C69_4D45;
+pub const LIME_VERSION: u32 = 1;
+pub const LIME_RANGE_HEADER_SIZE: usize = 32;
+
+/// Builds the header that precedes the memory of a range. The end address is inclusive.
+fn lime_range_header(start_address: u64, end_address: u64) -> [u8; LIME_RANGE_HEADER_SIZE] {
+    let mut header = [0u8; LIME_RANGE_HEADER_SIZE];
+    put_u32(&mut header, 0x0, LIME_MAGIC);
+    put_u32(&mut header, 0x4, LIME_VERSION);
+    put_u64(&mut header, 0x8, start_address);
+    put_u64(&mut header, 0x10, end_address);
+    header
+}
+
+/// Writes a LiME image of the guest physical memory of the loaded saved state to the given output.
+/// Every guest physical memory chunk is stored as a single range, made of its header followed by
+/// the memory of the chunk. Resumed output is validated against the header of the first range.
+pub fn write_lime_image<W: Read + Write + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    output: &mut W,
+    control: &mut DumpControl,
+) -> VmSavedStateDumpResult<DumpStatistics> {
+    let (page_size, mut memory_chunks) = provider.guest_physical_memory_chunks()?;
+    memory_chunks.retain(|chunk| chunk.page_count != 0);
+
+    let range_headers: Vec<_> = memory_chunks
+        .iter()
+        .map(|chunk| {
+            let start_address = chunk.guest_physical_start_page_index * page_size;
+            lime_range_header(
+                start_address,
+                start_address + chunk.page_count * page_size - 1,
+            )
+        })
+        .collect();
+    let mut chunk_offsets = vec![0];
+    for chunk in &memory_chunks {
+        let offset = chunk_offsets[chunk_offsets.len() - 1];
+        chunk_offsets.push(offset + LIME_RANGE_HEADER_SIZE as u64 + chunk.page_count * page_size);
+    }
+
+    let resume = control.resume;
+    let mut tracker = control.begin(provider)?;
+    let start = output.stream_position()?;
+    let resume_point = match range_headers.first() {
+        Some(range_header) if resume => {
+            find_resume_point(output, start, &[(0, range_header)], &chunk_offsets)?
+        }
+        _ => None,
+    };
+    let first_chunk = resume_point.unwrap_or(0);
+    output.seek(SeekFrom::Start(start + chunk_offsets[first_chunk]))?;
+
+    let resumed_pages = total_page_count(&memory_chunks[..first_chunk]);
+    tracker.skip(resumed_pages * page_size);
+    let mut statistics = DumpStatistics {
+        pages_written: resumed_pages,
+        bytes_written: chunk_offsets[first_chunk],
+        ..Default::default()
+    };
+
+    for (chunk, range_header) in memory_chunks
+        .iter()
+        .zip(range_headers.iter())
+        .skip(first_chunk)
+    {
+        output.write_all(range_header)?;
+        statistics.bytes_written += LIME_RANGE_HEADER_SIZE as u64;
+
+        let mut reader = MemoryBlockReader::new(provider, page_size, std::slice::from_ref(chunk));
+        while let Some((_, block)) = reader.next_block()? {
+            output.write_all(block)?;
+            statistics.pages_written += block.len() as u64 / page_size;
+            statistics.bytes_written += block.len() as u64;
+            tracker.advance(block.len() as u64)?;
+        }
+    }
+
+    output.flush()?;
+    Ok(statistics)
+}
+
+fn malformed_range_header() -> VmSavedStateDumpError {
+    ResultCode::Io(std::io::ErrorKind::InvalidData).into()
+}
+
+/// Reads the layout of a LiME image, made of every range found up to the end of the image.
+pub(crate) fn read_lime_image_layout<R: Read + Seek>(
+    input: &mut R,
+    page_size: u64,
+) -> VmSavedStateDumpResult<DumpLayout> {
+    let length = input.seek(SeekFrom::End(0))?;
+    let mut runs = Vec::new();
+    let mut range_header = [0u8; LIME_RANGE_HEADER_SIZE];
+    let mut offset = 0;
+
+    while offset + LIME_RANGE_HEADER_SIZE as u64 <= length {
+        input.seek(SeekFrom::Start(offset))?;
+        input.read_exact(&mut range_header)?;
+        if get_u32(&range_header, 0x0) != LIME_MAGIC || get_u32(&range_header, 0x4) != LIME_VERSION
+        {
+            return Err(ResultCode::InvalidArgument.into());
+        }
+
+        // Range headers come from the file, so their addresses can't be trusted to add up
+        let start_address = get_u64(&range_header, 0x8);
+        let size = get_u64(&range_header, 0x10)
+            .checked_sub(start_address)
+            .and_then(|size| size.checked_add(1))
+            .ok_or_else(malformed_range_header)?;
+        runs.push(StoredRun {
+            first_page_index: start_address / page_size,
+            page_count: size / page_size,
+            offset: offset + LIME_RANGE_HEADER_SIZE as u64,
+        });
+        offset = (offset + LIME_RANGE_HEADER_SIZE as u64)
+            .checked_add(size)
+            .ok_or_else(malformed_range_header)?;
+    }
+
+    Ok(DumpLayout {
+        pages: StoredPages::Runs(runs),
+        registers: Vec::new(),
+        page_index_limit: None,
+        zero_pages_elided: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn lime_image_layout() {
+        let mut image = lime_range_header(0x1000, 0x2FFF).to_vec();
+        image.resize(LIME_RANGE_HEADER_SIZE + 0x2000, 0);
+        image.extend_from_slice(&lime_range_header(0x10000, 0x10FFF));
+        image.resize(2 * LIME_RANGE_HEADER_SIZE + 0x3000, 0);
+
+        let layout = read_lime_image_layout(&mut Cursor::new(image), 0x1000).unwrap();
+        match layout.pages {
+            StoredPages::Runs(runs) => assert_eq!(
+                vec![
+                    StoredRun {
+                        first_page_index: 1,
+                        page_count: 2,
+                        offset: LIME_RANGE_HEADER_SIZE as u64,
+                    },
+                    StoredRun {
+                        first_page_index: 0x10,
+                        page_count: 1,
+                        offset: 2 * LIME_RANGE_HEADER_SIZE as u64 + 0x2000,
+                    },
+                ],
+                runs
+            ),
+            _ => panic!("LiME images are made of runs"),
+        }
+        assert!(!layout.zero_pages_elided);
+    }
+
+    #[test]
+    fn malformed_lime_range_headers() {
+        let invalid_data = ResultCode::Io(std::io::ErrorKind::InvalidData);
+
+        // The range ends before it starts
+        let image = lime_range_header(0x2000, 0x1000);
+        let error = read_lime_image_layout(&mut Cursor::new(image), 0x1000).unwrap_err();
+        assert_eq!(invalid_data, error);
+
+        // The range spans the whole address space, so its size doesn't fit 64 bits
+        let image = lime_range_header(0, u64::MAX);
+        let error = read_lime_image_layout(&mut Cursor::new(image), 0x1000).unwrap_err();
+        assert_eq!(invalid_data, error);
+
+        // The range runs past the largest offset a file can have
+        let image = lime_range_header(0, u64::MAX - 1);
+        let error = read_lime_image_layout(&mut Cursor::new(image), 0x1000).unwrap_err();
+        assert_eq!(invalid_data, error);
+
+        let mut image = lime_range_header(0, 0xFFF).to_vec();
+        image[0] ^= 0xFF;
+        let error = read_lime_image_layout(&mut Cursor::new(image), 0x1000).unwrap_err();
+        assert_eq!(ResultCode::InvalidArgument, error);
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! LZO1X-1 compressor, producing streams that `lzo1x_decompress_safe` accepts.
+//! This is a port of the compressor found in the Linux kernel, without the run length
+//! encoding extension, which keeps the output compatible with every LZO1X decompressor.
+//! A decompressor of LZO1X streams is included as well, used to read back compressed dumps.
+
+const M2_MAX_LEN: usize = 8;
+const M3_MAX_LEN: usize = 33;
+const M4_MAX_LEN: usize = 9;
+const M2_MAX_OFFSET: usize = 0x0800;
+const M3_MAX_OFFSET: usize = 0x4000;
+const M4_MAX_OFFSET: usize = 0xBFFF;
+const M3_MARKER: u8 = 32;
+const M4_MARKER: u8 = 16;
+
+const DICTIONARY_BITS: u32 = 13;
+const DICTIONARY_SIZE: usize = 1 << DICTIONARY_BITS;
+
+fn get_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+/// Appends the length of a literal run or match that doesn't fit its instruction byte,
+/// as a sequence of zero bytes each worth 255, followed by the remainder.
+fn push_extended_length(output: &mut Vec<u8>, mut length: usize) {
+    while length > 255 {
+        length -= 255;
+        output.push(0);
+    }
+    output.push(length as u8);
+}
+
+/// Appends a run of literals. Runs of up to 3 literals are encoded in the low bits
+/// of the previous instruction, which always has room for them.
+fn push_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    let length = literals.len();
+    if length == 0 {
+        return;
+    }
+
+    if length <= 3 {
+        let state = output.len() - 2;
+        output[state] |= length as u8;
+    } else if length <= 18 {
+        output.push((length - 3) as u8);
+    } else {
+        output.push(0);
+        push_extended_length(output, length - 18);
+    }
+
+    output.extend_from_slice(literals);
+}
+
+/// Compresses the block of at most `M4_MAX_OFFSET + 1` bytes of the input that starts at the
+/// given offset, appending the instructions to the output. Literals that precede the block and
+/// haven't been written yet are passed in, and the count of literals left pending is returned.
+fn compress_block(
+    input: &[u8],
+    block_start: usize,
+    block_end: usize,
+    pending_literals: usize,
+    output: &mut Vec<u8>,
+    dictionary: &mut [u16],
+) -> usize {
+    let position_limit = block_end - 20;
+    let mut literal_start = block_start - pending_literals;
+    let mut position = block_start + 4 - std::cmp::min(pending_literals, 4);
+
+    loop {
+        position += 1 + ((position - literal_start) >> 5);
+
+        loop {
+            if position >= position_limit {
+                return block_end - literal_start;
+            }
+
+            let value = get_u32(input, position);
+            let hash = (value.wrapping_mul(0x1824_429D) >> (32 - DICTIONARY_BITS)) as usize
+                & (DICTIONARY_SIZE - 1);
+            let match_position = block_start + dictionary[hash] as usize;
+            dictionary[hash] = (position - block_start) as u16;
+            if value != get_u32(input, match_position) {
+                break;
+            }
+
+            push_literals(output, &input[literal_start..position]);
+
+            let mut match_length = 4;
+            while position + match_length < position_limit
+                && input[position + match_length] == input[match_position + match_length]
+            {
+                match_length += 1;
+            }
+
+            let match_offset = position - match_position;
+            position += match_length;
+            literal_start = position;
+
+            if match_length <= M2_MAX_LEN && match_offset <= M2_MAX_OFFSET {
+                let offset = match_offset - 1;
+                output.push((((match_length - 1) << 5) | ((offset & 7) << 2)) as u8);
+                output.push((offset >> 3) as u8);
+            } else if match_offset <= M3_MAX_OFFSET {
+                let offset = match_offset - 1;
+                if match_length <= M3_MAX_LEN {
+                    output.push(M3_MARKER | (match_length - 2) as u8);
+                } else {
+                    output.push(M3_MARKER);
+                    push_extended_length(output, match_length - M3_MAX_LEN);
+                }
+                output.push((offset << 2) as u8);
+                output.push((offset >> 6) as u8);
+            } else {
+                let offset = match_offset - 0x4000;
+                let high_offset_bit = ((offset >> 11) & 8) as u8;
+                if match_length <= M4_MAX_LEN {
+                    output.push(M4_MARKER | high_offset_bit | (match_length - 2) as u8);
+                } else {
+                    output.push(M4_MARKER | high_offset_bit);
+                    push_extended_length(output, match_length - M4_MAX_LEN);
+                }
+                output.push((offset << 2) as u8);
+                output.push((offset >> 6) as u8);
+            }
+        }
+    }
+}
+
+/// Compresses the input into a single LZO1X stream.
+pub(crate) fn lzo1x_1_compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 16 + 64 + 3);
+    let mut dictionary = vec![0u16; DICTIONARY_SIZE];
+    let mut block_start = 0;
+    let mut pending_literals = 0;
+
+    while input.len() - block_start > 20 {
+        let block_size = std::cmp::min(input.len() - block_start, M4_MAX_OFFSET + 1);
+        dictionary.iter_mut().for_each(|entry| *entry = 0);
+        pending_literals = compress_block(
+            input,
+            block_start,
+            block_start + block_size,
+            pending_literals,
+            &mut output,
+            &mut dictionary,
+        );
+        block_start += block_size;
+    }
+
+    let literals = &input[block_start - pending_literals..];
+    if output.is_empty() && !literals.is_empty() && literals.len() <= 238 {
+        output.push((17 + literals.len()) as u8);
+        output.extend_from_slice(literals);
+    } else {
+        push_literals(&mut output, literals);
+    }
+
+    // End of stream marker, which is an M4 match with a distance of 0x4000
+    output.extend_from_slice(&[M4_MARKER | 1, 0, 0]);
+    output
+}
+
+/// Reads the length of a literal run or match that doesn't fit its instruction byte,
+/// returning it added to the given base length.
+fn read_extended_length(input: &[u8], position: &mut usize, base: usize) -> Option<usize> {
+    let mut length = base;
+    while *input.get(*position)? == 0 {
+        length += 255;
+        *position += 1;
+    }
+    length += *input.get(*position)? as usize;
+    *position += 1;
+    Some(length)
+}
+
+/// Decompresses a single LZO1X stream, returning `None` if the stream is malformed
+/// or decompresses to more than `output_limit` bytes.
+pub(crate) fn lzo1x_decompress(input: &[u8], output_limit: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_limit);
+    let mut position = 0;
+
+    // State holds the count of literals copied after the last instruction,
+    // with 4 standing for a run of 4 or more
+    let mut state = 0;
+    let push_literals = |output: &mut Vec<u8>, position: &mut usize, length: usize| {
+        let literals = input.get(*position..*position + length)?;
+        if output.len() + length > output_limit {
+            return None;
+        }
+        output.extend_from_slice(literals);
+        *position += length;
+        Some(())
+    };
+
+    if *input.first()? > 17 {
+        let length = *input.first()? as usize - 17;
+        position = 1;
+        push_literals(&mut output, &mut position, length)?;
+        state = std::cmp::min(length, 4);
+    }
+
+    loop {
+        let instruction = *input.get(position)? as usize;
+        position += 1;
+
+        let (length, distance, trailing_literals) = if instruction < 16 {
+            if state == 0 {
+                let length = if instruction == 0 {
+                    read_extended_length(input, &mut position, 18)?
+                } else {
+                    instruction + 3
+                };
+                push_literals(&mut output, &mut position, length)?;
+                state = 4;
+                continue;
+            }
+
+            let high = *input.get(position)? as usize;
+            position += 1;
+            if state < 4 {
+                (2, (instruction >> 2) + (high << 2) + 1, instruction & 3)
+            } else {
+                (
+                    3,
+                    (instruction >> 2) + (high << 2) + M2_MAX_OFFSET + 1,
+                    instruction & 3,
+                )
+            }
+        } else if instruction >= 64 {
+            let high = *input.get(position)? as usize;
+            position += 1;
+            (
+                (instruction >> 5) + 1,
+                ((instruction >> 2) & 7) + (high << 3) + 1,
+                instruction & 3,
+            )
+        } else {
+            let length = if instruction >= M3_MARKER as usize {
+                match instruction & 31 {
+                    0 => read_extended_length(input, &mut position, 31)?,
+                    length => length,
+                }
+            } else {
+                match instruction & 7 {
+                    0 => read_extended_length(input, &mut position, 7)?,
+                    length => length,
+                }
+            };
+            let offset = *input.get(position)? as usize | (*input.get(position + 1)? as usize) << 8;
+            position += 2;
+
+            let distance = if instruction >= M3_MARKER as usize {
+                (offset >> 2) + 1
+            } else {
+                let distance = M3_MAX_OFFSET + ((instruction & 8) << 11) + (offset >> 2);
+                if distance == M3_MAX_OFFSET {
+                    return if position == input.len() {
+                        Some(output)
+                    } else {
+                        None
+                    };
+                }
+                distance
+            };
+            (length + 2, distance, offset & 3)
+        };
+
+        if distance > output.len() || output.len() + length > output_limit {
+            return None;
+        }
+        for _ in 0..length {
+            output.push(output[output.len() - distance]);
+        }
+
+        push_literals(&mut output, &mut position, trailing_literals)?;
+        state = trailing_literals;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns bytes of a xorshift sequence, which don't compress.
+    fn incompressible(length: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = lzo1x_1_compress(input);
+        assert_eq!(
+            Some(input),
+            lzo1x_decompress(&compressed, input.len()).as_deref()
+        );
+        compressed
+    }
+
+    #[test]
+    fn empty_input() {
+        let compressed = round_trip(&[]);
+        assert_eq!(vec![M4_MARKER | 1, 0, 0], compressed);
+    }
+
+    #[test]
+    fn inputs_shorter_than_a_block() {
+        for length in 1..20 {
+            let input = incompressible(length);
+            let compressed = round_trip(&input);
+            assert_eq!(17 + length, compressed[0] as usize);
+        }
+    }
+
+    #[test]
+    fn single_page() {
+        let page = b"struct page\0"
+            .iter()
+            .cycle()
+            .take(0x1000)
+            .copied()
+            .collect::<Vec<_>>();
+        assert!(round_trip(&page).len() < 0x100);
+    }
+
+    #[test]
+    fn zero_pages() {
+        assert!(round_trip(&[0u8; 0x1000]).len() < 0x40);
+        assert!(round_trip(&[0u8; 0x10000]).len() < 0x400);
+    }
+
+    #[test]
+    fn incompressible_page() {
+        let page = incompressible(0x1000);
+        let compressed = round_trip(&page);
+        assert!(compressed.len() > page.len());
+        assert!(compressed.len() <= page.len() + page.len() / 16 + 64 + 3);
+    }
+
+    #[test]
+    fn multiple_blocks_with_far_matches() {
+        // Copies that lie further than M3_MAX_OFFSET behind are encoded as M4 matches,
+        // and the input spans several blocks of M4_MAX_OFFSET + 1 bytes
+        let mut input = incompressible(0x20000);
+        input.copy_within(0..0x800, 0x6000);
+        input.copy_within(0xC100..0xC900, 0x15000);
+        let compressed = round_trip(&input);
+        assert!(compressed.len() < input.len());
+    }
+
+    #[test]
+    fn malformed_streams_are_rejected() {
+        let input = incompressible(0x1000);
+        let compressed = lzo1x_1_compress(&input);
+
+        assert_eq!(None, lzo1x_decompress(&compressed, input.len() - 1));
+        assert_eq!(
+            None,
+            lzo1x_decompress(&compressed[..compressed.len() - 3], input.len())
+        );
+        assert_eq!(None, lzo1x_decompress(&[], 0x1000));
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module contains writers that convert a loaded VM saved state into
+//! dump file formats consumable by debuggers and memory analysis tools.
+
+pub mod elf;
+pub mod kdump;
+pub mod lime;
+pub(crate) mod lzo;
+pub mod raw;
+pub mod verify;
+pub mod windows;
+
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Number of guest pages read from the saved state at once when streaming guest memory.
+pub(crate) const STREAM_BLOCK_PAGES: u64 = 256;
+
+/// Statistics of a written dump file.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "python",
+    pyo3::pyclass(get_all, frozen, module = "vmsavedstatedump")
+)]
+pub struct DumpStatistics {
+    /// Count of guest memory pages stored in the dump file.
+    pub pages_written: u64,
+    /// Total size of the dump file in bytes.
+    pub bytes_written: u64,
+    /// Count of guest memory pages filled with zeros that were left out of the dump file.
+    pub zero_pages_elided: u64,
+    /// Set when a Windows kernel crash dump was written without a KdDebuggerDataBlock,
+    /// because the guest memory scan couldn't find it.
+    pub kd_debugger_data_block_missing: bool,
+}
+
+/// Progress of a dump being written, reported after every block of guest memory.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct DumpProgress {
+    /// Bytes of guest memory stored in the dump file so far, including those of resumed output.
+    pub bytes_written: u64,
+    /// Size of the guest memory stored in the saved state.
+    pub total_bytes: u64,
+}
+
+/// Token used to cancel dumps being written, which can be cloned and shared across threads.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the dumps that use this token, which return `ResultCode::Cancelled`
+    /// before writing their next block of guest memory.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Controls how a dump is written: progress reporting, cancellation, and resuming of partial output.
+#[derive(Default)]
+pub struct DumpControl<'a> {
+    /// Called after every block of guest memory is written.
+    pub progress: Option<Box<dyn FnMut(DumpProgress) + 'a>>,
+    pub cancellation: Option<CancellationToken>,
+    /// Continues writing an output that already holds part of the same dump, such as one
+    /// left behind by a cancelled dump. The output must start with the headers the dump would
+    /// write, and writing resumes at the first memory chunk that isn't completely stored.
+    /// Formats whose headers aren't written first are always written from scratch.
+    pub resume: bool,
+}
+
+impl<'a> DumpControl<'a> {
+    /// Starts tracking the progress of a dump, failing if it has already been cancelled.
+    pub(crate) fn begin(
+        &mut self,
+        provider: &VmSavedStateDumpProvider,
+    ) -> VmSavedStateDumpResult<ProgressTracker<'_, 'a>> {
+        let tracker = ProgressTracker {
+            progress: DumpProgress {
+                bytes_written: 0,
+                total_bytes: provider.guest_raw_saved_memory_size()?,
+            },
+            control: self,
+        };
+        tracker.check_cancelled()?;
+        Ok(tracker)
+    }
+}
+
+/// Progress of a dump being written under a `DumpControl`.
+pub(crate) struct ProgressTracker<'c, 'a> {
+    control: &'c mut DumpControl<'a>,
+    progress: DumpProgress,
+}
+
+impl<'c, 'a> ProgressTracker<'c, 'a> {
+    fn check_cancelled(&self) -> VmSavedStateDumpResult<()> {
+        match &self.control.cancellation {
+            Some(cancellation) if cancellation.is_cancelled() => Err(ResultCode::Cancelled.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records guest memory found already stored in resumed output.
+    pub(crate) fn skip(&mut self, bytes: u64) {
+        self.progress.bytes_written += bytes;
+    }
+
+    /// Records a block of guest memory as written, and reports the progress of the dump.
+    /// Fails if the dump has been cancelled, so no more guest memory gets written.
+    pub(crate) fn advance(&mut self, bytes: u64) -> VmSavedStateDumpResult<()> {
+        self.progress.bytes_written += bytes;
+        if let Some(progress) = &mut self.control.progress {
+            progress(self.progress);
+        }
+        self.check_cancelled()
+    }
+}
+
+/// Finds where to resume writing a dump whose output may already hold part of it.
+/// `expected` holds the bytes the dump starts with, as offsets relative to `start` along with their
+/// contents, and `chunk_offsets` holds the offset at which every memory chunk is stored,
+/// followed by the offset at which the last one ends. Returns the index of the first memory chunk
+/// that isn't completely stored in the output, or `None` if the output doesn't start with the
+/// expected bytes and has to be written from scratch.
+pub(crate) fn find_resume_point<R: Read + Seek>(
+    output: &mut R,
+    start: u64,
+    expected: &[(u64, &[u8])],
+    chunk_offsets: &[u64],
+) -> VmSavedStateDumpResult<Option<usize>> {
+    let length = output.seek(SeekFrom::End(0))?.saturating_sub(start);
+
+    for (offset, bytes) in expected {
+        if offset + bytes.len() as u64 > length {
+            return Ok(None);
+        }
+
+        let mut existing = vec![0u8; bytes.len()];
+        output.seek(SeekFrom::Start(start + offset))?;
+        output.read_exact(&mut existing)?;
+        if existing != *bytes {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(
+        chunk_offsets
+            .windows(2)
+            .take_while(|offsets| offsets[1] <= length)
+            .count(),
+    ))
+}
+
+/// Rounds up the given value to the next multiple of `alignment`, which must be a power of 2.
+pub(crate) fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+pub(crate) fn put_u16(buffer: &mut [u8], offset: usize, value: u16) {
+    buffer[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u64(buffer: &mut [u8], offset: usize, value: u64) {
+    buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn get_u16(buffer: &[u8], offset: usize) -> u16 {
+    let mut bytes = [0u8; 2];
+    bytes.copy_from_slice(&buffer[offset..offset + 2]);
+    u16::from_le_bytes(bytes)
+}
+
+pub(crate) fn get_u32(buffer: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buffer[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+pub(crate) fn get_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns true if the given page is filled with zeros.
+pub(crate) fn is_zero_page(page: &[u8]) -> bool {
+    page.iter().all(|byte| *byte == 0)
+}
+
+/// Returns the total count of pages described by the given memory chunks.
+pub(crate) fn total_page_count(memory_chunks: &[GpaMemoryChunk]) -> u64 {
+    memory_chunks.iter().map(|chunk| chunk.page_count).sum()
+}
+
+/// Returns the index of the page that follows the highest page described by the given memory chunks.
+pub(crate) fn page_index_limit(memory_chunks: &[GpaMemoryChunk]) -> u64 {
+    memory_chunks
+        .iter()
+        .map(|chunk| chunk.guest_physical_start_page_index + chunk.page_count)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the first virtual processor that was running in kernel mode,
+/// falling back to the first virtual processor if none was.
+pub(crate) fn kernel_mode_vp_id(
+    provider: &VmSavedStateDumpProvider,
+) -> VmSavedStateDumpResult<u32> {
+    for vp_id in 0..provider.vp_count()? {
+        if provider.get_vp_privilege_level(vp_id)? == 0 {
+            return Ok(vp_id);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Sequential reader of all of the guest physical memory described by a list of memory chunks.
+/// Memory is read in blocks of up to `STREAM_BLOCK_PAGES` pages, and blocks never span
+/// more than a single memory chunk, so each block is read with a single guest physical read.
+pub(crate) struct MemoryBlockReader<'a> {
+    provider: &'a VmSavedStateDumpProvider,
+    page_size: u64,
+    memory_chunks: &'a [GpaMemoryChunk],
+    chunk_index: usize,
+    page_index: u64,
+    buffer: Vec<u8>,
+}
+
+impl<'a> MemoryBlockReader<'a> {
+    pub(crate) fn new(
+        provider: &'a VmSavedStateDumpProvider,
+        page_size: u64,
+        memory_chunks: &'a [GpaMemoryChunk],
+    ) -> MemoryBlockReader<'a> {
+        MemoryBlockReader {
+            provider,
+            page_size,
+            memory_chunks,
+            chunk_index: 0,
+            page_index: memory_chunks
+                .first()
+                .map(|chunk| chunk.guest_physical_start_page_index)
+                .unwrap_or(0),
+            buffer: vec![0u8; (STREAM_BLOCK_PAGES * page_size) as usize],
+        }
+    }
+
+    /// Reads the next block of guest memory and returns it along with its guest physical address,
+    /// or `None` once all of the memory chunks have been read.
+    pub(crate) fn next_block(
+        &mut self,
+    ) -> VmSavedStateDumpResult<Option<(GuestPhysicalAddress, &[u8])>> {
+        loop {
+            let chunk = match self.memory_chunks.get(self.chunk_index) {
+                Some(chunk) => chunk,
+                None => return Ok(None),
+            };
+
+            let end_page_index = chunk.guest_physical_start_page_index + chunk.page_count;
+            if self.page_index >= end_page_index {
+                self.chunk_index += 1;
+                if let Some(next_chunk) = self.memory_chunks.get(self.chunk_index) {
+                    self.page_index = next_chunk.guest_physical_start_page_index;
+                }
+                continue;
+            }
+
+            let page_count = std::cmp::min(STREAM_BLOCK_PAGES, end_page_index - self.page_index);
+            let physical_address = self.page_index * self.page_size;
+            let block = &mut self.buffer[..(page_count * self.page_size) as usize];
+
+            let bytes_read = self
+                .provider
+                .read_guest_physical_address(physical_address, block)?;
+            if bytes_read as usize != block.len() {
+                return Err(VmSavedStateDumpError::new(ResultCode::Unexpected)
+                    .with_address(physical_address));
+            }
+
+            self.page_index += page_count;
+            return Ok(Some((physical_address, block)));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Writer of flat raw images of the guest physical memory, where the byte at every offset
+//! of the image is the byte at the same guest physical address.
+
+use crate::dump::*;
+use crate::vmsavedstatedump::*;
+use std::io::{Seek, SeekFrom, Write};
+
+/// Options that control how a raw image is written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RawImageOptions {
+    /// Skips over the holes between guest physical memory chunks and over the pages filled
+    /// with zeros instead of writing zeros, which leaves them as sparse holes on file systems
+    /// that support them.
+    pub sparse: bool,
+}
+
+/// Writes a raw image of the guest physical memory of the loaded saved state to the given output.
+/// Guest physical addresses that aren't backed by a memory chunk read as zeros in the image,
+/// which ends at the end of the highest memory chunk. Raw images have no header that identifies
+/// them, so they're always written from scratch rather than resumed.
+pub fn write_raw_image<W: Write + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    output: &mut W,
+    options: &RawImageOptions,
+    control: &mut DumpControl,
+) -> VmSavedStateDumpResult<DumpStatistics> {
+    let (page_size, mut memory_chunks) = provider.guest_physical_memory_chunks()?;
+    memory_chunks.sort_by_key(|chunk| chunk.guest_physical_start_page_index);
+
+    let start = output.stream_position()?;
+    let zero_block = vec![0u8; (STREAM_BLOCK_PAGES * page_size) as usize];
+    let mut tracker = control.begin(provider)?;
+    let mut statistics = DumpStatistics::default();
+
+    // Offset up to which the image has been written, which trails behind the skipped holes
+    let mut written_size = 0;
+    let mut write_run = |output: &mut W, address: u64, run: &[u8]| -> std::io::Result<()> {
+        if run.is_empty() {
+            return Ok(());
+        }
+
+        if address > written_size {
+            if options.sparse {
+                output.seek(SeekFrom::Start(start + address))?;
+            } else {
+                let mut hole_size = address - written_size;
+                while hole_size > 0 {
+                    let size = std::cmp::min(hole_size, zero_block.len() as u64);
+                    output.write_all(&zero_block[..size as usize])?;
+                    hole_size -= size;
+                }
+            }
+        }
+
+        output.write_all(run)?;
+        written_size = address + run.len() as u64;
+        Ok(())
+    };
+
+    let mut reader = MemoryBlockReader::new(provider, page_size, &memory_chunks);
+    while let Some((address, block)) = reader.next_block()? {
+        let mut run_start = 0;
+        for (index, page) in block.chunks(page_size as usize).enumerate() {
+            if !options.sparse || !is_zero_page(page) {
+                continue;
+            }
+
+            let run = &block[run_start..index * page_size as usize];
+            write_run(output, address + run_start as u64, run)?;
+            statistics.pages_written += run.len() as u64 / page_size;
+            statistics.zero_pages_elided += 1;
+            run_start = (index + 1) * page_size as usize;
+        }
+
+        let run = &block[run_start..];
+        write_run(output, address + run_start as u64, run)?;
+        statistics.pages_written += run.len() as u64 / page_size;
+        statistics.bytes_written = address + block.len() as u64;
+        tracker.advance(block.len() as u64)?;
+    }
+
+    // The image must still extend up to its end when it ends with skipped pages
+    if written_size < statistics.bytes_written {
+        output.seek(SeekFrom::Start(start + statistics.bytes_written - 1))?;
+        output.write_all(&[0])?;
+    }
+
+    output.flush()?;
+    Ok(statistics)
+}
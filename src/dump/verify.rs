@@ -0,0 +1,544 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Verification of written dump files against the saved state they were converted from.
+//! Dumps are read back with the reader of their format, and every stored page and register
+//! is compared with the one in the saved state.
+
+use crate::dump::elf::read_elf_core_layout;
+use crate::dump::kdump::{decompress_page, read_kdump_layout};
+use crate::dump::lime::read_lime_image_layout;
+use crate::dump::windows::{
+    addressable_memory_chunks, read_windows_crash_dump_layout, WindowsCrashDumpOptions,
+};
+use crate::dump::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+/// Count of kdump page descriptors read from the dump at once.
+const DESCRIPTOR_BATCH: usize = 256;
+const PAGE_DESCRIPTOR_SIZE: usize = 24;
+
+/// Run of consecutive guest pages stored back to back in a dump file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct StoredRun {
+    pub(crate) first_page_index: u64,
+    pub(crate) page_count: u64,
+    pub(crate) offset: u64,
+}
+
+/// Pages stored in a kdump-compressed dump, each described by a page descriptor.
+/// Descriptors are stored in the order of the pages set in the dumped pages bitmap.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct KdumpPages {
+    pub(crate) present_pages: Vec<u8>,
+    pub(crate) dumped_pages: Vec<u8>,
+    pub(crate) descriptors_offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum StoredPages {
+    Runs(Vec<StoredRun>),
+    Kdump(KdumpPages),
+}
+
+/// Register value stored in a dump file, along with its size in bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct StoredRegister {
+    pub(crate) vp_id: u32,
+    pub(crate) register: Register,
+    pub(crate) value: u64,
+    pub(crate) size: usize,
+}
+
+/// Where the pages and registers of a dump file are stored, as decoded by the reader of its format.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct DumpLayout {
+    pub(crate) pages: StoredPages,
+    pub(crate) registers: Vec<StoredRegister>,
+    /// Index of the first page the format can't store, for formats that only describe
+    /// the memory a guest can address. Pages past it are left out of the dump on purpose.
+    pub(crate) page_index_limit: Option<u64>,
+    /// Whether the format leaves pages filled with zeros out of the dump. Pages of formats that
+    /// store every page are reported as missing when they aren't found, even if they're zeros.
+    pub(crate) zero_pages_elided: bool,
+}
+
+/// Reason a guest page doesn't match the saved state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PageMismatchKind {
+    /// The page holds data in the saved state, but isn't stored in the dump.
+    Missing,
+    /// The page is stored past the end of the dump file.
+    Truncated,
+    /// The page is stored in the dump, but can't be decoded.
+    Unreadable,
+    /// The contents of the page differ, identified by their FNV-1a hashes.
+    ContentDiffers {
+        expected_hash: u64,
+        actual_hash: u64,
+    },
+}
+
+/// Range of consecutive guest pages that don't match the saved state for the same reason.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PageMismatch {
+    pub first_page_index: u64,
+    pub page_count: u64,
+    pub kind: PageMismatchKind,
+}
+
+/// Register whose value stored in the dump differs from the one in the saved state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RegisterMismatch {
+    pub vp_id: u32,
+    pub register: Register,
+    /// Value in the saved state, truncated to the size the dump stores it with.
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Result of verifying a dump file against the saved state it was converted from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct VerificationReport {
+    /// Count of guest pages stored in the dump that match the saved state.
+    pub pages_verified: u64,
+    /// Count of guest pages left out of the dump on purpose, either because they're filled
+    /// with zeros in a format that elides them or because the format marks them as excluded.
+    pub pages_excluded: u64,
+    pub page_mismatches: Vec<PageMismatch>,
+    /// Count of registers stored in the dump that match the saved state.
+    pub registers_verified: u64,
+    pub register_mismatches: Vec<RegisterMismatch>,
+    /// Virtual processors whose registers should be stored in the dump, but aren't.
+    pub missing_vp_ids: Vec<u32>,
+}
+
+impl VerificationReport {
+    /// Returns true if the dump matches the saved state.
+    pub fn is_valid(&self) -> bool {
+        self.page_mismatches.is_empty()
+            && self.register_mismatches.is_empty()
+            && self.missing_vp_ids.is_empty()
+    }
+
+    /// Records a mismatched page, merging it with the previous mismatch if they're consecutive
+    /// and don't carry page specific details.
+    fn push_page_mismatch(&mut self, page_index: u64, kind: PageMismatchKind) {
+        if let Some(last) = self.page_mismatches.last_mut() {
+            let mergeable = match kind {
+                PageMismatchKind::ContentDiffers { .. } => false,
+                _ => last.kind == kind,
+            };
+            if mergeable && last.first_page_index + last.page_count == page_index {
+                last.page_count += 1;
+                return;
+            }
+        }
+
+        self.page_mismatches.push(PageMismatch {
+            first_page_index: page_index,
+            page_count: 1,
+            kind,
+        });
+    }
+}
+
+/// Returns the 64 bit FNV-1a hash of the given page.
+fn page_hash(page: &[u8]) -> u64 {
+    page.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// Reads from the given offset until the buffer is full or the input ends,
+/// returning the count of bytes read.
+fn read_at<R: Read + Seek>(
+    input: &mut R,
+    offset: u64,
+    buffer: &mut [u8],
+) -> VmSavedStateDumpResult<usize> {
+    input.seek(SeekFrom::Start(offset))?;
+    let mut bytes_read = 0;
+    while bytes_read < buffer.len() {
+        match input.read(&mut buffer[bytes_read..]) {
+            Ok(0) => break,
+            Ok(count) => bytes_read += count,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// Reads a region whose size is stored in the dump itself, failing when the region
+/// lies past the end of the input rather than allocating a corrupted size.
+pub(crate) fn read_region<R: Read + Seek>(
+    input: &mut R,
+    offset: u64,
+    size: u64,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    let length = input.seek(SeekFrom::End(0))?;
+    match offset.checked_add(size) {
+        Some(end) if end <= length => {}
+        _ => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+    }
+
+    let mut region = vec![0u8; size as usize];
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(&mut region)?;
+    Ok(region)
+}
+
+fn bit_is_set(bitmap: &[u8], index: u64) -> bool {
+    bitmap
+        .get((index / 8) as usize)
+        .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+}
+
+fn set_bit(bitmap: &mut [u8], index: u64) {
+    if let Some(byte) = bitmap.get_mut((index / 8) as usize) {
+        *byte |= 1 << (index % 8);
+    }
+}
+
+/// Compares the pages of a dump with the saved state, tracking which of them were seen.
+struct PageVerifier<'a> {
+    provider: &'a VmSavedStateDumpProvider,
+    page_size: u64,
+    /// Pages backed by a memory chunk of the saved state.
+    source_pages: Vec<u8>,
+    /// Pages found stored in the dump, or excluded from it.
+    seen_pages: Vec<u8>,
+    source_page: Vec<u8>,
+    report: VerificationReport,
+}
+
+impl<'a> PageVerifier<'a> {
+    fn new(
+        provider: &'a VmSavedStateDumpProvider,
+        page_size: u64,
+        memory_chunks: &[GpaMemoryChunk],
+    ) -> PageVerifier<'a> {
+        let bitmap_size = (align_up(page_index_limit(memory_chunks), 8) / 8) as usize;
+        let mut source_pages = vec![0u8; bitmap_size];
+        for chunk in memory_chunks {
+            let start = chunk.guest_physical_start_page_index;
+            for page_index in start..start + chunk.page_count {
+                set_bit(&mut source_pages, page_index);
+            }
+        }
+
+        PageVerifier {
+            provider,
+            page_size,
+            source_pages,
+            seen_pages: vec![0u8; bitmap_size],
+            source_page: vec![0u8; page_size as usize],
+            report: VerificationReport::default(),
+        }
+    }
+
+    /// Marks a page of the saved state as excluded from the dump.
+    fn exclude(&mut self, page_index: u64) {
+        if bit_is_set(&self.source_pages, page_index) && !bit_is_set(&self.seen_pages, page_index) {
+            set_bit(&mut self.seen_pages, page_index);
+            self.report.pages_excluded += 1;
+        }
+    }
+
+    /// Records a page stored in the dump that can't be read back. Pages that aren't
+    /// part of the saved state are ignored, as they don't hold guest memory.
+    fn mismatch(&mut self, page_index: u64, kind: PageMismatchKind) {
+        if bit_is_set(&self.source_pages, page_index) {
+            set_bit(&mut self.seen_pages, page_index);
+            self.report.push_page_mismatch(page_index, kind);
+        }
+    }
+
+    /// Marks the pages of the saved state at or past the given index as excluded from the dump.
+    fn exclude_from(&mut self, page_index_limit: u64, memory_chunks: &[GpaMemoryChunk]) {
+        for chunk in memory_chunks {
+            let start = std::cmp::max(chunk.guest_physical_start_page_index, page_index_limit);
+            for page_index in start..chunk.guest_physical_start_page_index + chunk.page_count {
+                self.exclude(page_index);
+            }
+        }
+    }
+
+    /// Compares a page stored in the dump with the one in the saved state.
+    fn verify(&mut self, page_index: u64, page: &[u8]) -> VmSavedStateDumpResult<()> {
+        if !bit_is_set(&self.source_pages, page_index) {
+            return Ok(());
+        }
+        set_bit(&mut self.seen_pages, page_index);
+
+        let bytes_read = self
+            .provider
+            .read_guest_physical_address(page_index * self.page_size, &mut self.source_page)?;
+        if bytes_read as usize != self.source_page.len() {
+            return Err(VmSavedStateDumpError::new(ResultCode::Unexpected)
+                .with_address(page_index * self.page_size));
+        }
+
+        if page == &self.source_page[..] {
+            self.report.pages_verified += 1;
+        } else {
+            let kind = PageMismatchKind::ContentDiffers {
+                expected_hash: page_hash(&self.source_page),
+                actual_hash: page_hash(page),
+            };
+            self.report.push_page_mismatch(page_index, kind);
+        }
+        Ok(())
+    }
+
+    /// Compares the runs of pages stored back to back in the dump.
+    fn verify_runs<R: Read + Seek>(
+        &mut self,
+        input: &mut R,
+        runs: &[StoredRun],
+    ) -> VmSavedStateDumpResult<()> {
+        let mut block = vec![0u8; (STREAM_BLOCK_PAGES * self.page_size) as usize];
+        for run in runs {
+            let mut page = 0;
+            while page < run.page_count {
+                let page_count = std::cmp::min(STREAM_BLOCK_PAGES, run.page_count - page);
+                let block = &mut block[..(page_count * self.page_size) as usize];
+                let bytes_read = read_at(input, run.offset + page * self.page_size, block)?;
+
+                for (index, stored_page) in block.chunks(self.page_size as usize).enumerate() {
+                    let page_index = run.first_page_index + page + index as u64;
+                    if (index + 1) * self.page_size as usize <= bytes_read {
+                        self.verify(page_index, stored_page)?;
+                    } else {
+                        self.mismatch(page_index, PageMismatchKind::Truncated);
+                    }
+                }
+
+                page += page_count;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compares the pages described by the page descriptors of a kdump-compressed dump.
+    fn verify_kdump_pages<R: Read + Seek>(
+        &mut self,
+        input: &mut R,
+        pages: &KdumpPages,
+    ) -> VmSavedStateDumpResult<()> {
+        let mut descriptors = vec![0u8; DESCRIPTOR_BATCH * PAGE_DESCRIPTOR_SIZE];
+        let mut descriptor_count = 0;
+        let mut descriptor_index = 0;
+        let mut descriptors_read = 0;
+        let mut data = Vec::new();
+
+        let page_index_limit = pages.dumped_pages.len() as u64 * 8;
+        for page_index in 0..page_index_limit {
+            if !bit_is_set(&pages.dumped_pages, page_index) {
+                if bit_is_set(&pages.present_pages, page_index) {
+                    self.exclude(page_index);
+                }
+                continue;
+            }
+
+            if descriptor_index == descriptor_count {
+                let offset =
+                    pages.descriptors_offset + (descriptors_read * PAGE_DESCRIPTOR_SIZE) as u64;
+                descriptor_count = read_at(input, offset, &mut descriptors)? / PAGE_DESCRIPTOR_SIZE;
+                descriptor_index = 0;
+                if descriptor_count == 0 {
+                    self.mismatch(page_index, PageMismatchKind::Truncated);
+                    continue;
+                }
+            }
+
+            let descriptor = &descriptors[descriptor_index * PAGE_DESCRIPTOR_SIZE..];
+            descriptor_index += 1;
+            descriptors_read += 1;
+            let offset = get_u64(descriptor, 0x0);
+            let size = get_u32(descriptor, 0x8) as usize;
+            let flags = get_u32(descriptor, 0xC);
+
+            data.resize(size, 0);
+            if read_at(input, offset, &mut data)? != size {
+                self.mismatch(page_index, PageMismatchKind::Truncated);
+                continue;
+            }
+
+            match decompress_page(flags, &data, self.page_size as usize) {
+                Some(page) => self.verify(page_index, &page)?,
+                None => self.mismatch(page_index, PageMismatchKind::Unreadable),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the pages of the saved state that weren't found in the dump, which is only
+    /// expected of pages filled with zeros, and only when the format elides them.
+    fn finish(
+        mut self,
+        memory_chunks: &[GpaMemoryChunk],
+        zero_pages_elided: bool,
+    ) -> VmSavedStateDumpResult<VerificationReport> {
+        let mut reader = MemoryBlockReader::new(self.provider, self.page_size, memory_chunks);
+        while let Some((address, block)) = reader.next_block()? {
+            for (index, page) in block.chunks(self.page_size as usize).enumerate() {
+                let page_index = address / self.page_size + index as u64;
+                if bit_is_set(&self.seen_pages, page_index) {
+                    continue;
+                }
+
+                if zero_pages_elided && is_zero_page(page) {
+                    self.report.pages_excluded += 1;
+                } else {
+                    self.report
+                        .push_page_mismatch(page_index, PageMismatchKind::Missing);
+                }
+            }
+        }
+
+        Ok(self.report)
+    }
+}
+
+/// Compares the pages and registers of a dump with the saved state. The registers of the given
+/// virtual processors are expected to be stored in the dump.
+fn verify_layout<R: Read + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    input: &mut R,
+    layout: &DumpLayout,
+    register_vp_ids: &[u32],
+) -> VmSavedStateDumpResult<VerificationReport> {
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    let mut verifier = PageVerifier::new(provider, page_size, &memory_chunks);
+    match &layout.pages {
+        StoredPages::Runs(runs) => verifier.verify_runs(input, runs)?,
+        StoredPages::Kdump(pages) => verifier.verify_kdump_pages(input, pages)?,
+    }
+    let mut report = match layout.page_index_limit {
+        Some(page_index_limit) => {
+            verifier.exclude_from(page_index_limit, &memory_chunks);
+            verifier.finish(
+                &addressable_memory_chunks(&memory_chunks, page_index_limit),
+                layout.zero_pages_elided,
+            )?
+        }
+        None => verifier.finish(&memory_chunks, layout.zero_pages_elided)?,
+    };
+
+    for stored in &layout.registers {
+        if stored.vp_id >= provider.vp_count()? {
+            continue;
+        }
+
+        let mask = match stored.size {
+            8 => u64::MAX,
+            size => (1 << (size * 8)) - 1,
+        };
+        let expected = provider
+            .get_vp_register_value(stored.vp_id, stored.register)?
+            .value
+            & mask;
+        if expected == stored.value {
+            report.registers_verified += 1;
+        } else {
+            report.register_mismatches.push(RegisterMismatch {
+                vp_id: stored.vp_id,
+                register: stored.register,
+                expected,
+                actual: stored.value,
+            });
+        }
+    }
+
+    report.missing_vp_ids = register_vp_ids
+        .iter()
+        .filter(|vp_id| {
+            !layout
+                .registers
+                .iter()
+                .any(|stored| stored.vp_id == **vp_id)
+        })
+        .copied()
+        .collect();
+
+    Ok(report)
+}
+
+/// Verifies a Windows kernel crash dump written with the given options.
+/// The registers of the virtual processor whose context is stored in the header are verified.
+/// Pages of 32-bit dumps past the physical memory the guest can address are counted as excluded.
+pub fn verify_windows_crash_dump<R: Read + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    input: &mut R,
+    options: &WindowsCrashDumpOptions,
+) -> VmSavedStateDumpResult<VerificationReport> {
+    let layout = read_windows_crash_dump_layout(input, options.context_vp_id)?;
+    verify_layout(provider, input, &layout, &[options.context_vp_id])
+}
+
+/// Verifies an ELF core file, along with the registers of every virtual processor.
+pub fn verify_elf_core<R: Read + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    input: &mut R,
+) -> VmSavedStateDumpResult<VerificationReport> {
+    let (page_size, _) = provider.guest_physical_memory_chunks()?;
+    let layout = read_elf_core_layout(input, page_size)?;
+    let vp_ids: Vec<u32> = (0..provider.vp_count()?).collect();
+    verify_layout(provider, input, &layout, &vp_ids)
+}
+
+/// Verifies a kdump-compressed dump, along with the registers of every virtual processor.
+/// Pages compressed with an algorithm whose feature isn't enabled are reported as unreadable.
+pub fn verify_kdump<R: Read + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    input: &mut R,
+) -> VmSavedStateDumpResult<VerificationReport> {
+    let layout = read_kdump_layout(input)?;
+    let vp_ids: Vec<u32> = (0..provider.vp_count()?).collect();
+    verify_layout(provider, input, &layout, &vp_ids)
+}
+
+/// Verifies a LiME image.
+pub fn verify_lime_image<R: Read + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    input: &mut R,
+) -> VmSavedStateDumpResult<VerificationReport> {
+    let (page_size, _) = provider.guest_physical_memory_chunks()?;
+    let layout = read_lime_image_layout(input, page_size)?;
+    verify_layout(provider, input, &layout, &[])
+}
+
+/// Verifies a raw image, where every guest page is stored at its guest physical address.
+pub fn verify_raw_image<R: Read + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    input: &mut R,
+) -> VmSavedStateDumpResult<VerificationReport> {
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    let runs = memory_chunks
+        .iter()
+        .map(|chunk| StoredRun {
+            first_page_index: chunk.guest_physical_start_page_index,
+            page_count: chunk.page_count,
+            offset: chunk.guest_physical_start_page_index * page_size,
+        })
+        .collect();
+    // The pages skipped by sparse images read back as zeros, so every page is found
+    let layout = DumpLayout {
+        pages: StoredPages::Runs(runs),
+        registers: Vec::new(),
+        page_index_limit: None,
+        zero_pages_elided: false,
+    };
+    verify_layout(provider, input, &layout, &[])
+}
@@ -0,0 +1,1724 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! Writer of Windows kernel crash dump files (MEMORY.DMP) that can be loaded by WinDbg,
+//! along with the reader used to verify them.
+
+use crate::descriptors::*;
+use crate::dump::verify::*;
+use crate::dump::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops;
+
+pub(crate) const PAGE_SIGNATURE: u32 = 0x4547_4150; // "PAGE"
+pub(crate) const DUMP32_SIGNATURE: u32 = 0x504D_5544; // "DUMP"
+pub(crate) const DUMP64_SIGNATURE: u32 = 0x3436_5544; // "DU64"
+const FULL_DUMP_SIGNATURE: u32 = 0x504D_4446; // "FDMP"
+const VALID_DUMP_SIGNATURE: u32 = 0x504D_5544; // "DUMP"
+const KDBG_TAG: u32 = 0x4742_444B; // "KDBG"
+
+const DUMP_MAJOR_VERSION_FREE_BUILD: u32 = 0xF;
+const IMAGE_FILE_MACHINE_I386: u32 = 0x14C;
+const IMAGE_FILE_MACHINE_AMD64: u32 = 0x8664;
+
+/// Size of the header of a 32-bit crash dump.
+const DUMP_HEADER32_SIZE: usize = 0x1000;
+/// Maximum count of physical memory runs that fit in the header of a 32-bit crash dump.
+const DUMP_HEADER32_MAX_RUNS: usize = 86;
+
+/// Size of the header of a 64-bit crash dump.
+const DUMP_HEADER64_SIZE: usize = 0x2000;
+/// Maximum count of physical memory runs that fit in the header of a 64-bit crash dump.
+const DUMP_HEADER64_MAX_RUNS: usize = 42;
+
+mod header32 {
+    pub const MAJOR_VERSION: usize = 0x8;
+    pub const MINOR_VERSION: usize = 0xC;
+    pub const DIRECTORY_TABLE_BASE: usize = 0x10;
+    pub const PFN_DATA_BASE: usize = 0x14;
+    pub const PS_LOADED_MODULE_LIST: usize = 0x18;
+    pub const PS_ACTIVE_PROCESS_HEAD: usize = 0x1C;
+    pub const MACHINE_IMAGE_TYPE: usize = 0x20;
+    pub const NUMBER_PROCESSORS: usize = 0x24;
+    pub const BUGCHECK_CODE: usize = 0x28;
+    pub const BUGCHECK_PARAMETERS: usize = 0x2C;
+    pub const VERSION_USER: usize = 0x3C;
+    pub const PAE_ENABLED: usize = 0x5C;
+    pub const KD_DEBUGGER_DATA_BLOCK: usize = 0x60;
+    pub const PHYSICAL_MEMORY_BLOCK: usize = 0x64;
+    pub const CONTEXT: usize = 0x320;
+    pub const EXCEPTION: usize = 0x7D0;
+    pub const COMMENT: usize = 0x820;
+    pub const COMMENT_END: usize = 0x8A0;
+    pub const DUMP_TYPE: usize = 0xF88;
+    pub const PRODUCT_TYPE: usize = 0xF94;
+    pub const SUITE_MASK: usize = 0xF98;
+    pub const REQUIRED_DUMP_SPACE: usize = 0xFA0;
+    pub const SYSTEM_UP_TIME: usize = 0xFB8;
+    pub const SYSTEM_TIME: usize = 0xFC0;
+}
+
+mod header64 {
+    pub const MAJOR_VERSION: usize = 0x8;
+    pub const MINOR_VERSION: usize = 0xC;
+    pub const DIRECTORY_TABLE_BASE: usize = 0x10;
+    pub const PFN_DATA_BASE: usize = 0x18;
+    pub const PS_LOADED_MODULE_LIST: usize = 0x20;
+    pub const PS_ACTIVE_PROCESS_HEAD: usize = 0x28;
+    pub const MACHINE_IMAGE_TYPE: usize = 0x30;
+    pub const NUMBER_PROCESSORS: usize = 0x34;
+    pub const BUGCHECK_CODE: usize = 0x38;
+    pub const BUGCHECK_PARAMETERS: usize = 0x40;
+    pub const VERSION_USER: usize = 0x60;
+    pub const KD_DEBUGGER_DATA_BLOCK: usize = 0x80;
+    pub const PHYSICAL_MEMORY_BLOCK: usize = 0x88;
+    pub const CONTEXT: usize = 0x348;
+    pub const EXCEPTION: usize = 0xF00;
+    pub const DUMP_TYPE: usize = 0xF98;
+    pub const REQUIRED_DUMP_SPACE: usize = 0xFA0;
+    pub const SYSTEM_TIME: usize = 0xFA8;
+    pub const COMMENT: usize = 0xFB0;
+    pub const SYSTEM_UP_TIME: usize = 0x1030;
+    pub const PRODUCT_TYPE: usize = 0x1040;
+    pub const SUITE_MASK: usize = 0x1044;
+}
+
+mod bitmap_header {
+    pub const SIGNATURE: usize = 0x0;
+    pub const VALID_DUMP: usize = 0x4;
+    pub const FIRST_PAGE: usize = 0x20;
+    pub const TOTAL_PRESENT_PAGES: usize = 0x28;
+    pub const PAGES: usize = 0x30;
+    pub const BITMAP: usize = 0x38;
+}
+
+/// Offsets of the fields of the KDDEBUGGER_DATA64 structure used to fill the dump header.
+mod kdbg {
+    pub const OWNER_TAG: usize = 0x10;
+    pub const SIZE: usize = 0x14;
+    pub const KERN_BASE: usize = 0x18;
+    pub const PS_LOADED_MODULE_LIST: usize = 0x48;
+    pub const PS_ACTIVE_PROCESS_HEAD: usize = 0x50;
+    pub const NT_BUILD_LAB: usize = 0x208;
+    pub const MM_PFN_DATABASE: usize = 0xC0;
+    /// Amount of the structure read to extract all of the fields above.
+    pub const READ_SIZE: usize = 0x210;
+}
+
+/// Offsets of the fields of the KUSER_SHARED_DATA structure used to fill the dump header.
+mod kuser_shared_data {
+    pub const ADDRESS_X86: u64 = 0xFFDF_0000;
+    pub const ADDRESS_X64: u64 = 0xFFFF_F780_0000_0000;
+    pub const INTERRUPT_TIME: usize = 0x8;
+    pub const SYSTEM_TIME: usize = 0x14;
+    pub const NT_PRODUCT_TYPE: usize = 0x264;
+    pub const SUITE_MASK: usize = 0x2D0;
+    /// Amount of the structure read to extract all of the fields above.
+    pub const READ_SIZE: usize = 0x2D4;
+}
+
+/// Size of the x86 CONTEXT structure.
+const CONTEXT32_SIZE: usize = 0x2CC;
+/// CONTEXT_i386 | CONTEXT_CONTROL | CONTEXT_INTEGER | CONTEXT_SEGMENTS
+/// | CONTEXT_FLOATING_POINT | CONTEXT_DEBUG_REGISTERS | CONTEXT_EXTENDED_REGISTERS.
+const CONTEXT32_FLAGS: u32 = 0x0001_003F;
+
+mod context32 {
+    pub const CONTEXT_FLAGS: usize = 0x0;
+    pub const FLOAT_SAVE: usize = 0x1C;
+    pub const EIP: usize = 0xB8;
+    pub const EXTENDED_REGISTERS: usize = 0xCC;
+}
+
+/// 32 bit registers stored in the x86 CONTEXT structure, along with their offsets.
+const CONTEXT32_REGISTERS: [(RegisterIdx86, usize); 22] = [
+    (RegisterIdx86::Dr0, 0x4),
+    (RegisterIdx86::Dr1, 0x8),
+    (RegisterIdx86::Dr2, 0xC),
+    (RegisterIdx86::Dr3, 0x10),
+    (RegisterIdx86::Dr6, 0x14),
+    (RegisterIdx86::Dr7, 0x18),
+    (RegisterIdx86::SegGs, 0x8C),
+    (RegisterIdx86::SegFs, 0x90),
+    (RegisterIdx86::SegEs, 0x94),
+    (RegisterIdx86::SegDs, 0x98),
+    (RegisterIdx86::Edi, 0x9C),
+    (RegisterIdx86::Esi, 0xA0),
+    (RegisterIdx86::Ebx, 0xA4),
+    (RegisterIdx86::Edx, 0xA8),
+    (RegisterIdx86::Ecx, 0xAC),
+    (RegisterIdx86::Eax, 0xB0),
+    (RegisterIdx86::Ebp, 0xB4),
+    (RegisterIdx86::Eip, context32::EIP),
+    (RegisterIdx86::SegCs, 0xBC),
+    (RegisterIdx86::EFlags, 0xC0),
+    (RegisterIdx86::Esp, 0xC4),
+    (RegisterIdx86::SegSs, 0xC8),
+];
+
+/// Count of XMM registers available to 32-bit code.
+const XMM_REGISTER_COUNT_X86: usize = 8;
+
+/// Size of the AMD64 CONTEXT structure.
+const CONTEXT64_SIZE: usize = 0x4D0;
+/// CONTEXT_AMD64 | CONTEXT_CONTROL | CONTEXT_INTEGER | CONTEXT_SEGMENTS
+/// | CONTEXT_FLOATING_POINT | CONTEXT_DEBUG_REGISTERS.
+const CONTEXT64_FLAGS: u32 = 0x0010_001F;
+
+mod context64 {
+    pub const CONTEXT_FLAGS: usize = 0x30;
+    pub const MX_CSR: usize = 0x34;
+    pub const EFLAGS: usize = 0x44;
+    pub const RIP: usize = 0xF8;
+    pub const FLT_SAVE: usize = 0x100;
+}
+
+/// Segment selectors stored in the AMD64 CONTEXT structure, along with their offsets.
+const CONTEXT64_SEGMENT_REGISTERS: [(RegisterIdx64, usize); 6] = [
+    (RegisterIdx64::SegCs, 0x38),
+    (RegisterIdx64::SegDs, 0x3A),
+    (RegisterIdx64::SegEs, 0x3C),
+    (RegisterIdx64::SegFs, 0x3E),
+    (RegisterIdx64::SegGs, 0x40),
+    (RegisterIdx64::SegSs, 0x42),
+];
+
+/// 64 bit registers stored in the AMD64 CONTEXT structure, along with their offsets.
+const CONTEXT64_REGISTERS: [(RegisterIdx64, usize); 23] = [
+    (RegisterIdx64::Dr0, 0x48),
+    (RegisterIdx64::Dr1, 0x50),
+    (RegisterIdx64::Dr2, 0x58),
+    (RegisterIdx64::Dr3, 0x60),
+    (RegisterIdx64::Dr6, 0x68),
+    (RegisterIdx64::Dr7, 0x70),
+    (RegisterIdx64::Rax, 0x78),
+    (RegisterIdx64::Rcx, 0x80),
+    (RegisterIdx64::Rdx, 0x88),
+    (RegisterIdx64::Rbx, 0x90),
+    (RegisterIdx64::Rsp, 0x98),
+    (RegisterIdx64::Rbp, 0xA0),
+    (RegisterIdx64::Rsi, 0xA8),
+    (RegisterIdx64::Rdi, 0xB0),
+    (RegisterIdx64::R8, 0xB8),
+    (RegisterIdx64::R9, 0xC0),
+    (RegisterIdx64::R10, 0xC8),
+    (RegisterIdx64::R11, 0xD0),
+    (RegisterIdx64::R12, 0xD8),
+    (RegisterIdx64::R13, 0xE0),
+    (RegisterIdx64::R14, 0xE8),
+    (RegisterIdx64::R15, 0xF0),
+    (RegisterIdx64::Rip, context64::RIP),
+];
+
+/// Kind of Windows kernel crash dump to write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum WindowsDumpType {
+    /// Complete memory dump where the physical memory runs are stored back to back.
+    Full,
+    /// Complete memory dump where the stored pages are described by a bitmap.
+    /// Only supported for 64-bit guests.
+    Bitmap,
+}
+
+impl WindowsDumpType {
+    fn raw_value(self) -> u32 {
+        match self {
+            WindowsDumpType::Full => 1,
+            WindowsDumpType::Bitmap => 5,
+        }
+    }
+}
+
+/// Options that control how a Windows kernel crash dump is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowsCrashDumpOptions {
+    pub dump_type: WindowsDumpType,
+    /// Guest virtual address of the KdDebuggerDataBlock. When `None`,
+    /// the guest physical memory is scanned to find it.
+    pub kd_debugger_data_block: Option<GuestVirtualAddress>,
+    /// Keys the KdDebuggerDataBlock is encoded with, which is needed to read it
+    /// from Windows 8 and later 64-bit guests. Without `kd_debugger_data_block`,
+    /// the memory scan decodes every candidate with them.
+    pub kd_debugger_data_encoding: Option<KdDebuggerDataEncoding>,
+    pub bugcheck_code: u32,
+    pub bugcheck_parameters: [u64; 4],
+    /// Virtual processor whose context is stored in the dump header.
+    /// The context of the rest of the processors is found by the debugger in their KPRCB.
+    pub context_vp_id: u32,
+    /// Comment stored in the dump header, truncated to 127 bytes.
+    pub comment: String,
+    /// Leaves pages filled with zeros out of bitmap dumps. The headers of such dumps are only
+    /// known once every page has been read, so they're written last and the dump can't be resumed.
+    pub exclude_zero_pages: bool,
+}
+
+impl Default for WindowsCrashDumpOptions {
+    fn default() -> Self {
+        WindowsCrashDumpOptions {
+            dump_type: WindowsDumpType::Bitmap,
+            kd_debugger_data_block: None,
+            kd_debugger_data_encoding: None,
+            bugcheck_code: 0,
+            bugcheck_parameters: [0; 4],
+            context_vp_id: 0,
+            comment: String::from("Converted from a VM saved state"),
+            exclude_zero_pages: true,
+        }
+    }
+}
+
+/// Kernel information extracted from the guest's KdDebuggerDataBlock.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KdDebuggerData {
+    pub virtual_address: GuestVirtualAddress,
+    pub kern_base: GuestVirtualAddress,
+    pub ps_loaded_module_list: GuestVirtualAddress,
+    pub ps_active_process_head: GuestVirtualAddress,
+    /// Address of the kernel variable that points to the PFN database.
+    pub mm_pfn_database: GuestVirtualAddress,
+    pub nt_build_lab: GuestVirtualAddress,
+}
+
+impl KdDebuggerData {
+    /// Decodes the raw KDDEBUGGER_DATA64 structure found at the given virtual address.
+    /// Returns `None` if the raw bytes do not look like a KdDebuggerDataBlock.
+    pub fn parse(virtual_address: GuestVirtualAddress, raw: &[u8]) -> Option<KdDebuggerData> {
+        if raw.len() < kdbg::READ_SIZE || get_u32(raw, kdbg::OWNER_TAG) != KDBG_TAG {
+            return None;
+        }
+
+        let size = get_u32(raw, kdbg::SIZE);
+        let kern_base = get_u64(raw, kdbg::KERN_BASE);
+        if !(0x200..0x1000).contains(&size) || kern_base == 0 || kern_base & 0xFFF != 0 {
+            return None;
+        }
+
+        Some(KdDebuggerData {
+            virtual_address,
+            kern_base,
+            ps_loaded_module_list: get_u64(raw, kdbg::PS_LOADED_MODULE_LIST),
+            ps_active_process_head: get_u64(raw, kdbg::PS_ACTIVE_PROCESS_HEAD),
+            mm_pfn_database: get_u64(raw, kdbg::MM_PFN_DATABASE),
+            nt_build_lab: get_u64(raw, kdbg::NT_BUILD_LAB),
+        })
+    }
+}
+
+/// Keys that Windows 8 and later 64-bit kernels encode the KdDebuggerDataBlock with,
+/// unless a kernel debugger is enabled. They are the values of the nt!KiWaitNever and
+/// nt!KiWaitAlways variables, along with the address of nt!KdpDataBlockEncoded,
+/// and can only be located through the symbols of the guest kernel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KdDebuggerDataEncoding {
+    pub wait_never: u64,
+    pub wait_always: u64,
+    pub data_block_encoded: GuestVirtualAddress,
+}
+
+impl KdDebuggerDataEncoding {
+    /// Decodes the raw KDDEBUGGER_DATA64 structure in place, the same way KdCopyDataBlock does.
+    pub fn decode(&self, raw: &mut [u8]) {
+        for entry in raw.chunks_exact_mut(8) {
+            let value = u64::from_le_bytes([
+                entry[0], entry[1], entry[2], entry[3], entry[4], entry[5], entry[6], entry[7],
+            ]);
+            entry.copy_from_slice(&self.decode_entry(value).to_le_bytes());
+        }
+    }
+
+    /// Decodes a single 8 byte entry of the structure, as every entry is encoded on its own.
+    fn decode_entry(&self, value: u64) -> u64 {
+        let value = (value ^ self.wait_never).rotate_left(self.wait_never as u8 as u32)
+            ^ self.data_block_encoded;
+        value.swap_bytes() ^ self.wait_always
+    }
+}
+
+/// Returns the given kernel virtual address in the form the virtual processor translates it.
+/// 32-bit kernel structures store their pointers sign extended to 64 bits.
+fn kernel_virtual_address(
+    architecture: VirtualProcessorArch,
+    virtual_address: GuestVirtualAddress,
+) -> GuestVirtualAddress {
+    match architecture {
+        VirtualProcessorArch::X86 => virtual_address & 0xFFFF_FFFF,
+        _ => virtual_address,
+    }
+}
+
+/// Reads guest kernel memory through the given virtual processor.
+fn read_kernel_memory(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    virtual_address: GuestVirtualAddress,
+    buffer: &mut [u8],
+) -> VmSavedStateDumpResult<u32> {
+    let architecture = provider.get_vp_architecture(vp_id)?;
+    provider.read_guest_virtual_address(
+        vp_id,
+        kernel_virtual_address(architecture, virtual_address),
+        buffer,
+    )
+}
+
+/// Returns the size of the PE image loaded at the given virtual address, if any.
+fn image_size(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    image_base: GuestVirtualAddress,
+) -> Option<u64> {
+    let mut headers = vec![0u8; 0x1000];
+    match read_kernel_memory(provider, vp_id, image_base, &mut headers) {
+        Ok(bytes_read) if bytes_read as usize == headers.len() => {}
+        _ => return None,
+    }
+
+    if get_u16(&headers, 0) != 0x5A4D {
+        return None;
+    }
+
+    // SizeOfImage lives in the optional header, which follows the PE signature and the file header
+    let nt_headers = get_u32(&headers, 0x3C) as usize;
+    let size_of_image = nt_headers + 0x18 + 0x38;
+    if size_of_image + 4 > headers.len() || get_u32(&headers, nt_headers) != 0x0000_4550 {
+        return None;
+    }
+
+    Some(u64::from(get_u32(&headers, size_of_image)))
+}
+
+/// Finds the virtual address that maps the given physical address within the kernel image.
+fn kernel_image_virtual_address(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    kern_base: GuestVirtualAddress,
+    physical_address: GuestPhysicalAddress,
+) -> Option<GuestVirtualAddress> {
+    let architecture = provider.get_vp_architecture(vp_id).ok()?;
+    let size = image_size(provider, vp_id, kern_base)?;
+    let page_offset = physical_address % 0x1000;
+
+    (kern_base..kern_base.wrapping_add(size))
+        .step_by(0x1000)
+        .find(|page| {
+            provider.guest_virtual_to_physical_address(
+                vp_id,
+                kernel_virtual_address(architecture, *page),
+            ) == Ok(physical_address - page_offset)
+        })
+        .map(|page| page + page_offset)
+}
+
+/// Reads and decodes the KdDebuggerDataBlock found at the given virtual address.
+pub fn read_kd_debugger_data(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    virtual_address: GuestVirtualAddress,
+) -> VmSavedStateDumpResult<KdDebuggerData> {
+    read_kd_debugger_data_with(provider, vp_id, virtual_address, None)
+}
+
+/// Reads, decodes with the given keys and parses the KdDebuggerDataBlock found
+/// at the given virtual address.
+pub fn read_encoded_kd_debugger_data(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    virtual_address: GuestVirtualAddress,
+    encoding: &KdDebuggerDataEncoding,
+) -> VmSavedStateDumpResult<KdDebuggerData> {
+    read_kd_debugger_data_with(provider, vp_id, virtual_address, Some(encoding))
+}
+
+fn read_kd_debugger_data_with(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    virtual_address: GuestVirtualAddress,
+    encoding: Option<&KdDebuggerDataEncoding>,
+) -> VmSavedStateDumpResult<KdDebuggerData> {
+    let mut raw = vec![0u8; kdbg::READ_SIZE];
+    let bytes_read = read_kernel_memory(provider, vp_id, virtual_address, &mut raw)?;
+    raw.truncate(bytes_read as usize);
+    if let Some(encoding) = encoding {
+        encoding.decode(&mut raw);
+    }
+
+    KdDebuggerData::parse(virtual_address, &raw)
+        .ok_or_else(|| ResultCode::KdDebuggerDataBlockNotFound.into())
+}
+
+/// Scans the guest physical memory looking for the KdDebuggerDataBlock, and returns it once
+/// its virtual address has been resolved through the kernel image mapped by the given virtual processor.
+/// Only plaintext blocks are found, so this fails on Windows 8 and later 64-bit guests that
+/// encode theirs; those are found by `find_encoded_kd_debugger_data_block`.
+pub fn find_kd_debugger_data_block(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+) -> VmSavedStateDumpResult<KdDebuggerData> {
+    scan_kd_debugger_data_block(provider, vp_id, None)
+}
+
+/// Scans the guest physical memory looking for a KdDebuggerDataBlock encoded with the given keys,
+/// decoding every candidate before checking its tag.
+pub fn find_encoded_kd_debugger_data_block(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    encoding: &KdDebuggerDataEncoding,
+) -> VmSavedStateDumpResult<KdDebuggerData> {
+    scan_kd_debugger_data_block(provider, vp_id, Some(encoding))
+}
+
+fn scan_kd_debugger_data_block(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    encoding: Option<&KdDebuggerDataEncoding>,
+) -> VmSavedStateDumpResult<KdDebuggerData> {
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    let mut reader = MemoryBlockReader::new(provider, page_size, &memory_chunks);
+    let mut candidate = vec![0u8; kdbg::READ_SIZE];
+
+    while let Some((address, block)) = reader.next_block()? {
+        for tag_offset in (0..block.len()).step_by(8) {
+            // The tag shares its entry with the size, so only the low half is compared
+            let entry = get_u64(block, tag_offset);
+            let entry = encoding.map_or(entry, |encoding| encoding.decode_entry(entry));
+            if entry as u32 != KDBG_TAG {
+                continue;
+            }
+
+            let physical_address =
+                match (address + tag_offset as u64).checked_sub(kdbg::OWNER_TAG as u64) {
+                    Some(physical_address) => physical_address,
+                    None => continue,
+                };
+            match provider.read_guest_physical_address(physical_address, &mut candidate) {
+                Ok(bytes_read) if bytes_read as usize == candidate.len() => {}
+                _ => continue,
+            }
+            if let Some(encoding) = encoding {
+                encoding.decode(&mut candidate);
+            }
+
+            let kern_base = match KdDebuggerData::parse(0, &candidate) {
+                Some(data) => data.kern_base,
+                None => continue,
+            };
+
+            if let Some(virtual_address) =
+                kernel_image_virtual_address(provider, vp_id, kern_base, physical_address)
+            {
+                if let Ok(data) =
+                    read_kd_debugger_data_with(provider, vp_id, virtual_address, encoding)
+                {
+                    return Ok(data);
+                }
+            }
+        }
+    }
+
+    Err(ResultCode::KdDebuggerDataBlockNotFound.into())
+}
+
+/// Returns the range of virtual addresses the kernel image described by the
+/// KdDebuggerDataBlock is loaded at, sized by the SizeOfImage of its PE headers.
+pub fn kernel_image_range(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    kd_debugger_data: &KdDebuggerData,
+) -> VmSavedStateDumpResult<ops::Range<GuestVirtualAddress>> {
+    let architecture = provider.get_vp_architecture(vp_id)?;
+    let kern_base = kernel_virtual_address(architecture, kd_debugger_data.kern_base);
+    let size = image_size(provider, vp_id, kern_base).ok_or(ResultCode::Fail)?;
+    Ok(kern_base..kern_base.saturating_add(size))
+}
+
+/// Locates the kernel image of the guest and returns, for every virtual processor,
+/// the present interrupt and trap gates of its IDT whose handler lies outside of it.
+/// Hooked interrupt handlers commonly point outside of the kernel image.
+pub fn find_interrupt_handlers_outside_kernel(
+    provider: &VmSavedStateDumpProvider,
+) -> VmSavedStateDumpResult<Vec<(u32, Vec<GateDescriptor>)>> {
+    let vp_id = kernel_mode_vp_id(provider)?;
+    let kd_debugger_data = find_kd_debugger_data_block(provider, vp_id)?;
+    let kernel_image = kernel_image_range(provider, vp_id, &kd_debugger_data)?;
+
+    (0..provider.vp_count()?)
+        .map(|vp_id| {
+            let idt = provider.get_vp_idt(vp_id)?;
+            let gates = idt.handlers_outside(&kernel_image).copied().collect();
+            Ok((vp_id, gates))
+        })
+        .collect()
+}
+
+/// Kernel state used to fill the dump header that is not part of the KdDebuggerDataBlock.
+#[derive(Debug, Default)]
+struct KernelState {
+    directory_table_base: u64,
+    pae_enabled: bool,
+    pfn_database: u64,
+    build_number: u32,
+    system_time: u64,
+    system_up_time: u64,
+    product_type: u32,
+    suite_mask: u32,
+}
+
+/// Reads a KSYSTEM_TIME structure, ignoring its second copy of the high part.
+fn system_time(buffer: &[u8], offset: usize) -> u64 {
+    u64::from(get_u32(buffer, offset + 4)) << 32 | u64::from(get_u32(buffer, offset))
+}
+
+/// Parses the build number that prefixes the NtBuildLab string, such as "17763.1.amd64fre...".
+fn read_build_number(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    nt_build_lab: GuestVirtualAddress,
+) -> u32 {
+    let mut build_lab = [0u8; 16];
+    if read_kernel_memory(provider, vp_id, nt_build_lab, &mut build_lab).is_err() {
+        return 0;
+    }
+
+    build_lab
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .fold(0u32, |number, digit| {
+            number
+                .wrapping_mul(10)
+                .wrapping_add(u32::from(digit - b'0'))
+        })
+}
+
+/// Gathers the kernel state using the given virtual processor to translate virtual addresses.
+/// Values that can't be read from the guest, or need a missing KdDebuggerDataBlock, are left as 0.
+fn read_kernel_state(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    kd_debugger_data: Option<&KdDebuggerData>,
+) -> VmSavedStateDumpResult<KernelState> {
+    let architecture = provider.get_vp_architecture(vp_id)?;
+    let cr3 = provider.get_vp_control_registers(vp_id)?.cr3;
+    let pae_enabled = provider.get_vp_paging_mode(vp_id)? == PagingMode::Pae;
+    let (directory_table_base, pointer_size, shared_data_address) = match architecture {
+        VirtualProcessorArch::X64 => (
+            cr3 & 0x000F_FFFF_FFFF_F000,
+            8,
+            kuser_shared_data::ADDRESS_X64,
+        ),
+        _ if pae_enabled => (cr3 & 0xFFFF_FFE0, 4, kuser_shared_data::ADDRESS_X86),
+        _ => (cr3 & 0xFFFF_F000, 4, kuser_shared_data::ADDRESS_X86),
+    };
+
+    let mut state = KernelState {
+        directory_table_base,
+        pae_enabled,
+        ..Default::default()
+    };
+
+    // The build number and PFN database are only known through the KdDebuggerDataBlock
+    if let Some(kd_debugger_data) = kd_debugger_data {
+        state.build_number = read_build_number(provider, vp_id, kd_debugger_data.nt_build_lab);
+
+        let mut pfn_database = [0u8; 8];
+        if let Ok(bytes_read) = read_kernel_memory(
+            provider,
+            vp_id,
+            kd_debugger_data.mm_pfn_database,
+            &mut pfn_database[..pointer_size],
+        ) {
+            if bytes_read as usize == pointer_size {
+                state.pfn_database = u64::from_le_bytes(pfn_database);
+            }
+        }
+    }
+
+    let mut shared_data = vec![0u8; kuser_shared_data::READ_SIZE];
+    match read_kernel_memory(provider, vp_id, shared_data_address, &mut shared_data) {
+        Ok(bytes_read) if bytes_read as usize == shared_data.len() => {
+            state.system_time = system_time(&shared_data, kuser_shared_data::SYSTEM_TIME);
+            state.system_up_time = system_time(&shared_data, kuser_shared_data::INTERRUPT_TIME);
+            state.product_type = get_u32(&shared_data, kuser_shared_data::NT_PRODUCT_TYPE);
+            state.suite_mask = get_u32(&shared_data, kuser_shared_data::SUITE_MASK);
+        }
+        _ => {}
+    }
+
+    Ok(state)
+}
+
+/// Stores the legacy floating point and SSE state of a virtual processor in the FXSAVE layout.
+/// The instruction and data pointers carry their selectors in bits 32 to 47 for 32-bit guests,
+/// which matches the 32-bit FXSAVE layout.
+fn put_fxsave_area(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+    buffer: &mut [u8],
+    xmm_register_count: usize,
+) -> VmSavedStateDumpResult<()> {
+    let fp = provider.get_vp_fp_control_status(vp_id)?;
+    let xmm = provider.get_vp_xmm_control_status(vp_id)?;
+    put_u16(buffer, 0x0, fp.control.raw());
+    put_u16(buffer, 0x2, fp.status.raw());
+    buffer[0x4] = fp.tag;
+    put_u16(buffer, 0x6, fp.last_opcode);
+    put_u64(buffer, 0x8, fp.last_instruction_pointer);
+    put_u64(buffer, 0x10, xmm.last_data_pointer);
+    put_u32(buffer, 0x18, xmm.mxcsr.raw());
+    put_u32(buffer, 0x1C, xmm.mxcsr_mask);
+
+    for xmm_index in 0..xmm_register_count {
+        let offset = 0xA0 + xmm_index * 16;
+        let value = provider.get_vp_xmm_register(vp_id, xmm_index as u8)?;
+        buffer[offset..offset + 16].copy_from_slice(&value.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Builds the x86 CONTEXT structure of the given virtual processor.
+fn build_context32(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    let mut context = vec![0u8; CONTEXT32_SIZE];
+    put_u32(&mut context, context32::CONTEXT_FLAGS, CONTEXT32_FLAGS);
+
+    for (register_id, offset) in CONTEXT32_REGISTERS.iter() {
+        let value = provider
+            .get_vp_register_value(vp_id, Register::X86(*register_id))?
+            .value;
+        put_u32(&mut context, *offset, value as u32);
+    }
+
+    put_fxsave_area(
+        provider,
+        vp_id,
+        &mut context[context32::EXTENDED_REGISTERS..],
+        XMM_REGISTER_COUNT_X86,
+    )?;
+
+    // FLOATING_SAVE_AREA holds the FSAVE view of the same state, with the full tag word
+    // where every register without a valid abridged tag bit is marked as empty
+    let fp = provider.get_vp_fp_control_status(vp_id)?;
+    let xmm = provider.get_vp_xmm_control_status(vp_id)?;
+    let tag_word = (0..8).fold(0u32, |tag_word, index| {
+        if fp.tag & (1 << index) == 0 {
+            tag_word | 0b11 << (index * 2)
+        } else {
+            tag_word
+        }
+    });
+    let float_save = context32::FLOAT_SAVE;
+    put_u32(&mut context, float_save, u32::from(fp.control.raw()));
+    put_u32(&mut context, float_save + 0x4, u32::from(fp.status.raw()));
+    put_u32(&mut context, float_save + 0x8, tag_word);
+    put_u32(
+        &mut context,
+        float_save + 0xC,
+        fp.last_instruction_pointer as u32,
+    );
+    put_u32(
+        &mut context,
+        float_save + 0x10,
+        (fp.last_instruction_pointer >> 32) as u16 as u32 | u32::from(fp.last_opcode) << 16,
+    );
+    put_u32(
+        &mut context,
+        float_save + 0x14,
+        xmm.last_data_pointer as u32,
+    );
+    put_u32(
+        &mut context,
+        float_save + 0x18,
+        (xmm.last_data_pointer >> 32) as u16 as u32,
+    );
+
+    Ok(context)
+}
+
+/// Builds the AMD64 CONTEXT structure of the given virtual processor.
+fn build_context64(
+    provider: &VmSavedStateDumpProvider,
+    vp_id: u32,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    let mut context = vec![0u8; CONTEXT64_SIZE];
+    let register_value = |register_id: RegisterIdx64| {
+        provider
+            .get_vp_register_value(vp_id, Register::X64(register_id))
+            .map(|register| register.value)
+    };
+
+    put_u32(&mut context, context64::CONTEXT_FLAGS, CONTEXT64_FLAGS);
+
+    for (register_id, offset) in CONTEXT64_SEGMENT_REGISTERS.iter() {
+        put_u16(&mut context, *offset, register_value(*register_id)? as u16);
+    }
+
+    for (register_id, offset) in CONTEXT64_REGISTERS.iter() {
+        put_u64(&mut context, *offset, register_value(*register_id)?);
+    }
+
+    put_u32(
+        &mut context,
+        context64::EFLAGS,
+        register_value(RegisterIdx64::RFlags)? as u32,
+    );
+
+    put_fxsave_area(
+        provider,
+        vp_id,
+        &mut context[context64::FLT_SAVE..],
+        XMM_REGISTERS_X64.len(),
+    )?;
+    let mxcsr = get_u32(&context, context64::FLT_SAVE + 0x18);
+    put_u32(&mut context, context64::MX_CSR, mxcsr);
+
+    Ok(context)
+}
+
+/// Returns a dump header buffer of the given size filled with the "PAGE" pattern,
+/// which is what the fields that are not explicitly written hold.
+fn page_filled_header(size: usize, valid_dump_signature: u32) -> Vec<u8> {
+    let mut header = vec![0u8; size];
+    for offset in (0..size).step_by(4) {
+        put_u32(&mut header, offset, PAGE_SIGNATURE);
+    }
+
+    put_u32(&mut header, 0x4, valid_dump_signature);
+    header
+}
+
+/// Stores a null terminated comment, truncating it to fit the given buffer.
+fn put_comment(buffer: &mut [u8], comment: &str) {
+    buffer.iter_mut().for_each(|byte| *byte = 0);
+    let comment_length = std::cmp::min(comment.len(), buffer.len() - 1);
+    buffer[..comment_length].copy_from_slice(&comment.as_bytes()[..comment_length]);
+}
+
+/// Builds the DUMP_HEADER32 structure of a crash dump.
+fn build_header32(
+    provider: &VmSavedStateDumpProvider,
+    options: &WindowsCrashDumpOptions,
+    kd_debugger_data: &KdDebuggerData,
+    kernel_state: &KernelState,
+    memory_chunks: &[GpaMemoryChunk],
+    required_dump_space: u64,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    let mut header = page_filled_header(DUMP_HEADER32_SIZE, DUMP32_SIGNATURE);
+
+    put_u32(
+        &mut header,
+        header32::MAJOR_VERSION,
+        DUMP_MAJOR_VERSION_FREE_BUILD,
+    );
+    put_u32(
+        &mut header,
+        header32::MINOR_VERSION,
+        kernel_state.build_number,
+    );
+    put_u32(
+        &mut header,
+        header32::DIRECTORY_TABLE_BASE,
+        kernel_state.directory_table_base as u32,
+    );
+    put_u32(
+        &mut header,
+        header32::PFN_DATA_BASE,
+        kernel_state.pfn_database as u32,
+    );
+    put_u32(
+        &mut header,
+        header32::PS_LOADED_MODULE_LIST,
+        kd_debugger_data.ps_loaded_module_list as u32,
+    );
+    put_u32(
+        &mut header,
+        header32::PS_ACTIVE_PROCESS_HEAD,
+        kd_debugger_data.ps_active_process_head as u32,
+    );
+    put_u32(
+        &mut header,
+        header32::MACHINE_IMAGE_TYPE,
+        IMAGE_FILE_MACHINE_I386,
+    );
+    put_u32(
+        &mut header,
+        header32::NUMBER_PROCESSORS,
+        provider.vp_count()?,
+    );
+    put_u32(&mut header, header32::BUGCHECK_CODE, options.bugcheck_code);
+    for (index, parameter) in options.bugcheck_parameters.iter().enumerate() {
+        put_u32(
+            &mut header,
+            header32::BUGCHECK_PARAMETERS + index * 4,
+            *parameter as u32,
+        );
+    }
+    header[header32::VERSION_USER..header32::KD_DEBUGGER_DATA_BLOCK]
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    header[header32::PAE_ENABLED] = kernel_state.pae_enabled as u8;
+    put_u32(
+        &mut header,
+        header32::KD_DEBUGGER_DATA_BLOCK,
+        kd_debugger_data.virtual_address as u32,
+    );
+
+    // PHYSICAL_MEMORY_DESCRIPTOR with 32 bit page numbers, which can describe
+    // the 36 bit physical address space of PAE guests as well
+    let runs = &memory_chunks[..std::cmp::min(memory_chunks.len(), DUMP_HEADER32_MAX_RUNS)];
+    let block = header32::PHYSICAL_MEMORY_BLOCK;
+    put_u32(&mut header, block, runs.len() as u32);
+    put_u32(&mut header, block + 0x4, total_page_count(runs) as u32);
+    for (index, run) in runs.iter().enumerate() {
+        let offset = block + 0x8 + index * 0x8;
+        put_u32(
+            &mut header,
+            offset,
+            run.guest_physical_start_page_index as u32,
+        );
+        put_u32(&mut header, offset + 0x4, run.page_count as u32);
+    }
+
+    let context = build_context32(provider, options.context_vp_id)?;
+    header[header32::CONTEXT..header32::CONTEXT + context.len()].copy_from_slice(&context);
+
+    // EXCEPTION_RECORD32 that points at the instruction the context processor was executing
+    let exception = header32::EXCEPTION;
+    header[exception..header32::COMMENT]
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    put_u32(&mut header, exception, 0x8000_0003);
+    put_u32(
+        &mut header,
+        exception + 0xC,
+        get_u32(&context, context32::EIP),
+    );
+
+    put_comment(
+        &mut header[header32::COMMENT..header32::COMMENT_END],
+        &options.comment,
+    );
+
+    put_u32(
+        &mut header,
+        header32::DUMP_TYPE,
+        options.dump_type.raw_value(),
+    );
+    put_u32(
+        &mut header,
+        header32::PRODUCT_TYPE,
+        kernel_state.product_type,
+    );
+    put_u32(&mut header, header32::SUITE_MASK, kernel_state.suite_mask);
+    put_u64(
+        &mut header,
+        header32::REQUIRED_DUMP_SPACE,
+        required_dump_space,
+    );
+    put_u64(
+        &mut header,
+        header32::SYSTEM_UP_TIME,
+        kernel_state.system_up_time,
+    );
+    put_u64(&mut header, header32::SYSTEM_TIME, kernel_state.system_time);
+
+    Ok(header)
+}
+
+/// Builds the DUMP_HEADER64 structure of a crash dump.
+fn build_header64(
+    provider: &VmSavedStateDumpProvider,
+    options: &WindowsCrashDumpOptions,
+    kd_debugger_data: &KdDebuggerData,
+    kernel_state: &KernelState,
+    memory_chunks: &[GpaMemoryChunk],
+    required_dump_space: u64,
+) -> VmSavedStateDumpResult<Vec<u8>> {
+    let mut header = page_filled_header(DUMP_HEADER64_SIZE, DUMP64_SIGNATURE);
+
+    put_u32(
+        &mut header,
+        header64::MAJOR_VERSION,
+        DUMP_MAJOR_VERSION_FREE_BUILD,
+    );
+    put_u32(
+        &mut header,
+        header64::MINOR_VERSION,
+        kernel_state.build_number,
+    );
+    put_u64(
+        &mut header,
+        header64::DIRECTORY_TABLE_BASE,
+        kernel_state.directory_table_base,
+    );
+    put_u64(
+        &mut header,
+        header64::PFN_DATA_BASE,
+        kernel_state.pfn_database,
+    );
+    put_u64(
+        &mut header,
+        header64::PS_LOADED_MODULE_LIST,
+        kd_debugger_data.ps_loaded_module_list,
+    );
+    put_u64(
+        &mut header,
+        header64::PS_ACTIVE_PROCESS_HEAD,
+        kd_debugger_data.ps_active_process_head,
+    );
+    put_u32(
+        &mut header,
+        header64::MACHINE_IMAGE_TYPE,
+        IMAGE_FILE_MACHINE_AMD64,
+    );
+    put_u32(
+        &mut header,
+        header64::NUMBER_PROCESSORS,
+        provider.vp_count()?,
+    );
+    put_u32(&mut header, header64::BUGCHECK_CODE, options.bugcheck_code);
+    for (index, parameter) in options.bugcheck_parameters.iter().enumerate() {
+        put_u64(
+            &mut header,
+            header64::BUGCHECK_PARAMETERS + index * 8,
+            *parameter,
+        );
+    }
+    header[header64::VERSION_USER..header64::KD_DEBUGGER_DATA_BLOCK]
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    put_u64(
+        &mut header,
+        header64::KD_DEBUGGER_DATA_BLOCK,
+        kd_debugger_data.virtual_address,
+    );
+
+    // PHYSICAL_MEMORY_DESCRIPTOR with as many runs as fit in the header
+    let runs = &memory_chunks[..std::cmp::min(memory_chunks.len(), DUMP_HEADER64_MAX_RUNS)];
+    let block = header64::PHYSICAL_MEMORY_BLOCK;
+    put_u32(&mut header, block, runs.len() as u32);
+    put_u32(&mut header, block + 0x4, 0);
+    put_u64(&mut header, block + 0x8, total_page_count(runs));
+    for (index, run) in runs.iter().enumerate() {
+        let offset = block + 0x10 + index * 0x10;
+        put_u64(&mut header, offset, run.guest_physical_start_page_index);
+        put_u64(&mut header, offset + 0x8, run.page_count);
+    }
+
+    let context = build_context64(provider, options.context_vp_id)?;
+    header[header64::CONTEXT..header64::CONTEXT + context.len()].copy_from_slice(&context);
+
+    // EXCEPTION_RECORD64 that points at the instruction the context processor was executing
+    let exception = header64::EXCEPTION;
+    header[exception..header64::DUMP_TYPE]
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    put_u32(&mut header, exception, 0x8000_0003);
+    put_u64(
+        &mut header,
+        exception + 0x10,
+        get_u64(&context, context64::RIP),
+    );
+
+    put_u32(
+        &mut header,
+        header64::DUMP_TYPE,
+        options.dump_type.raw_value(),
+    );
+    put_u32(&mut header, header64::DUMP_TYPE + 0x4, 0);
+    put_u64(
+        &mut header,
+        header64::REQUIRED_DUMP_SPACE,
+        required_dump_space,
+    );
+    put_u64(&mut header, header64::SYSTEM_TIME, kernel_state.system_time);
+
+    put_comment(
+        &mut header[header64::COMMENT..header64::SYSTEM_UP_TIME],
+        &options.comment,
+    );
+
+    put_u64(
+        &mut header,
+        header64::SYSTEM_UP_TIME,
+        kernel_state.system_up_time,
+    );
+    put_u64(&mut header, header64::SYSTEM_UP_TIME + 0x8, 0);
+    put_u32(
+        &mut header,
+        header64::PRODUCT_TYPE,
+        kernel_state.product_type,
+    );
+    put_u32(&mut header, header64::SUITE_MASK, kernel_state.suite_mask);
+
+    Ok(header)
+}
+
+/// Builds the bitmap header and bitmap that describe the pages stored in a bitmap dump.
+/// The returned buffer is padded so that the first page is stored page aligned.
+fn build_bitmap_header(memory_chunks: &[GpaMemoryChunk], page_size: u64) -> Vec<u8> {
+    let page_index_limit = page_index_limit(memory_chunks);
+    let bitmap_size = align_up(page_index_limit, 64) / 8;
+    let first_page = align_up(
+        DUMP_HEADER64_SIZE as u64 + bitmap_header::BITMAP as u64 + bitmap_size,
+        page_size,
+    );
+
+    let mut header = vec![0u8; (first_page - DUMP_HEADER64_SIZE as u64) as usize];
+    put_u32(&mut header, bitmap_header::SIGNATURE, FULL_DUMP_SIGNATURE);
+    put_u32(&mut header, bitmap_header::VALID_DUMP, VALID_DUMP_SIGNATURE);
+    put_u64(&mut header, bitmap_header::FIRST_PAGE, first_page);
+    put_u64(
+        &mut header,
+        bitmap_header::TOTAL_PRESENT_PAGES,
+        total_page_count(memory_chunks),
+    );
+    put_u64(&mut header, bitmap_header::PAGES, page_index_limit);
+
+    let bitmap = &mut header[bitmap_header::BITMAP..];
+    for chunk in memory_chunks {
+        let start = chunk.guest_physical_start_page_index;
+        for page_index in start..start + chunk.page_count {
+            bitmap[(page_index / 8) as usize] |= 1 << (page_index % 8);
+        }
+    }
+
+    header
+}
+
+/// Returns the count of physical pages 32-bit guests can address,
+/// which is 4GB of physical memory without PAE, and 64GB with it.
+fn addressable_page_index_limit(pae_enabled: bool) -> u64 {
+    if pae_enabled {
+        1 << 24
+    } else {
+        1 << 20
+    }
+}
+
+/// Returns the memory chunks clamped to the pages the given physical page count limit allows.
+pub(crate) fn addressable_memory_chunks(
+    memory_chunks: &[GpaMemoryChunk],
+    page_index_limit: u64,
+) -> Vec<GpaMemoryChunk> {
+    memory_chunks
+        .iter()
+        .filter(|chunk| chunk.guest_physical_start_page_index < page_index_limit)
+        .map(|chunk| GpaMemoryChunk {
+            guest_physical_start_page_index: chunk.guest_physical_start_page_index,
+            page_count: std::cmp::min(
+                chunk.page_count,
+                page_index_limit - chunk.guest_physical_start_page_index,
+            ),
+        })
+        .collect()
+}
+
+/// Returns the memory chunks sorted by their first page, with adjacent and overlapping chunks merged.
+fn merged_memory_chunks(memory_chunks: &[GpaMemoryChunk]) -> Vec<GpaMemoryChunk> {
+    let mut sorted = memory_chunks
+        .iter()
+        .filter(|chunk| chunk.page_count != 0)
+        .copied()
+        .collect::<Vec<_>>();
+    sorted.sort_by_key(|chunk| chunk.guest_physical_start_page_index);
+
+    let mut merged: Vec<GpaMemoryChunk> = Vec::with_capacity(sorted.len());
+    for chunk in sorted {
+        match merged.last_mut() {
+            Some(last)
+                if last.guest_physical_start_page_index + last.page_count
+                    >= chunk.guest_physical_start_page_index =>
+            {
+                let end = std::cmp::max(
+                    last.guest_physical_start_page_index + last.page_count,
+                    chunk.guest_physical_start_page_index + chunk.page_count,
+                );
+                last.page_count = end - last.guest_physical_start_page_index;
+            }
+            _ => merged.push(chunk),
+        }
+    }
+
+    merged
+}
+
+/// Writes a Windows kernel crash dump of the loaded saved state to the given output.
+/// 64-bit guests produce a DUMP_HEADER64 based dump, and 32-bit guests a DUMP_HEADER32
+/// based full dump. Memory above 4GB is left out for 32-bit guests that don't use PAE.
+/// The KdDebuggerDataBlock is discovered by scanning guest memory unless the options supply it.
+/// When the scan doesn't find it, such as on Windows 8 and later 64-bit guests that encode it
+/// and no keys were given, the dump is written without it and the statistics report it missing.
+/// The header fields taken from it are then left as 0, so debuggers have to locate the kernel.
+/// Full dumps fail with `InvalidArgument` when the guest memory, once adjacent chunks are merged,
+/// has more runs than fit in the header; bitmap dumps don't have that limit.
+/// Guest memory is streamed from the saved state, so the dump is never held in memory at once.
+pub fn write_windows_crash_dump<W: Read + Write + Seek>(
+    provider: &VmSavedStateDumpProvider,
+    output: &mut W,
+    options: &WindowsCrashDumpOptions,
+    control: &mut DumpControl,
+) -> VmSavedStateDumpResult<DumpStatistics> {
+    let architecture = provider.get_vp_architecture(options.context_vp_id)?;
+    let (header_size, max_runs) = match (architecture, options.dump_type) {
+        (VirtualProcessorArch::X64, _) => (DUMP_HEADER64_SIZE, DUMP_HEADER64_MAX_RUNS),
+        (VirtualProcessorArch::X86, WindowsDumpType::Full) => {
+            (DUMP_HEADER32_SIZE, DUMP_HEADER32_MAX_RUNS)
+        }
+        _ => return Err(ResultCode::InvalidArgument.into()),
+    };
+
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+    if page_size != 0x1000 {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let vp_id = kernel_mode_vp_id(provider)?;
+    let kd_debugger_data = match options.kd_debugger_data_block {
+        Some(virtual_address) => Some(read_kd_debugger_data_with(
+            provider,
+            vp_id,
+            virtual_address,
+            options.kd_debugger_data_encoding.as_ref(),
+        )?),
+        None => match scan_kd_debugger_data_block(
+            provider,
+            vp_id,
+            options.kd_debugger_data_encoding.as_ref(),
+        ) {
+            Ok(kd_debugger_data) => Some(kd_debugger_data),
+            Err(error) if error == ResultCode::KdDebuggerDataBlockNotFound => None,
+            Err(error) => return Err(error),
+        },
+    };
+    let kernel_state = read_kernel_state(provider, vp_id, kd_debugger_data.as_ref())?;
+    let kd_debugger_data_block_missing = kd_debugger_data.is_none();
+    let kd_debugger_data = kd_debugger_data.unwrap_or_default();
+
+    let memory_chunks = match architecture {
+        VirtualProcessorArch::X86 => addressable_memory_chunks(
+            &memory_chunks,
+            addressable_page_index_limit(kernel_state.pae_enabled),
+        ),
+        _ => memory_chunks,
+    };
+
+    // Full dumps can only describe as many runs as fit in the header, and memory is never left out
+    let memory_chunks = merged_memory_chunks(&memory_chunks);
+    if options.dump_type == WindowsDumpType::Full && memory_chunks.len() > max_runs {
+        return Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+            .with_operation("write_windows_crash_dump"));
+    }
+
+    let pages_size = total_page_count(&memory_chunks) * page_size;
+    let mut bitmap_header = match options.dump_type {
+        WindowsDumpType::Full => Vec::new(),
+        WindowsDumpType::Bitmap => build_bitmap_header(&memory_chunks, page_size),
+    };
+    let required_dump_space = (header_size + bitmap_header.len()) as u64 + pages_size;
+
+    let mut header = match architecture {
+        VirtualProcessorArch::X86 => build_header32(
+            provider,
+            options,
+            &kd_debugger_data,
+            &kernel_state,
+            &memory_chunks,
+            required_dump_space,
+        )?,
+        _ => build_header64(
+            provider,
+            options,
+            &kd_debugger_data,
+            &kernel_state,
+            &memory_chunks,
+            required_dump_space,
+        )?,
+    };
+    let exclude_zero_pages =
+        options.exclude_zero_pages && options.dump_type == WindowsDumpType::Bitmap;
+    let resume = control.resume && !exclude_zero_pages;
+    let mut tracker = control.begin(provider)?;
+    let mut statistics = DumpStatistics {
+        kd_debugger_data_block_missing,
+        ..Default::default()
+    };
+
+    // Pages are stored right after the headers, in the order of the memory chunks
+    let mut chunk_offsets = vec![(header.len() + bitmap_header.len()) as u64];
+    for chunk in &memory_chunks {
+        chunk_offsets.push(chunk_offsets[chunk_offsets.len() - 1] + chunk.page_count * page_size);
+    }
+
+    let start = output.stream_position()?;
+    let resume_point = if resume {
+        find_resume_point(
+            output,
+            start,
+            &[(0, &header), (header.len() as u64, &bitmap_header)],
+            &chunk_offsets,
+        )?
+    } else {
+        None
+    };
+    let first_chunk = match resume_point {
+        Some(chunk_index) => {
+            output.seek(SeekFrom::Start(start + chunk_offsets[chunk_index]))?;
+            chunk_index
+        }
+        None if exclude_zero_pages => {
+            output.seek(SeekFrom::Start(start + chunk_offsets[0]))?;
+            0
+        }
+        None => {
+            output.seek(SeekFrom::Start(start))?;
+            output.write_all(&header)?;
+            output.write_all(&bitmap_header)?;
+            0
+        }
+    };
+
+    let resumed_pages = total_page_count(&memory_chunks[..first_chunk]);
+    tracker.skip(resumed_pages * page_size);
+    statistics.pages_written = resumed_pages;
+    statistics.bytes_written = chunk_offsets[first_chunk];
+
+    let mut reader = MemoryBlockReader::new(provider, page_size, &memory_chunks[first_chunk..]);
+    while let Some((address, block)) = reader.next_block()? {
+        let mut run_start = 0;
+        for (index, page) in block.chunks(page_size as usize).enumerate() {
+            if !exclude_zero_pages || !is_zero_page(page) {
+                continue;
+            }
+
+            // Zero pages are cleared from the bitmap, and the pages before them written
+            let page_index = address / page_size + index as u64;
+            bitmap_header[bitmap_header::BITMAP + (page_index / 8) as usize] &=
+                !(1 << (page_index % 8));
+            statistics.zero_pages_elided += 1;
+
+            let run = &block[run_start..index * page_size as usize];
+            output.write_all(run)?;
+            statistics.pages_written += run.len() as u64 / page_size;
+            statistics.bytes_written += run.len() as u64;
+            run_start = (index + 1) * page_size as usize;
+        }
+
+        let run = &block[run_start..];
+        output.write_all(run)?;
+        statistics.pages_written += run.len() as u64 / page_size;
+        statistics.bytes_written += run.len() as u64;
+        tracker.advance(block.len() as u64)?;
+    }
+
+    if exclude_zero_pages {
+        put_u64(
+            &mut bitmap_header,
+            bitmap_header::TOTAL_PRESENT_PAGES,
+            statistics.pages_written,
+        );
+        put_u64(
+            &mut header,
+            header64::REQUIRED_DUMP_SPACE,
+            statistics.bytes_written,
+        );
+
+        output.seek(SeekFrom::Start(start))?;
+        output.write_all(&header)?;
+        output.write_all(&bitmap_header)?;
+        output.seek(SeekFrom::Start(start + statistics.bytes_written))?;
+    }
+
+    output.flush()?;
+    Ok(statistics)
+}
+
+/// Reads the layout of a Windows kernel crash dump, whose context is decoded
+/// as the registers of the given virtual processor.
+pub(crate) fn read_windows_crash_dump_layout<R: Read + Seek>(
+    input: &mut R,
+    context_vp_id: u32,
+) -> VmSavedStateDumpResult<DumpLayout> {
+    let mut header = vec![0u8; DUMP_HEADER32_SIZE];
+    input.seek(SeekFrom::Start(0))?;
+    input.read_exact(&mut header)?;
+    if get_u32(&header, 0x0) != PAGE_SIGNATURE {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let mut runs = Vec::new();
+    let mut registers = Vec::new();
+    let mut page_index_limit = None;
+    let mut zero_pages_elided = false;
+    match get_u32(&header, 0x4) {
+        DUMP32_SIGNATURE => {
+            page_index_limit = Some(addressable_page_index_limit(
+                header[header32::PAE_ENABLED] != 0,
+            ));
+            let block = header32::PHYSICAL_MEMORY_BLOCK;
+            let run_count = std::cmp::min(get_u32(&header, block) as usize, DUMP_HEADER32_MAX_RUNS);
+            let mut offset = DUMP_HEADER32_SIZE as u64;
+            for index in 0..run_count {
+                let run = block + 0x8 + index * 0x8;
+                let page_count = u64::from(get_u32(&header, run + 0x4));
+                runs.push(StoredRun {
+                    first_page_index: u64::from(get_u32(&header, run)),
+                    page_count,
+                    offset,
+                });
+                offset += page_count * 0x1000;
+            }
+
+            let context = &header[header32::CONTEXT..header32::CONTEXT + CONTEXT32_SIZE];
+            for (register_id, offset) in CONTEXT32_REGISTERS.iter() {
+                registers.push(StoredRegister {
+                    vp_id: context_vp_id,
+                    register: Register::X86(*register_id),
+                    value: u64::from(get_u32(context, *offset)),
+                    size: 4,
+                });
+            }
+        }
+        DUMP64_SIGNATURE => {
+            header.resize(DUMP_HEADER64_SIZE, 0);
+            input.read_exact(&mut header[DUMP_HEADER32_SIZE..])?;
+
+            let dump_type = get_u32(&header, header64::DUMP_TYPE);
+            if dump_type == WindowsDumpType::Full.raw_value() {
+                let block = header64::PHYSICAL_MEMORY_BLOCK;
+                let run_count =
+                    std::cmp::min(get_u32(&header, block) as usize, DUMP_HEADER64_MAX_RUNS);
+                let mut offset = DUMP_HEADER64_SIZE as u64;
+                for index in 0..run_count {
+                    let run = block + 0x10 + index * 0x10;
+                    let page_count = get_u64(&header, run + 0x8);
+                    runs.push(StoredRun {
+                        first_page_index: get_u64(&header, run),
+                        page_count,
+                        offset,
+                    });
+                    offset = offset.saturating_add(page_count.saturating_mul(0x1000));
+                }
+            } else if dump_type == WindowsDumpType::Bitmap.raw_value() {
+                runs = read_bitmap_runs(input)?;
+                zero_pages_elided = true;
+            } else {
+                return Err(ResultCode::InvalidArgument.into());
+            }
+
+            let context = &header[header64::CONTEXT..header64::CONTEXT + CONTEXT64_SIZE];
+            for (register_id, offset) in CONTEXT64_SEGMENT_REGISTERS.iter() {
+                registers.push(StoredRegister {
+                    vp_id: context_vp_id,
+                    register: Register::X64(*register_id),
+                    value: u64::from(get_u16(context, *offset)),
+                    size: 2,
+                });
+            }
+            for (register_id, offset) in CONTEXT64_REGISTERS.iter() {
+                registers.push(StoredRegister {
+                    vp_id: context_vp_id,
+                    register: Register::X64(*register_id),
+                    value: get_u64(context, *offset),
+                    size: 8,
+                });
+            }
+            registers.push(StoredRegister {
+                vp_id: context_vp_id,
+                register: Register::X64(RegisterIdx64::RFlags),
+                value: u64::from(get_u32(context, context64::EFLAGS)),
+                size: 4,
+            });
+        }
+        _ => return Err(ResultCode::InvalidArgument.into()),
+    }
+
+    Ok(DumpLayout {
+        pages: StoredPages::Runs(runs),
+        registers,
+        page_index_limit,
+        zero_pages_elided,
+    })
+}
+
+/// Reads the bitmap that follows the header of a bitmap dump,
+/// and returns the runs of consecutive pages it describes.
+fn read_bitmap_runs<R: Read + Seek>(input: &mut R) -> VmSavedStateDumpResult<Vec<StoredRun>> {
+    let mut header = [0u8; bitmap_header::BITMAP];
+    input.seek(SeekFrom::Start(DUMP_HEADER64_SIZE as u64))?;
+    input.read_exact(&mut header)?;
+    if get_u32(&header, bitmap_header::SIGNATURE) != FULL_DUMP_SIGNATURE {
+        return Err(ResultCode::InvalidArgument.into());
+    }
+
+    let page_count = get_u64(&header, bitmap_header::PAGES);
+    let bitmap = read_region(
+        input,
+        (DUMP_HEADER64_SIZE + bitmap_header::BITMAP) as u64,
+        page_count / 8 + u64::from(page_count & 0x7 != 0),
+    )?;
+
+    let mut runs: Vec<StoredRun> = Vec::new();
+    let mut offset = get_u64(&header, bitmap_header::FIRST_PAGE);
+    for page_index in 0..page_count {
+        if bitmap[(page_index / 8) as usize] & (1 << (page_index % 8)) == 0 {
+            continue;
+        }
+
+        match runs.last_mut() {
+            Some(run) if run.first_page_index + run.page_count == page_index => run.page_count += 1,
+            _ => runs.push(StoredRun {
+                first_page_index: page_index,
+                page_count: 1,
+                offset,
+            }),
+        }
+        offset += 0x1000;
+    }
+
+    Ok(runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(guest_physical_start_page_index: u64, page_count: u64) -> GpaMemoryChunk {
+        GpaMemoryChunk {
+            guest_physical_start_page_index,
+            page_count,
+        }
+    }
+
+    /// Encodes a raw KDDEBUGGER_DATA64 structure the way the kernel does, reversing `decode`.
+    fn encode(encoding: &KdDebuggerDataEncoding, raw: &mut [u8]) {
+        for entry in raw.chunks_exact_mut(8) {
+            let value = get_u64(entry, 0);
+            let value = (value ^ encoding.wait_always).swap_bytes() ^ encoding.data_block_encoded;
+            let value = value.rotate_right(encoding.wait_never as u8 as u32) ^ encoding.wait_never;
+            entry.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn merged_memory_chunks_sorts_and_merges() {
+        let merged = merged_memory_chunks(&[
+            chunk(0x200, 0x100),
+            chunk(0, 0x10),
+            chunk(0x10, 0x20),
+            chunk(0x40, 0),
+            chunk(0x250, 0x100),
+            chunk(0x1000, 1),
+        ]);
+        assert_eq!(
+            vec![chunk(0, 0x30), chunk(0x200, 0x150), chunk(0x1000, 1)],
+            merged
+        );
+        assert!(merged_memory_chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn encoded_kd_debugger_data_decoding() {
+        let mut raw = vec![0u8; kdbg::READ_SIZE];
+        put_u32(&mut raw, kdbg::OWNER_TAG, KDBG_TAG);
+        put_u32(&mut raw, kdbg::SIZE, 0x368);
+        put_u64(&mut raw, kdbg::KERN_BASE, 0xFFFF_F803_1C60_0000);
+        put_u64(&mut raw, kdbg::PS_LOADED_MODULE_LIST, 0xFFFF_F803_1CA2_A0A0);
+        put_u64(&mut raw, kdbg::MM_PFN_DATABASE, 0xFFFF_F803_1CB0_1230);
+
+        let encoding = KdDebuggerDataEncoding {
+            wait_never: 0x6E9D_2B61_CB3F_D0A7,
+            wait_always: 0x91A3_0C5E_2D77_48F2,
+            data_block_encoded: 0xFFFF_F803_1CBF_C2E8,
+        };
+        let mut encoded = raw.clone();
+        encode(&encoding, &mut encoded);
+        assert_ne!(KDBG_TAG, get_u32(&encoded, kdbg::OWNER_TAG));
+        assert_eq!(None, KdDebuggerData::parse(0xFFFF_F803_1CA0_0000, &encoded));
+
+        // The memory scan only decodes the entry of the tag to find candidates
+        let tag_entry = encoding.decode_entry(get_u64(&encoded, kdbg::OWNER_TAG));
+        assert_eq!(KDBG_TAG, tag_entry as u32);
+        assert_eq!(0x368, (tag_entry >> 32) as u32);
+
+        encoding.decode(&mut encoded);
+        assert_eq!(raw, encoded);
+        let kd_debugger_data = KdDebuggerData::parse(0xFFFF_F803_1CA0_0000, &encoded).unwrap();
+        assert_eq!(0xFFFF_F803_1C60_0000, kd_debugger_data.kern_base);
+        assert_eq!(
+            0xFFFF_F803_1CA2_A0A0,
+            kd_debugger_data.ps_loaded_module_list
+        );
+        assert_eq!(0xFFFF_F803_1CB0_1230, kd_debugger_data.mm_pfn_database);
+    }
+
+    #[test]
+    fn comment_is_truncated_and_terminated() {
+        let mut buffer = [0xFFu8; 8];
+        put_comment(&mut buffer, "a long comment");
+        assert_eq!(b"a long \0", &buffer);
+
+        put_comment(&mut buffer, "ok");
+        assert_eq!(b"ok\0\0\0\0\0\0", &buffer);
+    }
+
+    #[test]
+    fn full_dump64_header_layout() {
+        let mut header = page_filled_header(DUMP_HEADER64_SIZE, DUMP64_SIGNATURE);
+        put_u32(
+            &mut header,
+            header64::DUMP_TYPE,
+            WindowsDumpType::Full.raw_value(),
+        );
+
+        let runs = [chunk(1, 0x9F), chunk(0x100, 0x3F00)];
+        let block = header64::PHYSICAL_MEMORY_BLOCK;
+        put_u32(&mut header, block, runs.len() as u32);
+        put_u64(&mut header, block + 0x8, total_page_count(&runs));
+        for (index, run) in runs.iter().enumerate() {
+            put_u64(
+                &mut header,
+                block + 0x10 + index * 0x10,
+                run.guest_physical_start_page_index,
+            );
+            put_u64(&mut header, block + 0x18 + index * 0x10, run.page_count);
+        }
+
+        let context = header64::CONTEXT;
+        put_u32(
+            &mut header,
+            context + context64::CONTEXT_FLAGS,
+            CONTEXT64_FLAGS,
+        );
+        put_u16(&mut header, context + 0x38, 0x10);
+        put_u64(&mut header, context + 0x98, 0xFFFF_F803_2000_1FF8);
+        put_u64(&mut header, context + context64::RIP, 0xFFFF_F803_1C81_2345);
+        put_u32(&mut header, context + context64::EFLAGS, 0x0004_0246);
+
+        let layout = read_windows_crash_dump_layout(&mut Cursor::new(header), 1).unwrap();
+        assert_eq!(
+            StoredPages::Runs(vec![
+                StoredRun {
+                    first_page_index: 1,
+                    page_count: 0x9F,
+                    offset: DUMP_HEADER64_SIZE as u64,
+                },
+                StoredRun {
+                    first_page_index: 0x100,
+                    page_count: 0x3F00,
+                    offset: DUMP_HEADER64_SIZE as u64 + 0x9F * 0x1000,
+                },
+            ]),
+            layout.pages
+        );
+
+        let register = |register_id| {
+            layout
+                .registers
+                .iter()
+                .find(|register| register.register == Register::X64(register_id))
+                .map(|register| (register.vp_id, register.value, register.size))
+        };
+        assert_eq!(Some((1, 0x10, 2)), register(RegisterIdx64::SegCs));
+        assert_eq!(
+            Some((1, 0xFFFF_F803_2000_1FF8, 8)),
+            register(RegisterIdx64::Rsp)
+        );
+        assert_eq!(
+            Some((1, 0xFFFF_F803_1C81_2345, 8)),
+            register(RegisterIdx64::Rip)
+        );
+        assert_eq!(Some((1, 0x0004_0246, 4)), register(RegisterIdx64::RFlags));
+        assert_eq!(None, layout.page_index_limit);
+        assert!(!layout.zero_pages_elided);
+    }
+
+    #[test]
+    fn dump32_layout_limits_pages_to_addressable_memory() {
+        let mut header = page_filled_header(DUMP_HEADER32_SIZE, DUMP32_SIGNATURE);
+        put_u32(&mut header, header32::PHYSICAL_MEMORY_BLOCK, 0);
+
+        header[header32::PAE_ENABLED] = 0;
+        let layout = read_windows_crash_dump_layout(&mut Cursor::new(&header), 0).unwrap();
+        assert_eq!(Some(1 << 20), layout.page_index_limit);
+        assert!(!layout.zero_pages_elided);
+
+        header[header32::PAE_ENABLED] = 1;
+        let layout = read_windows_crash_dump_layout(&mut Cursor::new(&header), 0).unwrap();
+        assert_eq!(Some(1 << 24), layout.page_index_limit);
+    }
+
+    #[test]
+    fn bitmap_header_round_trip() {
+        let memory_chunks = [chunk(0, 0x9F), chunk(0x100, 0x41), chunk(0x300, 1)];
+        let bitmap_header = build_bitmap_header(&memory_chunks, 0x1000);
+
+        let first_page = get_u64(&bitmap_header, bitmap_header::FIRST_PAGE);
+        assert_eq!(0, first_page % 0x1000);
+        assert_eq!(
+            first_page,
+            (DUMP_HEADER64_SIZE + bitmap_header.len()) as u64
+        );
+        assert_eq!(
+            FULL_DUMP_SIGNATURE,
+            get_u32(&bitmap_header, bitmap_header::SIGNATURE)
+        );
+        assert_eq!(
+            0x9F + 0x41 + 1,
+            get_u64(&bitmap_header, bitmap_header::TOTAL_PRESENT_PAGES)
+        );
+        assert_eq!(0x301, get_u64(&bitmap_header, bitmap_header::PAGES));
+
+        let mut dump = page_filled_header(DUMP_HEADER64_SIZE, DUMP64_SIGNATURE);
+        put_u32(
+            &mut dump,
+            header64::DUMP_TYPE,
+            WindowsDumpType::Bitmap.raw_value(),
+        );
+        dump.extend_from_slice(&bitmap_header);
+        let runs = read_bitmap_runs(&mut Cursor::new(&dump)).unwrap();
+        assert_eq!(
+            vec![
+                StoredRun {
+                    first_page_index: 0,
+                    page_count: 0x9F,
+                    offset: first_page,
+                },
+                StoredRun {
+                    first_page_index: 0x100,
+                    page_count: 0x41,
+                    offset: first_page + 0x9F * 0x1000,
+                },
+                StoredRun {
+                    first_page_index: 0x300,
+                    page_count: 1,
+                    offset: first_page + (0x9F + 0x41) * 0x1000,
+                },
+            ],
+            runs
+        );
+
+        // Bitmap dumps leave pages filled with zeros out
+        let layout = read_windows_crash_dump_layout(&mut Cursor::new(&dump), 0).unwrap();
+        assert_eq!(StoredPages::Runs(runs), layout.pages);
+        assert!(layout.zero_pages_elided);
+    }
+
+    #[test]
+    fn addressable_memory_chunks_are_clamped() {
+        let memory_chunks = [chunk(0, 0x100), chunk(0xFFF00, 0x200), chunk(0x100000, 1)];
+        assert_eq!(
+            vec![chunk(0, 0x100), chunk(0xFFF00, 0x100)],
+            addressable_memory_chunks(&memory_chunks, 1 << 20)
+        );
+    }
+}
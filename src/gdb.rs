@@ -0,0 +1,1004 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module provides a GDB remote serial protocol server over a loaded VM saved state,
+//! so that gdb, or any other debugger speaking the protocol, can attach to the frozen VM.
+//!
+//! Each virtual processor is exposed as a thread, whose id is the virtual processor id plus one
+//! since the protocol reserves thread id 0. Registers follow the layout gdb uses for i386 and
+//! x86-64 targets, described in the target description served to the debugger; x87 stack
+//! registers aren't saved and are reported as unavailable. Memory reads translate addresses
+//! through the current thread's virtual processor. Since gdb refuses to read outside of the
+//! memory map, the one served lists the virtual addresses mapped by the page tables of any
+//! virtual processor; the layout of guest physical memory is shown by the `monitor chunks`
+//! command instead. The saved state can't be modified or resumed, so write requests fail
+//! and resuming reports the same stop again.
+//!
+//! The server reads through `SavedStateReader`, so besides a loaded provider it can serve any
+//! other backend implementing it.
+//!
+//! ```rust,ignore
+//! GdbServer::new(&provider)?.listen("127.0.0.1:1234")?;
+//! ```
+
+use crate::paging::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+
+/// Largest packet the server accepts, advertised to the debugger.
+const PACKET_SIZE: usize = 0x4000;
+
+/// Interrupt request the debugger sends outside of packets.
+const INTERRUPT: u8 = 0x03;
+
+/// Signal reported for every stop, SIGTRAP.
+const STOP_SIGNAL: u8 = 0x05;
+
+/// Where the value of a register in the gdb layout comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum RegisterSource {
+    Register(Register),
+    Xmm(u8),
+    FpControl,
+    FpStatus,
+    FpTag,
+    FpInstructionOffset,
+    FpDataOffset,
+    FpOpcode,
+    Mxcsr,
+    Unavailable,
+}
+
+/// Register of the gdb layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct GdbRegister {
+    name: &'static str,
+    bit_size: usize,
+    register_type: &'static str,
+    feature: &'static str,
+    source: RegisterSource,
+}
+
+const CORE_FEATURE: &str = "org.gnu.gdb.i386.core";
+const SSE_FEATURE: &str = "org.gnu.gdb.i386.sse";
+
+/// Returns the registers gdb expects for the given architecture, in register number order.
+fn gdb_registers(architecture: VirtualProcessorArch) -> Vec<GdbRegister> {
+    let core = |name, bit_size, register_type, source| GdbRegister {
+        name,
+        bit_size,
+        register_type,
+        feature: CORE_FEATURE,
+        source,
+    };
+    let x86 = |register_id| RegisterSource::Register(Register::X86(register_id));
+    let x64 = |register_id| RegisterSource::Register(Register::X64(register_id));
+
+    let (mut registers, xmm_count) = match architecture {
+        VirtualProcessorArch::X64 => (
+            vec![
+                core("rax", 64, "int64", x64(RegisterIdx64::Rax)),
+                core("rbx", 64, "int64", x64(RegisterIdx64::Rbx)),
+                core("rcx", 64, "int64", x64(RegisterIdx64::Rcx)),
+                core("rdx", 64, "int64", x64(RegisterIdx64::Rdx)),
+                core("rsi", 64, "int64", x64(RegisterIdx64::Rsi)),
+                core("rdi", 64, "int64", x64(RegisterIdx64::Rdi)),
+                core("rbp", 64, "data_ptr", x64(RegisterIdx64::Rbp)),
+                core("rsp", 64, "data_ptr", x64(RegisterIdx64::Rsp)),
+                core("r8", 64, "int64", x64(RegisterIdx64::R8)),
+                core("r9", 64, "int64", x64(RegisterIdx64::R9)),
+                core("r10", 64, "int64", x64(RegisterIdx64::R10)),
+                core("r11", 64, "int64", x64(RegisterIdx64::R11)),
+                core("r12", 64, "int64", x64(RegisterIdx64::R12)),
+                core("r13", 64, "int64", x64(RegisterIdx64::R13)),
+                core("r14", 64, "int64", x64(RegisterIdx64::R14)),
+                core("r15", 64, "int64", x64(RegisterIdx64::R15)),
+                core("rip", 64, "code_ptr", x64(RegisterIdx64::Rip)),
+                core("eflags", 32, "int32", x64(RegisterIdx64::RFlags)),
+                core("cs", 32, "int32", x64(RegisterIdx64::SegCs)),
+                core("ss", 32, "int32", x64(RegisterIdx64::SegSs)),
+                core("ds", 32, "int32", x64(RegisterIdx64::SegDs)),
+                core("es", 32, "int32", x64(RegisterIdx64::SegEs)),
+                core("fs", 32, "int32", x64(RegisterIdx64::SegFs)),
+                core("gs", 32, "int32", x64(RegisterIdx64::SegGs)),
+            ],
+            16,
+        ),
+        _ => (
+            vec![
+                core("eax", 32, "int32", x86(RegisterIdx86::Eax)),
+                core("ecx", 32, "int32", x86(RegisterIdx86::Ecx)),
+                core("edx", 32, "int32", x86(RegisterIdx86::Edx)),
+                core("ebx", 32, "int32", x86(RegisterIdx86::Ebx)),
+                core("esp", 32, "data_ptr", x86(RegisterIdx86::Esp)),
+                core("ebp", 32, "data_ptr", x86(RegisterIdx86::Ebp)),
+                core("esi", 32, "int32", x86(RegisterIdx86::Esi)),
+                core("edi", 32, "int32", x86(RegisterIdx86::Edi)),
+                core("eip", 32, "code_ptr", x86(RegisterIdx86::Eip)),
+                core("eflags", 32, "int32", x86(RegisterIdx86::EFlags)),
+                core("cs", 32, "int32", x86(RegisterIdx86::SegCs)),
+                core("ss", 32, "int32", x86(RegisterIdx86::SegSs)),
+                core("ds", 32, "int32", x86(RegisterIdx86::SegDs)),
+                core("es", 32, "int32", x86(RegisterIdx86::SegEs)),
+                core("fs", 32, "int32", x86(RegisterIdx86::SegFs)),
+                core("gs", 32, "int32", x86(RegisterIdx86::SegGs)),
+            ],
+            8,
+        ),
+    };
+
+    const ST_NAMES: [&str; 8] = ["st0", "st1", "st2", "st3", "st4", "st5", "st6", "st7"];
+    for name in ST_NAMES.iter() {
+        registers.push(core(name, 80, "i387_ext", RegisterSource::Unavailable));
+    }
+    registers.extend_from_slice(&[
+        core("fctrl", 32, "int", RegisterSource::FpControl),
+        core("fstat", 32, "int", RegisterSource::FpStatus),
+        core("ftag", 32, "int", RegisterSource::FpTag),
+        core("fiseg", 32, "int", RegisterSource::Unavailable),
+        core("fioff", 32, "int", RegisterSource::FpInstructionOffset),
+        core("foseg", 32, "int", RegisterSource::Unavailable),
+        core("fooff", 32, "int", RegisterSource::FpDataOffset),
+        core("fop", 32, "int", RegisterSource::FpOpcode),
+    ]);
+
+    const XMM_NAMES: [&str; 16] = [
+        "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7", "xmm8", "xmm9", "xmm10",
+        "xmm11", "xmm12", "xmm13", "xmm14", "xmm15",
+    ];
+    for (index, name) in XMM_NAMES.iter().take(xmm_count).enumerate() {
+        registers.push(GdbRegister {
+            name,
+            bit_size: 128,
+            register_type: "uint128",
+            feature: SSE_FEATURE,
+            source: RegisterSource::Xmm(index as u8),
+        });
+    }
+    registers.push(GdbRegister {
+        name: "mxcsr",
+        bit_size: 32,
+        register_type: "int",
+        feature: SSE_FEATURE,
+        source: RegisterSource::Mxcsr,
+    });
+
+    registers
+}
+
+/// Returns the target description of the given register layout.
+fn target_description(architecture: VirtualProcessorArch, registers: &[GdbRegister]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n");
+    let _ = writeln!(
+        xml,
+        "<architecture>{}</architecture>",
+        match architecture {
+            VirtualProcessorArch::X64 => "i386:x86-64",
+            _ => "i386",
+        }
+    );
+
+    for feature in &[CORE_FEATURE, SSE_FEATURE] {
+        let _ = writeln!(xml, "<feature name=\"{}\">", feature);
+        for (number, register) in registers.iter().enumerate() {
+            if register.feature == *feature {
+                let _ = writeln!(
+                    xml,
+                    "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+                    register.name, register.bit_size, register.register_type, number
+                );
+            }
+        }
+        xml.push_str("</feature>\n");
+    }
+
+    xml.push_str("</target>\n");
+    xml
+}
+
+/// Monitor commands, listed by `monitor help`.
+const MONITOR_HELP: &str = "\
+chunks    Lists the guest physical memory chunks
+help      Lists the monitor commands
+";
+
+/// Returns the memory map of the given ranges of guest virtual addresses.
+fn memory_map(ranges: &[MappedRange]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE memory-map SYSTEM \"gdb-memory-map.dtd\">\n<memory-map>\n");
+    for range in ranges {
+        let _ = writeln!(
+            xml,
+            "<memory type=\"ram\" start=\"{:#x}\" length=\"{:#x}\"/>",
+            range.virtual_address, range.size
+        );
+    }
+    xml.push_str("</memory-map>\n");
+    xml
+}
+
+/// Returns the layout of guest physical memory shown by `monitor chunks`, one chunk per line.
+fn physical_memory_layout(page_size: u64, memory_chunks: &[GpaMemoryChunk]) -> String {
+    let mut text = String::new();
+    for chunk in memory_chunks {
+        let start = chunk.guest_physical_start_page_index * page_size;
+        let _ = writeln!(
+            text,
+            "{:016x} - {:016x}  {:#x} pages",
+            start,
+            start + chunk.page_count * page_size,
+            chunk.page_count
+        );
+    }
+    text
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(value: &[u8]) -> Option<u64> {
+    u64::from_str_radix(std::str::from_utf8(value).ok()?, 16).ok()
+}
+
+/// Parses a hex encoded byte string, such as the command of a `qRcmd` packet.
+fn parse_hex_bytes(value: &[u8]) -> Option<Vec<u8>> {
+    let bytes = value.chunks_exact(2);
+    if !bytes.remainder().is_empty() {
+        return None;
+    }
+    bytes.map(|byte| Some(parse_hex(byte)? as u8)).collect()
+}
+
+/// Parses the `offset,length` annex of `qXfer` and memory packets.
+fn parse_range(value: &[u8]) -> Option<(u64, u64)> {
+    let separator = value.iter().position(|byte| *byte == b',')?;
+    Some((
+        parse_hex(&value[..separator])?,
+        parse_hex(&value[separator + 1..])?,
+    ))
+}
+
+/// Returns the checksum of the data of a packet, the modulo 256 sum of its bytes.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Error reply with the given errno.
+fn error_reply(errno: u8) -> Vec<u8> {
+    format!("E{:02x}", errno).into_bytes()
+}
+
+/// Replies to a `qXfer` read of the given object with the requested range of it.
+fn transfer(object: &[u8], range: &[u8]) -> Vec<u8> {
+    let (offset, length) = match parse_range(range) {
+        Some(range) => range,
+        None => return error_reply(22),
+    };
+
+    let start = std::cmp::min(offset, object.len() as u64) as usize;
+    let end = std::cmp::min(
+        start as u64 + std::cmp::min(length, PACKET_SIZE as u64 / 2),
+        object.len() as u64,
+    ) as usize;
+
+    let mut reply = vec![if end < object.len() { b'm' } else { b'l' }];
+    for byte in &object[start..end] {
+        match byte {
+            b'#' | b'$' | b'}' | b'*' => reply.extend_from_slice(&[b'}', byte ^ 0x20]),
+            _ => reply.push(*byte),
+        }
+    }
+    reply
+}
+
+/// GDB remote serial protocol server over a loaded VM saved state, or any other reader of
+/// saved guest state.
+pub struct GdbServer<'a, R: ?Sized = VmSavedStateDumpProvider> {
+    provider: &'a R,
+    vp_count: u32,
+    registers: Vec<GdbRegister>,
+    target_description: String,
+    physical_memory_layout: String,
+    /// Memory map of the guest virtual addresses mapped by any virtual processor,
+    /// built when the debugger first asks for it.
+    memory_map: Option<String>,
+    /// Virtual processor register and memory requests apply to.
+    vp_id: u32,
+    no_ack_mode: bool,
+}
+
+impl<'a, R: SavedStateReader + ?Sized> GdbServer<'a, R> {
+    /// Creates a server over the given provider, using the architecture of virtual processor 0
+    /// for the register layout of every thread.
+    pub fn new(provider: &'a R) -> VmSavedStateDumpResult<GdbServer<'a, R>> {
+        let architecture = provider.get_vp_architecture(0)?;
+        let registers = gdb_registers(architecture);
+        let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+
+        Ok(GdbServer {
+            provider,
+            vp_count: provider.vp_count()?,
+            target_description: target_description(architecture, &registers),
+            physical_memory_layout: physical_memory_layout(page_size, &memory_chunks),
+            memory_map: None,
+            registers,
+            vp_id: 0,
+            no_ack_mode: false,
+        })
+    }
+
+    /// Accepts debugger connections on the given address, serving them one at a time.
+    /// This only returns when accepting a connection fails.
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> VmSavedStateDumpResult<()> {
+        let listener = TcpListener::bind(address)?;
+        loop {
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            // A dropped connection only ends that debugging session
+            let _ = self.serve(stream);
+        }
+    }
+
+    /// Serves a debugger connected through the given stream, until it detaches, kills the
+    /// target or closes the connection.
+    pub fn serve<S: Read + Write>(&mut self, stream: S) -> VmSavedStateDumpResult<()> {
+        self.vp_id = 0;
+        self.no_ack_mode = false;
+
+        let mut stream = BufReader::new(stream);
+        let mut byte = [0u8; 1];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(());
+            }
+
+            let reply = match byte[0] {
+                b'$' => match self.read_packet(&mut stream)? {
+                    Some(packet) => {
+                        let done = packet == b"k" || packet.starts_with(b"D");
+                        let reply = self.handle_packet(&packet);
+                        self.write_packet(stream.get_mut(), &reply)?;
+                        if done {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                    None => continue,
+                },
+                INTERRUPT => self.stop_reply(),
+                // Acknowledgments, and anything between packets
+                _ => continue,
+            };
+            self.write_packet(stream.get_mut(), &reply)?;
+        }
+    }
+
+    /// Reads the rest of a packet after its `$`, acknowledging it unless in no ack mode.
+    /// Returns None for packets with a wrong checksum, whose retransmission is requested.
+    fn read_packet<S: Read + Write>(
+        &self,
+        stream: &mut BufReader<S>,
+    ) -> VmSavedStateDumpResult<Option<Vec<u8>>> {
+        let mut packet = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            stream.read_exact(&mut byte)?;
+            if byte[0] == b'#' {
+                break;
+            }
+            packet.push(byte[0]);
+            if packet.len() > PACKET_SIZE {
+                return Err(ResultCode::Io(std::io::ErrorKind::InvalidData).into());
+            }
+        }
+
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum)?;
+        let valid = parse_hex(&checksum) == Some(u64::from(self::checksum(&packet)));
+
+        if !self.no_ack_mode {
+            stream
+                .get_mut()
+                .write_all(if valid { b"+" } else { b"-" })?;
+        }
+
+        // Escaped bytes are only sent by binary write packets, which are refused anyway,
+        // and are left escaped
+        Ok(if valid { Some(packet) } else { None })
+    }
+
+    fn write_packet<S: Write>(&self, stream: &mut S, data: &[u8]) -> VmSavedStateDumpResult<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+        stream.write_all(&packet)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn stop_reply(&self) -> Vec<u8> {
+        format!("T{:02x}thread:{:x};", STOP_SIGNAL, self.vp_id + 1).into_bytes()
+    }
+
+    /// Returns the virtual processor of a thread id, where 0 and -1 mean any or all of them.
+    fn thread_vp_id(&self, thread_id: &[u8]) -> Option<u32> {
+        match thread_id {
+            b"0" | b"-1" => Some(self.vp_id),
+            _ => match parse_hex(thread_id)? {
+                0 => None,
+                thread_id if thread_id <= u64::from(self.vp_count) => Some(thread_id as u32 - 1),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the reply to a packet, which is empty for packets that aren't supported.
+    pub fn handle_packet(&mut self, packet: &[u8]) -> Vec<u8> {
+        let (command, arguments) = match packet.split_first() {
+            Some((command, arguments)) => (*command, arguments),
+            None => return Vec::new(),
+        };
+
+        match command {
+            b'?' => self.stop_reply(),
+            b'g' => self.read_registers(),
+            b'p' => match parse_hex(arguments) {
+                Some(number) => self.read_register(number as usize),
+                None => error_reply(22),
+            },
+            b'm' => match parse_range(arguments) {
+                Some((address, length)) => self.read_memory(address, length),
+                None => error_reply(22),
+            },
+            b'H' if !arguments.is_empty() => match self.thread_vp_id(&arguments[1..]) {
+                Some(vp_id) => {
+                    self.vp_id = vp_id;
+                    b"OK".to_vec()
+                }
+                None => error_reply(22),
+            },
+            b'T' => match self.thread_vp_id(arguments) {
+                Some(_) => b"OK".to_vec(),
+                None => error_reply(22),
+            },
+            // The saved state can't be resumed, so it stops right away
+            b'c' | b's' | b'C' | b'S' => self.stop_reply(),
+            b'v' if arguments.starts_with(b"Cont;") => self.stop_reply(),
+            b'v' if arguments == b"Cont?" => b"vCont;c;C;s;S".to_vec(),
+            b'D' | b'k' => b"OK".to_vec(),
+            // Writes to registers and memory
+            b'G' | b'P' | b'M' | b'X' => error_reply(1),
+            b'q' => self.handle_query(arguments),
+            b'Q' if arguments == b"StartNoAckMode" => {
+                self.no_ack_mode = true;
+                b"OK".to_vec()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn handle_query(&mut self, query: &[u8]) -> Vec<u8> {
+        if query.starts_with(b"Supported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;qXfer:memory-map:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            )
+            .into_bytes();
+        }
+        if let Some(range) = query.strip_prefix(b"Xfer:features:read:target.xml:") {
+            return transfer(self.target_description.as_bytes(), range);
+        }
+        if let Some(range) = query.strip_prefix(b"Xfer:memory-map:read::") {
+            let memory_map = match &self.memory_map {
+                Some(memory_map) => memory_map,
+                None => self.memory_map.insert(memory_map(&self.mapped_ranges())),
+            };
+            return transfer(memory_map.as_bytes(), range);
+        }
+        if let Some(command) = query.strip_prefix(b"Rcmd,") {
+            return self.monitor_command(command);
+        }
+        if let Some(thread_id) = query.strip_prefix(b"ThreadExtraInfo,") {
+            return match self.thread_vp_id(thread_id) {
+                Some(vp_id) => {
+                    to_hex(format!("Virtual processor {}", vp_id).as_bytes()).into_bytes()
+                }
+                None => error_reply(22),
+            };
+        }
+
+        match query {
+            b"C" => format!("QC{:x}", self.vp_id + 1).into_bytes(),
+            b"fThreadInfo" => {
+                let thread_ids: Vec<String> = (1..=self.vp_count)
+                    .map(|thread_id| format!("{:x}", thread_id))
+                    .collect();
+                format!("m{}", thread_ids.join(",")).into_bytes()
+            }
+            b"sThreadInfo" => b"l".to_vec(),
+            b"Attached" => b"1".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the ranges of guest virtual addresses mapped by any virtual processor, walking
+    /// the page tables of each address space once. Virtual processors whose page tables
+    /// can't be walked add nothing.
+    fn mapped_ranges(&self) -> Vec<MappedRange> {
+        let mut address_spaces = HashSet::new();
+        let mut ranges = Vec::new();
+        for vp_id in 0..self.vp_count {
+            let address_space = match (
+                self.provider.get_vp_paging_mode(vp_id),
+                self.provider.get_vp_control_registers(vp_id),
+            ) {
+                (Ok(paging_mode), Ok(control_registers)) => (
+                    paging_mode,
+                    control_registers.cr3,
+                    control_registers.cr4.la57(),
+                ),
+                _ => continue,
+            };
+            if address_spaces.insert(address_space) {
+                ranges.extend(mapped_ranges(self.provider, vp_id).unwrap_or_default());
+            }
+        }
+
+        ranges.sort_by_key(|range| range.virtual_address);
+        let mut merged = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            MappedRange::push_merged(&mut merged, range);
+        }
+        merged
+    }
+
+    /// Replies to a `qRcmd` packet, sent by the `monitor` command of gdb with the hex encoded
+    /// command, with the hex encoded text the command prints.
+    fn monitor_command(&self, command: &[u8]) -> Vec<u8> {
+        let command = match parse_hex_bytes(command) {
+            Some(command) => command,
+            None => return error_reply(22),
+        };
+
+        let output = match String::from_utf8_lossy(&command).trim() {
+            "chunks" => self.physical_memory_layout.clone(),
+            "help" | "" => MONITOR_HELP.to_string(),
+            command => format!("Unknown monitor command {}\n{}", command, MONITOR_HELP),
+        };
+        to_hex(output.as_bytes()).into_bytes()
+    }
+
+    /// Returns the little endian bytes of a register, or None if it isn't available.
+    fn register_bytes(&self, register: &GdbRegister) -> VmSavedStateDumpResult<Option<Vec<u8>>> {
+        let value: u128 = match register.source {
+            RegisterSource::Register(register) => u128::from(
+                self.provider
+                    .get_vp_register_value(self.vp_id, register)?
+                    .value,
+            ),
+            RegisterSource::Xmm(index) => self.provider.get_vp_xmm_register(self.vp_id, index)?,
+            RegisterSource::FpControl => u128::from(
+                self.provider
+                    .get_vp_fp_control_status(self.vp_id)?
+                    .control
+                    .raw(),
+            ),
+            RegisterSource::FpStatus => u128::from(
+                self.provider
+                    .get_vp_fp_control_status(self.vp_id)?
+                    .status
+                    .raw(),
+            ),
+            RegisterSource::FpTag => {
+                // Expands the abridged tag word, marking every valid register as valid
+                // and every other one as empty
+                let tag = self.provider.get_vp_fp_control_status(self.vp_id)?.tag;
+                (0..8)
+                    .filter(|index| tag & (1 << index) == 0)
+                    .fold(0u128, |full_tag, index| full_tag | (0b11 << (index * 2)))
+            }
+            RegisterSource::FpInstructionOffset => u128::from(
+                self.provider
+                    .get_vp_fp_control_status(self.vp_id)?
+                    .last_instruction_pointer as u32,
+            ),
+            RegisterSource::FpDataOffset => u128::from(
+                self.provider
+                    .get_vp_xmm_control_status(self.vp_id)?
+                    .last_data_pointer as u32,
+            ),
+            RegisterSource::FpOpcode => u128::from(
+                self.provider
+                    .get_vp_fp_control_status(self.vp_id)?
+                    .last_opcode
+                    & 0x7FF,
+            ),
+            RegisterSource::Mxcsr => u128::from(
+                self.provider
+                    .get_vp_xmm_control_status(self.vp_id)?
+                    .mxcsr
+                    .raw(),
+            ),
+            RegisterSource::Unavailable => return Ok(None),
+        };
+
+        Ok(Some(value.to_le_bytes()[..register.bit_size / 8].to_vec()))
+    }
+
+    fn register_hex(&self, register: &GdbRegister) -> String {
+        match self.register_bytes(register) {
+            Ok(Some(bytes)) => to_hex(&bytes),
+            // Registers that can't be read are reported as unavailable
+            _ => "xx".repeat(register.bit_size / 8),
+        }
+    }
+
+    fn read_registers(&self) -> Vec<u8> {
+        self.registers
+            .iter()
+            .map(|register| self.register_hex(register))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn read_register(&self, number: usize) -> Vec<u8> {
+        match self.registers.get(number) {
+            Some(register) => self.register_hex(register).into_bytes(),
+            None => error_reply(22),
+        }
+    }
+
+    fn read_memory(&self, address: u64, length: u64) -> Vec<u8> {
+        let mut buffer = vec![0u8; std::cmp::min(length, PACKET_SIZE as u64 / 2) as usize];
+        match self
+            .provider
+            .read_guest_virtual_address(self.vp_id, address, &mut buffer)
+        {
+            Ok(bytes_read) if bytes_read > 0 || buffer.is_empty() => {
+                to_hex(&buffer[..bytes_read as usize]).into_bytes()
+            }
+            _ => error_reply(14),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const VIRTUAL_BASE: u64 = 0xFFFF_F800_0000_0000;
+
+    /// Two x64 virtual processors over two data pages, the first holding its offsets and the
+    /// second filled with 0xBB, followed by the page tables of each processor. At
+    /// `VIRTUAL_BASE`, the first processor maps both pages in order; the second one maps them
+    /// swapped and four pages apart, plus a 2MB page over the first 2MB of memory.
+    struct FakeReader {
+        registers: HashMap<(u32, Register), u64>,
+        memory: Vec<u8>,
+    }
+
+    impl FakeReader {
+        fn new() -> FakeReader {
+            let mut registers = HashMap::new();
+            registers.insert(
+                (0, Register::X64(RegisterIdx64::Rax)),
+                0x1122_3344_5566_7788,
+            );
+            registers.insert((0, Register::X64(RegisterIdx64::Rip)), VIRTUAL_BASE + 0x10);
+            registers.insert((1, Register::X64(RegisterIdx64::Rax)), 1);
+            registers.insert((0, Register::X64(RegisterIdx64::Cr3)), 0x2000);
+            registers.insert((1, Register::X64(RegisterIdx64::Cr3)), 0x6000);
+
+            let mut memory: Vec<u8> = (0..0x1000).map(|offset| offset as u8).collect();
+            memory.resize(0x2000, 0xBB);
+            memory.resize(0xA000, 0);
+            let pml4_index = ((VIRTUAL_BASE >> 39) & 0x1FF) as usize;
+            let entries = [
+                // First processor: PML4, PDPT, page directory and page table
+                (0x2000 + pml4_index * 8, 0x3003),
+                (0x3000, 0x4003),
+                (0x4000, 0x5003),
+                (0x5000, 0x0003),
+                (0x5008, 0x1003),
+                // Second processor, whose page directory also maps a 2MB page
+                (0x6000 + pml4_index * 8, 0x7003),
+                (0x7000, 0x8003),
+                (0x8000, 0x9003),
+                (0x8008, 0x0083),
+                (0x9000, 0x1003),
+                (0x9020, 0x0003),
+            ];
+            for (address, entry) in entries.iter() {
+                memory[*address..*address + 8].copy_from_slice(&u64::to_le_bytes(*entry));
+            }
+            FakeReader { registers, memory }
+        }
+    }
+
+    impl SavedStateReader for FakeReader {
+        fn vp_count(&self) -> VmSavedStateDumpResult<u32> {
+            Ok(2)
+        }
+
+        fn get_vp_architecture(&self, _: u32) -> VmSavedStateDumpResult<VirtualProcessorArch> {
+            Ok(VirtualProcessorArch::X64)
+        }
+
+        fn get_vp_register_value(
+            &self,
+            vp_id: u32,
+            register: Register,
+        ) -> VmSavedStateDumpResult<VirtualProcessorRegister> {
+            let value = self.registers.get(&(vp_id, register)).copied();
+            Ok(VirtualProcessorRegister {
+                register,
+                value: value.unwrap_or(0),
+            })
+        }
+
+        fn get_vp_paging_mode(&self, _: u32) -> VmSavedStateDumpResult<PagingMode> {
+            Ok(PagingMode::Long)
+        }
+
+        fn read_guest_physical_address(
+            &self,
+            physical_address: GuestPhysicalAddress,
+            buffer: &mut [u8],
+        ) -> VmSavedStateDumpResult<u32> {
+            let start = std::cmp::min(physical_address, self.memory.len() as u64) as usize;
+            let end = std::cmp::min(start + buffer.len(), self.memory.len());
+            buffer[..end - start].copy_from_slice(&self.memory[start..end]);
+            Ok((end - start) as u32)
+        }
+
+        fn guest_virtual_to_physical_address(
+            &self,
+            vp_id: u32,
+            virtual_address: GuestVirtualAddress,
+        ) -> VmSavedStateDumpResult<GuestPhysicalAddress> {
+            walk_page_tables(self, vp_id, virtual_address)?
+                .physical_address
+                .ok_or_else(|| ResultCode::InvalidArgument.into())
+        }
+
+        fn guest_physical_memory_chunks(
+            &self,
+        ) -> VmSavedStateDumpResult<(u64, Vec<GpaMemoryChunk>)> {
+            Ok((
+                0x1000,
+                vec![GpaMemoryChunk {
+                    guest_physical_start_page_index: 0,
+                    page_count: 0xA,
+                }],
+            ))
+        }
+    }
+
+    fn register_numbers(architecture: VirtualProcessorArch) -> Vec<&'static str> {
+        gdb_registers(architecture)
+            .iter()
+            .map(|register| register.name)
+            .collect()
+    }
+
+    #[test]
+    fn packet_checksums() {
+        assert_eq!(0, checksum(b""));
+        assert_eq!(0x3F, checksum(b"?"));
+        assert_eq!(0x37, checksum(b"qSupported"));
+        // The sum wraps around
+        assert_eq!(0x02, checksum(&[0xFF, 0x03]));
+    }
+
+    #[test]
+    fn hex_and_ranges() {
+        assert_eq!("00ff7f", to_hex(&[0x00, 0xFF, 0x7F]));
+        assert_eq!(Some(0xFFFF_F800_0000_1000), parse_hex(b"fffff80000001000"));
+        assert_eq!(None, parse_hex(b"xyz"));
+        assert_eq!(Some((0x1000, 0x10)), parse_range(b"1000,10"));
+        assert_eq!(None, parse_range(b"1000"));
+        assert_eq!(None, parse_range(b"1000,"));
+        assert_eq!(Some(b"chunks".to_vec()), parse_hex_bytes(b"6368756e6b73"));
+        assert_eq!(Some(Vec::new()), parse_hex_bytes(b""));
+        assert_eq!(None, parse_hex_bytes(b"636"));
+        assert_eq!(None, parse_hex_bytes(b"zz"));
+        assert_eq!(b"E16".to_vec(), error_reply(22));
+    }
+
+    #[test]
+    fn register_layouts() {
+        let x64 = register_numbers(VirtualProcessorArch::X64);
+        assert_eq!("rax", x64[0]);
+        assert_eq!("rip", x64[16]);
+        assert_eq!("eflags", x64[17]);
+        assert_eq!("xmm15", x64[x64.len() - 2]);
+        assert_eq!("mxcsr", x64[x64.len() - 1]);
+
+        let x86 = register_numbers(VirtualProcessorArch::X86);
+        assert_eq!("eax", x86[0]);
+        assert_eq!("eip", x86[8]);
+        assert_eq!("xmm7", x86[x86.len() - 2]);
+    }
+
+    #[test]
+    fn target_descriptions() {
+        let registers = gdb_registers(VirtualProcessorArch::X64);
+        let xml = target_description(VirtualProcessorArch::X64, &registers);
+        assert!(xml.contains("<architecture>i386:x86-64</architecture>"));
+        assert!(xml.contains("<feature name=\"org.gnu.gdb.i386.core\">"));
+        assert!(xml.contains("<feature name=\"org.gnu.gdb.i386.sse\">"));
+        assert!(xml.contains("<reg name=\"rip\" bitsize=\"64\" type=\"code_ptr\" regnum=\"16\"/>"));
+        assert_eq!(registers.len(), xml.matches("<reg ").count());
+        assert!(xml.ends_with("</target>\n"));
+
+        let registers = gdb_registers(VirtualProcessorArch::X86);
+        let xml = target_description(VirtualProcessorArch::X86, &registers);
+        assert!(xml.contains("<architecture>i386</architecture>"));
+        assert!(xml.contains("<reg name=\"eip\" bitsize=\"32\" type=\"code_ptr\" regnum=\"8\"/>"));
+    }
+
+    #[test]
+    fn physical_memory_layouts() {
+        let memory_chunks = [
+            GpaMemoryChunk {
+                guest_physical_start_page_index: 0,
+                page_count: 0xA0,
+            },
+            GpaMemoryChunk {
+                guest_physical_start_page_index: 0x100,
+                page_count: 0x3F00,
+            },
+        ];
+        assert_eq!(
+            "0000000000000000 - 00000000000a0000  0xa0 pages\n\
+             0000000000100000 - 0000000004000000  0x3f00 pages\n",
+            physical_memory_layout(0x1000, &memory_chunks)
+        );
+        assert_eq!("", physical_memory_layout(0x1000, &[]));
+    }
+
+    #[test]
+    fn transfers_in_chunks() {
+        let object = b"0123456789";
+        assert_eq!(b"m0123".to_vec(), transfer(object, b"0,4"));
+        assert_eq!(b"m4567".to_vec(), transfer(object, b"4,4"));
+        assert_eq!(b"l89".to_vec(), transfer(object, b"8,4"));
+        assert_eq!(b"l0123456789".to_vec(), transfer(object, b"0,a"));
+        assert_eq!(b"l".to_vec(), transfer(object, b"a,4"));
+        assert_eq!(b"l".to_vec(), transfer(object, b"ffffffff,4"));
+        assert_eq!(b"E16".to_vec(), transfer(object, b"4"));
+
+        // Replies are capped to what fits in a packet once hex encoded
+        let object = vec![b'a'; PACKET_SIZE];
+        let reply = transfer(&object, b"0,ffffffff");
+        assert_eq!(b'm', reply[0]);
+        assert_eq!(PACKET_SIZE / 2, reply.len() - 1);
+        let reply = transfer(&object, format!("{:x},ffff", PACKET_SIZE / 2).as_bytes());
+        assert_eq!(b'l', reply[0]);
+    }
+
+    #[test]
+    fn transfers_escape_special_bytes() {
+        assert_eq!(
+            b"l}\x03}\x04}]}\x0a<x>".to_vec(),
+            transfer(b"#$}*<x>", b"0,10")
+        );
+    }
+
+    #[test]
+    fn reads_registers() {
+        let reader = FakeReader::new();
+        let mut server = GdbServer::new(&reader).unwrap();
+
+        assert_eq!(b"8877665544332211".to_vec(), server.handle_packet(b"p0"));
+        assert_eq!(b"1000000000f8ffff".to_vec(), server.handle_packet(b"p10"));
+        // eflags is reported in 32 bits
+        assert_eq!(b"00000000".to_vec(), server.handle_packet(b"p11"));
+        // st0 isn't saved
+        assert_eq!(
+            b"xxxxxxxxxxxxxxxxxxxx".to_vec(),
+            server.handle_packet(b"p18")
+        );
+        assert_eq!(b"E16".to_vec(), server.handle_packet(b"p100"));
+        assert_eq!(b"E16".to_vec(), server.handle_packet(b"pxyz"));
+
+        let registers = server.handle_packet(b"g");
+        let register_bytes: usize = gdb_registers(VirtualProcessorArch::X64)
+            .iter()
+            .map(|register| register.bit_size / 8)
+            .sum();
+        assert_eq!(register_bytes * 2, registers.len());
+        assert!(registers.starts_with(b"8877665544332211"));
+    }
+
+    #[test]
+    fn reads_memory() {
+        let reader = FakeReader::new();
+        let mut server = GdbServer::new(&reader).unwrap();
+
+        let packet = format!("m{:x},4", VIRTUAL_BASE + 0x10);
+        assert_eq!(
+            b"10111213".to_vec(),
+            server.handle_packet(packet.as_bytes())
+        );
+        let packet = format!("m{:x},0", VIRTUAL_BASE);
+        assert_eq!(b"".to_vec(), server.handle_packet(packet.as_bytes()));
+        // Reads cross pages, and unmapped addresses fail with EFAULT
+        let packet = format!("m{:x},4", VIRTUAL_BASE + 0xFFE);
+        assert_eq!(
+            b"feffbbbb".to_vec(),
+            server.handle_packet(packet.as_bytes())
+        );
+        let packet = format!("m{:x},4", VIRTUAL_BASE + 0x2000);
+        assert_eq!(b"E0e".to_vec(), server.handle_packet(packet.as_bytes()));
+        assert_eq!(b"E16".to_vec(), server.handle_packet(b"m1000"));
+    }
+
+    #[test]
+    fn selects_threads() {
+        let reader = FakeReader::new();
+        let mut server = GdbServer::new(&reader).unwrap();
+
+        assert_eq!(b"m1,2".to_vec(), server.handle_packet(b"qfThreadInfo"));
+        assert_eq!(b"l".to_vec(), server.handle_packet(b"qsThreadInfo"));
+        assert_eq!(b"QC1".to_vec(), server.handle_packet(b"qC"));
+
+        // Registers and memory follow the selected thread
+        assert_eq!(b"OK".to_vec(), server.handle_packet(b"Hg2"));
+        assert_eq!(b"QC2".to_vec(), server.handle_packet(b"qC"));
+        assert_eq!(b"0100000000000000".to_vec(), server.handle_packet(b"p0"));
+        let packet = format!("m{:x},2", VIRTUAL_BASE);
+        assert_eq!(b"bbbb".to_vec(), server.handle_packet(packet.as_bytes()));
+        assert_eq!(b"T05thread:2;".to_vec(), server.handle_packet(b"?"));
+
+        // Any thread keeps the current one, and threads past the last one are refused
+        assert_eq!(b"OK".to_vec(), server.handle_packet(b"Hc-1"));
+        assert_eq!(b"OK".to_vec(), server.handle_packet(b"Hg0"));
+        assert_eq!(b"QC2".to_vec(), server.handle_packet(b"qC"));
+        assert_eq!(b"E16".to_vec(), server.handle_packet(b"Hg3"));
+        assert_eq!(b"E16".to_vec(), server.handle_packet(b"T3"));
+        assert_eq!(b"OK".to_vec(), server.handle_packet(b"T1"));
+    }
+
+    #[test]
+    fn serves_virtual_memory_map() {
+        let reader = FakeReader::new();
+        let mut server = GdbServer::new(&reader).unwrap();
+
+        let supported = server.handle_packet(b"qSupported:multiprocess+");
+        assert!(String::from_utf8(supported)
+            .unwrap()
+            .contains(";qXfer:memory-map:read+;"));
+
+        // The map is the union of the addresses every processor maps, and covers what `m` reads
+        let reply = server.handle_packet(b"qXfer:memory-map:read::0,fff");
+        assert_eq!(b'l', reply[0]);
+        let xml = String::from_utf8(reply[1..].to_vec()).unwrap();
+        assert_eq!(
+            "<memory type=\"ram\" start=\"0xfffff80000000000\" length=\"0x2000\"/>\n\
+             <memory type=\"ram\" start=\"0xfffff80000004000\" length=\"0x1000\"/>\n\
+             <memory type=\"ram\" start=\"0xfffff80000200000\" length=\"0x200000\"/>\n",
+            &xml[xml.find("<memory ").unwrap()..xml.find("</memory-map>").unwrap()]
+        );
+        assert!(xml.starts_with("<?xml version=\"1.0\"?>\n<!DOCTYPE memory-map"));
+        assert!(xml.ends_with("</memory-map>\n"));
+
+        // The physical layout stays available through `monitor chunks`
+        let reply = server.handle_packet(format!("qRcmd,{}", to_hex(b"chunks")).as_bytes());
+        assert_eq!(
+            to_hex(b"0000000000000000 - 000000000000a000  0xa pages\n").into_bytes(),
+            reply
+        );
+    }
+}
@@ -22,18 +22,56 @@
 //! let bin_vsv_provider = VmSavedStateDumpProvider::load_bin_vsv("file_path.bin", "file_path.vsv");
 //! ```
 //!
+//! Files can also be loaded through `open::SavedStateOpenOptions`, which detects the format
+//! from the file extension or header, finds the companion file of BIN/VSV pairs and can apply
+//! the pending replay log beforehand.
+//!
+//! `summary::SavedStateSummary` gathers the virtual processors, registers and guest memory
+//! layout of a saved state in one call. Enabling the `serde` feature makes it, along with
+//! the definitions it is made of, serializable.
+//!
+//! `shell::Shell` runs WinDbg-like commands over a loaded saved state, such as displaying
+//! memory at register relative addresses or walking page tables with `paging::walk_page_tables`.
+//! Its `u` command disassembles instructions when the `iced-x86` feature is enabled.
+//!
+//! `gdb::GdbServer` serves a loaded saved state over the GDB remote serial protocol,
+//! exposing each virtual processor as a thread so debuggers can attach to the frozen VM.
+//! It reads through the `vmsavedstatedump::SavedStateReader` trait, which the provider
+//! implements, so it can also serve guest state read by other backends.
+//!
+//! Enabling the `capi` feature exports a C ABI from the crate's `cdylib`, declared in the
+//! `include/vmss.h` header, so the saved state can be read from C and other languages with
+//! a C FFI. The build script generates the header into `OUT_DIR`, and the
+//! checked in copy is kept up to date with it by the integration tests.
+//!
+//! Enabling the `python` feature builds the `vmsavedstatedump` Python extension module
+//! (`python::PyVmSavedStateDumpProvider` and friends), which releases the GIL while reading.
+//!
 //! Once a provider has been instantiated, all of its related APIs can be used in the context
 //! of a loaded VM saved state file.
 //!
 //! The best source of code examples on how to use the APIs are the integration tests,
 //! found [here](https://github.com/rafawo/vmsavedstatetodump-rs/blob/master/vmsavedstatedump-rs/tests/integration_test.rs).
 
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod descriptors;
+pub mod dump;
+pub mod gdb;
+pub mod open;
+pub mod paging;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod registers;
+pub mod shell;
+pub mod summary;
 pub mod vmsavedstatedump;
 pub(crate) mod vmsavedstatedump_bindings;
 pub mod vmsavedstatedumpdefs;
 
 /// Module that declares aliases to windows definitions used by this crate
 /// using Rust's naming conventions.
+#[cfg(windows)]
 pub(crate) mod windefs {
     pub type DWord = winapi::shared::minwindef::DWORD;
     pub type HResult = winapi::shared::ntdef::HRESULT;
@@ -42,3 +80,15 @@ pub(crate) mod windefs {
     pub type PVoid = winapi::shared::ntdef::PVOID;
     pub type Void = winapi::shared::ntdef::VOID;
 }
+
+/// Module that declares the same windows definitions on other platforms,
+/// where winapi provides none of them.
+#[cfg(not(windows))]
+pub(crate) mod windefs {
+    pub type DWord = u32;
+    pub type HResult = i32;
+    pub type LPCWStr = *const u16;
+    pub type LPWStr = *mut u16;
+    pub type PVoid = *mut std::ffi::c_void;
+    pub type Void = std::ffi::c_void;
+}
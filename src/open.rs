@@ -0,0 +1,293 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module provides a builder that unifies the different ways to load VM saved state files,
+//! deciding which files to load and how to prepare them before loading,
+//! along with the detection of the format of a file from its header.
+//!
+//! ```rust,ignore
+//! let provider = SavedStateOpenOptions::new()
+//!     .replay_log(ReplayLog::ApplyToCopy(PathBuf::from("copy.vmrs")))
+//!     .page_cache_capacity(1024)
+//!     .open("file_path.vmrs")?;
+//! ```
+
+use crate::dump::elf::ET_CORE;
+use crate::dump::kdump::KDUMP_SIGNATURE;
+use crate::dump::lime::LIME_MAGIC;
+use crate::dump::windows::{DUMP32_SIGNATURE, DUMP64_SIGNATURE, PAGE_SIGNATURE};
+use crate::vmsavedstatedump::*;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Signature found at the start of saved state BIN memory files.
+const BIN_SIGNATURE: u32 = 0x0218_2003;
+
+/// Size of the header read to detect the format of a file.
+const DETECTION_HEADER_SIZE: usize = 0x20;
+
+/// Formats a file can be detected as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DetectedFormat {
+    /// VMRS saved state file.
+    Vmrs,
+    /// BIN memory file of a BIN/VSV saved state pair.
+    Bin,
+    /// VSV state file of a BIN/VSV saved state pair.
+    Vsv,
+    ElfCore,
+    WindowsCrashDump,
+    Kdump,
+    Lime,
+    Unknown,
+}
+
+/// Format of a file, along with the file it has to be loaded with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FormatDetection {
+    pub format: DetectedFormat,
+    /// File expected next to the detected one, which isn't checked for existence.
+    pub companion: Option<PathBuf>,
+}
+
+/// Detects the format of a file from the signature at the start of the given header.
+/// VMRS and VSV files have no signature this crate knows of, and are never detected here.
+pub fn detect_format_from_header(header: &[u8]) -> DetectedFormat {
+    let get_u32 = |offset: usize| {
+        header
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    if header.starts_with(&[0x7F, b'E', b'L', b'F'])
+        && header.get(0x10..0x12) == Some(&ET_CORE.to_le_bytes()[..])
+    {
+        DetectedFormat::ElfCore
+    } else if get_u32(0x0) == Some(PAGE_SIGNATURE)
+        && (get_u32(0x4) == Some(DUMP32_SIGNATURE) || get_u32(0x4) == Some(DUMP64_SIGNATURE))
+    {
+        DetectedFormat::WindowsCrashDump
+    } else if header.starts_with(KDUMP_SIGNATURE) {
+        DetectedFormat::Kdump
+    } else if get_u32(0x0) == Some(LIME_MAGIC) {
+        DetectedFormat::Lime
+    } else if get_u32(0x0) == Some(BIN_SIGNATURE) {
+        DetectedFormat::Bin
+    } else {
+        DetectedFormat::Unknown
+    }
+}
+
+/// Detects the format of the file at the given path from its header. Files whose header
+/// isn't recognized fall back to their extension, which is how VMRS and VSV files are detected.
+pub fn detect_format<P: AsRef<Path>>(path: P) -> VmSavedStateDumpResult<FormatDetection> {
+    let path = path.as_ref();
+    let mut header = Vec::with_capacity(DETECTION_HEADER_SIZE);
+    std::fs::File::open(path)?
+        .take(DETECTION_HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+
+    let format = match detect_format_from_header(&header) {
+        DetectedFormat::Unknown => match extension_of(path).as_deref() {
+            Some("vmrs") => DetectedFormat::Vmrs,
+            Some("bin") => DetectedFormat::Bin,
+            Some("vsv") => DetectedFormat::Vsv,
+            _ => DetectedFormat::Unknown,
+        },
+        format => format,
+    };
+    let companion = match format {
+        DetectedFormat::Bin => Some(path.with_extension("vsv")),
+        DetectedFormat::Vsv => Some(path.with_extension("bin")),
+        _ => None,
+    };
+
+    Ok(FormatDetection { format, companion })
+}
+
+/// Returns the lowercase extension of a path.
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+}
+
+/// Formats VM saved state files can be stored in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SavedStateFormat {
+    /// Single VMRS file.
+    Vmrs,
+    /// Legacy pair of a BIN memory file and a VSV state file.
+    BinVsv,
+}
+
+/// What to do with the replay log pending in a VMRS file before loading it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReplayLog {
+    /// Loads the file as is, without applying the pending replay log.
+    Ignore,
+    /// Applies the pending replay log to the file itself, modifying it.
+    ApplyInPlace,
+    /// Copies the file to the given path and applies the pending replay log to the copy,
+    /// which is the file that gets loaded. The copy is left in place for the caller to remove.
+    /// A path that resolves to the file itself is rejected with `InvalidArgument`.
+    ApplyToCopy(PathBuf),
+}
+
+/// Implementations that can read VM saved state files.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SavedStateBackend {
+    /// vmsavedstatedumpprovider.dll, from the Windows SDK. This is the only backend available,
+    /// and it fails to load any file with `E_NOTIMPL` on platforms other than Windows.
+    VmSavedStateDumpProvider,
+}
+
+/// Options and flags used to configure how VM saved state files are loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SavedStateOpenOptions {
+    format: Option<SavedStateFormat>,
+    companion: Option<PathBuf>,
+    replay_log: ReplayLog,
+    backend: SavedStateBackend,
+    page_cache_capacity: usize,
+}
+
+impl Default for SavedStateOpenOptions {
+    fn default() -> Self {
+        SavedStateOpenOptions::new()
+    }
+}
+
+impl SavedStateOpenOptions {
+    /// Creates options that detect the format of the file, ignore the pending
+    /// replay log and don't cache pages.
+    pub fn new() -> SavedStateOpenOptions {
+        SavedStateOpenOptions {
+            format: None,
+            companion: None,
+            replay_log: ReplayLog::Ignore,
+            backend: SavedStateBackend::VmSavedStateDumpProvider,
+            page_cache_capacity: 0,
+        }
+    }
+
+    /// Sets the format of the file, instead of detecting it.
+    pub fn format(&mut self, format: SavedStateFormat) -> &mut SavedStateOpenOptions {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the file that is loaded along with a BIN or VSV file, instead of looking for
+    /// the file with the same name and the other extension.
+    pub fn companion<P: AsRef<Path>>(&mut self, path: P) -> &mut SavedStateOpenOptions {
+        self.companion = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets what to do with the pending replay log of a VMRS file.
+    pub fn replay_log(&mut self, replay_log: ReplayLog) -> &mut SavedStateOpenOptions {
+        self.replay_log = replay_log;
+        self
+    }
+
+    /// Sets the implementation that reads the files.
+    pub fn backend(&mut self, backend: SavedStateBackend) -> &mut SavedStateOpenOptions {
+        self.backend = backend;
+        self
+    }
+
+    /// Caches up to the given count of guest physical pages read from the loaded files.
+    pub fn page_cache_capacity(&mut self, page_count: usize) -> &mut SavedStateOpenOptions {
+        self.page_cache_capacity = page_count;
+        self
+    }
+
+    /// Returns the files that are loaded when opening the given path. The format is detected
+    /// from the extension of the file, or from its header when the extension isn't known.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> VmSavedStateDumpResult<VmSavedStateFile> {
+        let path = path.as_ref();
+        let extension = extension_of(path);
+
+        let format = match (self.format, extension.as_deref()) {
+            (Some(SavedStateFormat::Vmrs), _) | (None, Some("vmrs")) => DetectedFormat::Vmrs,
+            (_, Some("vsv")) => DetectedFormat::Vsv,
+            (Some(SavedStateFormat::BinVsv), _) | (None, Some("bin")) => DetectedFormat::Bin,
+            (None, _) => detect_format(path)?.format,
+        };
+
+        match format {
+            DetectedFormat::Vmrs => Ok(VmSavedStateFile::Vmrs(path.to_path_buf())),
+            DetectedFormat::Bin => Ok(VmSavedStateFile::BinVsv(
+                path.to_path_buf(),
+                self.companion_or(path, "vsv"),
+            )),
+            DetectedFormat::Vsv => Ok(VmSavedStateFile::BinVsv(
+                self.companion_or(path, "bin"),
+                path.to_path_buf(),
+            )),
+            _ => Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+                .with_operation("SavedStateOpenOptions::resolve")),
+        }
+    }
+
+    /// Returns the companion file, or the given path with the given extension.
+    fn companion_or(&self, path: &Path, extension: &str) -> PathBuf {
+        match &self.companion {
+            Some(companion) => companion.clone(),
+            None => path.with_extension(extension),
+        }
+    }
+
+    /// Loads the VM saved state file(s) found at the given path with these options.
+    pub fn open<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> VmSavedStateDumpResult<VmSavedStateDumpProvider> {
+        let mut provider = match self.backend {
+            SavedStateBackend::VmSavedStateDumpProvider => match self.resolve(path)? {
+                VmSavedStateFile::Vmrs(vmrs) => match &self.replay_log {
+                    ReplayLog::Ignore => VmSavedStateDumpProvider::load_vmrs(vmrs)?,
+                    ReplayLog::ApplyInPlace => {
+                        apply_pending_replay_log(&vmrs)?;
+                        VmSavedStateDumpProvider::load_vmrs(vmrs)?
+                    }
+                    ReplayLog::ApplyToCopy(copy) => {
+                        // Copying a file over itself would truncate it
+                        if is_same_file(&vmrs, copy)? {
+                            return Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+                                .with_operation("SavedStateOpenOptions::open"));
+                        }
+                        std::fs::copy(&vmrs, copy)?;
+                        apply_pending_replay_log(copy)?;
+                        VmSavedStateDumpProvider::load_vmrs(copy)?
+                    }
+                },
+                VmSavedStateFile::BinVsv(bin, vsv) => match &self.replay_log {
+                    ReplayLog::Ignore => VmSavedStateDumpProvider::load_bin_vsv(bin, vsv)?,
+                    // Only VMRS files carry a replay log
+                    _ => {
+                        return Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+                            .with_operation("SavedStateOpenOptions::open"))
+                    }
+                },
+            },
+        };
+
+        provider.set_page_cache_capacity(self.page_cache_capacity);
+        Ok(provider)
+    }
+}
+
+/// Returns whether both paths resolve to the same existing file.
+fn is_same_file(path: &Path, other: &Path) -> VmSavedStateDumpResult<bool> {
+    match other.canonicalize() {
+        Ok(other) => Ok(path.canonicalize()? == other),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
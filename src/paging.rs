@@ -0,0 +1,495 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module walks the page tables of a virtual processor, exposing every paging structure
+//! entry a guest virtual address translates through, and enumerates the ranges of guest
+//! virtual addresses they map.
+
+use crate::registers::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::fmt;
+
+/// Paging structures an entry can belong to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PageTableLevel {
+    /// Only used by 5-level paging, when CR4.LA57 is set.
+    Pml5,
+    Pml4,
+    Pdpt,
+    PageDirectory,
+    PageTable,
+}
+
+impl PageTableLevel {
+    /// Returns the name given to the entries of this paging structure.
+    pub fn entry_name(&self) -> &'static str {
+        match self {
+            PageTableLevel::Pml5 => "PML5E",
+            PageTableLevel::Pml4 => "PML4E",
+            PageTableLevel::Pdpt => "PDPTE",
+            PageTableLevel::PageDirectory => "PDE",
+            PageTableLevel::PageTable => "PTE",
+        }
+    }
+}
+
+/// Entry of a paging structure, along with where it was read from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PageTableEntry {
+    pub level: PageTableLevel,
+    /// Guest physical address the entry is stored at.
+    pub address: GuestPhysicalAddress,
+    pub value: u64,
+}
+
+impl PageTableEntry {
+    pub fn present(&self) -> bool {
+        self.value & (1 << 0) != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.value & (1 << 1) != 0
+    }
+
+    pub fn user(&self) -> bool {
+        self.value & (1 << 2) != 0
+    }
+
+    pub fn accessed(&self) -> bool {
+        self.value & (1 << 5) != 0
+    }
+
+    pub fn dirty(&self) -> bool {
+        self.value & (1 << 6) != 0
+    }
+
+    /// Returns the page size flag, which page directory and PDPT entries set when they map
+    /// a large page instead of referencing the next paging structure.
+    pub fn page_size(&self) -> bool {
+        self.level != PageTableLevel::PageTable && self.value & (1 << 7) != 0
+    }
+
+    pub fn no_execute(&self) -> bool {
+        self.value & (1 << 63) != 0
+    }
+}
+
+impl fmt::Display for PageTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<5} at {:#018x} contains {:#018x}",
+            self.level.entry_name(),
+            self.address,
+            self.value
+        )?;
+        if !self.present() {
+            return write!(f, " not present");
+        }
+
+        let flags = [
+            (self.writable(), "W", "R"),
+            (self.user(), "U", "K"),
+            (self.accessed(), "A", "-"),
+            (self.dirty(), "D", "-"),
+            (self.page_size(), "L", "-"),
+            (self.no_execute(), "-", "E"),
+        ];
+        write!(f, " ")?;
+        for (set, set_flag, clear_flag) in flags.iter() {
+            write!(f, "{}", if *set { set_flag } else { clear_flag })?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of translating a guest virtual address through the page tables of a virtual processor.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PageTableWalk {
+    pub virtual_address: GuestVirtualAddress,
+    pub paging_mode: PagingMode,
+    /// Entries the address translated through, ending at the entry that maps the page
+    /// or at the first entry that isn't present.
+    pub entries: Vec<PageTableEntry>,
+    /// Guest physical address the virtual address translates to, if it is mapped.
+    pub physical_address: Option<GuestPhysicalAddress>,
+}
+
+/// Paging structures of a paging mode: level, index shift, index bit count.
+const LA57_LEVELS: [(PageTableLevel, u32, u32); 5] = [
+    (PageTableLevel::Pml5, 48, 9),
+    (PageTableLevel::Pml4, 39, 9),
+    (PageTableLevel::Pdpt, 30, 9),
+    (PageTableLevel::PageDirectory, 21, 9),
+    (PageTableLevel::PageTable, 12, 9),
+];
+const LONG_MODE_LEVELS: [(PageTableLevel, u32, u32); 4] = [
+    (PageTableLevel::Pml4, 39, 9),
+    (PageTableLevel::Pdpt, 30, 9),
+    (PageTableLevel::PageDirectory, 21, 9),
+    (PageTableLevel::PageTable, 12, 9),
+];
+const PAE_LEVELS: [(PageTableLevel, u32, u32); 3] = [
+    (PageTableLevel::Pdpt, 30, 2),
+    (PageTableLevel::PageDirectory, 21, 9),
+    (PageTableLevel::PageTable, 12, 9),
+];
+const BIT32_LEVELS: [(PageTableLevel, u32, u32); 2] = [
+    (PageTableLevel::PageDirectory, 22, 10),
+    (PageTableLevel::PageTable, 12, 10),
+];
+
+/// Physical address bits of 64 bit paging structure entries.
+const ENTRY_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Paging structures a virtual processor translates addresses through.
+struct PagingStructures {
+    paging_mode: PagingMode,
+    levels: &'static [(PageTableLevel, u32, u32)],
+    root_address: GuestPhysicalAddress,
+    entry_size: usize,
+    /// CR4.PSE, which enables the 4MB pages of 32 bit paging.
+    pse: bool,
+}
+
+impl PagingStructures {
+    /// Returns the paging structures of the given paging mode, or None if it doesn't page.
+    fn new(paging_mode: PagingMode, control_registers: &ControlRegisters) -> Option<Self> {
+        let cr3 = control_registers.cr3;
+        let (levels, root_address, entry_size): (&'static [_], u64, usize) = match paging_mode {
+            PagingMode::Long if control_registers.cr4.la57() => {
+                (&LA57_LEVELS, cr3 & ENTRY_ADDRESS_MASK, 8)
+            }
+            PagingMode::Long => (&LONG_MODE_LEVELS, cr3 & ENTRY_ADDRESS_MASK, 8),
+            PagingMode::Pae => (&PAE_LEVELS, cr3 & 0xFFFF_FFE0, 8),
+            PagingMode::Bit32 => (&BIT32_LEVELS, cr3 & 0xFFFF_F000, 4),
+            PagingMode::NonPaged | PagingMode::Invalid => return None,
+        };
+
+        Some(PagingStructures {
+            paging_mode,
+            levels,
+            root_address,
+            entry_size,
+            pse: control_registers.cr4.pse(),
+        })
+    }
+
+    /// Returns whether a present entry maps a page, rather than referencing the next paging
+    /// structure. 4MB pages of 32 bit paging only exist with CR4.PSE set,
+    /// and neither PML5, PML4 nor PAE PDPT entries can map a page.
+    fn maps_page(&self, entry: &PageTableEntry) -> bool {
+        match (self.paging_mode, entry.level) {
+            (_, PageTableLevel::PageTable) => true,
+            (_, PageTableLevel::Pml5)
+            | (_, PageTableLevel::Pml4)
+            | (PagingMode::Pae, PageTableLevel::Pdpt) => false,
+            (PagingMode::Bit32, _) => entry.page_size() && self.pse,
+            _ => entry.page_size(),
+        }
+    }
+
+    /// Returns the guest physical address of the page an entry at the given index shift maps.
+    fn page_address(&self, entry: &PageTableEntry, shift: u32) -> GuestPhysicalAddress {
+        match self.paging_mode {
+            // Bits 39:32 of the address of a 4MB page are stored in bits 20:13
+            PagingMode::Bit32 if entry.level == PageTableLevel::PageDirectory => {
+                (entry.value & 0xFFC0_0000) | (((entry.value >> 13) & 0xFF) << 32)
+            }
+            PagingMode::Bit32 => entry.value & 0xFFFF_F000,
+            _ => entry.value & ENTRY_ADDRESS_MASK & !((1 << shift) - 1),
+        }
+    }
+
+    /// Returns the guest physical address of the paging structure an entry references.
+    fn table_address(&self, entry: &PageTableEntry) -> GuestPhysicalAddress {
+        match self.paging_mode {
+            PagingMode::Bit32 => entry.value & 0xFFFF_F000,
+            _ => entry.value & ENTRY_ADDRESS_MASK,
+        }
+    }
+
+    /// Returns the canonical form of a virtual address built from table indexes,
+    /// which sign extends the highest translated bit in long mode.
+    fn canonical(&self, virtual_address: GuestVirtualAddress) -> GuestVirtualAddress {
+        match self.paging_mode {
+            PagingMode::Long => {
+                let (_, shift, index_bits) = self.levels[0];
+                let unused_bits = 64 - (shift + index_bits);
+                (((virtual_address << unused_bits) as i64) >> unused_bits) as u64
+            }
+            _ => virtual_address,
+        }
+    }
+}
+
+/// Translates a guest virtual address through the page tables of the given virtual processor,
+/// reading every paging structure from guest physical memory.
+pub fn walk_page_tables<R: SavedStateReader + ?Sized>(
+    provider: &R,
+    vp_id: u32,
+    virtual_address: GuestVirtualAddress,
+) -> VmSavedStateDumpResult<PageTableWalk> {
+    let paging_mode = provider.get_vp_paging_mode(vp_id)?;
+    let control_registers = provider.get_vp_control_registers(vp_id)?;
+
+    let structures = match PagingStructures::new(paging_mode, &control_registers) {
+        Some(structures) => structures,
+        None if paging_mode == PagingMode::NonPaged => {
+            return Ok(PageTableWalk {
+                virtual_address,
+                paging_mode,
+                entries: Vec::new(),
+                physical_address: Some(virtual_address),
+            })
+        }
+        None => {
+            return Err(VmSavedStateDumpError::new(ResultCode::Unexpected)
+                .with_operation("walk_page_tables")
+                .with_vp_id(vp_id)
+                .with_address(virtual_address))
+        }
+    };
+
+    let mut walk = PageTableWalk {
+        virtual_address,
+        paging_mode,
+        entries: Vec::new(),
+        physical_address: None,
+    };
+    let entry_size = structures.entry_size;
+    let mut table_address = structures.root_address;
+
+    for (level, shift, index_bits) in structures.levels {
+        let index = (virtual_address >> shift) & ((1 << index_bits) - 1);
+        let address = table_address + index * entry_size as u64;
+        let mut raw = [0u8; 8];
+        let bytes_read =
+            provider.read_guest_physical_address(address, &mut raw[..entry_size])? as usize;
+        if bytes_read != entry_size {
+            return Err(VmSavedStateDumpError::new(ResultCode::Io(
+                std::io::ErrorKind::UnexpectedEof,
+            ))
+            .with_operation("walk_page_tables")
+            .with_vp_id(vp_id)
+            .with_address(address));
+        }
+
+        let entry = PageTableEntry {
+            level: *level,
+            address,
+            value: u64::from_le_bytes(raw),
+        };
+        walk.entries.push(entry);
+        if !entry.present() {
+            return Ok(walk);
+        }
+
+        if structures.maps_page(&entry) {
+            let page_offset = virtual_address & ((1 << shift) - 1);
+            walk.physical_address = Some(structures.page_address(&entry, *shift) | page_offset);
+            return Ok(walk);
+        }
+
+        table_address = structures.table_address(&entry);
+    }
+
+    Ok(walk)
+}
+
+/// Range of guest virtual addresses mapped by the page tables of a virtual processor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MappedRange {
+    pub virtual_address: GuestVirtualAddress,
+    pub size: u64,
+}
+
+impl MappedRange {
+    /// Appends a range to ranges sorted by address, merging it with the last one
+    /// when they overlap or are adjacent.
+    pub(crate) fn push_merged(ranges: &mut Vec<MappedRange>, range: MappedRange) {
+        if let Some(last) = ranges.last_mut() {
+            // Ends are exclusive, so the last range of the address space ends past u64::MAX
+            let last_end = u128::from(last.virtual_address) + u128::from(last.size);
+            if u128::from(range.virtual_address) <= last_end {
+                let end = u128::from(range.virtual_address) + u128::from(range.size);
+                last.size =
+                    (std::cmp::max(last_end, end) - u128::from(last.virtual_address)) as u64;
+                return;
+            }
+        }
+        ranges.push(range);
+    }
+}
+
+/// Returns the ranges of guest virtual addresses the page tables of the given virtual processor
+/// map, in address order and with adjacent ranges merged. Without paging, virtual addresses
+/// are physical ones, and these are the guest physical memory chunks. Paging structures that
+/// can't be read map nothing.
+pub fn mapped_ranges<R: SavedStateReader + ?Sized>(
+    provider: &R,
+    vp_id: u32,
+) -> VmSavedStateDumpResult<Vec<MappedRange>> {
+    let paging_mode = provider.get_vp_paging_mode(vp_id)?;
+    let control_registers = provider.get_vp_control_registers(vp_id)?;
+    let mut ranges = Vec::new();
+
+    match PagingStructures::new(paging_mode, &control_registers) {
+        Some(structures) => collect_mapped_ranges(
+            provider,
+            &structures,
+            0,
+            structures.root_address,
+            0,
+            &mut ranges,
+        ),
+        None if paging_mode == PagingMode::NonPaged => {
+            let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+            for chunk in memory_chunks {
+                let range = MappedRange {
+                    virtual_address: chunk.guest_physical_start_page_index * page_size,
+                    size: chunk.page_count * page_size,
+                };
+                MappedRange::push_merged(&mut ranges, range);
+            }
+        }
+        None => {
+            return Err(VmSavedStateDumpError::new(ResultCode::Unexpected)
+                .with_operation("mapped_ranges")
+                .with_vp_id(vp_id))
+        }
+    }
+
+    Ok(ranges)
+}
+
+/// Appends the ranges mapped through the paging structure at the given level and address,
+/// whose first entry translates the given virtual address.
+fn collect_mapped_ranges<R: SavedStateReader + ?Sized>(
+    provider: &R,
+    structures: &PagingStructures,
+    depth: usize,
+    table_address: GuestPhysicalAddress,
+    virtual_address: GuestVirtualAddress,
+    ranges: &mut Vec<MappedRange>,
+) {
+    let (level, shift, index_bits) = structures.levels[depth];
+    let entry_size = structures.entry_size;
+    let mut table = vec![0u8; (1 << index_bits) * entry_size];
+    let bytes_read = provider
+        .read_guest_physical_address(table_address, &mut table)
+        .unwrap_or(0) as usize;
+
+    for (index, raw) in table[..bytes_read].chunks_exact(entry_size).enumerate() {
+        let mut value = [0u8; 8];
+        value[..entry_size].copy_from_slice(raw);
+        let entry = PageTableEntry {
+            level,
+            address: table_address + (index * entry_size) as u64,
+            value: u64::from_le_bytes(value),
+        };
+        if !entry.present() {
+            continue;
+        }
+
+        let entry_virtual_address = virtual_address | (index as u64) << shift;
+        if structures.maps_page(&entry) {
+            let range = MappedRange {
+                virtual_address: structures.canonical(entry_virtual_address),
+                size: 1 << shift,
+            };
+            MappedRange::push_merged(ranges, range);
+        } else if depth + 1 < structures.levels.len() {
+            collect_mapped_ranges(
+                provider,
+                structures,
+                depth + 1,
+                structures.table_address(&entry),
+                entry_virtual_address,
+                ranges,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_table_entry_decoding() {
+        let entry = PageTableEntry {
+            level: PageTableLevel::PageDirectory,
+            address: 0x3000,
+            value: 0x8000_0000_0020_00E3,
+        };
+        assert!(entry.present());
+        assert!(entry.writable());
+        assert!(!entry.user());
+        assert!(entry.accessed());
+        assert!(entry.dirty());
+        assert!(entry.page_size());
+        assert!(entry.no_execute());
+        assert_eq!(
+            "PDE   at 0x0000000000003000 contains 0x80000000002000e3 WKADL-",
+            entry.to_string()
+        );
+
+        // Bit 7 of a page table entry is the PAT bit, not the page size flag
+        let entry = PageTableEntry {
+            level: PageTableLevel::PageTable,
+            address: 0x4008,
+            value: 0x0000_0000_0040_0085,
+        };
+        assert!(!entry.writable());
+        assert!(entry.user());
+        assert!(!entry.page_size());
+        assert!(!entry.no_execute());
+        assert_eq!(
+            "PTE   at 0x0000000000004008 contains 0x0000000000400085 RU---E",
+            entry.to_string()
+        );
+
+        let entry = PageTableEntry {
+            level: PageTableLevel::Pml5,
+            address: 0x1000,
+            value: 0x0000_0000_0000_2002,
+        };
+        assert!(!entry.present());
+        assert_eq!(
+            "PML5E at 0x0000000000001000 contains 0x0000000000002002 not present",
+            entry.to_string()
+        );
+    }
+
+    #[test]
+    fn mapped_ranges_merge() {
+        let range = |virtual_address, size| MappedRange {
+            virtual_address,
+            size,
+        };
+        let mut ranges = Vec::new();
+        MappedRange::push_merged(&mut ranges, range(0x1000, 0x1000));
+        // Adjacent and overlapping ranges extend the last one, others are appended
+        MappedRange::push_merged(&mut ranges, range(0x2000, 0x1000));
+        MappedRange::push_merged(&mut ranges, range(0x2800, 0x200));
+        MappedRange::push_merged(&mut ranges, range(0x5000, 0x1000));
+        // Ranges ending at the top of the address space don't overflow
+        MappedRange::push_merged(&mut ranges, range(0xFFFF_FFFF_FFE0_0000, 0x20_0000));
+        MappedRange::push_merged(&mut ranges, range(0xFFFF_FFFF_FFFF_F000, 0x1000));
+        assert_eq!(
+            vec![
+                range(0x1000, 0x2000),
+                range(0x5000, 0x1000),
+                range(0xFFFF_FFFF_FFE0_0000, 0x20_0000),
+            ],
+            ranges
+        );
+    }
+}
@@ -0,0 +1,675 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module implements the `vmsavedstatedump` Python extension module, built when the
+//! `python` feature is enabled, for example with `maturin build --features python`.
+//!
+//! Calls into a loaded saved state release the GIL, so other Python threads keep running
+//! while guest memory is read or dump files are written.
+//!
+//! Besides saved states loaded through the VmSavedStateDump API, a provider can wrap any other
+//! `SavedStateReader` with `PyVmSavedStateDumpProvider::from_reader`, which works where the API
+//! isn't available, such as on Linux.
+
+use crate::dump::elf::*;
+use crate::dump::kdump::*;
+use crate::dump::lime::*;
+use crate::dump::raw::*;
+use crate::dump::windows::*;
+use crate::dump::*;
+use crate::open::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use pyo3::create_exception;
+use pyo3::exceptions::{
+    PyException, PyIndexError, PyNotImplementedError, PyRuntimeError, PyRuntimeWarning,
+    PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+create_exception!(
+    vmsavedstatedump,
+    SavedStateError,
+    PyException,
+    "Error returned while reading a VM saved state."
+);
+
+fn to_py_err(error: VmSavedStateDumpError) -> PyErr {
+    SavedStateError::new_err(error.to_string())
+}
+
+/// Saved state read by a Python provider.
+enum SavedState {
+    /// Loaded through the VmSavedStateDump API, which every operation works with.
+    Provider(VmSavedStateDumpProvider),
+    /// Read by another backend, which only the `SavedStateReader` operations work with.
+    Reader(Box<dyn SavedStateReader + Send>),
+}
+
+impl SavedState {
+    fn reader(&self) -> &dyn SavedStateReader {
+        match self {
+            SavedState::Provider(provider) => provider,
+            SavedState::Reader(reader) => reader.as_ref(),
+        }
+    }
+
+    /// Returns the loaded provider, raising NotImplementedError for other backends.
+    fn provider(&self) -> PyResult<&VmSavedStateDumpProvider> {
+        match self {
+            SavedState::Provider(provider) => Ok(provider),
+            SavedState::Reader(_) => Err(PyNotImplementedError::new_err(
+                "only saved states loaded through the VmSavedStateDump API support this",
+            )),
+        }
+    }
+}
+
+/// Saved state that can be moved to the threads running with the GIL released.
+struct SendProvider(SavedState);
+
+// The handle of a loaded provider isn't bound to the thread that loaded it, other readers
+// are Send, and the mutex holding the saved state serializes every access to it,
+// page cache included.
+unsafe impl Send for SendProvider {}
+
+thread_local! {
+    /// Addresses of the providers whose mutex the current thread holds.
+    static HELD_PROVIDERS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks a provider as held by the current thread until dropped, so that Python code called
+/// while holding its mutex, such as a `write_dump` progress callable, raises RuntimeError
+/// when it uses the same provider instead of deadlocking on the mutex.
+struct HeldProvider(usize);
+
+impl HeldProvider {
+    fn hold(provider: &PyVmSavedStateDumpProvider) -> PyResult<HeldProvider> {
+        let address = provider as *const PyVmSavedStateDumpProvider as usize;
+        HELD_PROVIDERS.with(|held| {
+            let mut held = held.borrow_mut();
+            if held.contains(&address) {
+                return Err(PyRuntimeError::new_err(
+                    "the saved state is already in use by this thread, \
+                     which is the case in write_dump progress callables",
+                ));
+            }
+            held.push(address);
+            Ok(HeldProvider(address))
+        })
+    }
+}
+
+impl Drop for HeldProvider {
+    fn drop(&mut self) {
+        HELD_PROVIDERS.with(|held| held.borrow_mut().retain(|address| *address != self.0));
+    }
+}
+
+/// Loaded VM saved state.
+#[pyclass(name = "VmSavedStateDumpProvider", module = "vmsavedstatedump", frozen)]
+pub struct PyVmSavedStateDumpProvider {
+    provider: Mutex<Option<SendProvider>>,
+}
+
+impl PyVmSavedStateDumpProvider {
+    fn load<F>(py: Python, load: F) -> PyResult<PyVmSavedStateDumpProvider>
+    where
+        F: FnOnce() -> VmSavedStateDumpResult<VmSavedStateDumpProvider> + Send,
+    {
+        let provider = py
+            .allow_threads(|| load().map(|provider| SendProvider(SavedState::Provider(provider))))
+            .map_err(to_py_err)?;
+        Ok(PyVmSavedStateDumpProvider {
+            provider: Mutex::new(Some(provider)),
+        })
+    }
+
+    /// Wraps guest state read by a backend other than the VmSavedStateDump API, such as one
+    /// that works on Linux, so it can be handed to Python. Reading raw saved memory and writing
+    /// dumps need a loaded saved state, and raise NotImplementedError for it.
+    pub fn from_reader<R>(reader: R) -> PyVmSavedStateDumpProvider
+    where
+        R: SavedStateReader + Send + 'static,
+    {
+        PyVmSavedStateDumpProvider {
+            provider: Mutex::new(Some(SendProvider(SavedState::Reader(Box::new(reader))))),
+        }
+    }
+
+    /// Runs a call on the saved state with the GIL released. The mutex is only waited on
+    /// without holding the GIL, so a thread blocked on it never blocks other Python threads.
+    fn with_saved_state<T, F>(&self, py: Python, call: F) -> PyResult<T>
+    where
+        T: Send,
+        F: FnOnce(&SavedState) -> PyResult<T> + Send,
+    {
+        py.allow_threads(|| {
+            let _held = HeldProvider::hold(self)?;
+            let provider = self
+                .provider
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match provider.as_ref() {
+                Some(provider) => call(&provider.0),
+                None => Err(PyValueError::new_err("the saved state is closed")),
+            }
+        })
+    }
+
+    /// Runs a call on the saved state through the operations every backend supports.
+    fn with_reader<T, F>(&self, py: Python, call: F) -> PyResult<T>
+    where
+        T: Send,
+        F: FnOnce(&dyn SavedStateReader) -> VmSavedStateDumpResult<T> + Send,
+    {
+        self.with_saved_state(py, |saved_state| {
+            call(saved_state.reader()).map_err(to_py_err)
+        })
+    }
+
+    /// Runs a call on a saved state loaded through the VmSavedStateDump API.
+    fn with_provider<T, F>(&self, py: Python, call: F) -> PyResult<T>
+    where
+        T: Send,
+        F: FnOnce(&VmSavedStateDumpProvider) -> VmSavedStateDumpResult<T> + Send,
+    {
+        self.with_saved_state(py, |saved_state| {
+            call(saved_state.provider()?).map_err(to_py_err)
+        })
+    }
+}
+
+/// Largest size of a single read, so that a wrong size raises instead of exhausting memory.
+const MAX_READ_SIZE: usize = 0x400_0000;
+
+/// Reads up to `size` bytes with the given read call, returning the bytes actually read.
+/// Sizes over `MAX_READ_SIZE` raise ValueError before allocating anything.
+fn read_bytes<'py, F>(
+    py: Python<'py>,
+    provider: &PyVmSavedStateDumpProvider,
+    size: usize,
+    read: F,
+) -> PyResult<Bound<'py, PyBytes>>
+where
+    F: FnOnce(&SavedState, &mut [u8]) -> PyResult<u32> + Send,
+{
+    if size > MAX_READ_SIZE {
+        return Err(PyValueError::new_err(format!(
+            "read size {:#x} is over the maximum of {:#x} bytes",
+            size, MAX_READ_SIZE
+        )));
+    }
+
+    let buffer = provider.with_saved_state(py, |saved_state| {
+        let mut buffer = vec![0u8; size];
+        let bytes_read = read(saved_state, &mut buffer)? as usize;
+        buffer.truncate(bytes_read);
+        Ok(buffer)
+    })?;
+    Ok(PyBytes::new(py, &buffer))
+}
+
+/// Dump formats, named as in the `vmss2dump convert` command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum DumpFormat {
+    WindowsFull,
+    WindowsBitmap,
+    Elf,
+    Kdump,
+    Lime,
+    Raw,
+}
+
+impl DumpFormat {
+    fn from_name(name: &str) -> PyResult<DumpFormat> {
+        match name {
+            "windows-full" => Ok(DumpFormat::WindowsFull),
+            "windows-bitmap" => Ok(DumpFormat::WindowsBitmap),
+            "elf" => Ok(DumpFormat::Elf),
+            "kdump" => Ok(DumpFormat::Kdump),
+            "lime" => Ok(DumpFormat::Lime),
+            "raw" => Ok(DumpFormat::Raw),
+            _ => Err(PyValueError::new_err(format!(
+                "unknown dump format {}, expected one of windows-full, windows-bitmap, elf, kdump, lime, raw",
+                name
+            ))),
+        }
+    }
+
+    fn write(
+        self,
+        provider: &VmSavedStateDumpProvider,
+        path: &Path,
+        kd_debugger_data_encoding: Option<KdDebuggerDataEncoding>,
+        control: &mut DumpControl,
+    ) -> VmSavedStateDumpResult<DumpStatistics> {
+        let mut output = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let windows_options = |dump_type| WindowsCrashDumpOptions {
+            dump_type,
+            kd_debugger_data_encoding,
+            ..Default::default()
+        };
+
+        match self {
+            DumpFormat::WindowsFull => write_windows_crash_dump(
+                provider,
+                &mut output,
+                &windows_options(WindowsDumpType::Full),
+                control,
+            ),
+            DumpFormat::WindowsBitmap => write_windows_crash_dump(
+                provider,
+                &mut output,
+                &windows_options(WindowsDumpType::Bitmap),
+                control,
+            ),
+            DumpFormat::Elf => {
+                write_elf_core(provider, &mut output, &ElfCoreOptions::default(), control)
+            }
+            DumpFormat::Kdump => {
+                write_kdump(provider, &mut output, &KdumpOptions::default(), control)
+            }
+            DumpFormat::Lime => write_lime_image(provider, &mut output, control),
+            DumpFormat::Raw => {
+                write_raw_image(provider, &mut output, &RawImageOptions::default(), control)
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl PyVmSavedStateDumpProvider {
+    /// Loads the VM saved state file(s) found at the given path, detecting their format.
+    #[new]
+    #[pyo3(signature = (path, companion = None, page_cache_capacity = 0))]
+    fn new(
+        py: Python,
+        path: PathBuf,
+        companion: Option<PathBuf>,
+        page_cache_capacity: usize,
+    ) -> PyResult<PyVmSavedStateDumpProvider> {
+        PyVmSavedStateDumpProvider::load(py, move || {
+            let mut options = SavedStateOpenOptions::new();
+            options.page_cache_capacity(page_cache_capacity);
+            if let Some(companion) = companion {
+                options.companion(companion);
+            }
+            options.open(path)
+        })
+    }
+
+    #[staticmethod]
+    fn load_vmrs(py: Python, vmrs: PathBuf) -> PyResult<PyVmSavedStateDumpProvider> {
+        PyVmSavedStateDumpProvider::load(py, move || VmSavedStateDumpProvider::load_vmrs(vmrs))
+    }
+
+    #[staticmethod]
+    fn load_bin_vsv(
+        py: Python,
+        bin: PathBuf,
+        vsv: PathBuf,
+    ) -> PyResult<PyVmSavedStateDumpProvider> {
+        PyVmSavedStateDumpProvider::load(py, move || {
+            VmSavedStateDumpProvider::load_bin_vsv(bin, vsv)
+        })
+    }
+
+    /// Releases the loaded saved state. Any further call raises ValueError.
+    fn close(&self, py: Python) -> PyResult<()> {
+        py.allow_threads(|| {
+            let _held = HeldProvider::hold(self)?;
+            let provider = self
+                .provider
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take();
+            match provider {
+                Some(SendProvider(SavedState::Provider(provider))) => {
+                    provider.close().map_err(to_py_err)
+                }
+                Some(SendProvider(SavedState::Reader(_))) | None => Ok(()),
+            }
+        })
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type = None, _exc_value = None, _traceback = None))]
+    fn __exit__(
+        &self,
+        py: Python,
+        _exc_type: Option<PyObject>,
+        _exc_value: Option<PyObject>,
+        _traceback: Option<PyObject>,
+    ) -> PyResult<bool> {
+        self.close(py)?;
+        Ok(false)
+    }
+
+    fn vp_count(&self, py: Python) -> PyResult<u32> {
+        self.with_reader(py, |reader| reader.vp_count())
+    }
+
+    /// Returns the virtual processor with the given id.
+    fn vp(slf: &Bound<'_, Self>, vp_id: u32) -> PyResult<PyVirtualProcessor> {
+        if vp_id >= slf.get().vp_count(slf.py())? {
+            return Err(PyIndexError::new_err(format!(
+                "virtual processor {} doesn't exist",
+                vp_id
+            )));
+        }
+        Ok(PyVirtualProcessor {
+            provider: slf.clone().unbind(),
+            id: vp_id,
+        })
+    }
+
+    /// Returns every virtual processor of the saved state.
+    fn virtual_processors(slf: &Bound<'_, Self>) -> PyResult<Vec<PyVirtualProcessor>> {
+        let vp_count = slf.get().vp_count(slf.py())?;
+        Ok((0..vp_count)
+            .map(|vp_id| PyVirtualProcessor {
+                provider: slf.clone().unbind(),
+                id: vp_id,
+            })
+            .collect())
+    }
+
+    /// Reads up to `size` bytes of guest physical memory.
+    fn read_physical<'py>(
+        &self,
+        py: Python<'py>,
+        physical_address: u64,
+        size: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        read_bytes(py, self, size, move |saved_state, buffer| {
+            saved_state
+                .reader()
+                .read_guest_physical_address(physical_address, buffer)
+                .map_err(to_py_err)
+        })
+    }
+
+    /// Reads up to `size` bytes of guest virtual memory, translated through the given virtual processor.
+    fn read_virtual<'py>(
+        &self,
+        py: Python<'py>,
+        vp_id: u32,
+        virtual_address: u64,
+        size: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        read_bytes(py, self, size, move |saved_state, buffer| {
+            saved_state
+                .reader()
+                .read_guest_virtual_address(vp_id, virtual_address, buffer)
+                .map_err(to_py_err)
+        })
+    }
+
+    fn virtual_to_physical(&self, py: Python, vp_id: u32, virtual_address: u64) -> PyResult<u64> {
+        self.with_reader(py, |reader| {
+            reader.guest_virtual_to_physical_address(vp_id, virtual_address)
+        })
+    }
+
+    /// Returns the page size and the (start page index, page count) chunks of guest physical memory.
+    fn memory_chunks(&self, py: Python) -> PyResult<(u64, Vec<(u64, u64)>)> {
+        let (page_size, chunks) =
+            self.with_reader(py, |reader| reader.guest_physical_memory_chunks())?;
+        let chunks = chunks
+            .iter()
+            .map(|chunk| (chunk.guest_physical_start_page_index, chunk.page_count))
+            .collect();
+        Ok((page_size, chunks))
+    }
+
+    /// Returns the size of the guest memory saved in the file. Raises NotImplementedError for
+    /// providers wrapping another reader, as does `read_raw_saved_memory`.
+    fn raw_saved_memory_size(&self, py: Python) -> PyResult<u64> {
+        self.with_provider(py, |provider| provider.guest_raw_saved_memory_size())
+    }
+
+    /// Reads up to `size` bytes of the guest memory saved in the file, as a flat layout.
+    fn read_raw_saved_memory<'py>(
+        &self,
+        py: Python<'py>,
+        offset: u64,
+        size: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        read_bytes(py, self, size, move |saved_state, buffer| {
+            saved_state
+                .provider()?
+                .read_guest_raw_saved_memory(offset, buffer)
+                .map_err(to_py_err)
+        })
+    }
+
+    /// Writes a dump of the saved state to the given path, in one of the formats of
+    /// `vmss2dump convert`. The optional progress callable is called with the bytes of guest
+    /// memory written so far and the total to write; raising from it cancels the dump.
+    /// The callable can't use this provider, or its virtual processors, which raises RuntimeError.
+    /// Windows dumps of Windows 8 and later 64-bit guests need the
+    /// `(KiWaitNever, KiWaitAlways, &KdpDataBlockEncoded)` keys their KdDebuggerDataBlock
+    /// is encoded with; when it isn't found, the dump is written without it and RuntimeWarning
+    /// is issued. Raises NotImplementedError for providers wrapping another reader.
+    #[pyo3(signature = (path, format, progress = None, kd_debugger_data_encoding = None))]
+    fn write_dump(
+        &self,
+        py: Python,
+        path: PathBuf,
+        format: &str,
+        progress: Option<PyObject>,
+        kd_debugger_data_encoding: Option<(u64, u64, u64)>,
+    ) -> PyResult<DumpStatistics> {
+        let format = DumpFormat::from_name(format)?;
+        let kd_debugger_data_encoding =
+            kd_debugger_data_encoding.map(|(wait_never, wait_always, data_block_encoded)| {
+                KdDebuggerDataEncoding {
+                    wait_never,
+                    wait_always,
+                    data_block_encoded,
+                }
+            });
+        let mut progress_error = None;
+
+        let written = self.with_provider(py, |provider| {
+            let cancellation = CancellationToken::new();
+            let cancel = cancellation.clone();
+            let progress_error = &mut progress_error;
+            let mut control = DumpControl {
+                progress: progress.as_ref().map(|progress| {
+                    Box::new(move |update: DumpProgress| {
+                        if progress_error.is_some() {
+                            return;
+                        }
+                        let result = Python::with_gil(|py| {
+                            progress.call1(py, (update.bytes_written, update.total_bytes))
+                        });
+                        if let Err(error) = result {
+                            *progress_error = Some(error);
+                            cancel.cancel();
+                        }
+                    }) as Box<dyn FnMut(DumpProgress) + '_>
+                }),
+                cancellation: Some(cancellation),
+                ..Default::default()
+            };
+            format.write(provider, &path, kd_debugger_data_encoding, &mut control)
+        });
+
+        let written = match progress_error {
+            Some(error) => Err(error),
+            None => written,
+        }?;
+        if written.kd_debugger_data_block_missing {
+            PyErr::warn(
+                py,
+                &py.get_type::<PyRuntimeWarning>(),
+                pyo3::ffi::c_str!(
+                    "the KdDebuggerDataBlock wasn't found, so the dump doesn't point to it"
+                ),
+                1,
+            )?;
+        }
+        Ok(written)
+    }
+}
+
+/// Register of a virtual processor, either a register enum or its name, such as "rip".
+#[derive(FromPyObject)]
+enum RegisterArgument {
+    X86(RegisterIdx86),
+    X64(RegisterIdx64),
+    Name(String),
+}
+
+/// Virtual processor of a loaded VM saved state.
+#[pyclass(name = "VirtualProcessor", module = "vmsavedstatedump", frozen)]
+pub struct PyVirtualProcessor {
+    provider: Py<PyVmSavedStateDumpProvider>,
+    id: u32,
+}
+
+#[pymethods]
+impl PyVirtualProcessor {
+    #[getter]
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn architecture(&self, py: Python) -> PyResult<VirtualProcessorArch> {
+        let vp_id = self.id;
+        self.provider
+            .get()
+            .with_reader(py, |reader| reader.get_vp_architecture(vp_id))
+    }
+
+    fn paging_mode(&self, py: Python) -> PyResult<PagingMode> {
+        let vp_id = self.id;
+        self.provider
+            .get()
+            .with_reader(py, |reader| reader.get_vp_paging_mode(vp_id))
+    }
+
+    fn register_value(&self, py: Python, register: RegisterArgument) -> PyResult<u64> {
+        let register = match register {
+            RegisterArgument::X86(register_id) => Register::X86(register_id),
+            RegisterArgument::X64(register_id) => Register::X64(register_id),
+            RegisterArgument::Name(name) => {
+                match Register::from_name(self.architecture(py)?, &name) {
+                    Some(register) => register,
+                    None => {
+                        return Err(PyValueError::new_err(format!("unknown register {}", name)))
+                    }
+                }
+            }
+        };
+
+        let vp_id = self.id;
+        self.provider.get().with_reader(py, |reader| {
+            Ok(reader.get_vp_register_value(vp_id, register)?.value)
+        })
+    }
+
+    /// Returns the values of every register, keyed by register name.
+    fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let vp_id = self.id;
+        let values = self.provider.get().with_reader(py, |reader| {
+            Register::all(reader.get_vp_architecture(vp_id)?)
+                .into_iter()
+                .map(|register| {
+                    let value = reader.get_vp_register_value(vp_id, register)?.value;
+                    Ok((register.name(), value))
+                })
+                .collect::<VmSavedStateDumpResult<Vec<_>>>()
+        })?;
+
+        let registers = PyDict::new(py);
+        for (name, value) in values {
+            registers.set_item(name, value)?;
+        }
+        Ok(registers)
+    }
+
+    fn xmm_register(&self, py: Python, xmm_index: u8) -> PyResult<u128> {
+        let vp_id = self.id;
+        self.provider
+            .get()
+            .with_reader(py, |reader| reader.get_vp_xmm_register(vp_id, xmm_index))
+    }
+
+    /// Reads up to `size` bytes of guest virtual memory, translated through this virtual processor.
+    fn read_virtual<'py>(
+        &self,
+        py: Python<'py>,
+        virtual_address: u64,
+        size: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        self.provider
+            .get()
+            .read_virtual(py, self.id, virtual_address, size)
+    }
+
+    fn virtual_to_physical(&self, py: Python, virtual_address: u64) -> PyResult<u64> {
+        self.provider
+            .get()
+            .virtual_to_physical(py, self.id, virtual_address)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("VirtualProcessor({})", self.id)
+    }
+}
+
+/// Detects the format of a file from its header, or from its extension when the header isn't
+/// recognized. Returns one of "vmrs", "bin", "vsv", "elf", "windows", "kdump", "lime" or "unknown".
+#[pyfunction]
+#[pyo3(name = "detect_format")]
+fn py_detect_format(py: Python, path: PathBuf) -> PyResult<&'static str> {
+    let detection = py
+        .allow_threads(|| detect_format(path))
+        .map_err(to_py_err)?;
+    Ok(match detection.format {
+        DetectedFormat::Vmrs => "vmrs",
+        DetectedFormat::Bin => "bin",
+        DetectedFormat::Vsv => "vsv",
+        DetectedFormat::ElfCore => "elf",
+        DetectedFormat::WindowsCrashDump => "windows",
+        DetectedFormat::Kdump => "kdump",
+        DetectedFormat::Lime => "lime",
+        DetectedFormat::Unknown => "unknown",
+    })
+}
+
+/// Reads Hyper-V VM saved state files and converts them to dump files.
+#[pymodule]
+fn vmsavedstatedump(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyVmSavedStateDumpProvider>()?;
+    module.add_class::<PyVirtualProcessor>()?;
+    module.add_class::<VirtualProcessorArch>()?;
+    module.add_class::<PagingMode>()?;
+    module.add_class::<RegisterIdx86>()?;
+    module.add_class::<RegisterIdx64>()?;
+    module.add_class::<DumpStatistics>()?;
+    module.add_function(wrap_pyfunction!(py_detect_format, module)?)?;
+    module.add("SavedStateError", module.py().get_type::<SavedStateError>())?;
+    module.add("MAX_READ_SIZE", MAX_READ_SIZE)?;
+    Ok(())
+}
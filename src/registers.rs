@@ -0,0 +1,628 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module contains decoders of raw virtual processor register values
+//! into their individual named fields.
+
+use std::fmt;
+
+/// Declares a newtype over a raw register value that exposes each of the supplied
+/// single bit fields as a getter, and whose `Display` output lists the names of the set bits.
+macro_rules! decoded_register {
+    (
+        $(#[$meta:meta])*
+        $name:ident($raw:ty) {
+            $($(#[$bit_meta:meta])* $getter:ident = $bit:expr, $label:expr;)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub struct $name(pub $raw);
+
+        impl $name {
+            /// Returns the raw register value.
+            pub fn raw(&self) -> $raw {
+                self.0
+            }
+
+            $(
+                $(#[$bit_meta])*
+                pub fn $getter(&self) -> bool {
+                    self.0 & (1 << $bit) != 0
+                }
+            )*
+
+            /// Returns the names of all the set bits decoded by this type.
+            pub fn set_bit_names(&self) -> Vec<&'static str> {
+                let mut names = Vec::new();
+                $(
+                    if self.$getter() {
+                        names.push($label);
+                    }
+                )*
+                names
+            }
+        }
+
+        impl From<$raw> for $name {
+            fn from(raw: $raw) -> Self {
+                $name(raw)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "{:#0width$x} [{}]",
+                    self.0,
+                    self.set_bit_names().join(" "),
+                    width = 2 + 2 * std::mem::size_of::<$raw>()
+                )
+            }
+        }
+    };
+}
+
+/// Rounding mode encoded in the RC field of the x87 control word and of MXCSR.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RoundingControl {
+    Nearest,
+    Down,
+    Up,
+    TowardZero,
+}
+
+impl RoundingControl {
+    fn from_bits(bits: u32) -> RoundingControl {
+        match bits & 0x3 {
+            0 => RoundingControl::Nearest,
+            1 => RoundingControl::Down,
+            2 => RoundingControl::Up,
+            _ => RoundingControl::TowardZero,
+        }
+    }
+}
+
+/// Precision encoded in the PC field of the x87 control word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PrecisionControl {
+    Single,
+    Reserved,
+    Double,
+    DoubleExtended,
+}
+
+decoded_register! {
+    /// Decoded SSE control and status register.
+    Mxcsr(u32) {
+        /// Invalid operation exception flag.
+        invalid_operation = 0, "IE";
+        /// Denormal exception flag.
+        denormal = 1, "DE";
+        /// Divide by zero exception flag.
+        divide_by_zero = 2, "ZE";
+        /// Overflow exception flag.
+        overflow = 3, "OE";
+        /// Underflow exception flag.
+        underflow = 4, "UE";
+        /// Precision exception flag.
+        precision = 5, "PE";
+        /// Denormals are zeros.
+        denormals_are_zeros = 6, "DAZ";
+        /// Invalid operation exception mask.
+        invalid_operation_mask = 7, "IM";
+        /// Denormal exception mask.
+        denormal_mask = 8, "DM";
+        /// Divide by zero exception mask.
+        divide_by_zero_mask = 9, "ZM";
+        /// Overflow exception mask.
+        overflow_mask = 10, "OM";
+        /// Underflow exception mask.
+        underflow_mask = 11, "UM";
+        /// Precision exception mask.
+        precision_mask = 12, "PM";
+        /// Flush to zero.
+        flush_to_zero = 15, "FZ";
+    }
+}
+
+impl Mxcsr {
+    /// Returns the rounding mode used by SSE instructions.
+    pub fn rounding_control(&self) -> RoundingControl {
+        RoundingControl::from_bits(self.0 >> 13)
+    }
+}
+
+decoded_register! {
+    /// Decoded x87 FPU control word.
+    X87ControlWord(u16) {
+        /// Invalid operation exception mask.
+        invalid_operation_mask = 0, "IM";
+        /// Denormal operand exception mask.
+        denormal_mask = 1, "DM";
+        /// Divide by zero exception mask.
+        divide_by_zero_mask = 2, "ZM";
+        /// Overflow exception mask.
+        overflow_mask = 3, "OM";
+        /// Underflow exception mask.
+        underflow_mask = 4, "UM";
+        /// Precision exception mask.
+        precision_mask = 5, "PM";
+        /// Infinity control, only meaningful on 287 and older FPUs.
+        infinity_control = 12, "X";
+    }
+}
+
+impl X87ControlWord {
+    /// Returns the precision used by x87 instructions.
+    pub fn precision_control(&self) -> PrecisionControl {
+        match (self.0 >> 8) & 0x3 {
+            0 => PrecisionControl::Single,
+            1 => PrecisionControl::Reserved,
+            2 => PrecisionControl::Double,
+            _ => PrecisionControl::DoubleExtended,
+        }
+    }
+
+    /// Returns the rounding mode used by x87 instructions.
+    pub fn rounding_control(&self) -> RoundingControl {
+        RoundingControl::from_bits(u32::from(self.0) >> 10)
+    }
+}
+
+decoded_register! {
+    /// Decoded x87 FPU status word.
+    X87StatusWord(u16) {
+        /// Invalid operation exception flag.
+        invalid_operation = 0, "IE";
+        /// Denormal operand exception flag.
+        denormal = 1, "DE";
+        /// Divide by zero exception flag.
+        divide_by_zero = 2, "ZE";
+        /// Overflow exception flag.
+        overflow = 3, "OE";
+        /// Underflow exception flag.
+        underflow = 4, "UE";
+        /// Precision exception flag.
+        precision = 5, "PE";
+        /// Stack fault.
+        stack_fault = 6, "SF";
+        /// Exception summary status.
+        error_summary = 7, "ES";
+        /// Condition code 0.
+        c0 = 8, "C0";
+        /// Condition code 1.
+        c1 = 9, "C1";
+        /// Condition code 2.
+        c2 = 10, "C2";
+        /// Condition code 3.
+        c3 = 14, "C3";
+        /// FPU busy.
+        busy = 15, "B";
+    }
+}
+
+impl X87StatusWord {
+    /// Returns the index of the register that is the current top of the x87 register stack.
+    pub fn top(&self) -> u8 {
+        ((self.0 >> 11) & 0x7) as u8
+    }
+}
+
+/// Decoded x87 FPU control and status state, as reported by the
+/// `LowFpControlStatus` and `HighFpControlStatus` register pair.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FpControlStatus {
+    pub control: X87ControlWord,
+    pub status: X87StatusWord,
+    /// Abridged tag word, one bit per x87 register.
+    pub tag: u8,
+    pub last_opcode: u16,
+    /// Instruction pointer of the last non-control x87 instruction.
+    pub last_instruction_pointer: u64,
+}
+
+impl FpControlStatus {
+    /// Decodes the x87 FPU control and status state from its two 64 bit register halves.
+    pub fn from_halves(low: u64, high: u64) -> FpControlStatus {
+        FpControlStatus {
+            control: X87ControlWord(low as u16),
+            status: X87StatusWord((low >> 16) as u16),
+            tag: (low >> 32) as u8,
+            last_opcode: (low >> 48) as u16,
+            last_instruction_pointer: high,
+        }
+    }
+}
+
+/// Decoded SSE control and status state, as reported by the
+/// `LowXmmControlStatus` and `HighXmmControlStatus` register pair.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct XmmControlStatus {
+    /// Data pointer of the last non-control x87 instruction.
+    pub last_data_pointer: u64,
+    pub mxcsr: Mxcsr,
+    pub mxcsr_mask: u32,
+}
+
+impl XmmControlStatus {
+    /// Decodes the SSE control and status state from its two 64 bit register halves.
+    pub fn from_halves(low: u64, high: u64) -> XmmControlStatus {
+        XmmControlStatus {
+            last_data_pointer: low,
+            mxcsr: Mxcsr(high as u32),
+            mxcsr_mask: (high >> 32) as u32,
+        }
+    }
+}
+
+decoded_register! {
+    /// Decoded EFLAGS/RFLAGS register.
+    RFlags(u64) {
+        /// Carry flag.
+        cf = 0, "CF";
+        /// Parity flag.
+        pf = 2, "PF";
+        /// Auxiliary carry flag.
+        af = 4, "AF";
+        /// Zero flag.
+        zf = 6, "ZF";
+        /// Sign flag.
+        sf = 7, "SF";
+        /// Trap flag.
+        tf = 8, "TF";
+        /// Interrupt enable flag.
+        interrupts_enabled = 9, "IF";
+        /// Direction flag.
+        df = 10, "DF";
+        /// Overflow flag.
+        of = 11, "OF";
+        /// Nested task flag.
+        nt = 14, "NT";
+        /// Resume flag.
+        rf = 16, "RF";
+        /// Virtual 8086 mode flag.
+        vm = 17, "VM";
+        /// Alignment check / access control flag.
+        ac = 18, "AC";
+        /// Virtual interrupt flag.
+        vif = 19, "VIF";
+        /// Virtual interrupt pending flag.
+        vip = 20, "VIP";
+        /// CPUID instruction availability flag.
+        id = 21, "ID";
+    }
+}
+
+/// The 32 bit EFLAGS register shares its layout with the lower half of RFLAGS.
+pub type EFlags = RFlags;
+
+impl RFlags {
+    /// Returns the I/O privilege level.
+    pub fn iopl(&self) -> u8 {
+        ((self.0 >> 12) & 0x3) as u8
+    }
+}
+
+decoded_register! {
+    /// Decoded CR0 control register.
+    Cr0(u64) {
+        /// Protection enable.
+        pe = 0, "PE";
+        /// Monitor coprocessor.
+        mp = 1, "MP";
+        /// x87 emulation.
+        em = 2, "EM";
+        /// Task switched.
+        ts = 3, "TS";
+        /// Extension type.
+        et = 4, "ET";
+        /// Numeric error.
+        ne = 5, "NE";
+        /// Write protect.
+        wp = 16, "WP";
+        /// Alignment mask.
+        am = 18, "AM";
+        /// Not write-through.
+        nw = 29, "NW";
+        /// Cache disable.
+        cd = 30, "CD";
+        /// Paging.
+        pg = 31, "PG";
+    }
+}
+
+decoded_register! {
+    /// Decoded CR4 control register.
+    Cr4(u64) {
+        /// Virtual 8086 mode extensions.
+        vme = 0, "VME";
+        /// Protected mode virtual interrupts.
+        pvi = 1, "PVI";
+        /// Time stamp disable.
+        tsd = 2, "TSD";
+        /// Debugging extensions.
+        de = 3, "DE";
+        /// Page size extensions.
+        pse = 4, "PSE";
+        /// Physical address extension.
+        pae = 5, "PAE";
+        /// Machine check enable.
+        mce = 6, "MCE";
+        /// Page global enable.
+        pge = 7, "PGE";
+        /// Performance monitoring counter enable.
+        pce = 8, "PCE";
+        /// OS support for FXSAVE and FXRSTOR.
+        osfxsr = 9, "OSFXSR";
+        /// OS support for unmasked SIMD floating point exceptions.
+        osxmmexcpt = 10, "OSXMMEXCPT";
+        /// User mode instruction prevention.
+        umip = 11, "UMIP";
+        /// 57 bit linear addresses.
+        la57 = 12, "LA57";
+        /// VMX enable.
+        vmxe = 13, "VMXE";
+        /// SMX enable.
+        smxe = 14, "SMXE";
+        /// FSGSBASE instructions enable.
+        fsgsbase = 16, "FSGSBASE";
+        /// Process context identifiers enable.
+        pcide = 17, "PCIDE";
+        /// XSAVE and processor extended states enable.
+        osxsave = 18, "OSXSAVE";
+        /// Supervisor mode execution prevention.
+        smep = 20, "SMEP";
+        /// Supervisor mode access prevention.
+        smap = 21, "SMAP";
+        /// Protection keys for user mode pages.
+        pke = 22, "PKE";
+        /// Control flow enforcement technology.
+        cet = 23, "CET";
+        /// Protection keys for supervisor mode pages.
+        pks = 24, "PKS";
+    }
+}
+
+decoded_register! {
+    /// Decoded extended feature enable register.
+    Efer(u64) {
+        /// System call extensions.
+        sce = 0, "SCE";
+        /// Long mode enable.
+        lme = 8, "LME";
+        /// Long mode active.
+        lma = 10, "LMA";
+        /// No-execute enable.
+        nxe = 11, "NXE";
+        /// Secure virtual machine enable.
+        svme = 12, "SVME";
+        /// Long mode segment limit enable.
+        lmsle = 13, "LMSLE";
+        /// Fast FXSAVE and FXRSTOR.
+        ffxsr = 14, "FFXSR";
+        /// Translation cache extension.
+        tce = 15, "TCE";
+    }
+}
+
+decoded_register! {
+    /// Decoded DR6 debug status register.
+    Dr6(u64) {
+        /// Breakpoint condition 0 detected.
+        b0 = 0, "B0";
+        /// Breakpoint condition 1 detected.
+        b1 = 1, "B1";
+        /// Breakpoint condition 2 detected.
+        b2 = 2, "B2";
+        /// Breakpoint condition 3 detected.
+        b3 = 3, "B3";
+        /// Bus lock detected, active low.
+        bld = 11, "BLD";
+        /// Debug register access detected.
+        bd = 13, "BD";
+        /// Single step.
+        bs = 14, "BS";
+        /// Task switch.
+        bt = 15, "BT";
+        /// Debug exception inside an RTM region, active low.
+        rtm = 16, "RTM";
+    }
+}
+
+impl Dr6 {
+    /// Returns whether the condition of the given breakpoint (0 to 3) was detected.
+    pub fn breakpoint_hit(&self, index: u8) -> bool {
+        index < 4 && self.0 & (1 << index) != 0
+    }
+}
+
+/// Access that triggers a hardware breakpoint, as encoded in the DR7 R/W fields.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BreakpointCondition {
+    Execute,
+    Write,
+    Io,
+    ReadWrite,
+}
+
+decoded_register! {
+    /// Decoded DR7 debug control register.
+    Dr7(u64) {
+        /// Breakpoint 0 local enable.
+        l0 = 0, "L0";
+        /// Breakpoint 0 global enable.
+        g0 = 1, "G0";
+        /// Breakpoint 1 local enable.
+        l1 = 2, "L1";
+        /// Breakpoint 1 global enable.
+        g1 = 3, "G1";
+        /// Breakpoint 2 local enable.
+        l2 = 4, "L2";
+        /// Breakpoint 2 global enable.
+        g2 = 5, "G2";
+        /// Breakpoint 3 local enable.
+        l3 = 6, "L3";
+        /// Breakpoint 3 global enable.
+        g3 = 7, "G3";
+        /// Local exact breakpoint enable.
+        le = 8, "LE";
+        /// Global exact breakpoint enable.
+        ge = 9, "GE";
+        /// Restricted transactional memory debugging enable.
+        rtm = 11, "RTM";
+        /// General detect enable.
+        gd = 13, "GD";
+    }
+}
+
+impl Dr7 {
+    /// Returns whether the given breakpoint (0 to 3) is enabled, either locally or globally.
+    pub fn breakpoint_enabled(&self, index: u8) -> bool {
+        index < 4 && (self.0 >> (2 * index)) & 0x3 != 0
+    }
+
+    /// Returns the access that triggers the given breakpoint (0 to 3).
+    pub fn breakpoint_condition(&self, index: u8) -> Option<BreakpointCondition> {
+        if index >= 4 {
+            return None;
+        }
+
+        match (self.0 >> (16 + 4 * index)) & 0x3 {
+            0 => Some(BreakpointCondition::Execute),
+            1 => Some(BreakpointCondition::Write),
+            2 => Some(BreakpointCondition::Io),
+            _ => Some(BreakpointCondition::ReadWrite),
+        }
+    }
+
+    /// Returns the size in bytes of the memory range watched by the given breakpoint (0 to 3).
+    pub fn breakpoint_length(&self, index: u8) -> Option<u8> {
+        if index >= 4 {
+            return None;
+        }
+
+        match (self.0 >> (18 + 4 * index)) & 0x3 {
+            0 => Some(1),
+            1 => Some(2),
+            2 => Some(8),
+            _ => Some(4),
+        }
+    }
+}
+
+/// Snapshot of the control, flags and debug registers of a virtual processor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ControlRegisters {
+    pub flags: RFlags,
+    pub cr0: Cr0,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: Cr4,
+    pub cr8: u64,
+    pub efer: Efer,
+    pub dr0: u64,
+    pub dr1: u64,
+    pub dr2: u64,
+    pub dr3: u64,
+    pub dr6: Dr6,
+    pub dr7: Dr7,
+}
+
+impl fmt::Display for ControlRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "flags = {}", self.flags)?;
+        writeln!(f, "cr0   = {}", self.cr0)?;
+        writeln!(f, "cr2   = {:#018x}", self.cr2)?;
+        writeln!(f, "cr3   = {:#018x}", self.cr3)?;
+        writeln!(f, "cr4   = {}", self.cr4)?;
+        writeln!(f, "cr8   = {:#018x}", self.cr8)?;
+        writeln!(f, "efer  = {}", self.efer)?;
+        writeln!(f, "dr0   = {:#018x}", self.dr0)?;
+        writeln!(f, "dr1   = {:#018x}", self.dr1)?;
+        writeln!(f, "dr2   = {:#018x}", self.dr2)?;
+        writeln!(f, "dr3   = {:#018x}", self.dr3)?;
+        writeln!(f, "dr6   = {}", self.dr6)?;
+        write!(f, "dr7   = {}", self.dr7)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fp_and_xmm_control_status_decoding() {
+        let fp_control_status = FpControlStatus::from_halves(0x07FF_0081_7800_037F, 0xC0FFEE);
+        assert_eq!(0x037F, fp_control_status.control.raw());
+        assert_eq!(
+            PrecisionControl::DoubleExtended,
+            fp_control_status.control.precision_control()
+        );
+        assert_eq!(
+            RoundingControl::Nearest,
+            fp_control_status.control.rounding_control()
+        );
+        assert!(fp_control_status.control.invalid_operation_mask());
+        assert_eq!(7, fp_control_status.status.top());
+        assert!(fp_control_status.status.c3());
+        assert_eq!(0x81, fp_control_status.tag);
+        assert_eq!(0x07FF, fp_control_status.last_opcode);
+        assert_eq!(0xC0FFEE, fp_control_status.last_instruction_pointer);
+
+        let xmm_control_status = XmmControlStatus::from_halves(0xC0FFEE, 0x0000_FFFF_0000_7F80);
+        assert_eq!(0xC0FFEE, xmm_control_status.last_data_pointer);
+        assert_eq!(0xFFFF, xmm_control_status.mxcsr_mask);
+        assert_eq!(
+            RoundingControl::TowardZero,
+            xmm_control_status.mxcsr.rounding_control()
+        );
+        assert!(xmm_control_status.mxcsr.precision_mask());
+        assert!(!xmm_control_status.mxcsr.flush_to_zero());
+        assert_eq!(
+            "0x00007f80 [IM DM ZM OM UM PM]",
+            xmm_control_status.mxcsr.to_string()
+        );
+    }
+
+    #[test]
+    fn control_registers_decoding() {
+        let cr0 = Cr0(0x8005_0033);
+        assert!(cr0.pe() && cr0.wp() && cr0.pg() && cr0.ne());
+        assert!(!cr0.cd());
+        assert_eq!("0x0000000080050033 [PE MP ET NE WP AM PG]", cr0.to_string());
+
+        let cr4 = Cr4(0x0035_06F8);
+        assert!(cr4.pae() && cr4.smep() && cr4.smap() && cr4.fsgsbase());
+        assert!(!cr4.la57());
+
+        let efer = Efer(0xD01);
+        assert!(efer.sce() && efer.lme() && efer.lma() && efer.nxe());
+        assert_eq!("0x0000000000000d01 [SCE LME LMA NXE]", efer.to_string());
+
+        let flags: EFlags = RFlags(0x3246);
+        assert!(flags.interrupts_enabled() && flags.zf() && flags.pf());
+        assert!(!flags.tf());
+        assert_eq!(3, flags.iopl());
+
+        let dr6 = Dr6(0xFFFF_4FF2);
+        assert!(dr6.breakpoint_hit(1) && dr6.bs());
+        assert!(!dr6.breakpoint_hit(0));
+
+        let dr7 = Dr7(0x000D_0402);
+        assert!(dr7.breakpoint_enabled(0) && dr7.g0());
+        assert!(!dr7.breakpoint_enabled(1));
+        assert_eq!(
+            Some(BreakpointCondition::Write),
+            dr7.breakpoint_condition(0)
+        );
+        assert_eq!(Some(4), dr7.breakpoint_length(0));
+        assert_eq!(None, dr7.breakpoint_condition(4));
+    }
+}
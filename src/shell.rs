@@ -0,0 +1,702 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module provides an interactive shell to inspect a loaded VM saved state with
+//! WinDbg-like commands, without converting it to a dump file first.
+//!
+//! Like in WinDbg, the `db`, `dw`, `dd` and `dq` commands display guest virtual memory,
+//! and their `!db`, `!dw`, `!dd` and `!dq` forms display guest physical memory. So `dq` takes
+//! a virtual address like the other display commands, and quadwords of guest physical memory
+//! are displayed with `!dq <pa>`.
+//! Addresses are expressions made of hexadecimal numbers (`0n` prefixes decimal ones),
+//! registers of the current virtual processor (optionally prefixed with `@`),
+//! `+`, `-`, `*` and parentheses, for example `db rsp+0x20`.
+//!
+//! Commands entered are kept in a history listed by `history`, and are run again with
+//! `!<n>`, where `n` is the number `history` lists them with, or with `!!` for the last one.
+//!
+//! ```rust,ignore
+//! let mut shell = Shell::new(&provider);
+//! println!("{}", shell.execute("dq @rsp L4")?);
+//! shell.run(std::io::stdin().lock(), std::io::stdout())?;
+//! ```
+
+use crate::descriptors::CpuMode;
+use crate::paging::*;
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+vp [id]                       Lists the virtual processors, or switches to the given one
+r [register ...]              Shows all or the given registers of the current virtual processor
+db|dw|dd|dq <va> [L<count>]   Displays guest virtual memory as bytes, words, dwords or qwords
+!db|!dw|!dd|!dq <pa> [L<count>]
+                              Displays guest physical memory as bytes, words, dwords or qwords
+!pte <va>                     Shows the paging structure entries a virtual address maps through
+u <va> [L<count>]             Disassembles instructions at a virtual address
+chunks                        Lists the guest physical memory chunks
+? <expression>                Evaluates an address expression
+history                       Lists the commands entered so far
+!<n>                          Runs the command with the given number in the history again
+!!                            Runs the last command again
+q                             Quits the shell";
+
+/// Bytes displayed by memory commands when no count is given.
+const DEFAULT_DISPLAY_SIZE: u64 = 0x80;
+
+/// Largest range memory commands display at once.
+const MAX_DISPLAY_SIZE: u64 = 0x10_0000;
+
+/// Instructions disassembled when no count is given.
+const DEFAULT_INSTRUCTION_COUNT: u64 = 8;
+
+/// Largest size of an x86 instruction.
+const MAX_INSTRUCTION_SIZE: u64 = 15;
+
+/// Description of why a command couldn't be run, kept as the source of the returned error.
+#[derive(Debug)]
+struct CommandError(String);
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Writes the error of a command, described by its source when it has one.
+fn write_error<W: Write>(output: &mut W, error: &VmSavedStateDumpError) -> std::io::Result<()> {
+    match std::error::Error::source(error) {
+        Some(source) => writeln!(output, "error: {}", source),
+        None => writeln!(output, "error: {}", error),
+    }
+}
+
+fn command_error(message: String) -> VmSavedStateDumpError {
+    VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+        .with_operation("Shell::execute")
+        .with_source(CommandError(message))
+}
+
+/// Result of running a shell command.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CommandOutput {
+    /// Text the command printed, without a trailing new line.
+    Text(String),
+    /// The command asked to quit the shell.
+    Quit,
+}
+
+/// Interactive shell over a loaded VM saved state, which keeps the current virtual processor
+/// and the history of the commands entered.
+pub struct Shell<'a> {
+    provider: &'a VmSavedStateDumpProvider,
+    vp_id: u32,
+    history: Vec<String>,
+}
+
+impl<'a> Shell<'a> {
+    /// Creates a shell over the given provider, starting at virtual processor 0.
+    pub fn new(provider: &'a VmSavedStateDumpProvider) -> Shell<'a> {
+        Shell {
+            provider,
+            vp_id: 0,
+            history: Vec::new(),
+        }
+    }
+
+    /// Returns the virtual processor commands run against.
+    pub fn vp_id(&self) -> u32 {
+        self.vp_id
+    }
+
+    /// Returns the commands entered so far, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Returns the prompt shown before reading a command.
+    pub fn prompt(&self) -> String {
+        format!("{}: vmss> ", self.vp_id)
+    }
+
+    /// Reads commands from the given input until it ends or a quit command is entered,
+    /// writing the prompt, the output of the commands and their errors to the given output.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> std::io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(output, "{}", self.prompt())?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return writeln!(output),
+            };
+
+            // Commands recalled from the history are shown before running them
+            let line = match self.recall(&line) {
+                Ok(Some(command)) => {
+                    writeln!(output, "{}", command)?;
+                    command
+                }
+                Ok(None) => line,
+                Err(error) => {
+                    write_error(&mut output, &error)?;
+                    continue;
+                }
+            };
+
+            match self.execute(&line) {
+                Ok(CommandOutput::Quit) => return Ok(()),
+                Ok(CommandOutput::Text(text)) if text.is_empty() => {}
+                Ok(CommandOutput::Text(text)) => writeln!(output, "{}", text)?,
+                Err(error) => write_error(&mut output, &error)?,
+            }
+        }
+    }
+
+    /// Returns the command of the history a `!<n>` or `!!` command line refers to,
+    /// or None for any other command line.
+    pub fn recall(&self, line: &str) -> VmSavedStateDumpResult<Option<String>> {
+        let reference = match line.trim().strip_prefix('!') {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+
+        let index = if reference == "!" {
+            self.history.len().checked_sub(1)
+        } else if !reference.is_empty() && reference.bytes().all(|byte| byte.is_ascii_digit()) {
+            reference.parse::<usize>().ok()
+        } else {
+            return Ok(None);
+        };
+
+        match index.and_then(|index| self.history.get(index)) {
+            Some(command) => Ok(Some(command.clone())),
+            None => Err(command_error(format!(
+                "no command {} in the history",
+                line.trim()
+            ))),
+        }
+    }
+
+    /// Runs a single command line, adding it to the history. Command lines recalling a command
+    /// of the history run that command, which is the one added to the history.
+    pub fn execute(&mut self, line: &str) -> VmSavedStateDumpResult<CommandOutput> {
+        let line = match self.recall(line)? {
+            Some(command) => command,
+            None => line.trim().to_string(),
+        };
+        if line.is_empty() {
+            return Ok(CommandOutput::Text(String::new()));
+        }
+        self.history.push(line.clone());
+        let line = line.as_str();
+
+        let (command, arguments) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+
+        let text = match command.to_ascii_lowercase().as_str() {
+            "q" | "quit" | "exit" => return Ok(CommandOutput::Quit),
+            "help" | ".help" => HELP.to_string(),
+            "history" => self.history_command(),
+            "vp" => self.vp_command(arguments)?,
+            "r" => self.registers_command(arguments)?,
+            "chunks" => self.chunks_command()?,
+            "?" => {
+                let value = self.evaluate(arguments)?;
+                format!("Evaluate expression: {0} = {0:#018x}", value)
+            }
+            "!pte" => self.pte_command(arguments)?,
+            "u" => self.disassemble_command(arguments)?,
+            "db" => self.display_command(arguments, 1, false)?,
+            "dw" => self.display_command(arguments, 2, false)?,
+            "dd" => self.display_command(arguments, 4, false)?,
+            "dq" => self.display_command(arguments, 8, false)?,
+            "!db" => self.display_command(arguments, 1, true)?,
+            "!dw" => self.display_command(arguments, 2, true)?,
+            "!dd" => self.display_command(arguments, 4, true)?,
+            "!dq" => self.display_command(arguments, 8, true)?,
+            _ => {
+                return Err(command_error(format!(
+                    "unknown command {}, enter help to list the commands",
+                    command
+                )))
+            }
+        };
+
+        Ok(CommandOutput::Text(text))
+    }
+
+    /// Evaluates an address expression in the context of the current virtual processor.
+    pub fn evaluate(&self, expression: &str) -> VmSavedStateDumpResult<u64> {
+        let mut parser = ExpressionParser {
+            shell: self,
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+
+        let value = parser.expression()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(value),
+            Some(token) => Err(command_error(format!(
+                "unexpected {} in expression {}",
+                token, expression
+            ))),
+        }
+    }
+
+    fn register_value(&self, name: &str) -> VmSavedStateDumpResult<u64> {
+        let architecture = self.provider.get_vp_architecture(self.vp_id)?;
+        match Register::from_name(architecture, name) {
+            Some(register) => Ok(self
+                .provider
+                .get_vp_register_value(self.vp_id, register)?
+                .value),
+            None => Err(command_error(format!("unknown register {}", name))),
+        }
+    }
+
+    /// Splits the arguments of a command in an address expression and an optional `L<count>`.
+    fn address_and_count(&self, arguments: &str) -> VmSavedStateDumpResult<(u64, Option<u64>)> {
+        let (expression, count) = match arguments.rfind(['L', 'l']) {
+            Some(index)
+                if index > 0
+                    && arguments[..index].ends_with(char::is_whitespace)
+                    && !arguments[index + 1..].trim().is_empty() =>
+            {
+                let count = self.evaluate(&arguments[index + 1..])?;
+                (&arguments[..index], Some(count))
+            }
+            _ => (arguments, None),
+        };
+
+        if expression.trim().is_empty() {
+            return Err(command_error(String::from("missing address")));
+        }
+        Ok((self.evaluate(expression)?, count))
+    }
+
+    /// Reads memory page by page, stopping at the first page that can't be read.
+    fn read_memory(
+        &self,
+        address: u64,
+        size: u64,
+        physical: bool,
+    ) -> VmSavedStateDumpResult<Vec<u8>> {
+        let mut buffer = vec![0u8; size as usize];
+        let mut bytes_read = 0usize;
+
+        while bytes_read < buffer.len() {
+            let current_address = address.wrapping_add(bytes_read as u64);
+            let page_remaining = (GUEST_PAGE_SIZE - current_address % GUEST_PAGE_SIZE) as usize;
+            let read_size = std::cmp::min(page_remaining, buffer.len() - bytes_read);
+            let page = &mut buffer[bytes_read..bytes_read + read_size];
+
+            let read = match physical {
+                true => self
+                    .provider
+                    .read_guest_physical_address(current_address, page),
+                false => {
+                    self.provider
+                        .read_guest_virtual_address(self.vp_id, current_address, page)
+                }
+            };
+            let read = match read {
+                Ok(read) => read as usize,
+                Err(error) if bytes_read == 0 => return Err(error),
+                Err(_) => break,
+            };
+
+            bytes_read += read;
+            if read < read_size {
+                break;
+            }
+        }
+
+        buffer.truncate(bytes_read);
+        Ok(buffer)
+    }
+
+    fn history_command(&self) -> String {
+        let mut text = String::new();
+        for (index, command) in self.history.iter().enumerate() {
+            let _ = writeln!(text, "{:>5}  {}", index, command);
+        }
+        text.trim_end().to_string()
+    }
+
+    fn vp_command(&mut self, arguments: &str) -> VmSavedStateDumpResult<String> {
+        let vp_count = self.provider.vp_count()?;
+        if !arguments.is_empty() {
+            let vp_id = self.evaluate(arguments)?;
+            if vp_id >= u64::from(vp_count) {
+                return Err(command_error(format!(
+                    "virtual processor {} doesn't exist, there are {}",
+                    vp_id, vp_count
+                )));
+            }
+            self.vp_id = vp_id as u32;
+            return Ok(String::new());
+        }
+
+        let mut text = String::new();
+        for vp in self.provider.vp_iter()? {
+            let _ = writeln!(
+                text,
+                "{} {:>3}  {:?}, paging mode {:?}",
+                if vp.id() == self.vp_id { '*' } else { ' ' },
+                vp.id(),
+                vp.architecture()?,
+                vp.paging_mode()?
+            );
+        }
+        Ok(text.trim_end().to_string())
+    }
+
+    fn registers_command(&self, arguments: &str) -> VmSavedStateDumpResult<String> {
+        let mut text = String::new();
+        if !arguments.is_empty() {
+            for name in arguments.split_whitespace() {
+                let name = name.trim_start_matches('@');
+                let _ = writeln!(text, "{}={:016x}", name, self.register_value(name)?);
+            }
+            return Ok(text.trim_end().to_string());
+        }
+
+        let architecture = self.provider.get_vp_architecture(self.vp_id)?;
+        for (index, register) in Register::all(architecture).into_iter().enumerate() {
+            let value = self.provider.get_vp_register_value(self.vp_id, register)?;
+            let separator = if index % 3 == 2 { '\n' } else { ' ' };
+            let _ = write!(
+                text,
+                "{:>10}={:016x}{}",
+                register.name(),
+                value.value,
+                separator
+            );
+        }
+        Ok(text.trim_end().to_string())
+    }
+
+    fn chunks_command(&self) -> VmSavedStateDumpResult<String> {
+        let (page_size, memory_chunks) = self.provider.guest_physical_memory_chunks()?;
+        let mut text = String::new();
+        for chunk in &memory_chunks {
+            let start = chunk.guest_physical_start_page_index * page_size;
+            let _ = writeln!(
+                text,
+                "{:016x} - {:016x}  {:#x} pages",
+                start,
+                start + chunk.page_count * page_size,
+                chunk.page_count
+            );
+        }
+        Ok(text.trim_end().to_string())
+    }
+
+    fn pte_command(&self, arguments: &str) -> VmSavedStateDumpResult<String> {
+        let virtual_address = self.evaluate(arguments)?;
+        let walk = walk_page_tables(self.provider, self.vp_id, virtual_address)?;
+
+        let mut text = format!(
+            "VA {:016x}, paging mode {:?}\n",
+            virtual_address, walk.paging_mode
+        );
+        for entry in &walk.entries {
+            let _ = writeln!(text, "{}", entry);
+        }
+        match walk.physical_address {
+            Some(physical_address) => {
+                let _ = write!(text, "PA {:016x}", physical_address);
+            }
+            None => text.push_str("Not mapped"),
+        }
+        Ok(text)
+    }
+
+    fn display_command(
+        &self,
+        arguments: &str,
+        element_size: usize,
+        physical: bool,
+    ) -> VmSavedStateDumpResult<String> {
+        let (address, count) = self.address_and_count(arguments)?;
+        let size = match count {
+            Some(count) => count.saturating_mul(element_size as u64),
+            None => DEFAULT_DISPLAY_SIZE,
+        };
+        if size > MAX_DISPLAY_SIZE {
+            return Err(command_error(format!(
+                "range of {:#x} bytes is larger than {:#x}",
+                size, MAX_DISPLAY_SIZE
+            )));
+        }
+
+        let bytes = self.read_memory(address, size, physical)?;
+        let mut text = String::new();
+        for (index, line) in bytes.chunks(16).enumerate() {
+            let _ = write!(text, "{:016x} ", address.wrapping_add(index as u64 * 16));
+            for (element_index, element) in line.chunks(element_size).enumerate() {
+                if element.len() < element_size {
+                    break;
+                }
+                let mut raw = [0u8; 8];
+                raw[..element_size].copy_from_slice(element);
+                let separator = match element_size == 1 && element_index == 8 {
+                    true => '-',
+                    false => ' ',
+                };
+                let _ = write!(
+                    text,
+                    "{}{:0width$x}",
+                    separator,
+                    u64::from_le_bytes(raw),
+                    width = element_size * 2
+                );
+            }
+            if element_size == 1 {
+                let ascii: String = line
+                    .iter()
+                    .map(|byte| match byte {
+                        0x20..=0x7E => *byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                let _ = write!(
+                    text,
+                    "{:width$}  {}",
+                    "",
+                    ascii,
+                    width = (16 - line.len()) * 3
+                );
+            }
+            text.push('\n');
+        }
+
+        if (bytes.len() as u64) < size {
+            let _ = write!(
+                text,
+                "Memory at {:016x} couldn't be read",
+                address.wrapping_add(bytes.len() as u64)
+            );
+        }
+        Ok(text.trim_end().to_string())
+    }
+
+    fn disassemble_command(&self, arguments: &str) -> VmSavedStateDumpResult<String> {
+        let (address, count) = self.address_and_count(arguments)?;
+        let count = count.unwrap_or(DEFAULT_INSTRUCTION_COUNT);
+        let size = count.saturating_mul(MAX_INSTRUCTION_SIZE);
+        if size > MAX_DISPLAY_SIZE {
+            return Err(command_error(format!(
+                "{:#x} instructions are more than can be disassembled at once",
+                count
+            )));
+        }
+
+        let bitness = match self.provider.get_vp_cpu_mode(self.vp_id)? {
+            CpuMode::Long => 64,
+            CpuMode::Protected32 | CpuMode::Compatibility32 => 32,
+            _ => 16,
+        };
+        let bytes = self.read_memory(address, size, false)?;
+        disassemble(&bytes, address, bitness, count as usize)
+    }
+}
+
+/// Formats up to the given count of instructions decoded from the given bytes.
+#[cfg(feature = "iced-x86")]
+fn disassemble(
+    bytes: &[u8],
+    address: u64,
+    bitness: u32,
+    count: usize,
+) -> VmSavedStateDumpResult<String> {
+    use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, IntelFormatter};
+
+    let mut decoder = Decoder::with_ip(bitness, bytes, address, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    let mut instruction = Instruction::default();
+    let mut text = String::new();
+
+    for _ in 0..count {
+        if !decoder.can_decode() {
+            break;
+        }
+        let start = decoder.position();
+        decoder.decode_out(&mut instruction);
+        if instruction.is_invalid() && decoder.position() == bytes.len() {
+            // Instruction cut short by the end of the bytes read
+            break;
+        }
+
+        let hex: String = bytes[start..decoder.position()]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut formatted = String::new();
+        formatter.format(&instruction, &mut formatted);
+        let _ = writeln!(text, "{:016x} {:<24} {}", instruction.ip(), hex, formatted);
+    }
+    Ok(text.trim_end().to_string())
+}
+
+#[cfg(not(feature = "iced-x86"))]
+fn disassemble(_: &[u8], _: u64, _: u32, _: usize) -> VmSavedStateDumpResult<String> {
+    Err(command_error(String::from(
+        "disassembling requires the iced-x86 feature",
+    )))
+}
+
+/// Tokens address expressions are made of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Register(String),
+    Operator(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{:#x}", value),
+            Token::Register(name) => write!(f, "@{}", name),
+            Token::Operator(operator) => write!(f, "{}", operator),
+        }
+    }
+}
+
+/// Splits an address expression in tokens. Words that aren't register names
+/// are parsed as numbers, which are hexadecimal unless prefixed with `0n`.
+fn tokenize(expression: &str) -> VmSavedStateDumpResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '+' | '-' | '*' | '(' | ')' => tokens.push(Token::Operator(c)),
+            c if c == '@' || c == '`' || c == '_' || c.is_ascii_alphanumeric() => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c != '`' && c != '_' && !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(parse_word(&word)?);
+            }
+            _ => {
+                return Err(command_error(format!(
+                    "unexpected {} in expression {}",
+                    c, expression
+                )))
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_word(word: &str) -> VmSavedStateDumpResult<Token> {
+    if let Some(register) = word.strip_prefix('@') {
+        return Ok(Token::Register(register.to_ascii_lowercase()));
+    }
+
+    let lowercase = word.replace('`', "").to_ascii_lowercase();
+    if is_register_name(&lowercase) {
+        return Ok(Token::Register(lowercase));
+    }
+
+    let parsed = match lowercase.strip_prefix("0n") {
+        Some(decimal) => decimal.parse::<u64>(),
+        None => u64::from_str_radix(lowercase.strip_prefix("0x").unwrap_or(&lowercase), 16),
+    };
+    match parsed {
+        Ok(value) => Ok(Token::Number(value)),
+        Err(_) => Err(command_error(format!(
+            "{} is neither a register nor a number",
+            word
+        ))),
+    }
+}
+
+/// Returns whether the name is a register of any architecture.
+fn is_register_name(name: &str) -> bool {
+    [VirtualProcessorArch::X86, VirtualProcessorArch::X64]
+        .iter()
+        .any(|architecture| Register::from_name(*architecture, name).is_some())
+}
+
+/// Recursive descent parser of address expressions, evaluated as they are parsed.
+struct ExpressionParser<'s, 'a> {
+    shell: &'s Shell<'a>,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'s, 'a> ExpressionParser<'s, 'a> {
+    fn next_operator(&mut self, operators: &[char]) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.position += 1;
+                Some(*operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self) -> VmSavedStateDumpResult<u64> {
+        let mut value = self.term()?;
+        while let Some(operator) = self.next_operator(&['+', '-']) {
+            let operand = self.term()?;
+            value = match operator {
+                '+' => value.wrapping_add(operand),
+                _ => value.wrapping_sub(operand),
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> VmSavedStateDumpResult<u64> {
+        let mut value = self.factor()?;
+        while self.next_operator(&['*']).is_some() {
+            value = value.wrapping_mul(self.factor()?);
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> VmSavedStateDumpResult<u64> {
+        if self.next_operator(&['-']).is_some() {
+            return Ok(self.factor()?.wrapping_neg());
+        }
+        if self.next_operator(&['(']).is_some() {
+            let value = self.expression()?;
+            if self.next_operator(&[')']).is_none() {
+                return Err(command_error(String::from("missing ) in expression")));
+            }
+            return Ok(value);
+        }
+
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Register(name)) => self.shell.register_value(&name),
+            Some(token) => Err(command_error(format!("unexpected {} in expression", token))),
+            None => Err(command_error(String::from("incomplete expression"))),
+        }
+    }
+}
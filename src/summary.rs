@@ -0,0 +1,68 @@
+// Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// All files in the project carrying such notice may not be copied, modified, or distributed
+// except according to those terms.
+// THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+//! This module provides a summary of a loaded VM saved state, gathered in a single call.
+//! With the `serde` feature enabled, summaries can be serialized, for example to JSON.
+
+use crate::vmsavedstatedump::*;
+use crate::vmsavedstatedumpdefs::*;
+
+/// State of a virtual processor, along with the values of all of its registers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VirtualProcessorSummary {
+    pub id: u32,
+    pub architecture: VirtualProcessorArch,
+    pub paging_mode: PagingMode,
+    /// Every register of the architecture of the virtual processor, in register identifier order.
+    pub registers: Vec<VirtualProcessorRegister>,
+}
+
+/// Virtual processors and guest memory layout of a VM saved state.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SavedStateSummary {
+    pub vp_count: u32,
+    pub virtual_processors: Vec<VirtualProcessorSummary>,
+    pub page_size: u64,
+    pub memory_chunks: Vec<GpaMemoryChunk>,
+    pub raw_saved_memory_size: u64,
+}
+
+impl SavedStateSummary {
+    /// Gathers the summary of the VM saved state loaded by the given provider.
+    pub fn new(provider: &VmSavedStateDumpProvider) -> VmSavedStateDumpResult<SavedStateSummary> {
+        let vp_count = provider.vp_count()?;
+        let mut virtual_processors = Vec::with_capacity(vp_count as usize);
+
+        for vp in provider.vp_iter()? {
+            let architecture = vp.architecture()?;
+            let registers = Register::all(architecture)
+                .into_iter()
+                .map(|register| vp.register_value(register))
+                .collect::<VmSavedStateDumpResult<Vec<_>>>()?;
+
+            virtual_processors.push(VirtualProcessorSummary {
+                id: vp.id(),
+                architecture,
+                paging_mode: vp.paging_mode()?,
+                registers,
+            });
+        }
+
+        let (page_size, memory_chunks) = provider.guest_physical_memory_chunks()?;
+
+        Ok(SavedStateSummary {
+            vp_count,
+            virtual_processors,
+            page_size,
+            memory_chunks,
+            raw_saved_memory_size: provider.guest_raw_saved_memory_size()?,
+        })
+    }
+}
@@ -9,16 +9,25 @@
 //! This module implements safe wrappers of the unsafe API surface to VmSavedStateDump.
 //! Defines and provides Rust idiomatic abstractions of the API.
 
+use crate::descriptors::*;
+use crate::registers::*;
 use crate::vmsavedstatedump_bindings::*;
 use crate::vmsavedstatedumpdefs::*;
 use crate::windefs::*;
 
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
 use std::ops;
+use std::path::{Path, PathBuf};
 
-pub type VmSavedStateDumpResult<T> = Result<T, ResultCode>;
+pub type VmSavedStateDumpResult<T> = Result<T, VmSavedStateDumpError>;
+
+/// Size of the smallest page the guest virtual address translation works with.
+pub(crate) const GUEST_PAGE_SIZE: u64 = 0x1000;
 
 /// Common result codes that can be returned by the VmSavedStateDumpProvider API.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ResultCode {
     Success,
     OutOfMemory,
@@ -26,9 +35,240 @@ pub enum ResultCode {
     Fail,
     InvalidArgument,
     Unexpected,
+    RegisterArchitectureMismatch {
+        vp_architecture: VirtualProcessorArch,
+        register_architecture: VirtualProcessorArch,
+    },
+    KdDebuggerDataBlockNotFound,
+    Cancelled,
+    Io(std::io::ErrorKind),
     WindowsHResult(HResult),
 }
 
+impl ResultCode {
+    /// Returns the HRESULT that corresponds to this result code, or `None` for the codes
+    /// that have no HRESULT equivalent, such as I/O errors and cancellation.
+    /// Codes this crate reports on its own, like `InvalidArgument` for a rejected argument,
+    /// return their standard HRESULT too, so this doesn't tell whether the API reported them.
+    pub fn hresult(&self) -> Option<HResult> {
+        match self {
+            ResultCode::Success => Some(0),
+            ResultCode::OutOfMemory => Some(0x8007_000E_u32 as HResult),
+            ResultCode::FileNotFound => Some(0x8007_0002_u32 as HResult),
+            ResultCode::Fail => Some(0x8000_4005_u32 as HResult),
+            ResultCode::InvalidArgument => Some(0x8007_0057_u32 as HResult),
+            ResultCode::Unexpected => Some(0x8000_FFFF_u32 as HResult),
+            ResultCode::WindowsHResult(hresult) => Some(*hresult),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResultCode::Success => write!(f, "the operation completed successfully"),
+            ResultCode::OutOfMemory => write!(f, "not enough memory"),
+            ResultCode::FileNotFound => write!(f, "the file could not be found"),
+            ResultCode::Fail => write!(f, "unspecified failure"),
+            ResultCode::InvalidArgument => write!(f, "invalid argument"),
+            ResultCode::Unexpected => write!(f, "unexpected failure"),
+            ResultCode::RegisterArchitectureMismatch {
+                vp_architecture,
+                register_architecture,
+            } => write!(
+                f,
+                "{:?} register requested from a {:?} virtual processor",
+                register_architecture, vp_architecture
+            ),
+            ResultCode::KdDebuggerDataBlockNotFound => {
+                write!(f, "the kernel debugger data block could not be found")
+            }
+            ResultCode::Cancelled => write!(f, "the operation was cancelled"),
+            ResultCode::Io(kind) => write!(f, "I/O error: {:?}", kind),
+            ResultCode::WindowsHResult(hresult) => {
+                let info = HResultInfo::new(*hresult);
+                write!(f, "HRESULT {:#010X} (", info.hresult as u32)?;
+                match info.facility_name() {
+                    Some(name) => write!(f, "facility {}", name)?,
+                    None => write!(f, "facility {}", info.facility)?,
+                }
+                write!(f, ", code {:#06X})", info.code)
+            }
+        }
+    }
+}
+
+/// Decoded fields of an HRESULT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HResultInfo {
+    pub hresult: HResult,
+    /// Set when the severity bit marks the HRESULT as a failure.
+    pub is_failure: bool,
+    pub facility: u16,
+    pub code: u16,
+}
+
+impl HResultInfo {
+    pub fn new(hresult: HResult) -> HResultInfo {
+        let raw = hresult as u32;
+        HResultInfo {
+            hresult,
+            is_failure: raw & 0x8000_0000 != 0,
+            facility: ((raw >> 16) & 0x1FFF) as u16,
+            code: raw as u16,
+        }
+    }
+
+    /// Returns the name of the facility, if it's one that the API is known to report.
+    pub fn facility_name(&self) -> Option<&'static str> {
+        match self.facility {
+            0 => Some("Null"),
+            1 => Some("Rpc"),
+            2 => Some("Dispatch"),
+            3 => Some("Storage"),
+            4 => Some("Itf"),
+            7 => Some("Win32"),
+            8 => Some("Windows"),
+            53 => Some("Hypervisor"),
+            55 => Some("Virtualization"),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned by the APIs of this crate. Holds the result code along with the
+/// operation that failed and the virtual processor and address it was working with.
+#[derive(Debug)]
+pub struct VmSavedStateDumpError {
+    code: ResultCode,
+    operation: Option<&'static str>,
+    vp_id: Option<u32>,
+    address: Option<u64>,
+    source: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
+}
+
+impl VmSavedStateDumpError {
+    pub fn new(code: ResultCode) -> VmSavedStateDumpError {
+        VmSavedStateDumpError {
+            code,
+            operation: None,
+            vp_id: None,
+            address: None,
+            source: None,
+        }
+    }
+
+    /// Sets the name of the operation that failed, the path of the public function or method
+    /// that returned the error, such as `VmSavedStateDumpProvider::load_vmrs`.
+    pub fn with_operation(mut self, operation: &'static str) -> VmSavedStateDumpError {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Sets the virtual processor the failed operation was working with.
+    pub fn with_vp_id(mut self, vp_id: u32) -> VmSavedStateDumpError {
+        self.vp_id = Some(vp_id);
+        self
+    }
+
+    /// Sets the address or offset the failed operation was working with.
+    pub fn with_address(mut self, address: u64) -> VmSavedStateDumpError {
+        self.address = Some(address);
+        self
+    }
+
+    /// Sets the lower level error that caused this one.
+    pub fn with_source<E>(mut self, source: E) -> VmSavedStateDumpError
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn code(&self) -> ResultCode {
+        self.code
+    }
+
+    pub fn operation(&self) -> Option<&'static str> {
+        self.operation
+    }
+
+    pub fn vp_id(&self) -> Option<u32> {
+        self.vp_id
+    }
+
+    pub fn address(&self) -> Option<u64> {
+        self.address
+    }
+
+    /// Returns the decoded HRESULT that corresponds to the error code, if it has one.
+    /// See `ResultCode::hresult` for the codes that don't.
+    pub fn hresult(&self) -> Option<HResultInfo> {
+        self.code.hresult().map(HResultInfo::new)
+    }
+}
+
+impl std::fmt::Display for VmSavedStateDumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(operation) = self.operation {
+            write!(f, "{} failed", operation)?;
+            if let Some(vp_id) = self.vp_id {
+                write!(f, " for virtual processor {}", vp_id)?;
+            }
+            if let Some(address) = self.address {
+                write!(f, " at {:#x}", address)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.code)
+    }
+}
+
+impl std::error::Error for VmSavedStateDumpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.source {
+            Some(source) => Some(source.as_ref()),
+            None => None,
+        }
+    }
+}
+
+/// Errors compare equal when they carry the same code and context, regardless of their source.
+impl PartialEq for VmSavedStateDumpError {
+    fn eq(&self, other: &VmSavedStateDumpError) -> bool {
+        self.code == other.code
+            && self.operation == other.operation
+            && self.vp_id == other.vp_id
+            && self.address == other.address
+    }
+}
+
+impl PartialEq<ResultCode> for VmSavedStateDumpError {
+    fn eq(&self, other: &ResultCode) -> bool {
+        self.code == *other
+    }
+}
+
+impl PartialEq<VmSavedStateDumpError> for ResultCode {
+    fn eq(&self, other: &VmSavedStateDumpError) -> bool {
+        *self == other.code
+    }
+}
+
+impl From<ResultCode> for VmSavedStateDumpError {
+    fn from(code: ResultCode) -> Self {
+        VmSavedStateDumpError::new(code)
+    }
+}
+
+impl From<std::io::Error> for VmSavedStateDumpError {
+    fn from(error: std::io::Error) -> Self {
+        VmSavedStateDumpError::new(ResultCode::Io(error.kind())).with_source(error)
+    }
+}
+
 #[allow(overflowing_literals)]
 fn hresult_to_result_code(hresult: &HResult) -> ResultCode {
     match hresult {
@@ -38,15 +278,69 @@ fn hresult_to_result_code(hresult: &HResult) -> ResultCode {
         0x80004005 => ResultCode::Fail,
         0x80070057 => ResultCode::InvalidArgument,
         0x8000FFFF => ResultCode::Unexpected,
-        other => ResultCode::WindowsHResult(other.clone()),
+        other => ResultCode::WindowsHResult(*other),
+    }
+}
+
+/// Converts a string or path to the null terminated wide string the API expects,
+/// failing on strings with interior null characters.
+fn to_wide_cstring<S: AsRef<OsStr>>(
+    value: S,
+    operation: &'static str,
+) -> VmSavedStateDumpResult<widestring::U16CString> {
+    widestring::U16CString::from_os_str(value).map_err(|error| {
+        VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+            .with_operation(operation)
+            .with_source(error)
+    })
+}
+
+/// Takes ownership of a path allocated by the API with LocalAlloc, and frees it.
+/// Null pointers are read as empty paths.
+unsafe fn take_local_wide_path(buffer: LPWStr) -> PathBuf {
+    if buffer.is_null() {
+        return PathBuf::new();
+    }
+
+    let path = PathBuf::from(widestring::U16CStr::from_ptr_str(buffer).to_os_string());
+    #[cfg(windows)]
+    winapi::um::winbase::LocalFree(buffer as PVoid);
+    path
+}
+
+/// Checks that the count of bytes read into a buffer of the given size fits the `u32` it is
+/// returned as, which rules out buffers of 4 GiB or more.
+fn check_read_buffer_size(
+    buffer_size: usize,
+    operation: &'static str,
+    address: u64,
+) -> VmSavedStateDumpResult<()> {
+    if buffer_size > u32::MAX as usize {
+        return Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+            .with_operation(operation)
+            .with_address(address));
+    }
+    Ok(())
+}
+
+/// Returns the register identifier that matches the given virtual processor architecture.
+fn register_for_architecture(
+    architecture: VirtualProcessorArch,
+    register_id_x86: RegisterIdx86,
+    register_id_x64: RegisterIdx64,
+) -> VmSavedStateDumpResult<Register> {
+    match architecture {
+        VirtualProcessorArch::X86 => Ok(Register::X86(register_id_x86)),
+        VirtualProcessorArch::X64 => Ok(Register::X64(register_id_x64)),
+        VirtualProcessorArch::Unknown => Err(ResultCode::Unexpected.into()),
     }
 }
 
 /// Enum that represents all possible ways a VM Saved state file can be stored
 #[derive(Debug, PartialEq)]
 pub enum VmSavedStateFile {
-    BinVsv(String, String),
-    Vmrs(String),
+    BinVsv(PathBuf, PathBuf),
+    Vmrs(PathBuf),
 }
 
 /// Locates the saved state file(s) for a given VM and/or snapshot. This function uses WMI and the V1 or V2
@@ -55,9 +349,11 @@ pub fn locate_saved_state_files(
     vm_name: &str,
     snapshot_name: &str,
 ) -> VmSavedStateDumpResult<VmSavedStateFile> {
-    let widestr_bin_file_path: widestring::WideCString;
-    let widestr_vsv_file_path: widestring::WideCString;
-    let widestr_vmrs_file_path: widestring::WideCString;
+    let vm_name = to_wide_cstring(vm_name, "locate_saved_state_files")?;
+    let snapshot_name = to_wide_cstring(snapshot_name, "locate_saved_state_files")?;
+    let bin_file_path: PathBuf;
+    let vsv_file_path: PathBuf;
+    let vmrs_file_path: PathBuf;
     let result: HResult;
 
     unsafe {
@@ -66,34 +362,24 @@ pub fn locate_saved_state_files(
         let mut vmrs_file_path_buffer: LPWStr = std::ptr::null_mut();
 
         result = LocateSavedStateFiles(
-            widestring::WideCString::from_str(vm_name).unwrap().as_ptr(),
-            widestring::WideCString::from_str(snapshot_name)
-                .unwrap()
-                .as_ptr(),
+            vm_name.as_ptr(),
+            snapshot_name.as_ptr(),
             &mut bin_file_path_buffer as *mut LPWStr,
             &mut vsv_file_path_buffer as *mut LPWStr,
             &mut vmrs_file_path_buffer as *mut LPWStr,
         );
 
-        widestr_bin_file_path = widestring::WideCString::from_ptr_str(bin_file_path_buffer);
-        winapi::um::winbase::LocalFree(bin_file_path_buffer as PVoid);
-
-        widestr_vsv_file_path = widestring::WideCString::from_ptr_str(vsv_file_path_buffer);
-        winapi::um::winbase::LocalFree(vsv_file_path_buffer as PVoid);
-
-        widestr_vmrs_file_path = widestring::WideCString::from_ptr_str(vmrs_file_path_buffer);
-        winapi::um::winbase::LocalFree(vmrs_file_path_buffer as PVoid);
+        bin_file_path = take_local_wide_path(bin_file_path_buffer);
+        vsv_file_path = take_local_wide_path(vsv_file_path_buffer);
+        vmrs_file_path = take_local_wide_path(vmrs_file_path_buffer);
     }
 
-    let bin_file_path = widestr_bin_file_path.to_string_lossy();
-    let vsv_file_path = widestr_vsv_file_path.to_string_lossy();
-    let vmrs_file_path = widestr_vmrs_file_path.to_string_lossy();
-
     match hresult_to_result_code(&result) {
         ResultCode::Success => {
-            if vmrs_file_path.is_empty() {
-                if bin_file_path.is_empty() || vsv_file_path.is_empty() {
-                    Err(ResultCode::FileNotFound)
+            if vmrs_file_path.as_os_str().is_empty() {
+                if bin_file_path.as_os_str().is_empty() || vsv_file_path.as_os_str().is_empty() {
+                    Err(VmSavedStateDumpError::new(ResultCode::FileNotFound)
+                        .with_operation("locate_saved_state_files"))
                 } else {
                     Ok(VmSavedStateFile::BinVsv(bin_file_path, vsv_file_path))
                 }
@@ -101,23 +387,43 @@ pub fn locate_saved_state_files(
                 Ok(VmSavedStateFile::Vmrs(vmrs_file_path))
             }
         }
-        error => Err(error),
+        error => Err(VmSavedStateDumpError::new(error).with_operation("locate_saved_state_files")),
     }
 }
 
 /// Applies a pending replay log to a VMRS file.
-pub fn apply_pending_replay_log(vmrs: &str) -> VmSavedStateDumpResult<()> {
+pub fn apply_pending_replay_log<P: AsRef<Path>>(vmrs: P) -> VmSavedStateDumpResult<()> {
+    let vmrs = to_wide_cstring(vmrs.as_ref(), "apply_pending_replay_log")?;
     let result: HResult;
 
     unsafe {
-        result = ApplyPendingSavedStateFileReplayLog(
-            widestring::WideCString::from_str(vmrs).unwrap().as_ptr(),
-        )
+        result = ApplyPendingSavedStateFileReplayLog(vmrs.as_ptr());
     }
 
     match hresult_to_result_code(&result) {
         ResultCode::Success => Ok(()),
-        error => Err(error),
+        error => Err(VmSavedStateDumpError::new(error).with_operation("apply_pending_replay_log")),
+    }
+}
+
+/// Cache of guest physical pages read from the saved state, evicted in the order they were read.
+#[derive(Debug)]
+struct PageCache {
+    capacity: usize,
+    pages: HashMap<GuestPhysicalAddress, Vec<u8>>,
+    order: VecDeque<GuestPhysicalAddress>,
+}
+
+impl PageCache {
+    fn insert(&mut self, page_address: GuestPhysicalAddress, page: Vec<u8>) {
+        if self.order.len() == self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.pages.remove(&evicted);
+            }
+        }
+
+        self.order.push_back(page_address);
+        self.pages.insert(page_address, page);
     }
 }
 
@@ -125,59 +431,101 @@ pub fn apply_pending_replay_log(vmrs: &str) -> VmSavedStateDumpResult<()> {
 #[derive(Debug)]
 pub struct VmSavedStateDumpProvider {
     handle: VmSavedStateDumpHandle,
+    page_cache: Option<RefCell<PageCache>>,
 }
 
 impl ops::Drop for VmSavedStateDumpProvider {
     fn drop(&mut self) {
-        unsafe {
-            if hresult_to_result_code(&ReleaseSavedStateFiles(self.handle)) != ResultCode::Success {
-                panic!("Failed to release saved state files");
-            }
-        }
+        // Failures to release can't be reported from here, `close` returns them instead
+        let _ = self.release();
     }
 }
 
 impl VmSavedStateDumpProvider {
     /// Loads a BIN/VSV VM Saved state files and returns a VmSavedStateDumpProvider instance
     /// that provides the interface to the dump related APIs.
-    pub fn load_bin_vsv(bin: &str, vsv: &str) -> VmSavedStateDumpResult<VmSavedStateDumpProvider> {
+    pub fn load_bin_vsv<P: AsRef<Path>, Q: AsRef<Path>>(
+        bin: P,
+        vsv: Q,
+    ) -> VmSavedStateDumpResult<VmSavedStateDumpProvider> {
+        let bin = to_wide_cstring(bin.as_ref(), "VmSavedStateDumpProvider::load_bin_vsv")?;
+        let vsv = to_wide_cstring(vsv.as_ref(), "VmSavedStateDumpProvider::load_bin_vsv")?;
         let mut dump_handle: VmSavedStateDumpHandle = std::ptr::null_mut();
         let result: HResult;
 
         unsafe {
-            result = LoadSavedStateFiles(
-                widestring::WideCString::from_str(bin).unwrap().as_ptr(),
-                widestring::WideCString::from_str(vsv).unwrap().as_ptr(),
-                &mut dump_handle,
-            );
+            result = LoadSavedStateFiles(bin.as_ptr(), vsv.as_ptr(), &mut dump_handle);
         }
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(VmSavedStateDumpProvider {
                 handle: dump_handle,
+                page_cache: None,
             }),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::load_bin_vsv")),
         }
     }
 
     /// Loads a VMRS VM Saved state file and returns a VmSavedStateDumpProvider instance
     /// that provides the interface to the dump related APIs.
-    pub fn load_vmrs(vmrs: &str) -> VmSavedStateDumpResult<VmSavedStateDumpProvider> {
+    pub fn load_vmrs<P: AsRef<Path>>(vmrs: P) -> VmSavedStateDumpResult<VmSavedStateDumpProvider> {
+        let vmrs = to_wide_cstring(vmrs.as_ref(), "VmSavedStateDumpProvider::load_vmrs")?;
         let mut dump_handle: VmSavedStateDumpHandle = std::ptr::null_mut();
         let result: HResult;
 
         unsafe {
-            result = LoadSavedStateFile(
-                widestring::WideCString::from_str(vmrs).unwrap().as_ptr(),
-                &mut dump_handle,
-            );
+            result = LoadSavedStateFile(vmrs.as_ptr(), &mut dump_handle);
         }
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(VmSavedStateDumpProvider {
                 handle: dump_handle,
+                page_cache: None,
             }),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::load_vmrs")),
+        }
+    }
+
+    /// Caches up to the given count of guest physical pages read through
+    /// `read_guest_physical_address`, or disables the cache when the count is zero.
+    pub fn set_page_cache_capacity(&mut self, page_count: usize) {
+        self.page_cache = match page_count {
+            0 => None,
+            capacity => Some(RefCell::new(PageCache {
+                capacity,
+                pages: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        };
+    }
+
+    /// Releases the loaded saved state files, returning the result of the release
+    /// that is otherwise ignored when the provider is dropped.
+    pub fn close(mut self) -> VmSavedStateDumpResult<()> {
+        self.release()
+    }
+
+    /// Releases the saved state files the first time it's called.
+    fn release(&mut self) -> VmSavedStateDumpResult<()> {
+        if self.handle.is_null() {
+            return Ok(());
+        }
+
+        let handle = std::mem::replace(&mut self.handle, std::ptr::null_mut());
+        let result: HResult;
+
+        unsafe {
+            result = ReleaseSavedStateFiles(handle);
+        }
+
+        match hresult_to_result_code(&result) {
+            ResultCode::Success => Ok(()),
+            error => {
+                Err(VmSavedStateDumpError::new(error)
+                    .with_operation("VmSavedStateDumpProvider::close"))
+            }
         }
     }
 
@@ -192,17 +540,18 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(vp_count),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::vp_count")),
         }
     }
 
     /// Returns an iterator to virtual processors associated to this saved state file.
-    pub fn vp_iter(&self) -> VirtualProcessorIter {
-        VirtualProcessorIter {
-            provider: &self,
+    pub fn vp_iter(&self) -> VmSavedStateDumpResult<VirtualProcessorIter<'_>> {
+        Ok(VirtualProcessorIter {
+            provider: self,
             current_id: 0,
-            count: self.vp_count().unwrap(),
-        }
+            count: self.vp_count()?,
+        })
     }
 
     /// Returns the virtual processor architecture.
@@ -216,21 +565,36 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(vp_arch),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::get_vp_architecture")
+                .with_vp_id(vp_id)),
         }
     }
 
     /// Returns a virtual processor register value.
+    /// Fails with `ResultCode::RegisterArchitectureMismatch` if the supplied register
+    /// does not belong to the architecture the virtual processor is running at.
     pub fn get_vp_register_value(
         &self,
         vp_id: u32,
-        arch: VirtualProcessorArch,
-        register_id: RegisterRawId,
+        register: Register,
     ) -> VmSavedStateDumpResult<VirtualProcessorRegister> {
-        let mut vp_register_value: VirtualProcessorRegister = VirtualProcessorRegister {
-            architecture: arch,
+        let vp_architecture = self.get_vp_architecture(vp_id)?;
+
+        if vp_architecture != register.architecture() {
+            let error = ResultCode::RegisterArchitectureMismatch {
+                vp_architecture,
+                register_architecture: register.architecture(),
+            };
+            return Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::get_vp_register_value")
+                .with_vp_id(vp_id));
+        }
+
+        let mut vp_register_value = RawVirtualProcessorRegister {
+            architecture: register.architecture(),
             value: 0,
-            raw_id: register_id,
+            raw_id: register.raw_id(),
         };
         let result: HResult;
 
@@ -239,8 +603,172 @@ impl VmSavedStateDumpProvider {
         }
 
         match hresult_to_result_code(&result) {
-            ResultCode::Success => Ok(vp_register_value),
-            error => Err(error),
+            ResultCode::Success => Ok(VirtualProcessorRegister {
+                register,
+                value: vp_register_value.value,
+            }),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::get_vp_register_value")
+                .with_vp_id(vp_id)),
+        }
+    }
+
+    /// Returns the 128 bit value of a virtual processor XMM register, given its number.
+    pub fn get_vp_xmm_register(&self, vp_id: u32, xmm_index: u8) -> VmSavedStateDumpResult<u128> {
+        SavedStateReader::get_vp_xmm_register(self, vp_id, xmm_index)
+    }
+
+    /// Returns the decoded x87 FPU control and status state of a virtual processor.
+    pub fn get_vp_fp_control_status(&self, vp_id: u32) -> VmSavedStateDumpResult<FpControlStatus> {
+        SavedStateReader::get_vp_fp_control_status(self, vp_id)
+    }
+
+    /// Returns the decoded SSE control and status state of a virtual processor.
+    pub fn get_vp_xmm_control_status(
+        &self,
+        vp_id: u32,
+    ) -> VmSavedStateDumpResult<XmmControlStatus> {
+        SavedStateReader::get_vp_xmm_control_status(self, vp_id)
+    }
+
+    /// Returns a snapshot of the decoded control, flags and debug registers of a virtual processor.
+    pub fn get_vp_control_registers(&self, vp_id: u32) -> VmSavedStateDumpResult<ControlRegisters> {
+        SavedStateReader::get_vp_control_registers(self, vp_id)
+    }
+
+    /// Reads and decodes the global descriptor table of a virtual processor.
+    pub fn get_vp_gdt(&self, vp_id: u32) -> VmSavedStateDumpResult<Gdt> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let value = |register_id_x86, register_id_x64| {
+            register_value_for_architecture(
+                self,
+                vp_id,
+                architecture,
+                register_id_x86,
+                register_id_x64,
+            )
+        };
+
+        let base = value(RegisterIdx86::BaseGdtr, RegisterIdx64::BaseGdtr)?;
+        let limit = value(RegisterIdx86::LimitGdtr, RegisterIdx64::LimitGdtr)? as u16;
+        let efer = Efer(value(RegisterIdx86::Efer, RegisterIdx64::Efer)?);
+
+        let mut table = vec![0u8; limit as usize + 1];
+        let bytes_read = self.read_guest_virtual_address(vp_id, base, &mut table)?;
+        table.truncate(bytes_read as usize);
+
+        Ok(Gdt::parse(base, limit, &table, efer.lma()))
+    }
+
+    /// Reads and decodes the interrupt descriptor table of a virtual processor.
+    pub fn get_vp_idt(&self, vp_id: u32) -> VmSavedStateDumpResult<Idt> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let value = |register_id_x86, register_id_x64| {
+            register_value_for_architecture(
+                self,
+                vp_id,
+                architecture,
+                register_id_x86,
+                register_id_x64,
+            )
+        };
+
+        let base = value(RegisterIdx86::BaseIdtr, RegisterIdx64::BaseIdtr)?;
+        let limit = value(RegisterIdx86::LimitIdtr, RegisterIdx64::LimitIdtr)? as u16;
+        let efer = Efer(value(RegisterIdx86::Efer, RegisterIdx64::Efer)?);
+
+        let mut table = vec![0u8; limit as usize + 1];
+        let bytes_read = self.read_guest_virtual_address(vp_id, base, &mut table)?;
+        table.truncate(bytes_read as usize);
+
+        Ok(Idt::parse(base, limit, &table, efer.lma()))
+    }
+
+    /// Returns the descriptor referenced by the selector loaded in the given segment register
+    /// of a virtual processor, or `None` if the register holds a null selector.
+    /// Selectors that reference the LDT are resolved through the LDT loaded in LDTR.
+    pub fn get_vp_segment_descriptor(
+        &self,
+        vp_id: u32,
+        segment_register: SegmentRegister,
+    ) -> VmSavedStateDumpResult<Option<SegmentDescriptor>> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let (register_id_x86, register_id_x64) = segment_register.register_ids();
+        let selector = Selector(register_value_for_architecture(
+            self,
+            vp_id,
+            architecture,
+            register_id_x86,
+            register_id_x64,
+        )? as u16);
+
+        if selector.is_null() {
+            return Ok(None);
+        }
+
+        // The LDTR selector always refers to the GDT, whatever its table indicator says
+        let gdt = self.get_vp_gdt(vp_id)?;
+        if !selector.is_ldt() || segment_register == SegmentRegister::Ldtr {
+            return Ok(gdt.descriptor(selector).cloned());
+        }
+
+        let ldt = match self.get_vp_segment_descriptor(vp_id, SegmentRegister::Ldtr)? {
+            Some(ldt) => ldt,
+            None => return Ok(None),
+        };
+
+        let mut entry = [0u8; 8];
+        let offset = u64::from(selector.index()) * 8;
+        if offset + 7 > u64::from(ldt.limit) {
+            return Ok(None);
+        }
+
+        self.read_guest_virtual_address(vp_id, ldt.base.wrapping_add(offset), &mut entry)?;
+        let low = u64::from_le_bytes(entry);
+        if low == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(SegmentDescriptor::decode(selector, low, None)))
+    }
+
+    /// Returns the descriptor of the task state segment loaded in TR of a virtual processor.
+    pub fn get_vp_tss(&self, vp_id: u32) -> VmSavedStateDumpResult<Option<SegmentDescriptor>> {
+        self.get_vp_segment_descriptor(vp_id, SegmentRegister::Tr)
+    }
+
+    /// Returns the operating mode a virtual processor was running at.
+    pub fn get_vp_cpu_mode(&self, vp_id: u32) -> VmSavedStateDumpResult<CpuMode> {
+        let control_registers = self.get_vp_control_registers(vp_id)?;
+        let code_segment = if control_registers.cr0.pe() {
+            self.get_vp_segment_descriptor(vp_id, SegmentRegister::Cs)?
+        } else {
+            None
+        };
+
+        Ok(CpuMode::from_state(
+            control_registers.cr0,
+            control_registers.efer,
+            control_registers.flags,
+            code_segment.as_ref(),
+        ))
+    }
+
+    /// Returns the current privilege level a virtual processor was running at.
+    pub fn get_vp_privilege_level(&self, vp_id: u32) -> VmSavedStateDumpResult<u8> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let code_selector = Selector(register_value_for_architecture(
+            self,
+            vp_id,
+            architecture,
+            RegisterIdx86::SegCs,
+            RegisterIdx64::SegCs,
+        )? as u16);
+
+        match self.get_vp_cpu_mode(vp_id)? {
+            CpuMode::Real => Ok(0),
+            CpuMode::Virtual8086 => Ok(3),
+            _ => Ok(code_selector.rpl()),
         }
     }
 
@@ -255,15 +783,90 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(vp_paging_mode),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::get_vp_paging_mode")
+                .with_vp_id(vp_id)),
         }
     }
 
     /// Reads a sized guest physical address into the supplied buffer.
+    /// Buffers of 4 GiB or more fail with `InvalidArgument`.
     pub fn read_guest_physical_address(
         &self,
         physical_address: GuestPhysicalAddress,
         buffer: &mut [u8],
+    ) -> VmSavedStateDumpResult<u32> {
+        check_read_buffer_size(
+            buffer.len(),
+            "VmSavedStateDumpProvider::read_guest_physical_address",
+            physical_address,
+        )?;
+        match &self.page_cache {
+            Some(page_cache) => {
+                self.read_cached_guest_physical_address(page_cache, physical_address, buffer)
+            }
+            None => self.read_uncached_guest_physical_address(physical_address, buffer),
+        }
+    }
+
+    /// Reads a guest physical address range one page at a time, through the page cache.
+    /// Pages that can't be read in full aren't cached, and end the read.
+    fn read_cached_guest_physical_address(
+        &self,
+        page_cache: &RefCell<PageCache>,
+        physical_address: GuestPhysicalAddress,
+        buffer: &mut [u8],
+    ) -> VmSavedStateDumpResult<u32> {
+        let mut bytes_read: usize = 0;
+
+        while bytes_read < buffer.len() {
+            // Ranges past the end of the address space fail, as they do without the cache
+            let current_address = match physical_address.checked_add(bytes_read as u64) {
+                Some(current_address) => current_address,
+                None => {
+                    return Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+                        .with_operation("VmSavedStateDumpProvider::read_guest_physical_address")
+                        .with_address(physical_address))
+                }
+            };
+            let page_address = current_address - current_address % GUEST_PAGE_SIZE;
+            let page_offset = (current_address - page_address) as usize;
+            let read_size = std::cmp::min(
+                GUEST_PAGE_SIZE as usize - page_offset,
+                buffer.len() - bytes_read,
+            );
+            let destination = &mut buffer[bytes_read..bytes_read + read_size];
+
+            if let Some(page) = page_cache.borrow().pages.get(&page_address) {
+                destination.copy_from_slice(&page[page_offset..page_offset + read_size]);
+                bytes_read += read_size;
+                continue;
+            }
+
+            let mut page = vec![0u8; GUEST_PAGE_SIZE as usize];
+            let page_read = self.read_uncached_guest_physical_address(page_address, &mut page)?;
+            if page_read as usize != page.len() {
+                let available =
+                    std::cmp::min((page_read as usize).saturating_sub(page_offset), read_size);
+                destination[..available]
+                    .copy_from_slice(&page[page_offset..page_offset + available]);
+                bytes_read += available;
+                break;
+            }
+
+            destination.copy_from_slice(&page[page_offset..page_offset + read_size]);
+            page_cache.borrow_mut().insert(page_address, page);
+            bytes_read += read_size;
+        }
+
+        Ok(bytes_read as u32)
+    }
+
+    /// Reads a sized guest physical address into the supplied buffer straight from the API.
+    fn read_uncached_guest_physical_address(
+        &self,
+        physical_address: GuestPhysicalAddress,
+        buffer: &mut [u8],
     ) -> VmSavedStateDumpResult<u32> {
         let buffer_size = buffer.len() as u32;
         let buffer_ptr = buffer.as_mut_ptr();
@@ -282,7 +885,9 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(bytes_read),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::read_guest_physical_address")
+                .with_address(physical_address)),
         }
     }
 
@@ -307,10 +912,25 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(physical_address),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::guest_virtual_to_physical_address")
+                .with_vp_id(vp_id)
+                .with_address(virtual_address)),
         }
     }
 
+    /// Reads a sized guest virtual address range into the supplied buffer. Each page of the
+    /// range is translated to a physical address using the given virtual processor's state.
+    /// Buffers of 4 GiB or more fail with `InvalidArgument`.
+    pub fn read_guest_virtual_address(
+        &self,
+        vp_id: u32,
+        virtual_address: GuestVirtualAddress,
+        buffer: &mut [u8],
+    ) -> VmSavedStateDumpResult<u32> {
+        SavedStateReader::read_guest_virtual_address(self, vp_id, virtual_address, buffer)
+    }
+
     /// Returns a tuple with the page size and the layout of the physical memory of the guest.
     pub fn guest_physical_memory_chunks(
         &self,
@@ -348,13 +968,18 @@ impl VmSavedStateDumpProvider {
                         &mut chunk_count,
                     )
                 }
-                error => return Err(error), // Any other result here is unexpected
+                error => {
+                    // Any other result here is unexpected
+                    return Err(VmSavedStateDumpError::new(error)
+                        .with_operation("VmSavedStateDumpProvider::guest_physical_memory_chunks"));
+                }
             }
         }
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok((page_size, memory_chunks)),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::guest_physical_memory_chunks")),
         }
     }
 
@@ -376,17 +1001,27 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(raw_saved_memory_offset),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation(
+                    "VmSavedStateDumpProvider::guest_physical_address_to_raw_saved_memory_offset",
+                )
+                .with_address(physical_address)),
         }
     }
 
     /// Reads raw memory from the saved state file. This function reads raw memory from the saved state file
     /// as if it were a flat memory layout, regardless of the guest memory layout.
+    /// Buffers of 4 GiB or more fail with `InvalidArgument`.
     pub fn read_guest_raw_saved_memory(
         &self,
         offset: u64,
         buffer: &mut [u8],
     ) -> VmSavedStateDumpResult<u32> {
+        check_read_buffer_size(
+            buffer.len(),
+            "VmSavedStateDumpProvider::read_guest_raw_saved_memory",
+            offset,
+        )?;
         let buffer_size = buffer.len() as u32;
         let buffer_ptr = buffer.as_mut_ptr();
         let mut bytes_read: u32 = 0;
@@ -404,7 +1039,9 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(bytes_read),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::read_guest_raw_saved_memory")
+                .with_address(offset)),
         }
     }
 
@@ -419,11 +1056,252 @@ impl VmSavedStateDumpProvider {
 
         match hresult_to_result_code(&result) {
             ResultCode::Success => Ok(raw_memory_size),
-            error => Err(error),
+            error => Err(VmSavedStateDumpError::new(error)
+                .with_operation("VmSavedStateDumpProvider::guest_raw_saved_memory_size")),
         }
     }
 }
 
+/// Read access to the processor and memory state of a saved guest. `VmSavedStateDumpProvider`
+/// implements it on top of the VmSavedStateDump API; other backends, such as memory images or
+/// test fixtures, can implement it to be served by the GDB server and the Python module.
+pub trait SavedStateReader {
+    /// Returns the virtual processor count.
+    fn vp_count(&self) -> VmSavedStateDumpResult<u32>;
+
+    /// Returns the virtual processor architecture.
+    fn get_vp_architecture(&self, vp_id: u32) -> VmSavedStateDumpResult<VirtualProcessorArch>;
+
+    /// Returns a virtual processor register value. Fails with
+    /// `ResultCode::RegisterArchitectureMismatch` if the supplied register does not belong to
+    /// the architecture the virtual processor is running at.
+    fn get_vp_register_value(
+        &self,
+        vp_id: u32,
+        register: Register,
+    ) -> VmSavedStateDumpResult<VirtualProcessorRegister>;
+
+    /// Returns a virtual processor paging mode.
+    fn get_vp_paging_mode(&self, vp_id: u32) -> VmSavedStateDumpResult<PagingMode>;
+
+    /// Reads a sized guest physical address into the supplied buffer, returning the count of
+    /// bytes read.
+    fn read_guest_physical_address(
+        &self,
+        physical_address: GuestPhysicalAddress,
+        buffer: &mut [u8],
+    ) -> VmSavedStateDumpResult<u32>;
+
+    /// Translates a virtual address to a physical address using the given virtual processor's
+    /// state.
+    fn guest_virtual_to_physical_address(
+        &self,
+        vp_id: u32,
+        virtual_address: GuestVirtualAddress,
+    ) -> VmSavedStateDumpResult<GuestPhysicalAddress>;
+
+    /// Returns a tuple with the page size and the layout of the physical memory of the guest.
+    fn guest_physical_memory_chunks(&self) -> VmSavedStateDumpResult<(u64, Vec<GpaMemoryChunk>)>;
+
+    /// Reads a sized guest virtual address range into the supplied buffer. Each page of the
+    /// range is translated to a physical address using the given virtual processor's state.
+    /// Buffers of 4 GiB or more fail with `InvalidArgument`.
+    fn read_guest_virtual_address(
+        &self,
+        vp_id: u32,
+        virtual_address: GuestVirtualAddress,
+        buffer: &mut [u8],
+    ) -> VmSavedStateDumpResult<u32> {
+        check_read_buffer_size(
+            buffer.len(),
+            "SavedStateReader::read_guest_virtual_address",
+            virtual_address,
+        )
+        .map_err(|error| error.with_vp_id(vp_id))?;
+        let mut bytes_read: usize = 0;
+
+        while bytes_read < buffer.len() {
+            let current_address = virtual_address.wrapping_add(bytes_read as u64);
+            let page_remaining = (GUEST_PAGE_SIZE - (current_address % GUEST_PAGE_SIZE)) as usize;
+            let read_size = std::cmp::min(page_remaining, buffer.len() - bytes_read);
+
+            let physical_address =
+                self.guest_virtual_to_physical_address(vp_id, current_address)?;
+            let read = self.read_guest_physical_address(
+                physical_address,
+                &mut buffer[bytes_read..bytes_read + read_size],
+            )? as usize;
+
+            bytes_read += read;
+            if read < read_size {
+                break;
+            }
+        }
+
+        Ok(bytes_read as u32)
+    }
+
+    /// Returns the 128 bit value of a virtual processor XMM register, given its number.
+    fn get_vp_xmm_register(&self, vp_id: u32, xmm_index: u8) -> VmSavedStateDumpResult<u128> {
+        let xmm_index = xmm_index as usize;
+        if xmm_index >= XMM_REGISTERS_X64.len() {
+            return Err(VmSavedStateDumpError::new(ResultCode::InvalidArgument)
+                .with_operation("SavedStateReader::get_vp_xmm_register")
+                .with_vp_id(vp_id));
+        }
+
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let (low_x86, high_x86) = XMM_REGISTERS_X86[xmm_index];
+        let (low_x64, high_x64) = XMM_REGISTERS_X64[xmm_index];
+        let (low, high) = register_halves(
+            self,
+            vp_id,
+            register_for_architecture(architecture, low_x86, low_x64)?,
+            register_for_architecture(architecture, high_x86, high_x64)?,
+        )?;
+
+        Ok(u128::from(high) << 64 | u128::from(low))
+    }
+
+    /// Returns the decoded x87 FPU control and status state of a virtual processor.
+    fn get_vp_fp_control_status(&self, vp_id: u32) -> VmSavedStateDumpResult<FpControlStatus> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let (low, high) = register_halves(
+            self,
+            vp_id,
+            register_for_architecture(
+                architecture,
+                RegisterIdx86::LowFpControlStatus,
+                RegisterIdx64::LowFpControlStatus,
+            )?,
+            register_for_architecture(
+                architecture,
+                RegisterIdx86::HighFpControlStatus,
+                RegisterIdx64::HighFpControlStatus,
+            )?,
+        )?;
+
+        Ok(FpControlStatus::from_halves(low, high))
+    }
+
+    /// Returns the decoded SSE control and status state of a virtual processor.
+    fn get_vp_xmm_control_status(&self, vp_id: u32) -> VmSavedStateDumpResult<XmmControlStatus> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let (low, high) = register_halves(
+            self,
+            vp_id,
+            register_for_architecture(
+                architecture,
+                RegisterIdx86::LowXmmControlStatus,
+                RegisterIdx64::LowXmmControlStatus,
+            )?,
+            register_for_architecture(
+                architecture,
+                RegisterIdx86::HighXmmControlStatus,
+                RegisterIdx64::HighXmmControlStatus,
+            )?,
+        )?;
+
+        Ok(XmmControlStatus::from_halves(low, high))
+    }
+
+    /// Returns a snapshot of the decoded control, flags and debug registers of a virtual processor.
+    fn get_vp_control_registers(&self, vp_id: u32) -> VmSavedStateDumpResult<ControlRegisters> {
+        let architecture = self.get_vp_architecture(vp_id)?;
+        let value = |register_id_x86, register_id_x64| {
+            register_value_for_architecture(
+                self,
+                vp_id,
+                architecture,
+                register_id_x86,
+                register_id_x64,
+            )
+        };
+
+        Ok(ControlRegisters {
+            flags: RFlags(value(RegisterIdx86::EFlags, RegisterIdx64::RFlags)?),
+            cr0: Cr0(value(RegisterIdx86::Cr0, RegisterIdx64::Cr0)?),
+            cr2: value(RegisterIdx86::Cr2, RegisterIdx64::Cr2)?,
+            cr3: value(RegisterIdx86::Cr3, RegisterIdx64::Cr3)?,
+            cr4: Cr4(value(RegisterIdx86::Cr4, RegisterIdx64::Cr4)?),
+            cr8: value(RegisterIdx86::Cr8, RegisterIdx64::Cr8)?,
+            efer: Efer(value(RegisterIdx86::Efer, RegisterIdx64::Efer)?),
+            dr0: value(RegisterIdx86::Dr0, RegisterIdx64::Dr0)?,
+            dr1: value(RegisterIdx86::Dr1, RegisterIdx64::Dr1)?,
+            dr2: value(RegisterIdx86::Dr2, RegisterIdx64::Dr2)?,
+            dr3: value(RegisterIdx86::Dr3, RegisterIdx64::Dr3)?,
+            dr6: Dr6(value(RegisterIdx86::Dr6, RegisterIdx64::Dr6)?),
+            dr7: Dr7(value(RegisterIdx86::Dr7, RegisterIdx64::Dr7)?),
+        })
+    }
+}
+
+impl SavedStateReader for VmSavedStateDumpProvider {
+    fn vp_count(&self) -> VmSavedStateDumpResult<u32> {
+        VmSavedStateDumpProvider::vp_count(self)
+    }
+
+    fn get_vp_architecture(&self, vp_id: u32) -> VmSavedStateDumpResult<VirtualProcessorArch> {
+        VmSavedStateDumpProvider::get_vp_architecture(self, vp_id)
+    }
+
+    fn get_vp_register_value(
+        &self,
+        vp_id: u32,
+        register: Register,
+    ) -> VmSavedStateDumpResult<VirtualProcessorRegister> {
+        VmSavedStateDumpProvider::get_vp_register_value(self, vp_id, register)
+    }
+
+    fn get_vp_paging_mode(&self, vp_id: u32) -> VmSavedStateDumpResult<PagingMode> {
+        VmSavedStateDumpProvider::get_vp_paging_mode(self, vp_id)
+    }
+
+    fn read_guest_physical_address(
+        &self,
+        physical_address: GuestPhysicalAddress,
+        buffer: &mut [u8],
+    ) -> VmSavedStateDumpResult<u32> {
+        VmSavedStateDumpProvider::read_guest_physical_address(self, physical_address, buffer)
+    }
+
+    fn guest_virtual_to_physical_address(
+        &self,
+        vp_id: u32,
+        virtual_address: GuestVirtualAddress,
+    ) -> VmSavedStateDumpResult<GuestPhysicalAddress> {
+        VmSavedStateDumpProvider::guest_virtual_to_physical_address(self, vp_id, virtual_address)
+    }
+
+    fn guest_physical_memory_chunks(&self) -> VmSavedStateDumpResult<(u64, Vec<GpaMemoryChunk>)> {
+        VmSavedStateDumpProvider::guest_physical_memory_chunks(self)
+    }
+}
+
+/// Returns the value of the register identifier that matches the given architecture.
+fn register_value_for_architecture<R: SavedStateReader + ?Sized>(
+    reader: &R,
+    vp_id: u32,
+    architecture: VirtualProcessorArch,
+    register_id_x86: RegisterIdx86,
+    register_id_x64: RegisterIdx64,
+) -> VmSavedStateDumpResult<u64> {
+    let register = register_for_architecture(architecture, register_id_x86, register_id_x64)?;
+    Ok(reader.get_vp_register_value(vp_id, register)?.value)
+}
+
+/// Returns the 128 bit value of a register that is split in a low and a high 64 bit half.
+fn register_halves<R: SavedStateReader + ?Sized>(
+    reader: &R,
+    vp_id: u32,
+    low: Register,
+    high: Register,
+) -> VmSavedStateDumpResult<(u64, u64)> {
+    let low = reader.get_vp_register_value(vp_id, low)?;
+    let high = reader.get_vp_register_value(vp_id, high)?;
+    Ok((low.value, high.value))
+}
+
 /// Represents a virtual processor of a VmSavedStateDumpProvider
 /// and exposes simpler APIs that work with the VP it represents.
 #[derive(Debug)]
@@ -450,7 +1328,7 @@ impl<'a> Iterator for VirtualProcessorIter<'a> {
 
         if vp_id < self.count {
             Some(VirtualProcessor {
-                provider: &self.provider,
+                provider: self.provider,
                 id: vp_id,
             })
         } else {
@@ -473,11 +1351,63 @@ impl<'a> VirtualProcessor<'a> {
     /// Returns the register value of a given virtual processor.
     pub fn register_value(
         &self,
-        arch: VirtualProcessorArch,
-        register_id: RegisterRawId,
+        register: Register,
     ) -> VmSavedStateDumpResult<VirtualProcessorRegister> {
+        self.provider.get_vp_register_value(self.id, register)
+    }
+
+    /// Returns the 128 bit value of an XMM register of a given virtual processor.
+    pub fn xmm_register(&self, xmm_index: u8) -> VmSavedStateDumpResult<u128> {
+        self.provider.get_vp_xmm_register(self.id, xmm_index)
+    }
+
+    /// Returns the decoded x87 FPU control and status state of a given virtual processor.
+    pub fn fp_control_status(&self) -> VmSavedStateDumpResult<FpControlStatus> {
+        self.provider.get_vp_fp_control_status(self.id)
+    }
+
+    /// Returns the decoded SSE control and status state of a given virtual processor.
+    pub fn xmm_control_status(&self) -> VmSavedStateDumpResult<XmmControlStatus> {
+        self.provider.get_vp_xmm_control_status(self.id)
+    }
+
+    /// Returns the decoded control, flags and debug registers of a given virtual processor.
+    pub fn control_registers(&self) -> VmSavedStateDumpResult<ControlRegisters> {
+        self.provider.get_vp_control_registers(self.id)
+    }
+
+    /// Returns the decoded global descriptor table of a given virtual processor.
+    pub fn gdt(&self) -> VmSavedStateDumpResult<Gdt> {
+        self.provider.get_vp_gdt(self.id)
+    }
+
+    /// Returns the decoded interrupt descriptor table of a given virtual processor.
+    pub fn idt(&self) -> VmSavedStateDumpResult<Idt> {
+        self.provider.get_vp_idt(self.id)
+    }
+
+    /// Returns the descriptor loaded in the given segment register of a given virtual processor.
+    pub fn segment_descriptor(
+        &self,
+        segment_register: SegmentRegister,
+    ) -> VmSavedStateDumpResult<Option<SegmentDescriptor>> {
         self.provider
-            .get_vp_register_value(self.id, arch, register_id)
+            .get_vp_segment_descriptor(self.id, segment_register)
+    }
+
+    /// Returns the task state segment descriptor of a given virtual processor.
+    pub fn tss(&self) -> VmSavedStateDumpResult<Option<SegmentDescriptor>> {
+        self.provider.get_vp_tss(self.id)
+    }
+
+    /// Returns the operating mode of a given virtual processor.
+    pub fn cpu_mode(&self) -> VmSavedStateDumpResult<CpuMode> {
+        self.provider.get_vp_cpu_mode(self.id)
+    }
+
+    /// Returns the current privilege level of a given virtual processor.
+    pub fn privilege_level(&self) -> VmSavedStateDumpResult<u8> {
+        self.provider.get_vp_privilege_level(self.id)
     }
 
     /// Returns the paging mode of a given virtual processor.
@@ -485,3 +1415,20 @@ impl<'a> VirtualProcessor<'a> {
         self.provider.get_vp_paging_mode(self.id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn read_buffer_sizes_fit_the_returned_count() {
+        assert!(check_read_buffer_size(0, "read", 0).is_ok());
+        assert!(check_read_buffer_size(u32::MAX as usize, "read", 0).is_ok());
+
+        let error = check_read_buffer_size(u32::MAX as usize + 1, "read", 0x1000).unwrap_err();
+        assert_eq!(ResultCode::InvalidArgument, error);
+        assert_eq!(Some("read"), error.operation());
+        assert_eq!(Some(0x1000), error.address());
+    }
+}
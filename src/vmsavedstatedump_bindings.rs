@@ -7,10 +7,18 @@
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
 //! This file contains the interface definitions for the VmSavedState Dump Provider APIs.
+//!
+//! vmsavedstatedumpprovider.dll only exists on Windows. On other platforms the same functions
+//! are declared by `unsupported`, failing every call with `E_NOTIMPL`, so that the parts of
+//! the crate that don't read saved state files still build and run there.
 
 use crate::vmsavedstatedumpdefs::*;
 use crate::windefs::*;
 
+#[cfg(not(windows))]
+pub use unsupported::*;
+
+#[cfg(windows)]
 #[link(name = "vmsavedstatedumpprovider")]
 extern "C" {
 
@@ -160,7 +168,7 @@ extern "C" {
     pub fn GetRegisterValue(
         VmSavedStateDumpHandle: VmSavedStateDumpHandle,
         VpId: u32,
-        Register: *mut VirtualProcessorRegister,
+        Register: *mut RawVirtualProcessorRegister,
     ) -> HResult;
 
     /// Queries for the current Paging Mode in use by the virtual processor at the time the
@@ -315,3 +323,132 @@ extern "C" {
     ) -> HResult;
 
 }
+
+/// Stand-ins for the functions of vmsavedstatedumpprovider.dll on platforms that don't have it.
+#[cfg(not(windows))]
+#[allow(non_snake_case, clippy::missing_safety_doc)]
+mod unsupported {
+    use super::*;
+
+    /// HRESULT returned by every function, E_NOTIMPL.
+    const E_NOTIMPL: HResult = 0x8000_4001_u32 as HResult;
+
+    pub unsafe fn LocateSavedStateFiles(
+        _VmName: LPCWStr,
+        _SnapshotName: LPCWStr,
+        _BinPath: *mut LPWStr,
+        _VsvPath: *mut LPWStr,
+        _VmrsPath: *mut LPWStr,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn LoadSavedStateFile(
+        _VmrsFile: LPCWStr,
+        _VmSavedStateDumpHandle: *mut VmSavedStateDumpHandle,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn ApplyPendingSavedStateFileReplayLog(_VmrsFile: LPCWStr) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn LoadSavedStateFiles(
+        _BinFile: LPCWStr,
+        _VsvFile: LPCWStr,
+        _VmSavedStateDumpHandle: *mut VmSavedStateDumpHandle,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn ReleaseSavedStateFiles(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GetVpCount(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _VpCount: *mut u32,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GetArchitecture(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _VpId: u32,
+        _Architecture: *mut VirtualProcessorArch,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GetRegisterValue(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _VpId: u32,
+        _Register: *mut RawVirtualProcessorRegister,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GetPagingMode(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _VpId: u32,
+        _PagingMode: *mut PagingMode,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn ReadGuestPhysicalAddress(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _PhysicalAddress: GuestPhysicalAddress,
+        _Buffer: PVoid,
+        _BufferSize: u32,
+        _BytesRead: *mut u32,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GuestVirtualAddressToPhysicalAddress(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _VpId: u32,
+        _VirtualAddress: GuestVirtualAddress,
+        _PhysicalAddress: *mut GuestPhysicalAddress,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GetGuestPhysicalMemoryChunks(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _MemoryChunkPageSize: *mut u64,
+        _MemoryChunks: *mut GpaMemoryChunk,
+        _MemoryChunkCount: *mut u64,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GuestPhysicalAddressToRawSavedMemoryOffset(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _PhysicalAddress: GuestPhysicalAddress,
+        _RawSavedMemoryOffset: *mut u64,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn ReadGuestRawSavedMemory(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _RawSavedMemoryOffset: u64,
+        _Buffer: PVoid,
+        _BufferSize: u32,
+        _BytesRead: *mut u32,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+
+    pub unsafe fn GetGuestRawSavedMemorySize(
+        _VmSavedStateDumpHandle: VmSavedStateDumpHandle,
+        _GuestRawSavedMemorySize: *mut u64,
+    ) -> HResult {
+        E_NOTIMPL
+    }
+}
@@ -16,6 +16,11 @@ pub type GuestPhysicalAddress = u64;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "python",
+    pyo3::pyclass(eq, eq_int, frozen, hash, module = "vmsavedstatedump")
+)]
 pub enum PagingMode {
     Invalid = 0,
     NonPaged,
@@ -26,6 +31,7 @@ pub enum PagingMode {
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GpaMemoryChunk {
     pub guest_physical_start_page_index: u64,
     pub page_count: u64,
@@ -33,6 +39,11 @@ pub struct GpaMemoryChunk {
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "python",
+    pyo3::pyclass(eq, eq_int, frozen, hash, module = "vmsavedstatedump")
+)]
 pub enum VirtualProcessorArch {
     Unknown = 0,
     X86,
@@ -41,6 +52,18 @@ pub enum VirtualProcessorArch {
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "python",
+    pyo3::pyclass(
+        eq,
+        eq_int,
+        frozen,
+        hash,
+        module = "vmsavedstatedump",
+        name = "RegisterX86"
+    )
+)]
 pub enum RegisterIdx86 {
     //
     // General Purpose Registers
@@ -146,6 +169,18 @@ pub enum RegisterIdx86 {
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "python",
+    pyo3::pyclass(
+        eq,
+        eq_int,
+        frozen,
+        hash,
+        module = "vmsavedstatedump",
+        name = "RegisterX64"
+    )
+)]
 pub enum RegisterIdx64 {
     //
     // General Purpose Registers
@@ -257,9 +292,288 @@ pub enum RegisterIdx64 {
     Count,
 }
 
+/// Type-safe register identifier. Each variant carries the architecture
+/// the register identifier belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Register {
+    X86(RegisterIdx86),
+    X64(RegisterIdx64),
+}
+
+impl Register {
+    /// Returns every register of the given architecture.
+    pub fn all(architecture: VirtualProcessorArch) -> Vec<Register> {
+        match architecture {
+            VirtualProcessorArch::X86 => {
+                REGISTERS_X86.iter().map(|id| Register::X86(*id)).collect()
+            }
+            VirtualProcessorArch::X64 => {
+                REGISTERS_X64.iter().map(|id| Register::X64(*id)).collect()
+            }
+            VirtualProcessorArch::Unknown => Vec::new(),
+        }
+    }
+
+    /// Returns the lowercase name of the register, as written in assembly for the registers
+    /// that have one (e.g. `rip`, `cs`), or derived from the register identifier otherwise.
+    pub fn name(&self) -> String {
+        let name = match self {
+            Register::X86(register_id) => format!("{:?}", register_id),
+            Register::X64(register_id) => format!("{:?}", register_id),
+        }
+        .to_ascii_lowercase();
+
+        match name.strip_prefix("seg") {
+            Some(segment) => segment.to_string(),
+            None => name,
+        }
+    }
+
+    /// Looks up a register of the given architecture by its name, ignoring case.
+    pub fn from_name(architecture: VirtualProcessorArch, name: &str) -> Option<Register> {
+        let name = name.to_ascii_lowercase();
+        Register::all(architecture)
+            .into_iter()
+            .find(|register| register.name() == name)
+    }
+
+    /// Returns the virtual processor architecture this register belongs to.
+    pub fn architecture(&self) -> VirtualProcessorArch {
+        match self {
+            Register::X86(_) => VirtualProcessorArch::X86,
+            Register::X64(_) => VirtualProcessorArch::X64,
+        }
+    }
+
+    /// Returns the raw register identifier used by the VmSavedStateDumpProvider API.
+    pub(crate) fn raw_id(&self) -> RegisterRawId {
+        match self {
+            Register::X86(register_id_x86) => RegisterRawId {
+                register_id_x86: *register_id_x86,
+            },
+            Register::X64(register_id_x64) => RegisterRawId {
+                register_id_x64: *register_id_x64,
+            },
+        }
+    }
+}
+
+impl From<RegisterIdx86> for Register {
+    fn from(register_id: RegisterIdx86) -> Self {
+        Register::X86(register_id)
+    }
+}
+
+impl From<RegisterIdx64> for Register {
+    fn from(register_id: RegisterIdx64) -> Self {
+        Register::X64(register_id)
+    }
+}
+
+/// Every x86 register, in register identifier order.
+pub const REGISTERS_X86: [RegisterIdx86; 72] = [
+    RegisterIdx86::Eax,
+    RegisterIdx86::Ecx,
+    RegisterIdx86::Edx,
+    RegisterIdx86::Ebx,
+    RegisterIdx86::Esp,
+    RegisterIdx86::Ebp,
+    RegisterIdx86::Esi,
+    RegisterIdx86::Edi,
+    RegisterIdx86::Eip,
+    RegisterIdx86::EFlags,
+    RegisterIdx86::LowXmm0,
+    RegisterIdx86::HighXmm0,
+    RegisterIdx86::LowXmm1,
+    RegisterIdx86::HighXmm1,
+    RegisterIdx86::LowXmm2,
+    RegisterIdx86::HighXmm2,
+    RegisterIdx86::LowXmm3,
+    RegisterIdx86::HighXmm3,
+    RegisterIdx86::LowXmm4,
+    RegisterIdx86::HighXmm4,
+    RegisterIdx86::LowXmm5,
+    RegisterIdx86::HighXmm5,
+    RegisterIdx86::LowXmm6,
+    RegisterIdx86::HighXmm6,
+    RegisterIdx86::LowXmm7,
+    RegisterIdx86::HighXmm7,
+    RegisterIdx86::LowXmm8,
+    RegisterIdx86::HighXmm8,
+    RegisterIdx86::LowXmm9,
+    RegisterIdx86::HighXmm9,
+    RegisterIdx86::LowXmm10,
+    RegisterIdx86::HighXmm10,
+    RegisterIdx86::LowXmm11,
+    RegisterIdx86::HighXmm11,
+    RegisterIdx86::LowXmm12,
+    RegisterIdx86::HighXmm12,
+    RegisterIdx86::LowXmm13,
+    RegisterIdx86::HighXmm13,
+    RegisterIdx86::LowXmm14,
+    RegisterIdx86::HighXmm14,
+    RegisterIdx86::LowXmm15,
+    RegisterIdx86::HighXmm15,
+    RegisterIdx86::LowXmmControlStatus,
+    RegisterIdx86::HighXmmControlStatus,
+    RegisterIdx86::LowFpControlStatus,
+    RegisterIdx86::HighFpControlStatus,
+    RegisterIdx86::Cr0,
+    RegisterIdx86::Cr2,
+    RegisterIdx86::Cr3,
+    RegisterIdx86::Cr4,
+    RegisterIdx86::Cr8,
+    RegisterIdx86::Efer,
+    RegisterIdx86::Dr0,
+    RegisterIdx86::Dr1,
+    RegisterIdx86::Dr2,
+    RegisterIdx86::Dr3,
+    RegisterIdx86::Dr6,
+    RegisterIdx86::Dr7,
+    RegisterIdx86::BaseGs,
+    RegisterIdx86::BaseFs,
+    RegisterIdx86::SegCs,
+    RegisterIdx86::SegDs,
+    RegisterIdx86::SegEs,
+    RegisterIdx86::SegFs,
+    RegisterIdx86::SegGs,
+    RegisterIdx86::SegSs,
+    RegisterIdx86::Tr,
+    RegisterIdx86::Ldtr,
+    RegisterIdx86::BaseIdtr,
+    RegisterIdx86::LimitIdtr,
+    RegisterIdx86::BaseGdtr,
+    RegisterIdx86::LimitGdtr,
+];
+
+/// Every x64 register, in register identifier order.
+pub const REGISTERS_X64: [RegisterIdx64; 80] = [
+    RegisterIdx64::Rax,
+    RegisterIdx64::Rcx,
+    RegisterIdx64::Rdx,
+    RegisterIdx64::Rbx,
+    RegisterIdx64::Rsp,
+    RegisterIdx64::Rbp,
+    RegisterIdx64::Rsi,
+    RegisterIdx64::Rdi,
+    RegisterIdx64::R8,
+    RegisterIdx64::R9,
+    RegisterIdx64::R10,
+    RegisterIdx64::R11,
+    RegisterIdx64::R12,
+    RegisterIdx64::R13,
+    RegisterIdx64::R14,
+    RegisterIdx64::R15,
+    RegisterIdx64::Rip,
+    RegisterIdx64::RFlags,
+    RegisterIdx64::LowXmm0,
+    RegisterIdx64::HighXmm0,
+    RegisterIdx64::LowXmm1,
+    RegisterIdx64::HighXmm1,
+    RegisterIdx64::LowXmm2,
+    RegisterIdx64::HighXmm2,
+    RegisterIdx64::LowXmm3,
+    RegisterIdx64::HighXmm3,
+    RegisterIdx64::LowXmm4,
+    RegisterIdx64::HighXmm4,
+    RegisterIdx64::LowXmm5,
+    RegisterIdx64::HighXmm5,
+    RegisterIdx64::LowXmm6,
+    RegisterIdx64::HighXmm6,
+    RegisterIdx64::LowXmm7,
+    RegisterIdx64::HighXmm7,
+    RegisterIdx64::LowXmm8,
+    RegisterIdx64::HighXmm8,
+    RegisterIdx64::LowXmm9,
+    RegisterIdx64::HighXmm9,
+    RegisterIdx64::LowXmm10,
+    RegisterIdx64::HighXmm10,
+    RegisterIdx64::LowXmm11,
+    RegisterIdx64::HighXmm11,
+    RegisterIdx64::LowXmm12,
+    RegisterIdx64::HighXmm12,
+    RegisterIdx64::LowXmm13,
+    RegisterIdx64::HighXmm13,
+    RegisterIdx64::LowXmm14,
+    RegisterIdx64::HighXmm14,
+    RegisterIdx64::LowXmm15,
+    RegisterIdx64::HighXmm15,
+    RegisterIdx64::LowXmmControlStatus,
+    RegisterIdx64::HighXmmControlStatus,
+    RegisterIdx64::LowFpControlStatus,
+    RegisterIdx64::HighFpControlStatus,
+    RegisterIdx64::Cr0,
+    RegisterIdx64::Cr2,
+    RegisterIdx64::Cr3,
+    RegisterIdx64::Cr4,
+    RegisterIdx64::Cr8,
+    RegisterIdx64::Efer,
+    RegisterIdx64::Dr0,
+    RegisterIdx64::Dr1,
+    RegisterIdx64::Dr2,
+    RegisterIdx64::Dr3,
+    RegisterIdx64::Dr6,
+    RegisterIdx64::Dr7,
+    RegisterIdx64::BaseGs,
+    RegisterIdx64::BaseFs,
+    RegisterIdx64::SegCs,
+    RegisterIdx64::SegDs,
+    RegisterIdx64::SegEs,
+    RegisterIdx64::SegFs,
+    RegisterIdx64::SegGs,
+    RegisterIdx64::SegSs,
+    RegisterIdx64::Tr,
+    RegisterIdx64::Ldtr,
+    RegisterIdx64::BaseIdtr,
+    RegisterIdx64::LimitIdtr,
+    RegisterIdx64::BaseGdtr,
+    RegisterIdx64::LimitGdtr,
+];
+
+/// Low and high 64 bit halves of the x86 XMM registers, indexed by XMM register number.
+pub(crate) const XMM_REGISTERS_X86: [(RegisterIdx86, RegisterIdx86); 16] = [
+    (RegisterIdx86::LowXmm0, RegisterIdx86::HighXmm0),
+    (RegisterIdx86::LowXmm1, RegisterIdx86::HighXmm1),
+    (RegisterIdx86::LowXmm2, RegisterIdx86::HighXmm2),
+    (RegisterIdx86::LowXmm3, RegisterIdx86::HighXmm3),
+    (RegisterIdx86::LowXmm4, RegisterIdx86::HighXmm4),
+    (RegisterIdx86::LowXmm5, RegisterIdx86::HighXmm5),
+    (RegisterIdx86::LowXmm6, RegisterIdx86::HighXmm6),
+    (RegisterIdx86::LowXmm7, RegisterIdx86::HighXmm7),
+    (RegisterIdx86::LowXmm8, RegisterIdx86::HighXmm8),
+    (RegisterIdx86::LowXmm9, RegisterIdx86::HighXmm9),
+    (RegisterIdx86::LowXmm10, RegisterIdx86::HighXmm10),
+    (RegisterIdx86::LowXmm11, RegisterIdx86::HighXmm11),
+    (RegisterIdx86::LowXmm12, RegisterIdx86::HighXmm12),
+    (RegisterIdx86::LowXmm13, RegisterIdx86::HighXmm13),
+    (RegisterIdx86::LowXmm14, RegisterIdx86::HighXmm14),
+    (RegisterIdx86::LowXmm15, RegisterIdx86::HighXmm15),
+];
+
+/// Low and high 64 bit halves of the x64 XMM registers, indexed by XMM register number.
+pub(crate) const XMM_REGISTERS_X64: [(RegisterIdx64, RegisterIdx64); 16] = [
+    (RegisterIdx64::LowXmm0, RegisterIdx64::HighXmm0),
+    (RegisterIdx64::LowXmm1, RegisterIdx64::HighXmm1),
+    (RegisterIdx64::LowXmm2, RegisterIdx64::HighXmm2),
+    (RegisterIdx64::LowXmm3, RegisterIdx64::HighXmm3),
+    (RegisterIdx64::LowXmm4, RegisterIdx64::HighXmm4),
+    (RegisterIdx64::LowXmm5, RegisterIdx64::HighXmm5),
+    (RegisterIdx64::LowXmm6, RegisterIdx64::HighXmm6),
+    (RegisterIdx64::LowXmm7, RegisterIdx64::HighXmm7),
+    (RegisterIdx64::LowXmm8, RegisterIdx64::HighXmm8),
+    (RegisterIdx64::LowXmm9, RegisterIdx64::HighXmm9),
+    (RegisterIdx64::LowXmm10, RegisterIdx64::HighXmm10),
+    (RegisterIdx64::LowXmm11, RegisterIdx64::HighXmm11),
+    (RegisterIdx64::LowXmm12, RegisterIdx64::HighXmm12),
+    (RegisterIdx64::LowXmm13, RegisterIdx64::HighXmm13),
+    (RegisterIdx64::LowXmm14, RegisterIdx64::HighXmm14),
+    (RegisterIdx64::LowXmm15, RegisterIdx64::HighXmm15),
+];
+
 #[repr(C)]
 #[derive(Copy, Clone)]
-pub union RegisterRawId {
+pub(crate) union RegisterRawId {
     pub register_id: DWord,
     pub register_id_x86: RegisterIdx86,
     pub register_id_x64: RegisterIdx64,
@@ -275,8 +589,16 @@ impl std::fmt::Debug for RegisterRawId {
 /// are inputs and the register value is an output.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-pub struct VirtualProcessorRegister {
+pub(crate) struct RawVirtualProcessorRegister {
     pub architecture: VirtualProcessorArch,
     pub value: u64,
     pub raw_id: RegisterRawId,
 }
+
+/// Value of a virtual processor register, along with the register it was read from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VirtualProcessorRegister {
+    pub register: Register,
+    pub value: u64,
+}
@@ -6,7 +6,20 @@
 // except according to those terms.
 // THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
 
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use vmsavedstatedump_rs::dump::elf::*;
+use vmsavedstatedump_rs::dump::kdump::*;
+use vmsavedstatedump_rs::dump::lime::*;
+use vmsavedstatedump_rs::dump::raw::*;
+use vmsavedstatedump_rs::dump::verify::*;
+use vmsavedstatedump_rs::dump::windows::*;
+use vmsavedstatedump_rs::dump::*;
+use vmsavedstatedump_rs::gdb::*;
+use vmsavedstatedump_rs::open::*;
+use vmsavedstatedump_rs::paging::*;
+use vmsavedstatedump_rs::shell::*;
+use vmsavedstatedump_rs::summary::*;
 use vmsavedstatedump_rs::vmsavedstatedump::*;
 use vmsavedstatedump_rs::vmsavedstatedumpdefs::*;
 
@@ -77,6 +90,68 @@ fn wrong_path_vmrs_file_cant_be_loaded() {
     assert_eq!(ResultCode::FileNotFound, provider.unwrap_err());
 }
 
+#[test]
+fn errors_carry_operation_context() {
+    use std::error::Error;
+
+    let error = VmSavedStateDumpProvider::load_vmrs("some_wrong_path.vmrs").unwrap_err();
+    assert_eq!(
+        Some("VmSavedStateDumpProvider::load_vmrs"),
+        error.operation()
+    );
+    let hresult = error.hresult().unwrap();
+    assert!(hresult.is_failure);
+    assert_eq!(Some("Win32"), hresult.facility_name());
+    assert_eq!(2, hresult.code);
+    assert!(error
+        .to_string()
+        .starts_with("VmSavedStateDumpProvider::load_vmrs failed"));
+    assert!(error.source().is_none());
+
+    let provider = get_vmrs_test_provider();
+    let error = provider.get_vp_xmm_register(0, 16).unwrap_err();
+    assert_eq!(Some(0), error.vp_id());
+    assert_eq!(0x57, error.hresult().unwrap().code);
+
+    // Reported HRESULTs are decoded into their facility and code
+    let error = VmSavedStateDumpError::new(ResultCode::WindowsHResult(0x8037_0101_u32 as i32));
+    let hresult = error.hresult().unwrap();
+    assert_eq!(55, hresult.facility);
+    assert_eq!(0x101, hresult.code);
+    assert_eq!(
+        "HRESULT 0x80370101 (facility Virtualization, code 0x0101)",
+        error.to_string()
+    );
+
+    // I/O errors are chained as the source
+    let error = verify_elf_core(&provider, &mut std::io::Cursor::new(Vec::new())).unwrap_err();
+    assert_eq!(ResultCode::Io(std::io::ErrorKind::UnexpectedEof), error);
+    let source = error.source().unwrap();
+    assert!(source.downcast_ref::<std::io::Error>().is_some());
+}
+
+#[test]
+fn paths_with_interior_nul_are_rejected() {
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        VmSavedStateDumpProvider::load_vmrs("some\0path.vmrs").unwrap_err()
+    );
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        VmSavedStateDumpProvider::load_bin_vsv("some\0path.bin", "some_path.vsv").unwrap_err()
+    );
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        apply_pending_replay_log("some\0path.vmrs").unwrap_err()
+    );
+}
+
+#[test]
+fn vmrs_provider_can_be_closed() {
+    let provider = get_vmrs_test_provider();
+    assert!(provider.close().is_ok());
+}
+
 fn validate_vp_count(provider: &VmSavedStateDumpProvider) {
     let vp_count = provider.vp_count();
     assert_eq!(4, vp_count.unwrap());
@@ -112,11 +187,7 @@ fn vmrs_get_architecture() {
 }
 
 fn validate_get_register_value(provider: &VmSavedStateDumpProvider) {
-    let register_id = RegisterRawId {
-        register_id_x86: RegisterIdx86::Ecx,
-    };
-
-    let register = provider.get_vp_register_value(0, VirtualProcessorArch::X86, register_id);
+    let register = provider.get_vp_register_value(0, Register::X86(RegisterIdx86::Ecx));
     assert_eq!(4, register.unwrap().value);
 }
 
@@ -132,6 +203,62 @@ fn vmrs_get_register_value() {
     validate_get_register_value(&provider);
 }
 
+fn validate_get_register_value_architecture_mismatch(provider: &VmSavedStateDumpProvider) {
+    let register = provider.get_vp_register_value(0, Register::X64(RegisterIdx64::Rcx));
+    assert_eq!(
+        ResultCode::RegisterArchitectureMismatch {
+            vp_architecture: VirtualProcessorArch::X86,
+            register_architecture: VirtualProcessorArch::X64,
+        },
+        register.unwrap_err()
+    );
+}
+
+#[test]
+fn bin_vsv_get_register_value_architecture_mismatch() {
+    let provider = get_bin_vsv_test_provider();
+    validate_get_register_value_architecture_mismatch(&provider);
+}
+
+#[test]
+fn vmrs_get_register_value_architecture_mismatch() {
+    let provider = get_vmrs_test_provider();
+    validate_get_register_value_architecture_mismatch(&provider);
+}
+
+#[test]
+fn vmrs_get_xmm_and_control_status_registers() {
+    let provider = get_vmrs_test_provider();
+    assert!(provider.get_vp_xmm_register(0, 0).is_ok());
+    assert!(provider.get_vp_xmm_register(0, 15).is_ok());
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        provider.get_vp_xmm_register(0, 16).unwrap_err()
+    );
+
+    let xmm_control_status = provider.get_vp_xmm_control_status(0).unwrap();
+    let mxcsr = provider
+        .get_vp_register_value(0, Register::X86(RegisterIdx86::HighXmmControlStatus))
+        .unwrap()
+        .value as u32;
+    assert_eq!(mxcsr, xmm_control_status.mxcsr.raw());
+    assert!(provider.get_vp_fp_control_status(0).is_ok());
+}
+
+#[test]
+fn vmrs_get_control_registers() {
+    let provider = get_vmrs_test_provider();
+    let control_registers = provider.get_vp_control_registers(0).unwrap();
+    let cr0 = provider
+        .get_vp_register_value(0, Register::X86(RegisterIdx86::Cr0))
+        .unwrap()
+        .value;
+    assert_eq!(cr0, control_registers.cr0.raw());
+
+    // The test file is a 32 bit guest, so long mode can't be active
+    assert!(!control_registers.efer.lma());
+}
+
 fn validate_get_paging_mode(provider: &VmSavedStateDumpProvider) {
     let paging_mode = provider.get_vp_paging_mode(0);
     assert_eq!(PagingMode::Bit32, paging_mode.unwrap());
@@ -152,11 +279,9 @@ fn vmrs_get_paging_mode() {
 #[test]
 fn vp_iterator() {
     let provider = get_vmrs_test_provider();
-    let vp_iter = provider.vp_iter();
+    let vp_iter = provider.vp_iter().unwrap();
     let mut vp_id = 0;
-    let register_id = RegisterRawId {
-        register_id_x86: RegisterIdx86::Ecx,
-    };
+    let register = Register::X86(RegisterIdx86::Ecx);
 
     assert_eq!(4, provider.vp_count().unwrap());
 
@@ -172,12 +297,7 @@ fn vp_iterator() {
             _ => 0,
         };
 
-        assert_eq!(
-            register_value,
-            vp.register_value(VirtualProcessorArch::X86, register_id)
-                .unwrap()
-                .value
-        );
+        assert_eq!(register_value, vp.register_value(register).unwrap().value);
         vp_id += 1;
     }
 
@@ -207,8 +327,7 @@ fn guest_raw_saved_memory() {
     let raw_memory_size = provider.guest_raw_saved_memory_size().unwrap();
     assert_eq!(264241152, raw_memory_size);
 
-    let mut buffer: Vec<u8> = Vec::new();
-    buffer.resize(1024 * 1024, 0);
+    let mut buffer: Vec<u8> = vec![0; 1024 * 1024];
 
     let mut offset: u64 = 0;
     let mut bytes_read = provider
@@ -285,8 +404,1293 @@ fn apply_replay_log() {
     let mut vmrs_file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     vmrs_file_path.push("tests\\test_file.apply_pending_replay_log.vmrs");
     assert!(Path::new(&vmrs_file_path).exists());
+    assert_eq!(Ok(()), apply_pending_replay_log(&vmrs_file_path));
+}
+
+#[test]
+fn open_options_resolve_saved_state_files() {
+    let options = SavedStateOpenOptions::new();
+    assert_eq!(
+        VmSavedStateFile::Vmrs(PathBuf::from("dir/file.VMRS")),
+        options.resolve("dir/file.VMRS").unwrap()
+    );
+    assert_eq!(
+        VmSavedStateFile::BinVsv(PathBuf::from("dir/file.bin"), PathBuf::from("dir/file.vsv")),
+        options.resolve("dir/file.bin").unwrap()
+    );
+    assert_eq!(
+        VmSavedStateFile::BinVsv(PathBuf::from("dir/file.bin"), PathBuf::from("dir/file.vsv")),
+        options.resolve("dir/file.vsv").unwrap()
+    );
+    assert_eq!(
+        ResultCode::Io(std::io::ErrorKind::NotFound),
+        options.resolve("dir/file.dmp").unwrap_err()
+    );
+
+    let mut options = SavedStateOpenOptions::new();
+    options
+        .format(SavedStateFormat::BinVsv)
+        .companion("other/state.vsv");
+    assert_eq!(
+        VmSavedStateFile::BinVsv(
+            PathBuf::from("dir/memory"),
+            PathBuf::from("other/state.vsv")
+        ),
+        options.resolve("dir/memory").unwrap()
+    );
+
+    // Only VMRS files carry a replay log
+    let (bin, _) = get_test_bin_vsv_file_paths();
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        SavedStateOpenOptions::new()
+            .replay_log(ReplayLog::ApplyInPlace)
+            .open(bin)
+            .unwrap_err()
+    );
+
+    // Applying the replay log to a copy that is the file itself is rejected before copying
+    let vmrs = PathBuf::from(get_test_vmrs_file_path());
+    let size = std::fs::metadata(&vmrs).unwrap().len();
+    let same_file = vmrs
+        .parent()
+        .unwrap()
+        .join(".")
+        .join(vmrs.file_name().unwrap());
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        SavedStateOpenOptions::new()
+            .replay_log(ReplayLog::ApplyToCopy(same_file))
+            .open(&vmrs)
+            .unwrap_err()
+    );
+    assert_eq!(size, std::fs::metadata(&vmrs).unwrap().len());
+}
+
+#[test]
+fn detect_saved_state_and_dump_formats() {
+    let mut bin_file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    bin_file_path.push("tests");
+    bin_file_path.push("test_file.bin");
+    let detection = detect_format(&bin_file_path).unwrap();
+    assert_eq!(DetectedFormat::Bin, detection.format);
+    assert_eq!(
+        Some(bin_file_path.with_extension("vsv")),
+        detection.companion
+    );
+
+    // BIN files are detected by their header regardless of their name
+    let mut header = vec![0u8; 0x20];
+    std::fs::File::open(&bin_file_path)
+        .unwrap()
+        .read_exact(&mut header)
+        .unwrap();
+    assert_eq!(DetectedFormat::Bin, detect_format_from_header(&header));
+
+    let provider = get_vmrs_test_provider();
+    let mut dump = std::io::Cursor::new(Vec::new());
+    write_elf_core(
+        &provider,
+        &mut dump,
+        &ElfCoreOptions::default(),
+        &mut DumpControl::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        DetectedFormat::ElfCore,
+        detect_format_from_header(dump.get_ref())
+    );
+
+    let mut dump = std::io::Cursor::new(Vec::new());
+    write_lime_image(&provider, &mut dump, &mut DumpControl::default()).unwrap();
+    assert_eq!(
+        DetectedFormat::Lime,
+        detect_format_from_header(dump.get_ref())
+    );
+
+    assert_eq!(
+        DetectedFormat::WindowsCrashDump,
+        detect_format_from_header(b"PAGEDU64")
+    );
+    assert_eq!(
+        DetectedFormat::Kdump,
+        detect_format_from_header(b"KDUMP   ")
+    );
+    assert_eq!(DetectedFormat::Unknown, detect_format_from_header(b"PAGE"));
+    assert_eq!(DetectedFormat::Unknown, detect_format_from_header(&[]));
+}
+
+#[test]
+fn vmrs_open_options_page_cache() {
+    let provider = get_vmrs_test_provider();
+    let cached_provider = SavedStateOpenOptions::new()
+        .page_cache_capacity(2)
+        .open(get_test_vmrs_file_path())
+        .unwrap();
+
+    // Reads that span pages, and reads served from the cache, match uncached reads
+    for address in &[0xFF0, 0x1FF8, 0xFF0, 0x3000] {
+        let mut expected = vec![0u8; 0x1020];
+        let mut actual = vec![0u8; 0x1020];
+        assert_eq!(
+            provider
+                .read_guest_physical_address(*address, &mut expected)
+                .unwrap(),
+            cached_provider
+                .read_guest_physical_address(*address, &mut actual)
+                .unwrap()
+        );
+        assert_eq!(expected, actual);
+    }
+
+    // Reads running past the end of the address space fail instead of overflowing
+    let mut buffer = vec![0u8; 0x20];
+    assert!(cached_provider
+        .read_guest_physical_address(u64::MAX - 0x10, &mut buffer)
+        .is_err());
+}
+
+#[test]
+fn kd_debugger_data_parsing() {
+    let mut raw = vec![0u8; 0x340];
+    raw[0x10..0x14].copy_from_slice(b"KDBG");
+    raw[0x14..0x18].copy_from_slice(&0x340u32.to_le_bytes());
+    raw[0x18..0x20].copy_from_slice(&0xFFFF_F800_0260_0000u64.to_le_bytes());
+    raw[0x48..0x50].copy_from_slice(&0xFFFF_F800_02A5_0000u64.to_le_bytes());
+    raw[0x50..0x58].copy_from_slice(&0xFFFF_F800_02A6_0000u64.to_le_bytes());
+    raw[0xC0..0xC8].copy_from_slice(&0xFFFF_F800_02A7_0000u64.to_le_bytes());
+
+    let kd_debugger_data = KdDebuggerData::parse(0xFFFF_F800_02A0_0000, &raw).unwrap();
+    assert_eq!(0xFFFF_F800_02A0_0000, kd_debugger_data.virtual_address);
+    assert_eq!(0xFFFF_F800_0260_0000, kd_debugger_data.kern_base);
+    assert_eq!(
+        0xFFFF_F800_02A5_0000,
+        kd_debugger_data.ps_loaded_module_list
+    );
+    assert_eq!(
+        0xFFFF_F800_02A6_0000,
+        kd_debugger_data.ps_active_process_head
+    );
+    assert_eq!(0xFFFF_F800_02A7_0000, kd_debugger_data.mm_pfn_database);
+
+    // A kernel base that is not page aligned can't belong to a KdDebuggerDataBlock
+    raw[0x18..0x20].copy_from_slice(&0xFFFF_F800_0260_0010u64.to_le_bytes());
+    assert_eq!(None, KdDebuggerData::parse(0, &raw));
+
+    raw[0x10..0x14].copy_from_slice(b"KDBF");
+    assert_eq!(None, KdDebuggerData::parse(0, &raw));
+}
+
+#[test]
+fn vmrs_interrupt_handlers_outside_kernel() {
+    let provider = get_vmrs_test_provider();
+    let kd_debugger_data = find_kd_debugger_data_block(&provider, 0).unwrap();
+    let kernel_image = kernel_image_range(&provider, 0, &kd_debugger_data).unwrap();
+    assert!(kernel_image.start < kernel_image.end);
+
+    let handlers = find_interrupt_handlers_outside_kernel(&provider).unwrap();
+    assert_eq!(provider.vp_count().unwrap() as usize, handlers.len());
+    for (index, (vp_id, gates)) in handlers.iter().enumerate() {
+        assert_eq!(index as u32, *vp_id);
+        assert!(gates
+            .iter()
+            .all(|gate| gate.present && !kernel_image.contains(&gate.handler)));
+    }
+}
+
+#[test]
+fn vmrs_write_windows_bitmap_crash_dump_requires_64_bit_guest() {
+    // The test file is a 32 bit guest, which only supports full dumps
+    let provider = get_vmrs_test_provider();
+    let mut dump = std::io::Cursor::new(Vec::new());
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        write_windows_crash_dump(
+            &provider,
+            &mut dump,
+            &WindowsCrashDumpOptions::default(),
+            &mut DumpControl::default()
+        )
+        .unwrap_err()
+    );
+    assert!(dump.into_inner().is_empty());
+}
+
+#[test]
+fn vmrs_write_windows_full_crash_dump() {
+    // The test file is a 32 bit guest, so its full dump has a DUMP_HEADER32
+    let provider = get_vmrs_test_provider();
+    let kd_debugger_data = find_kd_debugger_data_block(&provider, 0)
+        .expect("the KdDebuggerDataBlock of the test file must be found by scanning its memory");
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    let options = WindowsCrashDumpOptions {
+        dump_type: WindowsDumpType::Full,
+        ..Default::default()
+    };
+
+    let mut dump = std::io::Cursor::new(Vec::new());
+    let statistics =
+        write_windows_crash_dump(&provider, &mut dump, &options, &mut DumpControl::default())
+            .unwrap();
+    let page_count = memory_chunks
+        .iter()
+        .map(|chunk| chunk.page_count)
+        .sum::<u64>();
+    assert_eq!(page_count, statistics.pages_written);
+    assert_eq!(0x1000 + page_count * page_size, statistics.bytes_written);
+
+    let header = dump.get_ref();
+    let get_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            header[offset],
+            header[offset + 1],
+            header[offset + 2],
+            header[offset + 3],
+        ])
+    };
+    assert_eq!(b"PAGEDUMP", &header[..8]);
+    assert_eq!(
+        provider.get_vp_paging_mode(0).unwrap() == PagingMode::Pae,
+        header[0x5C] != 0
+    );
+    assert_eq!(kd_debugger_data.virtual_address as u32, get_u32(0x60));
+
+    // PHYSICAL_MEMORY_DESCRIPTOR
+    assert_eq!(memory_chunks.len() as u32, get_u32(0x64));
+    assert_eq!(page_count as u32, get_u32(0x68));
+    for (index, chunk) in memory_chunks.iter().enumerate() {
+        let run = 0x6C + index * 0x8;
+        assert_eq!(chunk.guest_physical_start_page_index as u32, get_u32(run));
+        assert_eq!(chunk.page_count as u32, get_u32(run + 0x4));
+    }
+
+    // CONTEXT of the first virtual processor
+    for (register_id, offset) in [
+        (RegisterIdx86::Eip, 0x320 + 0xB8),
+        (RegisterIdx86::Esp, 0x320 + 0xC4),
+        (RegisterIdx86::Eax, 0x320 + 0xB0),
+        (RegisterIdx86::EFlags, 0x320 + 0xC0),
+    ] {
+        let value = provider
+            .get_vp_register_value(0, Register::X86(register_id))
+            .unwrap()
+            .value;
+        assert_eq!(value as u32, get_u32(offset));
+    }
+
+    let report = verify_windows_crash_dump(&provider, &mut dump, &options).unwrap();
+    assert!(report.is_valid());
+    assert_eq!(page_count, report.pages_verified);
+    assert_ne!(0, report.registers_verified);
+}
+
+#[test]
+fn vmrs_write_elf_core() {
+    let provider = get_vmrs_test_provider();
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    let mut core = std::io::Cursor::new(Vec::new());
+    let statistics = write_elf_core(
+        &provider,
+        &mut core,
+        &ElfCoreOptions::default(),
+        &mut DumpControl::default(),
+    )
+    .unwrap();
+    let core = core.into_inner();
+    assert_eq!(statistics.bytes_written, core.len() as u64);
+
+    let get_u16 = |offset: usize| u16::from_le_bytes([core[offset], core[offset + 1]]);
+    let get_u64 = |offset: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&core[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    };
+
+    // The test file is a 32 bit guest
+    assert_eq!(b"\x7FELF\x02\x01", &core[..6]);
+    assert_eq!(3, get_u16(0x12));
+    assert_eq!(memory_chunks.len() + 2, get_u16(0x38) as usize);
+
+    // Notes come first and hold a 144 byte NT_PRSTATUS note per virtual processor
+    let vp_count = provider.vp_count().unwrap() as u64;
+    let note_size = get_u64(64 + 0x20);
+    assert!(note_size >= vp_count * (12 + 8 + 144));
+    assert_eq!(core.len() as u64, get_u64(64 + 0x8) + note_size);
+
+    // Virtual addresses are only stored for 64-bit Linux guests, so the kernel image is PT_NULL
+    assert_eq!(0, get_u64(64 + 56));
+    for (index, chunk) in memory_chunks.iter().enumerate() {
+        let program_header = 64 + (index + 2) * 56;
+        assert_eq!(0, get_u64(program_header + 0x10));
+        assert_eq!(
+            chunk.guest_physical_start_page_index * page_size,
+            get_u64(program_header + 0x18)
+        );
+        assert_eq!(chunk.page_count * page_size, get_u64(program_header + 0x20));
+    }
+}
+
+#[test]
+fn vmrs_write_kdump_requires_64_bit_guest() {
+    // The test file is a 32 bit guest, and kdump-compressed dumps are only written for x86_64
+    let provider = get_vmrs_test_provider();
+    let mut dump = std::io::Cursor::new(Vec::new());
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        write_kdump(
+            &provider,
+            &mut dump,
+            &KdumpOptions::default(),
+            &mut DumpControl::default()
+        )
+        .unwrap_err()
+    );
+    assert!(dump.into_inner().is_empty());
+}
+
+#[test]
+fn vmrs_write_lime_image() {
+    let provider = get_vmrs_test_provider();
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    let mut image = std::io::Cursor::new(Vec::new());
+    let statistics = write_lime_image(&provider, &mut image, &mut DumpControl::default()).unwrap();
+    let image = image.into_inner();
+    assert_eq!(statistics.bytes_written, image.len() as u64);
+
+    let get_u32 = |offset: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&image[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    };
+    let get_u64 = |offset: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&image[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    };
+
+    // Every memory chunk is a range, with an inclusive end address
+    let mut offset = 0;
+    for chunk in &memory_chunks {
+        let start_address = chunk.guest_physical_start_page_index * page_size;
+        let size = chunk.page_count * page_size;
+        assert_eq!(LIME_MAGIC, get_u32(offset));
+        assert_eq!(LIME_VERSION, get_u32(offset + 0x4));
+        assert_eq!(start_address, get_u64(offset + 0x8));
+        assert_eq!(start_address + size - 1, get_u64(offset + 0x10));
+        offset += LIME_RANGE_HEADER_SIZE + size as usize;
+    }
+    assert_eq!(image.len(), offset);
+}
+
+#[test]
+fn vmrs_write_raw_image() {
+    let provider = get_vmrs_test_provider();
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    let image_size = memory_chunks
+        .iter()
+        .map(|chunk| (chunk.guest_physical_start_page_index + chunk.page_count) * page_size)
+        .max()
+        .unwrap();
+
+    let mut images = Vec::new();
+    for sparse in [false, true].iter() {
+        let mut image = std::io::Cursor::new(Vec::new());
+        let options = RawImageOptions { sparse: *sparse };
+        let statistics =
+            write_raw_image(&provider, &mut image, &options, &mut DumpControl::default()).unwrap();
+        let image = image.into_inner();
+        assert_eq!(image_size, statistics.bytes_written);
+        assert_eq!(image_size, image.len() as u64);
+
+        // Only sparse images skip over zero pages
+        let page_count: u64 = memory_chunks.iter().map(|chunk| chunk.page_count).sum();
+        assert_eq!(
+            page_count,
+            statistics.pages_written + statistics.zero_pages_elided
+        );
+        if !*sparse {
+            assert_eq!(0, statistics.zero_pages_elided);
+        }
+
+        // Memory is stored at its guest physical address
+        let chunk = memory_chunks.last().unwrap();
+        let address = chunk.guest_physical_start_page_index * page_size;
+        let mut page = vec![0u8; page_size as usize];
+        provider
+            .read_guest_physical_address(address, &mut page)
+            .unwrap();
+        assert_eq!(
+            &page[..],
+            &image[address as usize..(address + page_size) as usize]
+        );
+        images.push(image);
+    }
+
+    assert!(images[0] == images[1]);
+}
+
+#[test]
+fn vmrs_dump_progress_cancellation_and_resume() {
+    let provider = get_vmrs_test_provider();
+    let mut complete_image = std::io::Cursor::new(Vec::new());
+    let statistics =
+        write_lime_image(&provider, &mut complete_image, &mut DumpControl::default()).unwrap();
+    let complete_image = complete_image.into_inner();
+
+    // Progress is reported against the size of the saved guest memory
+    let mut reports = Vec::new();
+    let mut control = DumpControl {
+        progress: Some(Box::new(|progress| reports.push(progress))),
+        ..Default::default()
+    };
+    let mut image = std::io::Cursor::new(Vec::new());
+    write_lime_image(&provider, &mut image, &mut control).unwrap();
+    drop(control);
+    let total_bytes = provider.guest_raw_saved_memory_size().unwrap();
+    let last_report = reports.last().unwrap();
+    assert_eq!(total_bytes, last_report.total_bytes);
+    assert_eq!(
+        statistics.pages_written * provider.guest_physical_memory_chunks().unwrap().0,
+        last_report.bytes_written
+    );
+
+    // Cancelled dumps stop writing after the current block, and can be resumed afterwards
+    let token = CancellationToken::new();
+    let callback_token = token.clone();
+    let mut control = DumpControl {
+        progress: Some(Box::new(move |_| callback_token.cancel())),
+        cancellation: Some(token),
+        ..Default::default()
+    };
+    let mut image = std::io::Cursor::new(Vec::new());
+    assert_eq!(
+        ResultCode::Cancelled,
+        write_lime_image(&provider, &mut image, &mut control).unwrap_err()
+    );
+    assert!(image.get_ref().len() < complete_image.len());
+
+    let mut control = DumpControl {
+        resume: true,
+        ..Default::default()
+    };
+    image.set_position(0);
+    assert_eq!(
+        Ok(statistics),
+        write_lime_image(&provider, &mut image, &mut control)
+    );
+    assert_eq!(complete_image, image.into_inner());
+}
+
+#[test]
+fn vmrs_verify_written_dumps() {
+    let provider = get_vmrs_test_provider();
+    let mut image = std::io::Cursor::new(Vec::new());
+    write_elf_core(
+        &provider,
+        &mut image,
+        &ElfCoreOptions::default(),
+        &mut DumpControl::default(),
+    )
+    .unwrap();
+    let report = verify_elf_core(&provider, &mut image).unwrap();
+    assert!(report.is_valid());
+    assert_ne!(0, report.pages_verified);
+    assert_ne!(0, report.registers_verified);
+
+    let mut image = std::io::Cursor::new(Vec::new());
+    let statistics = write_raw_image(
+        &provider,
+        &mut image,
+        &RawImageOptions { sparse: true },
+        &mut DumpControl::default(),
+    )
+    .unwrap();
+    let report = verify_raw_image(&provider, &mut image).unwrap();
+    assert!(report.is_valid());
+    assert_eq!(
+        statistics.pages_written + statistics.zero_pages_elided,
+        report.pages_verified
+    );
+}
+
+#[test]
+fn vmrs_verify_reports_corrupted_and_truncated_dumps() {
+    let provider = get_vmrs_test_provider();
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    let mut image = std::io::Cursor::new(Vec::new());
+    write_lime_image(&provider, &mut image, &mut DumpControl::default()).unwrap();
+    let mut image = image.into_inner();
+    let first_chunk = &memory_chunks[0];
+    let last_chunk = &memory_chunks[memory_chunks.len() - 1];
+    let last_page_index = last_chunk.guest_physical_start_page_index + last_chunk.page_count - 1;
+
+    // Flip a byte of the first page and drop the last page of the image
+    image[LIME_RANGE_HEADER_SIZE] ^= 0xFF;
+    image.truncate(image.len() - page_size as usize);
+    let report = verify_lime_image(&provider, &mut std::io::Cursor::new(image)).unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(2, report.page_mismatches.len());
+
+    let corrupted = &report.page_mismatches[0];
+    assert_eq!(
+        first_chunk.guest_physical_start_page_index,
+        corrupted.first_page_index
+    );
+    assert_eq!(1, corrupted.page_count);
+    match corrupted.kind {
+        PageMismatchKind::ContentDiffers {
+            expected_hash,
+            actual_hash,
+        } => assert_ne!(expected_hash, actual_hash),
+        kind => panic!("Unexpected mismatch {:?}", kind),
+    }
+    assert_eq!(
+        PageMismatch {
+            first_page_index: last_page_index,
+            page_count: 1,
+            kind: PageMismatchKind::Truncated,
+        },
+        report.page_mismatches[1]
+    );
+}
+
+#[test]
+fn register_names() {
+    assert_eq!(72, Register::all(VirtualProcessorArch::X86).len());
+    assert_eq!(80, Register::all(VirtualProcessorArch::X64).len());
+    assert!(Register::all(VirtualProcessorArch::Unknown).is_empty());
+
+    assert_eq!("rip", Register::X64(RegisterIdx64::Rip).name());
+    assert_eq!("cs", Register::X64(RegisterIdx64::SegCs).name());
+    assert_eq!(
+        Some(Register::X64(RegisterIdx64::Rsp)),
+        Register::from_name(VirtualProcessorArch::X64, "RSP")
+    );
+    assert_eq!(
+        Some(Register::X86(RegisterIdx86::SegFs)),
+        Register::from_name(VirtualProcessorArch::X86, "fs")
+    );
+    assert_eq!(None, Register::from_name(VirtualProcessorArch::X86, "rip"));
+
+    for architecture in &[VirtualProcessorArch::X86, VirtualProcessorArch::X64] {
+        for register in Register::all(*architecture) {
+            assert_eq!(
+                Some(register),
+                Register::from_name(*architecture, &register.name())
+            );
+        }
+    }
+}
+
+#[test]
+fn vmrs_vmss2dump_tool() {
+    let run = |arguments: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_vmss2dump"))
+            .args(arguments)
+            .output()
+            .unwrap()
+    };
+    let vmrs_file_path = get_test_vmrs_file_path();
+    let vmrs_file_path = vmrs_file_path.as_str();
+
+    let output = run(&["info", vmrs_file_path]);
+    assert!(output.status.success());
+    let info = String::from_utf8(output.stdout).unwrap();
+    assert!(info.contains("Virtual processors: "));
+    assert!(info.contains("Guest physical memory chunks: "));
+
+    let output = run(&["read", vmrs_file_path, "0x1000", "32"]);
+    assert!(output.status.success());
+    assert_eq!(2, String::from_utf8(output.stdout).unwrap().lines().count());
+
+    let mut output_path = std::env::temp_dir();
+    output_path.push("vmss2dump_tool_test.lime");
+    let output = run(&[
+        "convert",
+        vmrs_file_path,
+        "lime",
+        output_path.to_str().unwrap(),
+        "--verify",
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("Verification passed"));
+    std::fs::remove_file(&output_path).unwrap();
+
+    // Unknown formats are rejected before the output is opened, so it isn't truncated
+    std::fs::write(&output_path, b"existing").unwrap();
+    assert!(!run(&[
+        "convert",
+        vmrs_file_path,
+        "unknown",
+        output_path.to_str().unwrap()
+    ])
+    .status
+    .success());
+    assert_eq!(b"existing".to_vec(), std::fs::read(&output_path).unwrap());
+    std::fs::remove_file(&output_path).unwrap();
+    assert!(!run(&["info"]).status.success());
+}
+
+#[test]
+fn vmrs_saved_state_summary() {
+    let provider = get_vmrs_test_provider();
+    let summary = SavedStateSummary::new(&provider).unwrap();
+
+    assert_eq!(provider.vp_count().unwrap(), summary.vp_count);
+    assert_eq!(summary.vp_count as usize, summary.virtual_processors.len());
+    for vp in &summary.virtual_processors {
+        assert_eq!(provider.get_vp_paging_mode(vp.id).unwrap(), vp.paging_mode);
+        assert_eq!(Register::all(vp.architecture).len(), vp.registers.len());
+        for register in &vp.registers {
+            assert_eq!(
+                *register,
+                provider
+                    .get_vp_register_value(vp.id, register.register)
+                    .unwrap()
+            );
+        }
+    }
+
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    assert_eq!(page_size, summary.page_size);
+    assert_eq!(memory_chunks, summary.memory_chunks);
+    assert_eq!(
+        provider.guest_raw_saved_memory_size().unwrap(),
+        summary.raw_saved_memory_size
+    );
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&summary).unwrap();
+        assert_eq!(
+            summary,
+            serde_json::from_str::<SavedStateSummary>(&json).unwrap()
+        );
+    }
+}
+
+#[test]
+fn vmrs_page_table_walk() {
+    let provider = get_vmrs_test_provider();
+    let paging_mode = provider.get_vp_paging_mode(0).unwrap();
+    let control_registers = provider.get_vp_control_registers(0).unwrap();
+    let cr3 = control_registers.cr3;
+
+    let walk = walk_page_tables(&provider, 0, 0x1234).unwrap();
+    assert_eq!(0x1234, walk.virtual_address);
+    assert_eq!(paging_mode, walk.paging_mode);
+    match walk.entries.first() {
+        Some(entry) => {
+            assert_eq!(cr3 & !0xFFF, entry.address & !0xFFF);
+            let top_level = match paging_mode {
+                PagingMode::Long if control_registers.cr4.la57() => PageTableLevel::Pml5,
+                PagingMode::Long => PageTableLevel::Pml4,
+                PagingMode::Pae => PageTableLevel::Pdpt,
+                _ => PageTableLevel::PageDirectory,
+            };
+            assert_eq!(top_level, entry.level);
+        }
+        None => assert_eq!(PagingMode::NonPaged, paging_mode),
+    }
+    if walk.physical_address.is_none() {
+        assert!(!walk.entries.last().unwrap().present());
+    }
+    for entry in &walk.entries {
+        let mut raw = [0u8; 8];
+        let size = if paging_mode == PagingMode::Bit32 {
+            4
+        } else {
+            8
+        };
+        provider
+            .read_guest_physical_address(entry.address, &mut raw[..size])
+            .unwrap();
+        assert_eq!(u64::from_le_bytes(raw), entry.value);
+    }
+}
+
+#[test]
+fn vmrs_shell_commands() {
+    let provider = get_vmrs_test_provider();
+    let mut shell = Shell::new(&provider);
+    let text = |output: CommandOutput| match output {
+        CommandOutput::Text(text) => text,
+        CommandOutput::Quit => panic!("unexpected quit"),
+    };
+
+    // Address expressions
+    let architecture = provider.get_vp_architecture(0).unwrap();
+    let stack_pointer = Register::from_name(architecture, "rsp")
+        .or_else(|| Register::from_name(architecture, "esp"))
+        .unwrap();
+    let stack_pointer_value = provider
+        .get_vp_register_value(0, stack_pointer)
+        .unwrap()
+        .value;
+    let name = stack_pointer.name();
+    assert_eq!(0x1234, shell.evaluate("1234").unwrap());
+    assert_eq!(10, shell.evaluate("0n10").unwrap());
+    assert_eq!(0x1_0000_0000, shell.evaluate("1`00000000").unwrap());
+    assert_eq!(0x32, shell.evaluate("(0x10 + 0n9) * 2").unwrap());
+    assert_eq!(0x10, shell.evaluate("-(0x10) + 0x20").unwrap());
+    assert_eq!(
+        stack_pointer_value.wrapping_add(0x20),
+        shell.evaluate(&format!("{}+0x20", name)).unwrap()
+    );
+    assert_eq!(
+        stack_pointer_value,
+        shell
+            .evaluate(&format!("@{}", name.to_uppercase()))
+            .unwrap()
+    );
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        shell.evaluate("zzz").unwrap_err()
+    );
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        shell.evaluate("(1 + 2").unwrap_err()
+    );
+
+    // Memory display
+    let mut expected = [0u8; 8];
+    provider
+        .read_guest_physical_address(0x1000, &mut expected)
+        .unwrap();
+    let output = text(shell.execute("!dq 0x1000 L1").unwrap());
+    assert_eq!(
+        format!("{:016x}  {:016x}", 0x1000, u64::from_le_bytes(expected)),
+        output
+    );
+    let output = text(shell.execute("!db 1000").unwrap());
+    assert_eq!(8, output.lines().count());
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        shell.execute("!db").unwrap_err()
+    );
+
+    // Virtual processors and registers
+    let vp_count = provider.vp_count().unwrap();
+    assert_eq!(
+        vp_count as usize,
+        text(shell.execute("vp").unwrap()).lines().count()
+    );
+    shell.execute(&format!("vp {}", vp_count - 1)).unwrap();
+    assert_eq!(vp_count - 1, shell.vp_id());
+    assert!(shell.execute(&format!("vp {}", vp_count)).is_err());
+    assert_eq!(
+        format!(
+            "{}={:016x}",
+            name,
+            provider
+                .get_vp_register_value(vp_count - 1, stack_pointer)
+                .unwrap()
+                .value
+        ),
+        text(shell.execute(&format!("r {}", name)).unwrap())
+    );
+
+    let (_, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    assert_eq!(
+        memory_chunks.len(),
+        text(shell.execute("chunks").unwrap()).lines().count()
+    );
+    assert!(text(shell.execute("!pte 0x1234").unwrap()).starts_with("VA 0000000000001234"));
+    assert!(shell.execute("unknown").is_err());
+    assert_eq!(CommandOutput::Quit, shell.execute("q").unwrap());
+    assert_eq!(11, shell.history().len());
+
+    // Running over a reader stops at the quit command
+    let mut shell = Shell::new(&provider);
+    let mut output = Vec::new();
+    shell
+        .run(&b"? 0x10\nfoo\nq\n? 0x20\n"[..], &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("Evaluate expression: 16 = 0x0000000000000010"));
+    assert!(output.contains("error: unknown command foo"));
+    assert_eq!(3, shell.history().len());
+
+    // Commands of the history run again by number, or the last one with !!
+    let mut shell = Shell::new(&provider);
+    let evaluated = text(shell.execute("? 0x10").unwrap());
+    shell.execute("? 0x20").unwrap();
+    assert_eq!(evaluated, text(shell.execute("!0").unwrap()));
+    assert_eq!(evaluated, text(shell.execute(" !! ").unwrap()));
+    assert_eq!(["? 0x10", "? 0x20", "? 0x10", "? 0x10"], shell.history());
+    assert_eq!(
+        ResultCode::InvalidArgument,
+        shell.execute("!4").unwrap_err()
+    );
+    assert_eq!(4, shell.history().len());
+    assert_eq!(None, shell.recall("!db 1000").unwrap());
+    assert_eq!(None, shell.recall("!").unwrap());
+
+    // Recalled commands are shown before their output
+    let mut shell = Shell::new(&provider);
+    let mut output = Vec::new();
+    shell
+        .run(&b"? 0x10\n!0\n!!\n!9\nq\n"[..], &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        2,
+        output
+            .matches("? 0x10\nEvaluate expression: 16 = 0x0000000000000010")
+            .count()
+    );
+    assert!(output.contains("error: no command !9 in the history"));
+    assert_eq!(4, shell.history().len());
+}
+
+/// In-memory connection to a GDB remote protocol server.
+struct GdbConnection {
+    input: std::io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl std::io::Read for GdbConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl std::io::Write for GdbConnection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn to_hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn gdb_packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    format!("${}#{:02x}", data, checksum)
+}
+
+#[test]
+fn vmrs_gdb_server() {
+    let provider = get_vmrs_test_provider();
+    let mut server = GdbServer::new(&provider).unwrap();
+    let vp_count = provider.vp_count().unwrap();
+
+    // Threads
+    assert_eq!(b"T05thread:1;".to_vec(), server.handle_packet(b"?"));
+    let thread_ids: Vec<String> = (1..=vp_count).map(|id| format!("{:x}", id)).collect();
+    assert_eq!(
+        format!("m{}", thread_ids.join(",")).into_bytes(),
+        server.handle_packet(b"qfThreadInfo")
+    );
+    assert_eq!(b"l".to_vec(), server.handle_packet(b"qsThreadInfo"));
+    assert_eq!(
+        b"OK".to_vec(),
+        server.handle_packet(format!("Hg{:x}", vp_count).as_bytes())
+    );
+    assert_eq!(
+        format!("QC{:x}", vp_count).into_bytes(),
+        server.handle_packet(b"qC")
+    );
+    assert_eq!(
+        b"E16".to_vec(),
+        server.handle_packet(format!("Hg{:x}", vp_count + 1).as_bytes())
+    );
+
+    // Thread ids are virtual processor ids plus one, and 0 or -1 keep the current one
+    assert_eq!(b"E16".to_vec(), server.handle_packet(b"Hgx"));
+    assert_eq!(b"OK".to_vec(), server.handle_packet(b"Hg1"));
+    assert_eq!(b"QC1".to_vec(), server.handle_packet(b"qC"));
+    assert_eq!(b"OK".to_vec(), server.handle_packet(b"Hc-1"));
+    assert_eq!(b"OK".to_vec(), server.handle_packet(b"Hg0"));
+    assert_eq!(b"QC1".to_vec(), server.handle_packet(b"qC"));
+    assert_eq!(b"OK".to_vec(), server.handle_packet(b"T1"));
+    assert_eq!(
+        b"OK".to_vec(),
+        server.handle_packet(format!("T{:x}", vp_count).as_bytes())
+    );
+    assert_eq!(
+        b"E16".to_vec(),
+        server.handle_packet(format!("T{:x}", vp_count + 1).as_bytes())
+    );
+    assert_eq!(
+        to_hex_string(b"Virtual processor 0").into_bytes(),
+        server.handle_packet(b"qThreadExtraInfo,1")
+    );
+    assert_eq!(
+        b"OK".to_vec(),
+        server.handle_packet(format!("Hg{:x}", vp_count).as_bytes())
+    );
+
+    // Registers of the current thread, in the gdb register layout
+    let vp_id = vp_count - 1;
+    let (instruction_pointer, number, size) = match provider.get_vp_architecture(vp_id).unwrap() {
+        VirtualProcessorArch::X64 => (Register::X64(RegisterIdx64::Rip), 16, 8),
+        _ => (Register::X86(RegisterIdx86::Eip), 8, 4),
+    };
+    let value = provider
+        .get_vp_register_value(vp_id, instruction_pointer)
+        .unwrap()
+        .value;
+    let expected: String = value.to_le_bytes()[..size]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(
+        expected.clone().into_bytes(),
+        server.handle_packet(format!("p{:x}", number).as_bytes())
+    );
+    let registers = String::from_utf8(server.handle_packet(b"g")).unwrap();
+    assert_eq!(
+        expected,
+        registers[number * size * 2..(number + 1) * size * 2]
+    );
+    // x87 stack registers aren't available
+    assert!(registers.contains(&"xx".repeat(10)));
+
+    // Memory reads go through virtual address translation
+    let physical_address = provider
+        .guest_virtual_to_physical_address(vp_id, 0x1000)
+        .unwrap();
+    let mut expected = [0u8; 16];
+    provider
+        .read_guest_physical_address(physical_address, &mut expected)
+        .unwrap();
+    let expected: String = expected
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(expected.into_bytes(), server.handle_packet(b"m1000,10"));
+    assert_eq!(b"E01".to_vec(), server.handle_packet(b"M1000,1:00"));
+    assert_eq!(b"E01".to_vec(), server.handle_packet(b"X1000,1:}\x03"));
+    assert_eq!(b"E01".to_vec(), server.handle_packet(b"G00000000"));
+    assert_eq!(b"E01".to_vec(), server.handle_packet(b"P8=00000000"));
+    assert_eq!(b"E16".to_vec(), server.handle_packet(b"m1000"));
+
+    // Virtual addresses outside of every guest physical memory chunk are read too
+    let (page_size, memory_chunks) = provider.guest_physical_memory_chunks().unwrap();
+    assert!(memory_chunks.iter().all(|chunk| {
+        let start = chunk.guest_physical_start_page_index * page_size;
+        value < start || value >= start + chunk.page_count * page_size
+    }));
+    let mut expected = [0u8; 16];
+    provider
+        .read_guest_virtual_address(vp_id, value, &mut expected)
+        .unwrap();
+    assert_eq!(
+        to_hex_string(&expected).into_bytes(),
+        server.handle_packet(format!("m{:x},10", value).as_bytes())
+    );
+
+    // The memory map lists the virtual addresses mapped by the page tables, such as those read
+    let ranges = mapped_ranges(&provider, vp_id).unwrap();
+    for address in [0x1000, value].iter() {
+        assert!(ranges.iter().any(|range| *address >= range.virtual_address
+            && *address - range.virtual_address < range.size));
+    }
+    let memory_map = server.handle_packet(b"qXfer:memory-map:read::0,1000");
+    assert!(String::from_utf8(memory_map)
+        .unwrap()
+        .contains("<memory-map>\n<memory type=\"ram\""));
+
+    // The guest physical memory chunks are listed by a monitor command instead
+    let chunks = server.handle_packet(format!("qRcmd,{}", to_hex_string(b"chunks")).as_bytes());
+    let chunks = String::from_utf8(chunks).unwrap();
+    for chunk in &memory_chunks {
+        let start = chunk.guest_physical_start_page_index * page_size;
+        assert!(chunks.contains(&to_hex_string(
+            format!(
+                "{:016x} - {:016x}  {:#x} pages\n",
+                start,
+                start + chunk.page_count * page_size,
+                chunk.page_count
+            )
+            .as_bytes()
+        )));
+    }
+    let help = server.handle_packet(format!("qRcmd,{}", to_hex_string(b"help")).as_bytes());
+    assert!(String::from_utf8(help)
+        .unwrap()
+        .starts_with(&to_hex_string(b"chunks")));
+    assert_eq!(b"E16".to_vec(), server.handle_packet(b"qRcmd,6"));
+
+    let target = server.handle_packet(b"qXfer:features:read:target.xml:0,1000");
+    assert!(String::from_utf8(target)
+        .unwrap()
+        .contains("org.gnu.gdb.i386.core"));
+    assert!(server.handle_packet(b"qUnknown").is_empty());
+
+    // A whole session over a stream, with a retransmitted packet and no ack mode
+    let mut input = String::from("+");
+    input.push_str(&gdb_packet("qSupported:multiprocess+"));
+    input.push_str("$?#00");
+    // Binary writes arrive escaped, and are refused
+    input.push_str(&gdb_packet("X1000,1:}\u{3}"));
+    input.push_str(&gdb_packet("?"));
+    input.push_str(&gdb_packet("QStartNoAckMode"));
+    input.push('\u{3}');
+    input.push_str(&gdb_packet("D"));
+    input.push_str(&gdb_packet("?"));
+    let mut connection = GdbConnection {
+        input: std::io::Cursor::new(input.into_bytes()),
+        output: Vec::new(),
+    };
+    server.serve(&mut connection).unwrap();
+
+    let mut expected = String::from("+");
+    expected.push_str(&gdb_packet(
+        "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+",
+    ));
+    expected.push('-');
+    expected.push('+');
+    expected.push_str(&gdb_packet("E01"));
+    expected.push('+');
+    expected.push_str(&gdb_packet("T05thread:1;"));
+    expected.push('+');
+    expected.push_str(&gdb_packet("OK"));
+    expected.push_str(&gdb_packet("T05thread:1;"));
+    expected.push_str(&gdb_packet("OK"));
+    assert_eq!(expected, String::from_utf8(connection.output).unwrap());
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn vmrs_c_abi() {
+    use std::ffi::CString;
+    use std::os::raw::c_void;
+    use vmsavedstatedump_rs::capi::*;
+
+    let last_error_message = || unsafe {
+        let size = vmss_last_error_message(std::ptr::null_mut(), 0);
+        let mut message = vec![0u8; size];
+        vmss_last_error_message(message.as_mut_ptr() as *mut _, size);
+        message.pop();
+        String::from_utf8(message).unwrap()
+    };
+
+    unsafe {
+        let path = CString::new(get_test_vmrs_file_path()).unwrap();
+        let mut provider: *mut VmssProvider = std::ptr::null_mut();
+        assert_eq!(VmssResult::Success, vmss_open(path.as_ptr(), &mut provider));
+        let expected = get_vmrs_test_provider();
+
+        let mut vp_count = 0;
+        assert_eq!(VmssResult::Success, vmss_vp_count(provider, &mut vp_count));
+        assert_eq!(expected.vp_count().unwrap(), vp_count);
+
+        let mut architecture = VirtualProcessorArch::Unknown;
+        assert_eq!(
+            VmssResult::Success,
+            vmss_vp_architecture(provider, 0, &mut architecture)
+        );
+        assert_eq!(expected.get_vp_architecture(0).unwrap(), architecture);
+
+        let mut paging_mode = PagingMode::Invalid;
+        assert_eq!(
+            VmssResult::Success,
+            vmss_vp_paging_mode(provider, 0, &mut paging_mode)
+        );
+        assert_eq!(expected.get_vp_paging_mode(0).unwrap(), paging_mode);
+
+        let name = CString::new("cr3").unwrap();
+        let mut register_id = 0;
+        assert_eq!(
+            VmssResult::Success,
+            vmss_register_from_name(architecture as u32, name.as_ptr(), &mut register_id)
+        );
+        let mut value = 0;
+        assert_eq!(
+            VmssResult::Success,
+            vmss_vp_register_value(provider, 0, architecture as u32, register_id, &mut value)
+        );
+        assert_eq!(expected.get_vp_control_registers(0).unwrap().cr3, value);
+        assert_eq!(
+            VmssResult::InvalidArgument,
+            vmss_vp_register_value(provider, 0, architecture as u32, u32::MAX, &mut value)
+        );
+        assert!(last_error_message().contains("register"));
+
+        // Enum values that don't exist are rejected instead of taken as enums
+        assert_eq!(
+            VmssResult::InvalidArgument,
+            vmss_vp_register_value(provider, 0, 3, register_id, &mut value)
+        );
+        assert_eq!("unknown architecture 3", last_error_message());
+        assert_eq!(
+            VmssResult::InvalidArgument,
+            vmss_register_from_name(u32::MAX, name.as_ptr(), &mut register_id)
+        );
+
+        let mut page_size = 0;
+        let mut chunk_count = 0;
+        assert_eq!(
+            VmssResult::BufferTooSmall,
+            vmss_memory_chunks(
+                provider,
+                &mut page_size,
+                std::ptr::null_mut(),
+                &mut chunk_count
+            )
+        );
+        let mut chunks = vec![
+            GpaMemoryChunk {
+                guest_physical_start_page_index: 0,
+                page_count: 0,
+            };
+            chunk_count
+        ];
+        assert_eq!(
+            VmssResult::Success,
+            vmss_memory_chunks(
+                provider,
+                &mut page_size,
+                chunks.as_mut_ptr(),
+                &mut chunk_count
+            )
+        );
+        assert_eq!(
+            expected.guest_physical_memory_chunks().unwrap(),
+            (page_size, chunks)
+        );
+
+        let mut buffer = [0u8; 64];
+        let mut expected_buffer = [0u8; 64];
+        let mut bytes_read = 0;
+        assert_eq!(
+            VmssResult::Success,
+            vmss_read_physical_memory(
+                provider,
+                0x1000,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut bytes_read
+            )
+        );
+        expected
+            .read_guest_physical_address(0x1000, &mut expected_buffer)
+            .unwrap();
+        assert_eq!(buffer.len(), bytes_read);
+        assert_eq!(expected_buffer, buffer);
+
+        assert_eq!(
+            VmssResult::Success,
+            vmss_read_virtual_memory(
+                provider,
+                0,
+                0x1000,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut bytes_read
+            )
+        );
+        expected
+            .read_guest_virtual_address(0, 0x1000, &mut expected_buffer)
+            .unwrap();
+        assert_eq!(expected_buffer, buffer);
+        assert_eq!(
+            VmssResult::NullPointer,
+            vmss_read_physical_memory(
+                provider,
+                0x1000,
+                std::ptr::null_mut(),
+                buffer.len(),
+                &mut bytes_read
+            )
+        );
+        // Sizes of 4 GiB or more are rejected before the buffer is touched
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert_eq!(
+                VmssResult::InvalidArgument,
+                vmss_read_physical_memory(
+                    provider,
+                    0,
+                    buffer.as_mut_ptr(),
+                    u32::MAX as usize + 1,
+                    &mut bytes_read
+                )
+            );
+            assert_eq!(
+                VmssResult::InvalidArgument,
+                vmss_read_virtual_memory(
+                    provider,
+                    0,
+                    0,
+                    buffer.as_mut_ptr(),
+                    u32::MAX as usize + 1,
+                    &mut bytes_read
+                )
+            );
+        }
+
+        let mut output_path = std::env::temp_dir();
+        output_path.push("vmss_c_abi_test.lime");
+        let output = CString::new(output_path.to_str().unwrap()).unwrap();
+        extern "C" fn progress(bytes_written: u64, total_bytes: u64, context: *mut c_void) {
+            assert!(bytes_written <= total_bytes);
+            unsafe { *(context as *mut u32) += 1 };
+        }
+        let mut progress_calls = 0u32;
+        let mut statistics = VmssDumpStatistics::default();
+        assert_eq!(
+            VmssResult::Success,
+            vmss_write_dump(
+                provider,
+                output.as_ptr(),
+                VmssDumpFormat::Lime as u32,
+                std::ptr::null(),
+                Some(progress),
+                &mut progress_calls as *mut u32 as *mut c_void,
+                &mut statistics
+            )
+        );
+        assert!(progress_calls > 0);
+        assert_eq!(
+            std::fs::metadata(&output_path).unwrap().len(),
+            statistics.bytes_written
+        );
+        std::fs::remove_file(&output_path).unwrap();
+        assert_eq!(
+            VmssResult::InvalidArgument,
+            vmss_write_dump(
+                provider,
+                output.as_ptr(),
+                6,
+                std::ptr::null(),
+                None,
+                std::ptr::null_mut(),
+                std::ptr::null_mut()
+            )
+        );
+        assert_eq!("unknown dump format 6", last_error_message());
+        assert!(!output_path.exists());
+
+        assert_eq!(VmssResult::Success, vmss_close(provider));
+        assert_eq!(
+            VmssResult::NullPointer,
+            vmss_vp_count(std::ptr::null(), &mut vp_count)
+        );
+        assert_eq!("provider is null", last_error_message());
+
+        let path = CString::new("some_wrong_path.vmrs").unwrap();
+        assert_ne!(VmssResult::Success, vmss_open(path.as_ptr(), &mut provider));
+    }
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn c_abi_header_is_up_to_date() {
+    // The build generates the header into OUT_DIR, the checked in copy must match it
     assert_eq!(
-        Ok(()),
-        apply_pending_replay_log(vmrs_file_path.to_str().unwrap())
+        include_str!(concat!(env!("OUT_DIR"), "/vmss.h")),
+        include_str!("../include/vmss.h"),
+        "include/vmss.h is stale, regenerate it with \
+         `cbindgen --config cbindgen.toml --output include/vmss.h`"
     );
 }
@@ -0,0 +1,155 @@
+# Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+# Licensed under the Apache License, Version 2.0
+# <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+# All files in the project carrying such notice may not be copied, modified, or distributed
+# except according to those terms.
+# THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+"""Tests of the vmsavedstatedump Python module, built with `maturin develop`.
+
+They load the same saved state files as the integration tests of the crate, so they run where
+vmsavedstatedumpprovider.dll is available: `python -m pytest tests/python`.
+"""
+
+import os
+import threading
+
+import pytest
+
+vmsavedstatedump = pytest.importorskip("vmsavedstatedump")
+
+TESTS_DIR = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+VMRS_FILE_PATH = os.path.join(TESTS_DIR, "test_file.vmrs")
+BIN_FILE_PATH = os.path.join(TESTS_DIR, "test_file.bin")
+VSV_FILE_PATH = os.path.join(TESTS_DIR, "test_file.vsv")
+
+
+@pytest.fixture
+def provider():
+    with vmsavedstatedump.VmSavedStateDumpProvider(VMRS_FILE_PATH) as provider:
+        yield provider
+
+
+def test_detect_format():
+    assert vmsavedstatedump.detect_format(VMRS_FILE_PATH) == "vmrs"
+    assert vmsavedstatedump.detect_format(VSV_FILE_PATH) == "vsv"
+
+
+def test_load_bin_vsv():
+    provider = vmsavedstatedump.VmSavedStateDumpProvider.load_bin_vsv(BIN_FILE_PATH, VSV_FILE_PATH)
+    assert provider.vp_count() >= 1
+    provider.close()
+
+
+def test_wrong_path_cant_be_loaded():
+    with pytest.raises(vmsavedstatedump.SavedStateError):
+        vmsavedstatedump.VmSavedStateDumpProvider("some_wrong_path.vmrs")
+
+
+def test_virtual_processors(provider):
+    vp_count = provider.vp_count()
+    vps = provider.virtual_processors()
+    assert [vp.id for vp in vps] == list(range(vp_count))
+    assert provider.vp(0).architecture() == vmsavedstatedump.VirtualProcessorArch.X86
+
+    with pytest.raises(IndexError):
+        provider.vp(vp_count)
+
+
+def test_register_value(provider):
+    vp = provider.vp(0)
+    assert vp.register_value("ecx") == 4
+    assert vp.register_value(vmsavedstatedump.RegisterX86.Ecx) == 4
+    assert vp.registers()["ecx"] == 4
+
+    with pytest.raises(ValueError):
+        vp.register_value("not_a_register")
+    with pytest.raises(vmsavedstatedump.SavedStateError):
+        vp.register_value(vmsavedstatedump.RegisterX64.Rcx)
+
+
+def test_read_physical(provider):
+    page_size, chunks = provider.memory_chunks()
+    start_page_index, page_count = chunks[0]
+    assert page_count > 0
+
+    data = provider.read_physical(start_page_index * page_size, page_size)
+    assert isinstance(data, bytes)
+    assert len(data) == page_size
+
+
+def test_read_virtual(provider):
+    vp = provider.vp(0)
+    virtual_address = vp.register_value("eip")
+    data = vp.read_virtual(virtual_address, 0x40)
+    assert isinstance(data, bytes)
+    assert data == provider.read_virtual(0, virtual_address, 0x40)
+
+
+def test_oversized_reads_raise_value_error(provider):
+    with pytest.raises(ValueError):
+        provider.read_physical(0, 1 << 40)
+    with pytest.raises(ValueError):
+        provider.read_virtual(0, 0, vmsavedstatedump.MAX_READ_SIZE + 1)
+    with pytest.raises(ValueError):
+        provider.vp(0).read_virtual(0, vmsavedstatedump.MAX_READ_SIZE + 1)
+    with pytest.raises(ValueError):
+        provider.read_raw_saved_memory(0, 1 << 40)
+
+
+def test_reads_from_threads(provider):
+    expected = provider.read_physical(0, 0x10000)
+    results = []
+    threads = [
+        threading.Thread(target=lambda: results.append(provider.read_physical(0, 0x10000)))
+        for _ in range(4)
+    ]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    assert results == [expected] * len(threads)
+
+
+def test_closed_provider_raises_value_error():
+    with vmsavedstatedump.VmSavedStateDumpProvider(VMRS_FILE_PATH) as provider:
+        vp = provider.vp(0)
+    with pytest.raises(ValueError):
+        provider.vp_count()
+    with pytest.raises(ValueError):
+        vp.register_value("ecx")
+
+
+def test_write_dump(provider, tmp_path):
+    path = tmp_path / "test_file.lime"
+    progress = []
+    statistics = provider.write_dump(str(path), "lime", lambda written, total: progress.append((written, total)))
+
+    assert statistics.bytes_written == path.stat().st_size
+    assert statistics.pages_written > 0
+    assert progress
+    assert all(written <= total for written, total in progress)
+    assert vmsavedstatedump.detect_format(str(path)) == "lime"
+
+
+def test_write_dump_cancelled_by_progress(provider, tmp_path):
+    def cancel(written, total):
+        raise RuntimeError("cancelled")
+
+    with pytest.raises(RuntimeError):
+        provider.write_dump(str(tmp_path / "test_file.raw"), "raw", cancel)
+
+
+def test_write_dump_progress_cant_use_provider(provider, tmp_path):
+    def progress(written, total):
+        provider.vp(0).register_value("eip")
+
+    with pytest.raises(RuntimeError):
+        provider.write_dump(str(tmp_path / "test_file.raw"), "raw", progress)
+    assert provider.vp_count() >= 1
+
+
+def test_write_dump_unknown_format(provider, tmp_path):
+    with pytest.raises(ValueError):
+        provider.write_dump(str(tmp_path / "test_file.dmp"), "not_a_format")
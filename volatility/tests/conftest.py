@@ -0,0 +1,214 @@
+# Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+# Licensed under the Apache License, Version 2.0
+# <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+# All files in the project carrying such notice may not be copied, modified, or distributed
+# except according to those terms.
+# THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+"""Stubs of the parts of Volatility 3 and of the ``vmsavedstatedump`` module used by the layer.
+
+They replace both packages, so the layer and the stacker are tested without Volatility 3,
+the DLL or saved state files: ``python -m pytest volatility/tests``.
+"""
+
+import os
+import sys
+import types
+
+import pytest
+
+
+def _module(name: str, **attributes) -> types.ModuleType:
+    module = types.ModuleType(name)
+    module.__dict__.update(attributes)
+    sys.modules[name] = module
+    return module
+
+
+# volatility3.framework
+
+
+class VolatilityException(Exception):
+    pass
+
+
+class LayerException(VolatilityException):
+    def __init__(self, layer_name, *args):
+        super().__init__(layer_name, *args)
+        self.layer_name = layer_name
+
+
+class InvalidAddressException(LayerException):
+    def __init__(self, layer_name, invalid_address, *args):
+        super().__init__(layer_name, *args)
+        self.invalid_address = invalid_address
+
+
+class DataLayerInterface:
+    def __init__(self, context, config_path, name, metadata=None):
+        self._context = context
+        self._name = name
+        prefix = config_path + "."
+        self.config = {
+            key[len(prefix):]: value
+            for key, value in context.config.items()
+            if key.startswith(prefix)
+        }
+
+    @property
+    def name(self):
+        return self._name
+
+
+class Requirement:
+    def __init__(self, name, optional=False):
+        self.name = name
+        self.optional = optional
+
+
+class FileLayer:
+    def __init__(self, location):
+        self.location = location
+
+
+class LayerContainer(dict):
+    def free_layer_name(self, prefix):
+        index = 1
+        while f"{prefix}{index}" in self:
+            index += 1
+        return f"{prefix}{index}"
+
+
+class Context:
+    def __init__(self, **layers):
+        self.config = {}
+        self.layers = LayerContainer(layers)
+
+
+_module("volatility3")
+_module("volatility3.framework")
+_module("volatility3.framework.constants", LOGLEVEL_VVVV=6, ProgressCallback=object)
+_module(
+    "volatility3.framework.exceptions",
+    VolatilityException=VolatilityException,
+    LayerException=LayerException,
+    InvalidAddressException=InvalidAddressException,
+)
+_module(
+    "volatility3.framework.interfaces",
+    layers=types.SimpleNamespace(DataLayerInterface=DataLayerInterface),
+    automagic=types.SimpleNamespace(StackerLayerInterface=object),
+    configuration=types.SimpleNamespace(
+        path_join=lambda *parts: ".".join(parts), RequirementInterface=Requirement
+    ),
+    context=types.SimpleNamespace(ContextInterface=object),
+)
+_module("volatility3.framework.configuration")
+_module(
+    "volatility3.framework.configuration.requirements",
+    StringRequirement=Requirement,
+    TranslationLayerRequirement=Requirement,
+)
+_module("volatility3.framework.layers")
+_module("volatility3.framework.layers.physical", FileLayer=FileLayer)
+
+
+# vmsavedstatedump
+
+
+class SavedStateError(Exception):
+    pass
+
+
+class SavedState:
+    """Saved state read by the fake provider. Each byte of guest memory is the low byte of its
+    address. Reads starting at an address of `failures` raise, and reads crossing an address of
+    `short_reads` stop there."""
+
+    def __init__(self, chunks, page_size=0x1000, format="vmrs"):
+        self.chunks = chunks
+        self.page_size = page_size
+        self.format = format
+        self.failures = set()
+        self.short_reads = set()
+        self.reads = []
+        self.closed = False
+
+
+class VmSavedStateDumpProvider:
+    def __init__(self, path, companion=None, page_cache_capacity=0):
+        try:
+            self._state = SAVED_STATES[path]
+        except KeyError:
+            raise SavedStateError(f"cannot load {path}") from None
+
+    def memory_chunks(self):
+        return self._state.page_size, list(self._state.chunks)
+
+    def read_physical(self, physical_address, size):
+        if size > vmsavedstatedump.MAX_READ_SIZE:
+            raise ValueError(f"read size {size:#x} is over the maximum")
+        self._state.reads.append((physical_address, size))
+        if physical_address in self._state.failures:
+            raise SavedStateError(f"cannot read {physical_address:#x}")
+        end = physical_address + size
+        for short_read in self._state.short_reads:
+            if physical_address < short_read < end:
+                end = short_read
+        return bytes(address & 0xFF for address in range(physical_address, end))
+
+    def close(self):
+        self._state.closed = True
+
+
+def detect_format(path):
+    if path in SAVED_STATES:
+        return SAVED_STATES[path].format
+    if not os.path.exists(path):
+        raise SavedStateError(f"cannot open {path}")
+    return "unknown"
+
+
+# Saved states the fake provider loads, by path.
+SAVED_STATES = {}
+
+vmsavedstatedump = _module(
+    "vmsavedstatedump",
+    MAX_READ_SIZE=0x800,
+    SavedStateError=SavedStateError,
+    VmSavedStateDumpProvider=VmSavedStateDumpProvider,
+    detect_format=detect_format,
+)
+
+sys.path.insert(0, os.path.dirname(os.path.dirname(os.path.abspath(__file__))))
+
+
+@pytest.fixture(autouse=True)
+def saved_states():
+    SAVED_STATES.clear()
+    yield SAVED_STATES
+    SAVED_STATES.clear()
+
+
+@pytest.fixture
+def saved_state(saved_states, tmp_path):
+    """Creates a saved state file the fake provider loads, returning its path and state."""
+
+    def create(chunks, name="test_file.vmrs", **kwargs):
+        path = str(tmp_path / name)
+        open(path, "wb").close()
+        saved_states[path] = SavedState(chunks, **kwargs)
+        return path, saved_states[path]
+
+    return create
+
+
+@pytest.fixture
+def file_context():
+    """Creates a context with a file layer named "base" at the given location."""
+
+    def create(location):
+        return Context(base=FileLayer(location))
+
+    return create
@@ -0,0 +1,197 @@
+# Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+# Licensed under the Apache License, Version 2.0
+# <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+# All files in the project carrying such notice may not be copied, modified, or distributed
+# except according to those terms.
+# THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+"""Tests of the VM saved state layer and stacker, run against the stubs of conftest.py."""
+
+import urllib.request
+
+import pytest
+import vmsavedstate
+import vmsavedstatedump
+from volatility3.framework import exceptions
+
+# Out of order and with an empty chunk, they make the segments [0, 0x1000), [0x2000, 0x4000),
+# [0x4000, 0x5000) and [0x8000, 0x9000), with holes at 0x1000, 0x5000 and from 0x9000 on.
+CHUNKS = [(2, 2), (0, 1), (4, 1), (8, 1), (6, 0)]
+
+
+def guest_memory(offset, length):
+    return bytes(address & 0xFF for address in range(offset, offset + length))
+
+
+def file_location(path):
+    return "file:" + urllib.request.pathname2url(path)
+
+
+@pytest.fixture
+def layer(saved_state, file_context):
+    path, state = saved_state(CHUNKS)
+    context = file_context(file_location(path))
+    context.config["layer.location"] = path
+    layer = vmsavedstate.VmSavedStateLayer(context, "layer", "layer")
+    layer.state = state
+    return layer
+
+
+def test_segments(layer):
+    assert layer._segments == [(0, 0x1000), (0x2000, 0x2000), (0x4000, 0x1000), (0x8000, 0x1000)]
+    assert layer.minimum_address == 0
+    assert layer.maximum_address == 0x8FFF
+    assert layer.dependencies == []
+
+
+def test_maximum_address_without_chunks(saved_state, file_context):
+    path, _ = saved_state([(0, 0)])
+    context = file_context(file_location(path))
+    context.config["layer.location"] = path
+    layer = vmsavedstate.VmSavedStateLayer(context, "layer", "layer")
+    assert layer.maximum_address == 0
+    assert not layer.is_valid(0)
+
+
+def test_is_valid(layer):
+    assert layer.is_valid(0)
+    assert layer.is_valid(0, 0x1000)
+    assert layer.is_valid(0x8FFF)
+    # Across the boundary of adjacent segments
+    assert layer.is_valid(0x2000, 0x3000)
+    assert layer.is_valid(0x3FFF, 2)
+
+
+def test_is_valid_in_holes(layer):
+    assert not layer.is_valid(0x1000)
+    assert not layer.is_valid(0xFFF, 2)
+    assert not layer.is_valid(0x4FFF, 2)
+    assert not layer.is_valid(0x5000, 0x3000)
+    assert not layer.is_valid(0x7FFF, 2)
+    assert not layer.is_valid(0x9000)
+    assert not layer.is_valid(0x100000000)
+
+
+def test_read(layer):
+    assert layer.read(0x10, 0x20) == guest_memory(0x10, 0x20)
+    assert layer.read(0x8FF0, 0x10) == guest_memory(0x8FF0, 0x10)
+    assert layer.state.reads == [(0x10, 0x20), (0x8FF0, 0x10)]
+
+
+def test_read_across_segment_boundary(layer):
+    assert layer.read(0x3FF0, 0x20) == guest_memory(0x3FF0, 0x20)
+    assert layer.state.reads == [(0x3FF0, 0x10), (0x4000, 0x10)]
+
+
+def test_read_is_split_at_max_read_size(layer):
+    assert layer.read(0x2100, 0x1800) == guest_memory(0x2100, 0x1800)
+    assert layer.state.reads == [(0x2100, 0x800), (0x2900, 0x800), (0x3100, 0x800)]
+    assert all(size <= vmsavedstatedump.MAX_READ_SIZE for _, size in layer.state.reads)
+
+
+@pytest.mark.parametrize(
+    "offset, length, invalid_address",
+    [
+        (0xFF0, 0x20, 0x1000),
+        (0x1000, 0x10, 0x1000),
+        (0x4FF0, 0x20, 0x5000),
+        (0x8FF0, 0x20, 0x9000),
+        (0x10000, 0x10, 0x10000),
+    ],
+)
+def test_read_holes_without_padding(layer, offset, length, invalid_address):
+    with pytest.raises(exceptions.InvalidAddressException) as error:
+        layer.read(offset, length)
+    assert error.value.invalid_address == invalid_address
+
+
+def test_read_holes_with_padding(layer):
+    data = layer.read(0xFF0, 0x1020, pad=True)
+    assert data == guest_memory(0xFF0, 0x10) + bytes(0x1000) + guest_memory(0x2000, 0x10)
+
+    data = layer.read(0x4FF0, 0x3020, pad=True)
+    assert data == guest_memory(0x4FF0, 0x10) + bytes(0x3000) + guest_memory(0x8000, 0x10)
+
+    # Past the last segment, the hole never ends
+    assert layer.read(0x8FF0, 0x20, pad=True) == guest_memory(0x8FF0, 0x10) + bytes(0x10)
+    assert layer.read(0x10000, 0x10, pad=True) == bytes(0x10)
+
+    # Holes are padded without reading them
+    assert all(layer.is_valid(offset, size) for offset, size in layer.state.reads)
+
+
+def test_read_short(layer):
+    layer.state.short_reads.add(0x2010)
+    with pytest.raises(exceptions.InvalidAddressException) as error:
+        layer.read(0x2000, 0x20)
+    assert error.value.invalid_address == 0x2010
+
+    assert layer.read(0x2000, 0x20, pad=True) == guest_memory(0x2000, 0x10) + bytes(0x10)
+
+
+def test_read_failure(layer):
+    layer.state.failures.add(0x2000)
+    with pytest.raises(exceptions.InvalidAddressException) as error:
+        layer.read(0x2000, 0x20)
+    assert error.value.invalid_address == 0x2000
+
+    assert layer.read(0x1FF0, 0x20, pad=True) == bytes(0x20)
+    # Only the read that failed is padded
+    data = layer.read(0x2000, 0x1000, pad=True)
+    assert data == bytes(0x800) + guest_memory(0x2800, 0x800)
+
+
+def test_write_is_refused(layer):
+    with pytest.raises(exceptions.LayerException):
+        layer.write(0, b"\x00")
+
+
+def test_destroy_closes_the_provider(layer):
+    layer.destroy()
+    assert layer.state.closed
+
+
+@pytest.mark.parametrize("name", ["test_file.vmrs", "test_file.bin", "test_file.vsv"])
+def test_stack(saved_state, file_context, name):
+    path, _ = saved_state(CHUNKS, name=name, format=name.rsplit(".", 1)[1])
+    context = file_context(file_location(path))
+
+    layer = vmsavedstate.VmSavedStateStacker.stack(context, "base")
+
+    assert isinstance(layer, vmsavedstate.VmSavedStateLayer)
+    assert layer.name == "VmSavedStateLayer1"
+    assert layer.config["location"] == path
+    assert layer.dependencies == ["base"]
+    assert layer.read(0, 0x10) == guest_memory(0, 0x10)
+
+
+def test_stack_ignores_other_formats(saved_state, file_context):
+    path, _ = saved_state(CHUNKS, name="test_file.elf", format="elf")
+    assert vmsavedstate.VmSavedStateStacker.stack(file_context(file_location(path)), "base") is None
+
+
+def test_stack_ignores_missing_files(tmp_path, file_context):
+    path = str(tmp_path / "missing.vmrs")
+    assert vmsavedstate.VmSavedStateStacker.stack(file_context(file_location(path)), "base") is None
+
+
+def test_stack_ignores_files_that_cant_be_loaded(saved_state, file_context, monkeypatch):
+    path, _ = saved_state(CHUNKS)
+
+    def fail_to_load(*args, **kwargs):
+        raise vmsavedstatedump.SavedStateError("cannot load")
+
+    monkeypatch.setattr(vmsavedstatedump, "VmSavedStateDumpProvider", fail_to_load)
+    assert vmsavedstate.VmSavedStateStacker.stack(file_context(file_location(path)), "base") is None
+
+
+def test_stack_ignores_non_file_locations(file_context):
+    context = file_context("http://localhost/test_file.vmrs")
+    assert vmsavedstate.VmSavedStateStacker.stack(context, "base") is None
+
+
+def test_stack_ignores_non_file_layers(file_context):
+    context = file_context("file:///test_file.vmrs")
+    context.layers["base"] = object()
+    assert vmsavedstate.VmSavedStateStacker.stack(context, "base") is None
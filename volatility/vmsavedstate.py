@@ -0,0 +1,186 @@
+# Copyright (c) 2019 Rafael Alcaraz Mercado. All rights reserved.
+# Licensed under the Apache License, Version 2.0
+# <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+# All files in the project carrying such notice may not be copied, modified, or distributed
+# except according to those terms.
+# THE SOURCE CODE IS AVAILABLE UNDER THE ABOVE CHOSEN LICENSE "AS IS", WITH NO WARRANTIES.
+
+"""Volatility 3 physical layer over Hyper-V VM saved state files.
+
+Guest physical memory is read on demand through the ``vmsavedstatedump`` Python module, built
+with the ``python`` feature of vmsavedstatedump_rs, so existing plugins run directly on saved
+states without converting them to a dump file first::
+
+    vol -p <path to this directory> -f file_path.vmrs windows.pslist
+
+The stacker recognizes VMRS, BIN and VSV files given with ``-f``; the companion file of
+BIN/VSV pairs is expected next to them, with the same name.
+"""
+
+import bisect
+import logging
+import urllib.parse
+import urllib.request
+from typing import List, Optional, Tuple
+
+import vmsavedstatedump
+from volatility3.framework import constants, exceptions, interfaces
+from volatility3.framework.configuration import requirements
+from volatility3.framework.layers import physical
+
+vollog = logging.getLogger(__name__)
+
+# Guest physical pages cached by the saved state reader, as plugins reread page tables often.
+PAGE_CACHE_CAPACITY = 4096
+
+
+class VmSavedStateLayer(interfaces.layers.DataLayerInterface):
+    """Guest physical memory of a VM saved state, laid out as its guest physical memory chunks.
+
+    Addresses outside of the chunks are holes, which read as zeros when padding is requested.
+    """
+
+    def __init__(
+        self,
+        context: interfaces.context.ContextInterface,
+        config_path: str,
+        name: str,
+        metadata: Optional[dict] = None,
+    ) -> None:
+        super().__init__(
+            context=context, config_path=config_path, name=name, metadata=metadata
+        )
+        self._provider = vmsavedstatedump.VmSavedStateDumpProvider(
+            self.config["location"], page_cache_capacity=PAGE_CACHE_CAPACITY
+        )
+        page_size, chunks = self._provider.memory_chunks()
+        self._segments: List[Tuple[int, int]] = sorted(
+            (start_page * page_size, page_count * page_size)
+            for start_page, page_count in chunks
+            if page_count
+        )
+        self._segment_starts = [start for start, _ in self._segments]
+
+    @property
+    def minimum_address(self) -> int:
+        return 0
+
+    @property
+    def maximum_address(self) -> int:
+        if not self._segments:
+            return 0
+        start, length = self._segments[-1]
+        return start + length - 1
+
+    @property
+    def dependencies(self) -> List[str]:
+        base_layer = self.config.get("base_layer", None)
+        return [base_layer] if base_layer else []
+
+    def _segment_at(self, offset: int) -> Tuple[bool, Optional[int]]:
+        """Returns whether the offset is inside a segment, along with the offset that segment,
+        or the hole the offset is in, ends at. Holes past the last segment never end."""
+        index = bisect.bisect_right(self._segment_starts, offset) - 1
+        if index >= 0:
+            start, length = self._segments[index]
+            if offset < start + length:
+                return True, start + length
+        if index + 1 < len(self._segments):
+            return False, self._segments[index + 1][0]
+        return False, None
+
+    def is_valid(self, offset: int, length: int = 1) -> bool:
+        end = offset + length
+        while offset < end:
+            mapped, next_offset = self._segment_at(offset)
+            if not mapped:
+                return False
+            offset = next_offset
+        return True
+
+    def read(self, offset: int, length: int, pad: bool = False) -> bytes:
+        data = bytearray()
+        current = offset
+        end = offset + length
+        while current < end:
+            mapped, next_offset = self._segment_at(current)
+            if not mapped:
+                if not pad:
+                    raise exceptions.InvalidAddressException(
+                        self.name,
+                        current,
+                        f"Offset outside of the guest memory of {self.name}",
+                    )
+                hole_end = end if next_offset is None else min(next_offset, end)
+                data += b"\x00" * (hole_end - current)
+                current = hole_end
+                continue
+
+            # Reads over MAX_READ_SIZE raise ValueError, so large reads are split
+            size = min(next_offset, end, current + vmsavedstatedump.MAX_READ_SIZE) - current
+            try:
+                chunk = self._provider.read_physical(current, size)
+            except vmsavedstatedump.SavedStateError as error:
+                vollog.log(constants.LOGLEVEL_VVVV, f"Failed to read {current:#x}: {error}")
+                chunk = b""
+            if len(chunk) != size:
+                if not pad:
+                    raise exceptions.InvalidAddressException(
+                        self.name,
+                        current + len(chunk),
+                        f"Could not read sufficient bytes from {self.name}",
+                    )
+                chunk += b"\x00" * (size - len(chunk))
+            data += chunk
+            current += size
+        return bytes(data)
+
+    def write(self, offset: int, data: bytes) -> None:
+        raise exceptions.LayerException(self.name, "VM saved states are read only")
+
+    def destroy(self) -> None:
+        self._provider.close()
+
+    @classmethod
+    def get_requirements(cls) -> List[interfaces.configuration.RequirementInterface]:
+        return [
+            requirements.StringRequirement(name="location", optional=False),
+            requirements.TranslationLayerRequirement(name="base_layer", optional=True),
+        ]
+
+
+class VmSavedStateStacker(interfaces.automagic.StackerLayerInterface):
+    """Stacks a VmSavedStateLayer on top of file layers opened on VM saved state files."""
+
+    stack_order = 10
+
+    @classmethod
+    def stack(
+        cls,
+        context: interfaces.context.ContextInterface,
+        layer_name: str,
+        progress_callback: constants.ProgressCallback = None,
+    ) -> Optional[interfaces.layers.DataLayerInterface]:
+        base_layer = context.layers[layer_name]
+        if not isinstance(base_layer, physical.FileLayer):
+            return None
+
+        location = urllib.parse.urlparse(base_layer.location)
+        if location.scheme != "file":
+            return None
+        path = urllib.request.url2pathname(location.path)
+        try:
+            if vmsavedstatedump.detect_format(path) not in ("vmrs", "bin", "vsv"):
+                return None
+        except vmsavedstatedump.SavedStateError:
+            return None
+
+        new_name = context.layers.free_layer_name("VmSavedStateLayer")
+        context.config[interfaces.configuration.path_join(new_name, "location")] = path
+        context.config[interfaces.configuration.path_join(new_name, "base_layer")] = layer_name
+        try:
+            return VmSavedStateLayer(context, new_name, new_name)
+        except vmsavedstatedump.SavedStateError as error:
+            vollog.debug(f"Failed to load {path} as a VM saved state: {error}")
+            return None